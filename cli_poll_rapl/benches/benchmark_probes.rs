@@ -9,38 +9,38 @@ use rapl_probes::{
 };
 
 #[cfg(feature = "bench_ebpf")]
-use rapl_probes::ebpf::EbpfProbe;
+use rapl_probes::ebpf::{EbpfProbe, DEFAULT_BUF_PAGE_COUNT};
 
 fn init_powercap_probe<const CHECK_UTF: bool>(domains: &[RaplDomainType]) -> anyhow::Result<PowercapProbe<CHECK_UTF>> {
-    let cpu = rapl_probes::cpus_to_monitor()?.first().unwrap().clone();
+    let cpu = *rapl_probes::cpus_to_monitor()?.first().unwrap();
     let cpus = &[cpu];
     let all = powercap::all_power_zones()?.flat;
     let zones: Vec<&powercap::PowerZone> = all.iter().filter(|z| domains.contains(&z.domain) && (z.socket_id.is_some_and(|s| cpu.socket == s))).collect();
-    PowercapProbe::new(cpus, &zones)
+    Ok(PowercapProbe::new(cpus, &zones)?)
 }
 
 fn init_perf_probe(domains: &[RaplDomainType]) -> anyhow::Result<PerfEventProbe> {
-    let cpu = rapl_probes::cpus_to_monitor()?.first().unwrap().clone();
+    let cpu = *rapl_probes::cpus_to_monitor()?.first().unwrap();
     let cpus = &[cpu];
     let all = perf_event::all_power_events()?;
     let events: Vec<&perf_event::PowerEvent> = all.iter().filter(|e| domains.contains(&e.domain)).collect();
-    PerfEventProbe::new(cpus, &events)
+    Ok(PerfEventProbe::new(cpus, &events)?)
 }
 
 #[cfg(feature = "bench_ebpf")]
 fn init_ebpf_probe(domains: &[RaplDomainType]) -> anyhow::Result<EbpfProbe> {
     let all = perf_event::all_power_events()?;
-    let cpu = rapl_probes::cpus_to_monitor()?.first().unwrap().clone();
+    let cpu = *rapl_probes::cpus_to_monitor()?.first().unwrap();
     let cpus = &[cpu];
     let events: Vec<&perf_event::PowerEvent> = all.iter().filter(|e| domains.contains(&e.domain)).collect();
     let freq_hz = 1000;
-    EbpfProbe::new(cpus, &events, freq_hz)
+    Ok(EbpfProbe::new(cpus, &events, freq_hz, DEFAULT_BUF_PAGE_COUNT)?)
 }
 
 fn init_msr_probe(domains: &[RaplDomainType]) -> anyhow::Result<MsrProbe> {
-    let cpu = rapl_probes::cpus_to_monitor()?.first().unwrap().clone();
+    let cpu = *rapl_probes::cpus_to_monitor()?.first().unwrap();
     let cpus = &[cpu];
-    MsrProbe::new(cpus, domains)
+    Ok(MsrProbe::new(cpus, domains)?)
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
@@ -79,23 +79,23 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         // run it
         {
-            let mut probe_powercap = init_powercap_probe::<true>(&domains).unwrap();
+            let mut probe_powercap = init_powercap_probe::<true>(domains).unwrap();
             run_bench("powercap", &mut probe_powercap);
         }
 
         {
-            let mut probe_perf = init_perf_probe(&domains).unwrap();
+            let mut probe_perf = init_perf_probe(domains).unwrap();
             run_bench("perf", &mut probe_perf);
         }
 
         {
-            let mut probe_msr = init_msr_probe(&domains).unwrap();
+            let mut probe_msr = init_msr_probe(domains).unwrap();
             run_bench("msr", &mut probe_msr);
         }
 
         #[cfg(feature = "bench_powercap_unchecked")]
         {
-            let mut probe_powercap_unchecked = init_powercap_probe::<false>(&domains).unwrap();
+            let mut probe_powercap_unchecked = init_powercap_probe::<false>(domains).unwrap();
             run_bench("powercap-unchecked", &mut probe_powercap_unchecked);
         }
 
@@ -106,7 +106,7 @@ fn criterion_benchmark(c: &mut Criterion) {
             #[cfg(feature = "bench_ebpf")]
             let runtime = tokio::runtime::Runtime::new().unwrap(); // ebpf requires the tokio runtime to asynchronously poll the buffers
             #[cfg(feature = "bench_ebpf")]
-            let mut probe_ebpf = runtime.block_on(async { init_ebpf_probe(&domains).unwrap() });
+            let mut probe_ebpf = runtime.block_on(async { init_ebpf_probe(domains).unwrap() });
 
             run_bench("ebpf", &mut probe_ebpf);
         }
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use log::info;
+use rapl_probes::EnergyMeasurements;
+
+/// The polling period cannot be reduced below this value.
+pub const MIN_ADAPTIVE_PERIOD: Duration = Duration::from_millis(1);
+
+/// Time without overflow after which the period is increased again.
+pub const RELAX_WINDOW: Duration = Duration::from_secs(60);
+
+/// Adapts the polling period to avoid the overflows of the RAPL counters.
+///
+/// When a poll reports an overflow, the period is halved (down to `min_period`).
+/// After `relax_window` without overflow, it is doubled, up to the period requested by the user.
+pub struct AdaptivePeriod {
+    /// The period requested by the user, which is never exceeded.
+    max_period: Duration,
+    min_period: Duration,
+    relax_window: Duration,
+    current: Duration,
+    /// Time since the last overflow or the last change of period.
+    calm: Duration,
+}
+
+impl AdaptivePeriod {
+    pub fn new(period: Duration, min_period: Duration, relax_window: Duration) -> AdaptivePeriod {
+        let min_period = min_period.min(period);
+        AdaptivePeriod {
+            max_period: period,
+            min_period,
+            relax_window,
+            current: period,
+            calm: Duration::ZERO,
+        }
+    }
+
+    /// Takes the latest measurements into account, and returns the period to use for the next poll.
+    pub fn update(&mut self, measurements: &EnergyMeasurements) -> Duration {
+        let overflowed = measurements.per_socket.iter().any(|s| s.values().any(|c| c.overflowed));
+        if overflowed {
+            self.calm = Duration::ZERO;
+            let halved = (self.current / 2).max(self.min_period);
+            if halved < self.current {
+                info!("RAPL counter overflow detected, reducing the polling period from {:?} to {halved:?}", self.current);
+                self.current = halved;
+            }
+        } else {
+            self.calm += self.current;
+            if self.calm >= self.relax_window && self.current < self.max_period {
+                let doubled = (self.current * 2).min(self.max_period);
+                info!("No overflow for {:?}, increasing the polling period from {:?} to {doubled:?}", self.calm, self.current);
+                self.current = doubled;
+                self.calm = Duration::ZERO;
+            }
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::AdaptivePeriod;
+
+    #[test]
+    fn test_adaptive_period() {
+        let ms = Duration::from_millis;
+        let mut adaptive = AdaptivePeriod::new(ms(1000), ms(200), ms(3000));
+        let mut m = EnergyMeasurements::new(1);
+        let max = 1000;
+        let mut poll = |value: u64| {
+            m.push(0, RaplDomainType::Package, value, max, 1.0);
+            adaptive.update(&m)
+        };
+
+        assert_eq!(poll(100), ms(1000));
+        assert_eq!(poll(900), ms(1000));
+        // overflows: halve the period until the floor
+        assert_eq!(poll(300), ms(500));
+        assert_eq!(poll(200), ms(250));
+        assert_eq!(poll(100), ms(200));
+        assert_eq!(poll(50), ms(200));
+        // no overflow during 3 s (15 polls of 200 ms): double the period, but not above the initial one
+        let periods: Vec<Duration> = (0..15).map(|i| poll(100 + i)).collect();
+        assert_eq!(periods[13], ms(200));
+        assert_eq!(periods[14], ms(400));
+        // 8 polls of 400 ms, then 4 polls of 800 ms
+        let periods: Vec<Duration> = (0..16).map(|i| poll(200 + i)).collect();
+        assert_eq!((periods[6], periods[7]), (ms(400), ms(800)));
+        assert_eq!((periods[10], periods[11]), (ms(800), ms(1000)));
+        assert_eq!(periods[15], ms(1000));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use rapl_probes::RaplDomainType;
+
+use crate::main_optimized::MeasurementsMessage;
+
+/// Minimum time between two alerts for the same socket, to avoid flooding the logs.
+pub const ALERT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Returns the power of the domain, in Watts, if it is the `Package` domain and its power exceeds `threshold_watts`.
+pub fn power_above_threshold(domain: RaplDomainType, joules: f64, period: Duration, threshold_watts: f64) -> Option<f64> {
+    if domain != RaplDomainType::Package || period.is_zero() {
+        return None;
+    }
+    let watts = joules / period.as_secs_f64();
+    (watts > threshold_watts).then_some(watts)
+}
+
+/// Warns when the power of a package exceeds a threshold, e.g. to catch runaway jobs.
+pub struct PowerAlert {
+    threshold_watts: f64,
+    /// The polling period, used when the interval between two polls is unknown.
+    period: Duration,
+    /// Minimum time between two alerts of the same socket.
+    min_interval: Duration,
+    /// Time of the last alert of each socket.
+    last_alerts: HashMap<u32, SystemTime>,
+}
+
+impl PowerAlert {
+    pub fn new(threshold_watts: f64, period: Duration) -> PowerAlert {
+        PowerAlert {
+            threshold_watts,
+            period,
+            min_interval: ALERT_INTERVAL,
+            last_alerts: HashMap::new(),
+        }
+    }
+
+    /// Returns the sockets whose package power exceeds the threshold, with their power in Watts.
+    /// A socket is not returned again until `ALERT_INTERVAL` has elapsed since its last alert.
+    pub fn check(&mut self, msg: &MeasurementsMessage) -> Vec<(u32, f64)> {
+        let mut alerts = Vec::new();
+        for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+            let counter = &domains_of_socket[RaplDomainType::Package];
+            let Some(joules) = counter.joules else {
+                continue;
+            };
+            // the actual interval is more accurate than the period, which can be missed or changed by --adaptive
+            let period = counter.elapsed.unwrap_or(self.period);
+            let Some(watts) = power_above_threshold(RaplDomainType::Package, joules, period, self.threshold_watts) else {
+                continue;
+            };
+            let socket = socket_id as u32;
+            let rate_limited = self.last_alerts.get(&socket).is_some_and(|last| {
+                msg.timestamp.duration_since(*last).unwrap_or(Duration::ZERO) < self.min_interval
+            });
+            if !rate_limited {
+                self.last_alerts.insert(socket, msg.timestamp);
+                alerts.push((socket, watts));
+            }
+        }
+        alerts
+    }
+
+    /// Checks the measurements and logs a warning for each alert.
+    pub fn warn(&mut self, msg: &MeasurementsMessage) {
+        for (socket, watts) in self.check(msg) {
+            log::warn!(
+                "The power of package {socket} is {watts:.1} W, above the alert threshold of {} W",
+                self.threshold_watts
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::{power_above_threshold, PowerAlert};
+    use crate::main_optimized::MeasurementsMessage;
+
+    #[test]
+    fn test_threshold() {
+        let second = Duration::from_secs(1);
+        let ms = Duration::from_millis(100);
+        assert_eq!(power_above_threshold(RaplDomainType::Package, 120.0, second, 100.0), Some(120.0));
+        assert_eq!(power_above_threshold(RaplDomainType::Package, 12.0, ms, 100.0), Some(120.0));
+        assert_eq!(power_above_threshold(RaplDomainType::Package, 100.0, second, 100.0), None);
+        assert_eq!(power_above_threshold(RaplDomainType::Package, 8.0, ms, 100.0), None);
+        // only the package
+        assert_eq!(power_above_threshold(RaplDomainType::Dram, 120.0, second, 100.0), None);
+        assert_eq!(power_above_threshold(RaplDomainType::Package, 120.0, Duration::ZERO, 100.0), None);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut alert = PowerAlert::new(100.0, Duration::from_secs(1));
+        let mut m = EnergyMeasurements::new(2);
+        let t0 = Instant::now();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut alerted = Vec::new();
+        // socket 0 is always above the threshold, socket 1 only once
+        for secs in 0..=12u64 {
+            let t = t0 + Duration::from_secs(secs);
+            m.push_at(0, RaplDomainType::Package, secs * 150, u32::MAX as u64, 1.0, t);
+            let pkg1 = secs * 50 + if secs >= 5 { 250 } else { 0 };
+            m.push_at(1, RaplDomainType::Package, pkg1, u32::MAX as u64, 1.0, t);
+            let msg = MeasurementsMessage {
+                timestamp: start + Duration::from_secs(secs),
+                measurements: m.clone(),
+                source: None,
+            };
+            alerted.extend(alert.check(&msg).into_iter().map(|(socket, watts)| (secs, socket, watts)));
+        }
+        assert_eq!(alerted, vec![(1, 0, 150.0), (5, 1, 300.0), (11, 0, 150.0)]);
+    }
+}
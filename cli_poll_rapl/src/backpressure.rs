@@ -0,0 +1,276 @@
+//! What the polling task does when the writer task cannot keep up, for instance in continuous mode (negative frequency).
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+
+use crate::cli::OverflowPolicy;
+use crate::main_optimized::MeasurementsMessage;
+
+/// Capacity of the channel between the polling task and the writer task, in batches.
+pub const CHANNEL_CAPACITY: usize = 4096;
+
+/// The channel is considered full when less than this number of batches can be sent without waiting.
+/// With [`OverflowPolicy::Drop`] and [`OverflowPolicy::Coalesce`], the polling task never waits for the writer.
+const CHANNEL_HEADROOM: usize = CHANNEL_CAPACITY / 16;
+
+/// Combines the snapshots that cannot be sent into the most recent one.
+///
+/// The energy of the combined snapshots is added to the most recent snapshot, so that the total energy is preserved.
+/// When several probes are polled together, each source has its own pending snapshot: the measurements of two probes
+/// are never combined.
+#[derive(Default)]
+pub struct CoalescingBuffer {
+    /// The pending snapshot of each source, in the order of their first snapshot.
+    pending: Vec<MeasurementsMessage>,
+    /// Number of snapshots that have been combined with a more recent one.
+    coalesced: u64,
+}
+
+impl CoalescingBuffer {
+    /// Replaces the pending snapshot of the source of `msg` by `msg`, which also gets the energy of the pending snapshot.
+    pub fn push(&mut self, msg: MeasurementsMessage) {
+        let Some(pending) = self.pending.iter_mut().find(|p| p.source == msg.source) else {
+            self.pending.push(msg);
+            return;
+        };
+        let previous = std::mem::replace(pending, msg);
+        let per_socket = pending.measurements.per_socket.iter_mut().zip(previous.measurements.per_socket);
+        for (domains_of_socket, previous_domains) in per_socket {
+            for (domain, previous_counter) in previous_domains {
+                let counter = &mut domains_of_socket[domain];
+                if let Some(previous_joules) = previous_counter.joules {
+                    counter.joules = Some(counter.joules.unwrap_or(0.0) + previous_joules);
+                    counter.elapsed = match (counter.elapsed, previous_counter.elapsed) {
+                        (Some(a), Some(b)) => Some(a + b),
+                        (a, b) => a.or(b),
+                    };
+                    counter.overflowed |= previous_counter.overflowed;
+                }
+            }
+        }
+        self.coalesced += 1;
+    }
+
+    /// Returns the pending snapshots, one per source, and empties the buffer.
+    pub fn take(&mut self) -> Vec<MeasurementsMessage> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Number of snapshots that have been combined with a more recent one.
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+}
+
+/// Sends the measurements to the writer task, according to an [`OverflowPolicy`].
+pub struct MeasurementsSender {
+    tx: Sender<Vec<MeasurementsMessage>>,
+    policy: OverflowPolicy,
+    buffer: CoalescingBuffer,
+    /// Number of snapshots dropped with [`OverflowPolicy::Drop`].
+    dropped: u64,
+}
+
+impl MeasurementsSender {
+    pub fn new(tx: Sender<Vec<MeasurementsMessage>>, policy: OverflowPolicy) -> MeasurementsSender {
+        MeasurementsSender {
+            tx,
+            policy,
+            buffer: CoalescingBuffer::default(),
+            dropped: 0,
+        }
+    }
+
+    /// Sends a batch, and waits for some space in the channel if the policy is [`OverflowPolicy::Block`].
+    pub async fn send(&mut self, batch: Vec<MeasurementsMessage>) {
+        match self.policy {
+            OverflowPolicy::Block => self.tx.send(batch).await.expect("failed to send measurement through channel"),
+            OverflowPolicy::Drop | OverflowPolicy::Coalesce => self.send_or_shed(batch),
+        }
+    }
+
+    /// Like [`MeasurementsSender::send`], from a thread that is not managed by tokio.
+    pub fn blocking_send(&mut self, batch: Vec<MeasurementsMessage>) {
+        match self.policy {
+            OverflowPolicy::Block => self.tx.blocking_send(batch).expect("failed to send measurement through channel"),
+            OverflowPolicy::Drop | OverflowPolicy::Coalesce => self.send_or_shed(batch),
+        }
+    }
+
+    /// Sends the batch if the channel is not (almost) full, otherwise drops or coalesces it.
+    fn send_or_shed(&mut self, batch: Vec<MeasurementsMessage>) {
+        if self.tx.capacity() <= CHANNEL_HEADROOM {
+            self.shed(batch);
+            return;
+        }
+        let pending = self.buffer.take();
+        let batch = if pending.is_empty() {
+            batch
+        } else {
+            pending.into_iter().chain(batch).collect()
+        };
+        match self.tx.try_send(batch) {
+            Ok(()) => (),
+            Err(TrySendError::Full(batch)) => self.shed(batch),
+            Err(TrySendError::Closed(_)) => panic!("failed to send measurement through channel"),
+        }
+    }
+
+    fn shed(&mut self, batch: Vec<MeasurementsMessage>) {
+        match self.policy {
+            OverflowPolicy::Coalesce => batch.into_iter().for_each(|msg| self.buffer.push(msg)),
+            _ => self.dropped += batch.len() as u64,
+        }
+    }
+
+    /// Sends the coalesced snapshot, if any, and logs the number of snapshots that have been dropped or coalesced.
+    /// Dropping the sender closes the channel.
+    pub async fn finish(mut self) {
+        let pending = self.buffer.take();
+        if !pending.is_empty() {
+            self.tx.send(pending).await.expect("failed to send measurement through channel");
+        }
+        self.log_summary();
+    }
+
+    /// Like [`MeasurementsSender::finish`], from a thread that is not managed by tokio.
+    pub fn blocking_finish(mut self) {
+        let pending = self.buffer.take();
+        if !pending.is_empty() {
+            self.tx.blocking_send(pending).expect("failed to send measurement through channel");
+        }
+        self.log_summary();
+    }
+
+    fn log_summary(&self) {
+        match self.policy {
+            OverflowPolicy::Drop if self.dropped > 0 => log::warn!(
+                "{} snapshots have been dropped because the output could not keep up with the polling",
+                self.dropped
+            ),
+            OverflowPolicy::Coalesce if self.buffer.coalesced() > 0 => log::warn!(
+                "{} snapshots have been coalesced because the output could not keep up with the polling (the energy has been preserved)",
+                self.buffer.coalesced()
+            ),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+    use tokio::sync::mpsc;
+
+    use super::{CoalescingBuffer, MeasurementsSender, CHANNEL_CAPACITY};
+    use crate::cli::OverflowPolicy;
+    use crate::main_optimized::MeasurementsMessage;
+
+    /// Returns the messages of successive polls of a counter that increases by 10 J per second.
+    fn polls(n: u64) -> Vec<MeasurementsMessage> {
+        let mut m = EnergyMeasurements::new(1);
+        let t0 = Instant::now();
+        (0..n)
+            .map(|secs| {
+                m.push_at(0, RaplDomainType::Package, secs * 10, u32::MAX as u64, 1.0, t0 + Duration::from_secs(secs));
+                MeasurementsMessage {
+                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+                    measurements: m.clone(),
+                    source: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_coalescing_buffer() {
+        let mut buffer = CoalescingBuffer::default();
+        assert!(buffer.take().is_empty());
+
+        let mut polls = polls(5).into_iter();
+        // the first poll has no energy value
+        buffer.push(polls.next().unwrap());
+        buffer.push(polls.next().unwrap());
+        buffer.push(polls.next().unwrap());
+        assert_eq!(buffer.coalesced(), 2);
+
+        // only the most recent snapshot is kept, with the energy of the previous ones
+        let msg = buffer.take().pop().unwrap();
+        assert_eq!(msg.timestamp, SystemTime::UNIX_EPOCH + Duration::from_secs(2));
+        let counter = &msg.measurements.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, Some(20.0));
+        assert_eq!(counter.elapsed, Some(Duration::from_secs(2)));
+        assert!(buffer.take().is_empty());
+
+        // the buffer is empty: the next snapshot is kept as is
+        buffer.push(polls.next().unwrap());
+        let msg = buffer.take().pop().unwrap();
+        assert_eq!(msg.measurements.per_socket[0][RaplDomainType::Package].joules, Some(10.0));
+        assert_eq!(buffer.coalesced(), 2);
+    }
+
+    #[test]
+    fn test_coalescing_sources() {
+        let mut buffer = CoalescingBuffer::default();
+        // two probes polled together: their messages alternate
+        let sources: [Option<Arc<str>>; 2] = [Some(Arc::from("powercap")), Some(Arc::from("perf-event"))];
+        for poll in polls(4) {
+            for (source, joules_factor) in sources.iter().zip([1.0, 2.0]) {
+                let mut msg = MeasurementsMessage {
+                    timestamp: poll.timestamp,
+                    measurements: poll.measurements.clone(),
+                    source: source.clone(),
+                };
+                let counter = &mut msg.measurements.per_socket[0][RaplDomainType::Package];
+                counter.joules = counter.joules.map(|j| j * joules_factor);
+                buffer.push(msg);
+            }
+        }
+        assert_eq!(buffer.coalesced(), 6);
+
+        // one snapshot per source, each with its own energy
+        let pending = buffer.take();
+        let coalesced: Vec<_> = pending
+            .iter()
+            .map(|msg| (msg.source.as_deref(), msg.measurements.per_socket[0][RaplDomainType::Package].joules))
+            .collect();
+        assert_eq!(coalesced, [(Some("powercap"), Some(30.0)), (Some("perf-event"), Some(60.0))]);
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policies() {
+        let n = CHANNEL_CAPACITY as u64 + 10;
+        for policy in [OverflowPolicy::Drop, OverflowPolicy::Coalesce] {
+            // nobody reads the channel: it becomes full
+            let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+            let mut sender = MeasurementsSender::new(tx, policy);
+            for msg in polls(n) {
+                sender.send(vec![msg]).await;
+            }
+            // the snapshots that have not been sent individually
+            let lost = sender.dropped + sender.buffer.coalesced();
+            let mut received = Vec::new();
+            let finished = tokio::spawn(sender.finish());
+            while let Some(batch) = rx.recv().await {
+                received.extend(batch);
+            }
+            finished.await.unwrap();
+
+            let joules: f64 = received
+                .iter()
+                .filter_map(|msg| msg.measurements.per_socket[0][RaplDomainType::Package].joules)
+                .sum();
+            assert!(lost > 0);
+            assert_eq!(received.len() as u64 + lost, n);
+            // the dropped snapshots lose their energy, the coalesced ones do not
+            let total = ((n - 1) * 10) as f64;
+            match policy {
+                OverflowPolicy::Drop => assert!(joules < total),
+                _ => assert_eq!(joules, total),
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::stream::StreamExt;
+use rapl_probes::units::format_energy;
+use rapl_probes::{EnergyMeasurements, EnergyProbe};
+use tokio_timerfd::Interval;
+
+use crate::main_optimized::{shutdown_signal, total_joules};
+
+/// Running comparison of the consumed energy with an energy budget of `target_watts * elapsed`.
+pub struct EnergyBudget {
+    target_watts: f64,
+    /// Energy consumed since the beginning.
+    actual_joules: f64,
+    /// Time since the beginning.
+    elapsed: Duration,
+}
+
+/// The state of the budget after an update.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetStatus {
+    pub elapsed: Duration,
+    pub actual_joules: f64,
+    pub budget_joules: f64,
+}
+
+impl BudgetStatus {
+    /// The energy consumed over the budget (surplus) if positive, under the budget (deficit) if negative.
+    pub fn deviation_joules(&self) -> f64 {
+        self.actual_joules - self.budget_joules
+    }
+}
+
+impl EnergyBudget {
+    pub fn new(target_watts: f64) -> EnergyBudget {
+        EnergyBudget {
+            target_watts,
+            actual_joules: 0.0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Adds the energy consumed during `elapsed`, and returns the new state of the budget.
+    pub fn add(&mut self, joules: f64, elapsed: Duration) -> BudgetStatus {
+        self.actual_joules += joules;
+        self.elapsed += elapsed;
+        BudgetStatus {
+            elapsed: self.elapsed,
+            actual_joules: self.actual_joules,
+            budget_joules: self.target_watts * self.elapsed.as_secs_f64(),
+        }
+    }
+
+    /// Adds the energy of the last poll (package and dram of all the sockets).
+    /// Returns `None` if the probe has no values yet (first poll).
+    pub fn add_measurements(&mut self, measurements: &EnergyMeasurements) -> Option<BudgetStatus> {
+        let joules = total_joules(measurements, false)?;
+        // all the counters are read at the same time, take the longest interval in case a counter was missing
+        let elapsed = measurements
+            .per_socket
+            .iter()
+            .flat_map(|domains| domains.values().filter_map(|c| c.elapsed))
+            .max()?;
+        Some(self.add(joules, elapsed))
+    }
+}
+
+/// Formats the status as one human-readable line.
+fn format_status(status: &BudgetStatus) -> String {
+    let deviation = status.deviation_joules();
+    let verdict = if deviation > 0.0 {
+        format!("surplus {}", format_energy(deviation))
+    } else {
+        format!("deficit {}", format_energy(-deviation))
+    };
+    format!(
+        "{:>9.3}s  energy {:>10}  budget {:>10}  {verdict}",
+        status.elapsed.as_secs_f64(),
+        format_energy(status.actual_joules),
+        format_energy(status.budget_joules)
+    )
+}
+
+/// Polls the probe every `period` and prints the state of the budget, until Ctrl-C or `max_duration`.
+pub async fn run_budget(
+    mut probe: Box<dyn EnergyProbe>,
+    target_watts: f64,
+    period: Duration,
+    max_duration: Option<Duration>,
+) -> anyhow::Result<()> {
+    let mut budget = EnergyBudget::new(target_watts);
+    let mut interval = Interval::new_interval(period)?;
+    let shutdown = shutdown_signal(max_duration);
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            _ = interval.next() => (),
+        }
+        probe.poll().context("refreshing measurements")?;
+        if let Some(status) = budget.add_measurements(probe.measurements()) {
+            println!("{}", format_status(&status));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::{format_status, EnergyBudget};
+
+    #[test]
+    fn test_budget_deviation() {
+        // target: 10 W
+        let mut budget = EnergyBudget::new(10.0);
+        let deviations: Vec<f64> = [(12.0, 1), (8.0, 1), (5.0, 1), (40.0, 2)]
+            .into_iter()
+            .map(|(joules, secs)| budget.add(joules, Duration::from_secs(secs)).deviation_joules())
+            .collect();
+        assert_eq!(deviations, vec![2.0, 0.0, -5.0, 15.0]);
+
+        let status = budget.add(0.0, Duration::from_secs(5));
+        assert_eq!(status.elapsed, Duration::from_secs(10));
+        assert_eq!((status.actual_joules, status.budget_joules), (65.0, 100.0));
+        assert_eq!(format_status(&status), "   10.000s  energy       65 J  budget      100 J  deficit 35 J");
+    }
+
+    #[test]
+    fn test_budget_from_measurements() {
+        let mut m = EnergyMeasurements::new(2);
+        let mut budget = EnergyBudget::new(20.0);
+        let t0 = Instant::now();
+        for (secs, pkg0, pkg1) in [(0, 0, 0), (1, 15, 10)] {
+            let t = t0 + Duration::from_secs(secs);
+            m.push_at(0, RaplDomainType::Package, pkg0, u32::MAX as u64, 1.0, t);
+            m.push_at(1, RaplDomainType::Package, pkg1, u32::MAX as u64, 1.0, t);
+            let status = budget.add_measurements(&m);
+            if secs == 0 {
+                assert_eq!(status, None);
+            } else {
+                assert_eq!(status.unwrap().deviation_joules(), 5.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use rapl_probes::EnergyProbe;
+
+/// Number of polls done to measure the latency of a probe.
+pub const CALIBRATION_POLLS: usize = 10;
+
+/// Measures the time that `probe.poll()` takes, by timing `n_polls` calls.
+/// Returns the median latency, which is not affected by a single slow call (e.g. the first one).
+///
+/// The probe is reset afterwards, so that the measurements start from scratch.
+pub fn measure_poll_latency(probe: &mut dyn EnergyProbe, n_polls: usize) -> anyhow::Result<Duration> {
+    let mut latencies = Vec::with_capacity(n_polls);
+    for _ in 0..n_polls {
+        let t0 = Instant::now();
+        probe.poll()?;
+        latencies.push(t0.elapsed());
+    }
+    probe.reset();
+    latencies.sort_unstable();
+    Ok(latencies.get(n_polls / 2).copied().unwrap_or(Duration::ZERO))
+}
+
+/// Whether a polling period can be achieved by a probe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrequencyCheck {
+    Achievable,
+    /// The probe is too slow, the highest frequency that it can achieve is `max_frequency` (in Hertz).
+    Unattainable { max_frequency: f64 },
+}
+
+/// Checks that a probe whose `poll()` takes `latency` can be polled every `polling_period`.
+///
+/// A zero polling period means "continuous polling", which is always achievable.
+pub fn check_frequency(latency: Duration, polling_period: Duration) -> FrequencyCheck {
+    if polling_period.is_zero() || latency <= polling_period {
+        FrequencyCheck::Achievable
+    } else {
+        FrequencyCheck::Unattainable {
+            max_frequency: 1.0 / latency.as_secs_f64(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{check_frequency, FrequencyCheck};
+
+    #[test]
+    fn test_check_frequency() {
+        let ms = Duration::from_millis;
+        assert_eq!(check_frequency(ms(1), ms(10)), FrequencyCheck::Achievable);
+        assert_eq!(check_frequency(ms(10), ms(10)), FrequencyCheck::Achievable);
+        assert_eq!(check_frequency(ms(20), ms(10)), FrequencyCheck::Unattainable { max_frequency: 50.0 });
+        assert_eq!(check_frequency(ms(20), Duration::ZERO), FrequencyCheck::Achievable);
+    }
+}
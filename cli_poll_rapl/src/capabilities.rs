@@ -0,0 +1,180 @@
+//! Machine-readable description of what can be measured on this machine, for the scripts that run the measurements:
+//! the domains available with each probe, the cpus to monitor and whether each probe is usable.
+
+use rapl_probes::msr::{self, RaplVendor};
+use rapl_probes::perf_event::PowerEvent;
+use rapl_probes::powercap::PowerZoneHierarchy;
+use rapl_probes::{CpuId, ProbeCapabilities, ProbeCapability, RaplDomainType};
+use serde_json::{json, Value};
+
+use crate::cli::ProbeType;
+
+/// The scale of the powercap counters, which are in micro-Joules (`energy_uj`).
+const POWERCAP_SCALE: f64 = 1e-6;
+
+/// The discovered RAPL interfaces, see [`Discovery::to_json`].
+pub struct Discovery<'a> {
+    /// The cpu vendor, `None` if it is unknown or not supported.
+    pub vendor: Option<RaplVendor>,
+    pub socket_cpus: &'a [CpuId],
+    pub perf_events: &'a [PowerEvent],
+    pub power_zones: &'a PowerZoneHierarchy,
+    pub probes: &'a ProbeCapabilities,
+}
+
+impl Discovery<'_> {
+    /// Describes the vendor, the sockets, each RAPL domain with its perf event, powercap zones and MSR address
+    /// (`null` when the domain is not available with this interface), and the status of each probe.
+    pub fn to_json(&self) -> Value {
+        let domains: Vec<Value> = RaplDomainType::ALL.iter().map(|&domain| self.domain_json(domain)).collect();
+        let probes: serde_json::Map<String, Value> = [
+            (ProbeType::PowercapSysfs, &self.probes.powercap),
+            (ProbeType::PerfEvent, &self.probes.perf_event),
+            (ProbeType::Ebpf, &self.probes.ebpf),
+            (ProbeType::Msr, &self.probes.msr),
+            (ProbeType::Hwmon, &self.probes.hwmon),
+        ]
+        .into_iter()
+        .map(|(probe, cap)| {
+            let (status, details) = match cap {
+                ProbeCapability::Usable => ("usable", None),
+                ProbeCapability::Unavailable(reason) => ("unavailable", Some(reason)),
+                ProbeCapability::PermissionDenied(missing) => ("permission_denied", Some(missing)),
+            };
+            (probe.to_string(), json!({ "status": status, "details": details }))
+        })
+        .collect();
+        let vendor = self.vendor.map(|v| match v {
+            RaplVendor::Intel => "intel",
+            RaplVendor::Amd => "amd",
+        });
+        let cpus: Vec<Value> = self
+            .socket_cpus
+            .iter()
+            .map(|c| json!({ "cpu": c.cpu, "socket": c.socket, "numa_node": c.numa_node }))
+            .collect();
+        json!({
+            "vendor": vendor,
+            "socket_count": self.socket_cpus.len(),
+            "socket_cpus": cpus,
+            "domains": domains,
+            "probes": probes,
+        })
+    }
+
+    fn domain_json(&self, domain: RaplDomainType) -> Value {
+        let perf_event = self.perf_events.iter().find(|e| e.domain == domain).map(|e| {
+            json!({ "name": e.name, "code": e.code, "unit": e.unit, "scale": e.scale })
+        });
+        let zones: Vec<Value> = self
+            .power_zones
+            .flat
+            .iter()
+            .filter(|z| z.domain == domain)
+            .map(|z| json!({ "name": z.name, "socket": z.socket_id, "path": z.path }))
+            .collect();
+        let powercap = (!zones.is_empty()).then(|| json!({ "zones": zones, "scale": POWERCAP_SCALE }));
+        let msr_address = self.vendor.and_then(|v| msr::domain_msr_address(domain, v)).map(|addr| format!("{addr:#x}"));
+        json!({
+            "domain": domain.canonical_name(),
+            "available": perf_event.is_some() || powercap.is_some(),
+            "perf_event": perf_event,
+            "powercap": powercap,
+            "msr_address": msr_address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rapl_probes::msr::RaplVendor;
+    use rapl_probes::perf_event::PowerEvent;
+    use rapl_probes::powercap::{PowerZone, PowerZoneHierarchy};
+    use rapl_probes::{CpuId, ProbeCapabilities, ProbeCapability, RaplDomainType};
+    use serde_json::json;
+
+    use super::Discovery;
+
+    fn zone(name: &str, domain: RaplDomainType, socket_id: u32, path: &str) -> PowerZone {
+        PowerZone {
+            name: name.to_owned(),
+            domain,
+            path: PathBuf::from(path),
+            children: Vec::new(),
+            socket_id: Some(socket_id),
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let socket_cpus = [
+            CpuId { cpu: 0, socket: 0, numa_node: Some(0) },
+            CpuId { cpu: 8, socket: 1, numa_node: None },
+        ];
+        let perf_events = [PowerEvent {
+            name: String::from("pkg"),
+            domain: RaplDomainType::Package,
+            code: 2,
+            unit: String::from("Joules"),
+            scale: 0.5,
+        }];
+        let pkg0 = zone("package-0", RaplDomainType::Package, 0, "/powercap/intel-rapl:0");
+        let dram0 = zone("dram", RaplDomainType::Dram, 0, "/powercap/intel-rapl:0/intel-rapl:0:0");
+        let power_zones = PowerZoneHierarchy {
+            flat: vec![pkg0.clone(), dram0],
+            top: vec![pkg0],
+        };
+        let probes = ProbeCapabilities {
+            powercap: ProbeCapability::Usable,
+            perf_event: ProbeCapability::PermissionDenied(String::from("CAP_PERFMON")),
+            ebpf: ProbeCapability::Unavailable(String::from("not built with eBPF support")),
+            msr: ProbeCapability::Usable,
+            hwmon: ProbeCapability::Unavailable(String::from("no amd_energy sensor")),
+        };
+        let discovery = Discovery {
+            vendor: Some(RaplVendor::Intel),
+            socket_cpus: &socket_cpus,
+            perf_events: &perf_events,
+            power_zones: &power_zones,
+            probes: &probes,
+        };
+
+        let caps = discovery.to_json();
+        assert_eq!(caps["vendor"], "intel");
+        assert_eq!(caps["socket_count"], 2);
+        assert_eq!(caps["socket_cpus"][1], json!({"cpu": 8, "socket": 1, "numa_node": null}));
+
+        let domains = caps["domains"].as_array().unwrap();
+        assert_eq!(domains.len(), RaplDomainType::ALL.len());
+        let domain = |name: &str| domains.iter().find(|d| d["domain"] == name).unwrap().clone();
+        assert_eq!(
+            domain("package"),
+            json!({
+                "domain": "package",
+                "available": true,
+                "perf_event": {"name": "pkg", "code": 2, "unit": "Joules", "scale": 0.5},
+                "powercap": {
+                    "zones": [{"name": "package-0", "socket": 0, "path": "/powercap/intel-rapl:0"}],
+                    "scale": 1e-6
+                },
+                "msr_address": "0x611",
+            })
+        );
+        assert_eq!(domain("dram")["perf_event"], json!(null));
+        assert_eq!(domain("dram")["powercap"]["zones"][0]["name"], "dram");
+        // not discovered, but the MSR exists on Intel cpus
+        assert_eq!(domain("uncore")["available"], false);
+        assert_eq!(domain("uncore")["msr_address"], "0x641");
+
+        assert_eq!(caps["probes"]["powercap-sysfs"], json!({"status": "usable", "details": null}));
+        assert_eq!(caps["probes"]["perf-event"], json!({"status": "permission_denied", "details": "CAP_PERFMON"}));
+
+        // without a known vendor, the MSR addresses are unknown
+        let unknown_vendor = Discovery { vendor: None, ..discovery };
+        let caps = unknown_vendor.to_json();
+        assert_eq!(caps["vendor"], json!(null));
+        assert!(caps["domains"].as_array().unwrap().iter().all(|d| d["msr_address"].is_null()));
+    }
+}
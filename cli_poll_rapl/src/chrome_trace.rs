@@ -0,0 +1,157 @@
+//! Output in the [Trace Event Format](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU)
+//! of Chrome, which can be opened in `chrome://tracing` or in [Perfetto](https://ui.perfetto.dev).
+//!
+//! The power of each socket is a counter event (`"ph": "C"`), with one series per domain:
+//! ```json
+//! [
+//! {"name":"socket0","ph":"C","ts":1732110377500000,"pid":0,"tid":0,"args":{"package":45.2,"dram":6.1}},
+//! {"name":"socket1","ph":"C","ts":1732110377500000,"pid":0,"tid":0,"args":{"package":41.0,"dram":5.8}}
+//! ```
+//!
+//! - `ts` is the time of the measurement, in microseconds since the Unix epoch.
+//! - `args` contains the power of each domain, in Watts, named with [`RaplDomainType::canonical_name`].
+//!
+//! The events are written as they come, thus the closing `]` of the array is not written.
+//! The format explicitly allows this, so that a trace can be read even if the program was interrupted.
+
+use std::io::Write;
+use std::time::SystemTime;
+
+use rapl_probes::RaplDomainType;
+use serde_json::{json, Map, Value};
+
+use crate::main_optimized::MeasurementsMessage;
+use crate::output::MeasurementsOutput;
+
+/// Writes the measurements as Chrome trace counter events.
+pub struct ChromeTraceOutput {
+    writer: Box<dyn Write + Send>,
+    /// Set once the opening `[` has been written.
+    started: bool,
+}
+
+impl ChromeTraceOutput {
+    pub fn new(writer: Box<dyn Write + Send>) -> ChromeTraceOutput {
+        ChromeTraceOutput { writer, started: false }
+    }
+}
+
+impl MeasurementsOutput for ChromeTraceOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        for event in counter_events(msg)? {
+            if self.started {
+                writeln!(self.writer, ",")?;
+            } else {
+                writeln!(self.writer, "[")?;
+                self.started = true;
+            }
+            serde_json::to_writer(&mut self.writer, &event)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Builds the counter events of a message: one per socket that has at least one power measurement.
+pub(crate) fn counter_events(msg: &MeasurementsMessage) -> anyhow::Result<Vec<Value>> {
+    let ts = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_micros() as u64;
+    let mut events = Vec::new();
+    for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+        let mut args = Map::new();
+        for domain in RaplDomainType::ALL {
+            if let Some(watts) = domains_of_socket[domain].watts() {
+                args.insert(domain.canonical_name().to_owned(), json!(watts));
+            }
+        }
+        if args.is_empty() {
+            continue;
+        }
+        events.push(json!({
+            "name": format!("socket{socket_id}"),
+            "ph": "C",
+            "ts": ts,
+            "pid": 0,
+            "tid": 0,
+            "args": args,
+        }));
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+    use serde_json::{json, Value};
+
+    use super::ChromeTraceOutput;
+    use crate::main_optimized::MeasurementsMessage;
+    use crate::output::MeasurementsOutput;
+
+    /// A writer whose content can be read after the output has been dropped.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_chrome_trace() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = ChromeTraceOutput::new(Box::new(buffer.clone()));
+
+        let mut m = EnergyMeasurements::new(2);
+        let t0 = Instant::now();
+        let unit = 0.5_f64.powi(10);
+        for (i, millis) in [0, 500, 1000].into_iter().enumerate() {
+            let t = t0 + Duration::from_millis(millis);
+            // 20 J per 500 ms on socket 0, 3 J for the DRAM, 10 J on socket 1
+            let value = |joules: f64| 1000 + (i as f64 * joules / unit) as u64;
+            m.push_at(0, RaplDomainType::Package, value(20.0), u32::MAX as u64, unit, t);
+            m.push_at(0, RaplDomainType::Dram, value(3.0), u32::MAX as u64, unit, t);
+            m.push_at(1, RaplDomainType::Package, value(10.0), u32::MAX as u64, unit, t);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_732_110_377_000 + millis),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+        output.flush()?;
+
+        // the closing bracket is optional in the format, but required by serde_json
+        let mut trace = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        trace.push(']');
+        let events: Vec<Value> = serde_json::from_str(&trace)?;
+
+        // the first poll has no energy, thus no event
+        assert_eq!(events.len(), 4);
+        for event in &events {
+            assert_eq!(event["ph"], "C");
+            assert!(event["ts"].is_u64());
+            assert!(event["pid"].is_u64() && event["tid"].is_u64());
+        }
+        assert_eq!(
+            events[0],
+            json!({"name": "socket0", "ph": "C", "ts": 1_732_110_377_500_000_u64, "pid": 0, "tid": 0, "args": {"package": 40.0, "dram": 6.0}})
+        );
+        assert_eq!(events[1]["name"], "socket1");
+        assert_eq!(events[1]["args"], json!({"package": 20.0}));
+        assert_eq!(events[3]["ts"], 1_732_110_378_000_000_u64);
+        Ok(())
+    }
+}
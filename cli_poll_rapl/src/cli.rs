@@ -1,8 +1,11 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, path::PathBuf, str::FromStr};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rapl_probes::iostats::IoSource;
 use rapl_probes::RaplDomainType;
 
+use crate::workload::WorkloadCpus;
+
 #[derive(Parser)]
 #[command(author, version)]
 pub struct Cli {
@@ -15,12 +18,31 @@ pub enum Commands {
     /// Only show info about CPU and RAPL domains, then exit.
     Info,
 
+    /// Prints the RAPL domains available with each probe, the cpus to monitor and the status of each probe, as JSON.
+    /// This is meant for the scripts that choose the probe and the domains to measure.
+    #[command(alias = "list")]
+    Capabilities,
+
     /// Poll some RAPL domains continuously
     Poll {
         /// How to access RAPL counters.
         #[arg(value_enum)]
         probe: ProbeType,
 
+        /// Also polls the same domains with another probe, at the same time, to compare the probes.
+        /// Can be repeated. The CSV output gets a `source` column, with the name of the probe of each row.
+        /// The options that combine the polls of a probe (summary, cumulative values, ...) are not supported.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "PROBE",
+            conflicts_with_all = [
+                "realtime", "adaptive", "emit_every", "downsample_agg", "with_cumulative",
+                "summary", "heartbeat", "power_alert_watts", "frequency_variance", "smooth_alpha",
+            ]
+        )]
+        also_probe: Vec<ProbeType>,
+
         /// The RAPL domains to record.
         #[arg(short, long, value_delimiter = ',', required = true)]
         domains: Vec<RaplDomainType>,
@@ -29,21 +51,295 @@ pub enum Commands {
         #[arg(short, long)]
         frequency: f64,
 
+        /// Excludes a powercap zone, given by its name (e.g. `dram`), its directory (e.g. `intel-rapl:0:0`) or its path,
+        /// with its sub-zones. Only applies to the powercap probe. Can be repeated.
+        #[arg(long, value_name = "NAME_OR_PATH")]
+        exclude_zone: Vec<String>,
+
+        /// Only monitors the given sockets (e.g. `0,2`), instead of all of them.
+        /// The sockets keep their ids in the output.
+        #[arg(long, value_delimiter = ',')]
+        sockets: Option<Vec<u32>>,
+
+        /// The cpus that the measured workload is pinned to (e.g. `0-15`), to attribute the energy of the sockets.
+        /// Logs the sockets of these cpus, and warns if they span several sockets.
+        #[arg(long, value_name = "CPU_LIST")]
+        workload_cpus: Option<WorkloadCpus>,
+
+        /// Only warns, instead of failing, if the probe is too slow for the requested frequency.
+        #[arg(long)]
+        allow_unattainable_frequency: bool,
+
         /// Print energy measurements on each iteration.
         #[arg(short, long, value_enum)]
         output: OutputType,
         
-        /// Sets the output file, if output if set to file.
+        /// Sets the output file, if output if set to file (or to a non-CSV text format).
         #[arg(long)]
         output_file: Option<String>,
+
+        /// Overwrites the output file if it already exists.
+        #[arg(long, conflicts_with = "append")]
+        force: bool,
+
+        /// Appends the measurements to the output file if it already exists.
+        #[arg(long)]
+        append: bool,
+
+        /// Address of the HTTP server, if output is set to prometheus.
+        #[arg(long, default_value = "127.0.0.1:9105")]
+        prometheus_listen: String,
+
+        /// OTLP/HTTP endpoint of the OpenTelemetry collector, if output is set to otel.
+        #[arg(long, default_value = "http://localhost:4318/v1/metrics")]
+        otel_endpoint: String,
+
+        /// Sets the database file, if output is set to sqlite.
+        #[arg(long)]
+        sqlite_path: Option<String>,
+
+        /// The optional columns and rows of the CSV output.
+        #[command(flatten)]
+        csv: Box<CsvColumns>,
+
+        /// What is computed from the measurements, besides the output.
+        #[command(flatten)]
+        monitors: Box<MonitorOptions>,
+
+        /// Emits one measurement every N polls, by combining the intermediate polls.
+        #[arg(long, default_value_t = 1)]
+        emit_every: usize,
+
+        /// Discards the first N polls, which are not written nor included in the summary.
+        /// The first poll has no value, and the next ones can be noisy while the caches warm up.
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        warmup_samples: usize,
+
+        /// Stops the measurement after N samples, like Ctrl-C does, and flushes the output.
+        /// A sample is a poll that gives at least one energy value: the first poll, which has no value, does not count,
+        /// nor do the polls discarded by `--warmup-samples`.
+        #[arg(long, value_name = "N")]
+        samples: Option<usize>,
+
+        /// How to combine the polls when `--emit-every` is greater than 1.
+        /// `energy-sum` preserves the total energy, the other aggregations output Watts.
+        #[arg(long, value_enum, default_value_t = DownsampleAgg::EnergySum)]
+        downsample_agg: DownsampleAgg,
+
+        /// Polls on a dedicated thread with the SCHED_FIFO real-time policy, for the lowest jitter.
+        /// Requires CAP_SYS_NICE, otherwise the thread keeps the normal policy.
+        #[arg(long)]
+        realtime: bool,
+
+        /// Halves the polling period (down to 1 ms) when a RAPL counter overflows between two polls,
+        /// and doubles it again (up to the requested period) after one minute without overflow.
+        #[arg(long, conflicts_with_all = ["realtime", "emit_gaps"])]
+        adaptive: bool,
+
+        /// Stops the measurement after N seconds, like Ctrl-C does.
+        /// In both cases, the measurements are flushed to the output before exiting.
+        #[arg(long, value_name = "SECONDS")]
+        max_duration: Option<f64>,
+
+        /// Sends the measurements to the writer task in batches of N polls.
+        /// This reduces the overhead of the polling loop at very high frequencies.
+        #[arg(long, default_value_t = 1)]
+        batch_size: usize,
+
+        /// What to do when the output cannot keep up with the polling, e.g. in continuous mode (negative frequency):
+        /// wait for the output (`block`), drop the measurements (`drop`), or combine them into the most recent one (`coalesce`).
+        /// The number of dropped or coalesced measurements is logged at the end.
+        #[arg(long, value_enum, default_value_t = OverflowPolicy::Block)]
+        overflow_policy: OverflowPolicy,
+    },
+
+    /// Compares the energy consumption with a target average power, continuously.
+    /// Prints the energy surplus (over the budget) or deficit (under the budget) since the start.
+    Budget {
+        /// How to access RAPL counters.
+        #[arg(value_enum)]
+        probe: ProbeType,
+
+        /// The RAPL domains to measure. The consumption is the sum of `package` and `dram` (of all the sockets).
+        /// The other domains are ignored, thus `package` or `dram` is required.
+        #[arg(short, long, value_delimiter = ',', default_value = "package,dram")]
+        domains: Vec<RaplDomainType>,
+
+        /// The target average power, in Watts. The budget is `target_watts * elapsed`.
+        #[arg(long)]
+        target_watts: f64,
+
+        /// Update frequency, in Hertz.
+        #[arg(short, long, default_value_t = 1.0)]
+        frequency: f64,
+
+        /// Excludes a powercap zone, given by its name (e.g. `dram`), its directory (e.g. `intel-rapl:0:0`) or its path,
+        /// with its sub-zones. Only applies to the powercap probe. Can be repeated.
+        #[arg(long, value_name = "NAME_OR_PATH")]
+        exclude_zone: Vec<String>,
+
+        /// Only monitors the given sockets (e.g. `0,2`), instead of all of them.
+        /// The sockets keep their ids in the output.
+        #[arg(long, value_delimiter = ',')]
+        sockets: Option<Vec<u32>>,
+
+        /// Stops after N seconds, like Ctrl-C does.
+        #[arg(long, value_name = "SECONDS")]
+        max_duration: Option<f64>,
+    },
+    /// Measures the energy consumed during N seconds, then prints the total of each socket and domain.
+    Measure {
+        /// How to access RAPL counters.
+        #[arg(value_enum)]
+        probe: ProbeType,
+
+        /// The RAPL domains to measure.
+        #[arg(short, long, value_delimiter = ',', default_value = "package,dram")]
+        domains: Vec<RaplDomainType>,
+
+        /// Duration of the measurement.
+        #[arg(long, value_name = "SECONDS")]
+        duration: f64,
+
+        /// Polling period. It must be short enough for the counters not to overflow twice between two polls.
+        #[arg(long, value_name = "SECONDS", default_value_t = 1.0)]
+        period: f64,
+
+        /// Excludes a powercap zone, given by its name (e.g. `dram`), its directory (e.g. `intel-rapl:0:0`) or its path,
+        /// with its sub-zones. Only applies to the powercap probe. Can be repeated.
+        #[arg(long, value_name = "NAME_OR_PATH")]
+        exclude_zone: Vec<String>,
+
+        /// Only monitors the given sockets (e.g. `0,2`), instead of all of them.
+        #[arg(long, value_delimiter = ',')]
+        sockets: Option<Vec<u32>>,
+    },
+    /// Compares two CSV files of the same run, measured with different probes:
+    /// prints the mean absolute difference and the correlation of the energy of each domain.
+    Diff {
+        /// The first CSV file, written by `poll`.
+        a: PathBuf,
+
+        /// The second CSV file, written by `poll`.
+        b: PathBuf,
+
+        /// The rows of each file are summed per bucket of this duration before being compared,
+        /// because the two probes do not poll at the same instants.
+        #[arg(long, value_name = "MS", default_value_t = 1000)]
+        bucket_ms: u64,
     },
 }
 
+/// The options of the monitors, which print some information on stderr during or after the measurement.
+#[derive(Args)]
+pub struct MonitorOptions {
+    /// Prints a one-line power summary on stderr every N seconds, to show that the measurement is alive.
+    #[arg(long, value_name = "SECONDS")]
+    pub heartbeat: Option<f64>,
+
+    /// Logs a warning when the power of a package exceeds N Watts, e.g. to catch runaway jobs.
+    /// The warnings of a socket are limited to one every 10 seconds.
+    #[arg(long, value_name = "N")]
+    pub power_alert_watts: Option<f64>,
+
+    /// Prints the total energy and the min/max/mean power of each domain on stderr at the end of the measurement.
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Samples the cpu frequency at each poll, and prints on stderr at the end the correlation between
+    /// the frequency and the package power of each socket. Warns if the frequency varies too much
+    /// (e.g. because of turbo boost) for the energy to be comparable between runs.
+    #[arg(long)]
+    pub frequency_variance: bool,
+}
+
+/// The options of the CSV output, which add columns or rows.
+#[derive(Args)]
+pub struct CsvColumns {
+    /// Separator of the CSV columns, a single character or `tab`.
+    /// The fields that contain it are quoted.
+    #[arg(long, value_name = "CHAR", default_value = ";", value_parser = crate::csv_writer::parse_delimiter)]
+    pub csv_delimiter: char,
+
+    /// Adds a `cumulative_joules` column to the CSV output, with the running total of each socket and domain.
+    /// The total starts at the first written measurement, like the Prometheus and OpenTelemetry counters.
+    #[arg(long)]
+    pub with_cumulative: bool,
+
+    /// Adds an `elapsed_ms` column to the CSV output, with the number of milliseconds since the start of the measurement.
+    #[arg(long)]
+    pub with_elapsed: bool,
+
+    /// Order of the domains in the CSV rows of each socket.
+    #[arg(long, value_enum, default_value_t = DomainOrder::Declared)]
+    pub domain_order: DomainOrder,
+
+    /// Adds a synthetic `total` row to the CSV output after each poll, with the energy of all the sockets.
+    /// The total is the sum of the `package` and `dram` domains: `core` and `uncore` are part of the package.
+    #[arg(long)]
+    pub emit_totals: bool,
+
+    /// Also adds the `platform` (psys) domain to the totals.
+    /// By default it is excluded because it covers the energy of the package, which would be counted twice.
+    #[arg(long, requires = "emit_totals")]
+    pub totals_include_platform: bool,
+
+    /// Adds a `freq_mhz` column to the CSV output, with the average current frequency of the cpus of each socket.
+    #[arg(long)]
+    pub with_frequency: bool,
+
+    /// Adds `io_ops` and `joules_per_io_op` columns to the CSV output, with the number of IO operations
+    /// of a process (`--with-io-ops <PID>`, read and write syscalls) or of the whole system
+    /// (`--with-io-ops system`, completed reads and writes of the block devices).
+    /// The system-wide energy is divided by these operations, which is only meaningful for an IO-bound workload.
+    #[arg(long, value_name = "system|PID")]
+    pub with_io_ops: Option<IoSource>,
+
+    /// Adds `watts` and `watts_smoothed` columns to the CSV output, with the power of each row and its
+    /// exponentially weighted moving average: `smoothed = alpha * watts + (1 - alpha) * smoothed`.
+    /// A small alpha (e.g. 0.1) gives a smoother curve, which follows the changes more slowly.
+    #[arg(long, value_name = "ALPHA", value_parser = crate::smoothing::parse_alpha)]
+    pub smooth_alpha: Option<f64>,
+
+    /// Tags the CSV rows with the phases of the measured application, in a `label` column.
+    /// The application appends lines `<timestamp_ms> <label>` to this file when a phase starts.
+    #[arg(long)]
+    pub markers_file: Option<String>,
+
+    /// Adds a `gap` column to the CSV output, and writes a gap row (without values) for each missed poll,
+    /// for instance when a poll took longer than the period. Requires a positive frequency.
+    #[arg(long)]
+    pub emit_gaps: bool,
+
+    /// Writes the model of the cpu (vendor, family, model, stepping) in a `# cpu: ...` comment line before the CSV header,
+    /// to know which machine has produced a trace. The readers must skip the comment lines (e.g. `comment='#'` in pandas).
+    #[arg(long)]
+    pub with_cpu_model: bool,
+}
+
 #[derive(Clone, ValueEnum, Debug, PartialEq, Eq, Copy)]
 pub enum OutputType {
     None,
     Stdout,
     File,
+    /// One JSON object per line, written to the output file if set, to stdout otherwise.
+    Json,
+    /// Requires the `sqlite` feature.
+    Sqlite,
+    /// JSON format of Scaphandre, written to the output file if set, to stdout otherwise.
+    ScaphandreJson,
+    /// Chrome Trace Event Format (`chrome://tracing`, Perfetto), written to the output file if set, to stdout otherwise.
+    ChromeTrace,
+    /// InfluxDB line protocol (e.g. for Telegraf), written to the output file if set, to stdout otherwise.
+    InfluxLine,
+    /// Serves the cumulative energy over HTTP, for Prometheus. See `--prometheus-listen`.
+    Prometheus,
+    /// Exports the energy and the power to an OpenTelemetry collector, with OTLP over HTTP. See `--otel-endpoint`.
+    /// Requires the `otel` feature.
+    Otel,
+    /// Raw values of the counters, with their maximum value and energy unit, to compute the energy again offline
+    /// (see `rapl_probes::replay`). Written to the output file if set, to stdout otherwise.
+    Raw,
 }
 
 impl Display for OutputType {
@@ -52,12 +348,76 @@ impl Display for OutputType {
     }
 }
 
+#[derive(Clone, ValueEnum, Debug, PartialEq, Eq, Copy)]
+pub enum DomainOrder {
+    /// Order of the MSR addresses: package, dram, core, uncore, platform.
+    Addr,
+    /// Alphabetical order of the domain names.
+    Name,
+    /// Order of [`RaplDomainType::ALL`]: package, core, uncore, dram, platform.
+    Declared,
+}
+
+impl DomainOrder {
+    /// The domains, in this order.
+    pub fn domains(&self) -> Vec<RaplDomainType> {
+        match self {
+            DomainOrder::Addr => RaplDomainType::ALL_IN_ADDR_ORDER.to_vec(),
+            DomainOrder::Name => {
+                let mut domains = RaplDomainType::ALL.to_vec();
+                domains.sort_by_key(|d| d.to_string());
+                domains
+            }
+            DomainOrder::Declared => RaplDomainType::ALL.to_vec(),
+        }
+    }
+}
+
+impl Display for DomainOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+
+#[derive(Clone, ValueEnum, Debug, PartialEq, Eq, Copy)]
+pub enum DownsampleAgg {
+    /// Sum of the consumed energy, in Joules.
+    EnergySum,
+    /// Mean power, in Watts.
+    PowerMean,
+    /// Maximum power, in Watts.
+    PowerMax,
+}
+
+impl Display for DownsampleAgg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+
+#[derive(Clone, ValueEnum, Debug, PartialEq, Eq, Copy)]
+pub enum OverflowPolicy {
+    /// Waits until the writer has some space for the new measurements, which delays the next polls.
+    Block,
+    /// Drops the new measurements, and their energy.
+    Drop,
+    /// Combines the new measurements into the most recent one, which keeps the total energy.
+    Coalesce,
+}
+
+impl Display for OverflowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ProbeType {
     PowercapSysfs,
     PerfEvent,
     Ebpf,
     Msr,
+    Hwmon,
 }
 
 impl Display for ProbeType {
@@ -67,6 +427,7 @@ impl Display for ProbeType {
             ProbeType::PerfEvent => "perf-event",
             ProbeType::Ebpf => "ebpf",
             ProbeType::Msr => "msr",
+            ProbeType::Hwmon => "hwmon",
         };
         f.write_str(str)
     }
@@ -81,6 +442,7 @@ impl FromStr for ProbeType {
             "perf" | "perf-event" => Ok(ProbeType::PerfEvent),
             "ebpf" | "bpf" => Ok(ProbeType::Ebpf),
             "msr" => Ok(ProbeType::Msr),
+            "hwmon" | "amd_energy" => Ok(ProbeType::Hwmon),
             _ => Err(s.to_owned()),
         }
     }
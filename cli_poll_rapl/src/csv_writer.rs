@@ -0,0 +1,121 @@
+//! Minimal CSV writer, with a configurable delimiter and the quoting of the fields that need it.
+
+use std::borrow::Cow;
+use std::io::Write;
+
+/// The default delimiter, kept for the existing scripts.
+pub const DEFAULT_DELIMITER: char = ';';
+
+/// Writes rows of fields separated by a delimiter.
+///
+/// A field that contains the delimiter, a double quote or a line break is enclosed in double quotes,
+/// and its double quotes are doubled (RFC 4180).
+pub struct CsvWriter<W: Write> {
+    writer: W,
+    delimiter: char,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Creates a writer with the [`DEFAULT_DELIMITER`].
+    pub fn new(writer: W) -> CsvWriter<W> {
+        CsvWriter {
+            writer,
+            delimiter: DEFAULT_DELIMITER,
+        }
+    }
+
+    /// Sets the delimiter, which must be accepted by [`parse_delimiter`].
+    pub fn with_delimiter(mut self, delimiter: char) -> CsvWriter<W> {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Writes a row and its line break.
+    pub fn write_row<S: AsRef<str>>(&mut self, fields: &[S]) -> std::io::Result<()> {
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                write!(self.writer, "{}", self.delimiter)?;
+            }
+            self.writer.write_all(self.quote(field.as_ref()).as_bytes())?;
+        }
+        writeln!(self.writer)
+    }
+
+    /// Writes a comment line, `# <comment>`. The line breaks of `comment` are replaced by spaces.
+    pub fn write_comment(&mut self, comment: &str) -> std::io::Result<()> {
+        writeln!(self.writer, "# {}", comment.replace(['\n', '\r'], " "))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn quote<'a>(&self, field: &'a str) -> Cow<'a, str> {
+        if field.contains([self.delimiter, '"', '\n', '\r']) {
+            Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+        } else {
+            Cow::Borrowed(field)
+        }
+    }
+}
+
+/// Parses the value of `--csv-delimiter`: a single character, or `tab`.
+/// The double quote and the line breaks are rejected, because they are used by the quoting.
+pub fn parse_delimiter(s: &str) -> Result<char, String> {
+    let delimiter = match s {
+        "tab" => '\t',
+        _ => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(format!("the delimiter must be a single character or 'tab', not '{s}'")),
+            }
+        }
+    };
+    if matches!(delimiter, '"' | '\n' | '\r') {
+        return Err(format!("the delimiter cannot be {delimiter:?}"));
+    }
+    Ok(delimiter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_delimiter, CsvWriter};
+
+    fn write_rows(delimiter: char, rows: &[&[&str]]) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        let mut csv = CsvWriter::new(&mut out).with_delimiter(delimiter);
+        for row in rows {
+            csv.write_row(row).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_delimiter() {
+        let rows: &[&[&str]] = &[&["timestamp_ms", "socket", "domain"], &["1000", "0", "Package"]];
+        assert_eq!(write_rows(';', rows), "timestamp_ms;socket;domain\n1000;0;Package\n");
+        assert_eq!(write_rows(',', rows), "timestamp_ms,socket,domain\n1000,0,Package\n");
+        assert_eq!(write_rows('\t', rows), "timestamp_ms\tsocket\tdomain\n1000\t0\tPackage\n");
+
+        assert_eq!(parse_delimiter(","), Ok(','));
+        assert_eq!(parse_delimiter("tab"), Ok('\t'));
+        assert!(parse_delimiter(",;").is_err());
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter("\"").is_err());
+    }
+
+    #[test]
+    fn test_quoting() {
+        let rows: &[&[&str]] = &[&["1000", "phase 1, warmup", "say \"hi\"", "two\nlines", "a;b", ""]];
+        assert_eq!(
+            write_rows(',', rows),
+            "1000,\"phase 1, warmup\",\"say \"\"hi\"\"\",\"two\nlines\",a;b,\n"
+        );
+        // the fields are quoted according to the delimiter in use
+        assert_eq!(
+            write_rows(';', rows),
+            "1000;phase 1, warmup;\"say \"\"hi\"\"\";\"two\nlines\";\"a;b\";\n"
+        );
+    }
+}
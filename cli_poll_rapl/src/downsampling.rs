@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use rapl_probes::{EnergyCounter, EnergyMeasurements, RaplDomainType};
+
+use crate::cli::DownsampleAgg;
+use crate::main_optimized::MeasurementsMessage;
+
+/// Combines several consecutive polls into one emitted message.
+pub struct Downsampler {
+    /// Number of polls per emitted message.
+    emit_every: usize,
+    agg: DownsampleAgg,
+    /// Number of messages in the current window.
+    count: usize,
+    /// Aggregated values of the current window.
+    window: HashMap<(u32, RaplDomainType), WindowValue>,
+    /// Timestamp of the last message, used to compute the power.
+    previous_timestamp: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct WindowValue {
+    joules: f64,
+    watts_sum: f64,
+    watts_max: Option<f64>,
+    n_watts: usize,
+    overflowed: bool,
+    /// The values of the last message, they are cumulative.
+    overflow_count: u64,
+    cumulative_joules: f64,
+    /// The sum of the elapsed times of the counter, which is the duration of the window.
+    elapsed: Option<Duration>,
+}
+
+impl Downsampler {
+    pub fn new(emit_every: usize, agg: DownsampleAgg) -> Downsampler {
+        Downsampler {
+            emit_every: emit_every.max(1),
+            agg,
+            count: 0,
+            window: HashMap::new(),
+            previous_timestamp: None,
+        }
+    }
+
+    /// Adds a message to the current window.
+    /// Returns the aggregated message when the window is complete.
+    ///
+    /// With [`DownsampleAgg::EnergySum`], the `joules` of the returned message are the sum of the window.
+    /// With the other aggregations, they contain a power in Watts, not an energy.
+    pub fn push(&mut self, msg: MeasurementsMessage) -> Option<MeasurementsMessage> {
+        if self.emit_every == 1 && self.agg == DownsampleAgg::EnergySum {
+            // nothing to aggregate
+            return Some(msg);
+        }
+
+        let elapsed = self
+            .previous_timestamp
+            .and_then(|prev| msg.timestamp.duration_since(prev).ok())
+            .filter(|d| !d.is_zero());
+        self.previous_timestamp = Some(msg.timestamp);
+
+        for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+            for (domain, counter) in domains_of_socket {
+                if let Some(joules) = counter.joules {
+                    let value = self.window.entry((socket_id as u32, domain)).or_default();
+                    value.joules += joules;
+                    value.overflowed |= counter.overflowed;
+                    value.overflow_count = counter.overflow_count;
+                    value.cumulative_joules = counter.cumulative_joules;
+                    if let Some(e) = counter.elapsed {
+                        value.elapsed = Some(value.elapsed.unwrap_or_default() + e);
+                    }
+                    if let Some(dt) = elapsed {
+                        let watts = joules / dt.as_secs_f64();
+                        value.watts_sum += watts;
+                        value.n_watts += 1;
+                        value.watts_max = Some(value.watts_max.map_or(watts, |max| max.max(watts)));
+                    }
+                }
+            }
+        }
+
+        self.count += 1;
+        if self.count < self.emit_every {
+            return None;
+        }
+
+        // the window is complete, emit it
+        let mut measurements = EnergyMeasurements::new(msg.measurements.per_socket.len());
+        for ((socket, domain), value) in self.window.drain() {
+            let aggregated = match self.agg {
+                DownsampleAgg::EnergySum => Some(value.joules),
+                DownsampleAgg::PowerMean => (value.n_watts > 0).then(|| value.watts_sum / value.n_watts as f64),
+                DownsampleAgg::PowerMax => value.watts_max,
+            };
+            let mut counter = EnergyCounter::default();
+            counter.joules = aggregated;
+            counter.overflowed = value.overflowed;
+            counter.overflow_count = value.overflow_count;
+            counter.cumulative_joules = value.cumulative_joules;
+            // the power of the window is the summed energy divided by its duration, it is not defined for the other
+            // aggregations, whose value is already a power
+            if self.agg == DownsampleAgg::EnergySum {
+                counter.elapsed = value.elapsed;
+            }
+            measurements.per_socket[socket as usize][domain] = counter;
+        }
+        self.count = 0;
+        Some(MeasurementsMessage {
+            timestamp: msg.timestamp,
+            measurements,
+            source: msg.source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::Downsampler;
+    use crate::cli::DownsampleAgg;
+    use crate::main_optimized::MeasurementsMessage;
+    use crate::smoothing::EwmaFilter;
+
+    /// Polls every 100ms, the consumed energy is 1J, 2J, 3J, i.e. 10W, 20W, 30W.
+    fn run_window(agg: DownsampleAgg) -> Vec<MeasurementsMessage> {
+        let mut downsampler = Downsampler::new(4, agg);
+        let mut measurements = EnergyMeasurements::new(1);
+        let mut emitted = Vec::new();
+        for (i, value) in [0, 1, 3, 6].into_iter().enumerate() {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(100 * i as u64),
+                measurements: measurements.clone(),
+                source: None,
+            };
+            emitted.extend(downsampler.push(msg));
+        }
+        emitted
+    }
+
+    fn emitted_value(emitted: &[MeasurementsMessage]) -> f64 {
+        assert_eq!(emitted.len(), 1);
+        emitted[0].measurements.per_socket[0][RaplDomainType::Package].joules.unwrap()
+    }
+
+    #[test]
+    fn test_energy_sum() {
+        let emitted = run_window(DownsampleAgg::EnergySum);
+        assert_eq!(emitted_value(&emitted), 6.0);
+        assert_eq!(emitted[0].timestamp, SystemTime::UNIX_EPOCH + Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_energy_sum_watts() {
+        // 2 windows of 2 polls, every 100ms: 10 W, then 30 W during 200 ms
+        let mut downsampler = Downsampler::new(2, DownsampleAgg::EnergySum);
+        let mut smoothing = EwmaFilter::new(0.5);
+        let mut measurements = EnergyMeasurements::new(1);
+        let t0 = Instant::now();
+        let mut watts = Vec::new();
+        let mut cumulative = 0.0;
+        for (i, value) in [0, 1, 4, 7].into_iter().enumerate() {
+            let time = t0 + Duration::from_millis(100 * i as u64);
+            measurements.push_at(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0, time);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(100 * i as u64),
+                measurements: measurements.clone(),
+                source: None,
+            };
+            if let Some(emitted) = downsampler.push(msg) {
+                smoothing.update(&emitted.measurements);
+                let counter = &emitted.measurements.per_socket[0][RaplDomainType::Package];
+                watts.push(counter.watts());
+                cumulative = counter.cumulative_joules;
+            }
+        }
+        // the first window contains the first poll, which has no value
+        assert_eq!(watts, [Some(10.0), Some(30.0)]);
+        assert_eq!(smoothing.smoothed(0, RaplDomainType::Package), Some(20.0));
+        assert_eq!(cumulative, 7.0);
+    }
+
+    #[test]
+    fn test_power_mean() {
+        let emitted = run_window(DownsampleAgg::PowerMean);
+        assert!((emitted_value(&emitted) - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_power_max() {
+        let emitted = run_window(DownsampleAgg::PowerMax);
+        assert!((emitted_value(&emitted) - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_no_downsampling() {
+        let mut downsampler = Downsampler::new(1, DownsampleAgg::EnergySum);
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH,
+            measurements: EnergyMeasurements::new(1),
+            source: None,
+        };
+        assert!(downsampler.push(msg).is_some());
+    }
+}
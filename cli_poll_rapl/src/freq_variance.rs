@@ -0,0 +1,119 @@
+use rapl_probes::cpufreq::CpuFreqSampler;
+use rapl_probes::stats::Correlation;
+use rapl_probes::{CpuId, EnergyMeasurements, RaplDomainType};
+
+use crate::main_optimized::MeasurementsMessage;
+
+/// Above this coefficient of variation of the frequency (standard deviation divided by the mean),
+/// the energy of a workload depends too much on the DVFS state (e.g. turbo boost) to be compared between runs.
+pub const HIGH_FREQUENCY_VARIATION: f64 = 0.1;
+
+/// Correlation between the frequency and the package power of each socket, over the whole measurement.
+pub struct FrequencyVariance {
+    sampler: CpuFreqSampler,
+    per_socket: SocketCorrelations,
+}
+
+impl FrequencyVariance {
+    pub fn new(socket_cpus: &[CpuId]) -> anyhow::Result<FrequencyVariance> {
+        let sampler = CpuFreqSampler::for_sockets(socket_cpus)?;
+        let sockets = socket_cpus.iter().map(|c| c.socket).collect();
+        Ok(FrequencyVariance {
+            sampler,
+            per_socket: SocketCorrelations::new(sockets),
+        })
+    }
+
+    /// Samples the frequency of each socket, and pairs it with the package power of the message.
+    pub fn record(&mut self, msg: &MeasurementsMessage) {
+        let frequencies = self.sampler.sample();
+        self.per_socket.record(&frequencies, &msg.measurements);
+    }
+
+    /// Returns the report of each socket, and warns for the sockets whose frequency varies too much.
+    pub fn report(&self) -> Vec<String> {
+        self.per_socket.report()
+    }
+}
+
+struct SocketCorrelations {
+    /// The socket of each value returned by the sampler.
+    sockets: Vec<u32>,
+    /// The (frequency in MHz, package power in Watts) of each socket, indexed like `sockets`.
+    correlations: Vec<Correlation>,
+}
+
+impl SocketCorrelations {
+    fn new(sockets: Vec<u32>) -> SocketCorrelations {
+        let correlations = vec![Correlation::new(); sockets.len()];
+        SocketCorrelations { sockets, correlations }
+    }
+
+    /// Pairs the `frequencies` of the sockets, in MHz, with their package power.
+    fn record(&mut self, frequencies: &[Option<f64>], measurements: &EnergyMeasurements) {
+        for ((socket, frequency), correlation) in self.sockets.iter().zip(frequencies).zip(&mut self.correlations) {
+            let power = measurements
+                .per_socket
+                .get(*socket as usize)
+                .and_then(|domains| domains[RaplDomainType::Package].watts());
+            if let (Some(mhz), Some(watts)) = (frequency, power) {
+                correlation.push(*mhz, watts);
+            }
+        }
+    }
+
+    fn report(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (socket, correlation) in self.sockets.iter().zip(&self.correlations) {
+            let (Some(mean), Some(variation)) = (correlation.mean_x(), correlation.variation_x()) else {
+                continue;
+            };
+            let coefficient = match correlation.coefficient() {
+                Some(r) => format!("{r:.2}"),
+                None => String::from("undefined"),
+            };
+            lines.push(format!(
+                "socket {socket}: frequency {mean:.0} MHz ± {:.1}%, correlation with the package power {coefficient} ({} samples)",
+                variation * 100.0,
+                correlation.count()
+            ));
+            if variation > HIGH_FREQUENCY_VARIATION {
+                log::warn!(
+                    "The frequency of socket {socket} varied by {:.1}% during the measurement (turbo boost?): its energy may not be comparable with other runs. Consider disabling turbo boost or fixing the frequency.",
+                    variation * 100.0
+                );
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::SocketCorrelations;
+
+    #[test]
+    fn test_report() {
+        // only socket 1 is monitored
+        let mut per_socket = SocketCorrelations::new(vec![1]);
+        let mut m = EnergyMeasurements::new(2);
+        let t0 = Instant::now();
+        // turbo every other second: 3000 MHz and 60 W, otherwise 2000 MHz and 40 W
+        for secs in 0..=10u64 {
+            let turbo = secs % 2 == 0;
+            let joules = (0..secs).map(|s| if s % 2 == 1 { 60 } else { 40 }).sum();
+            m.push_at(1, RaplDomainType::Package, joules, u32::MAX as u64, 1.0, t0 + Duration::from_secs(secs));
+            m.push_at(0, RaplDomainType::Package, 0, u32::MAX as u64, 1.0, t0 + Duration::from_secs(secs));
+            let mhz = if turbo { 3000.0 } else { 2000.0 };
+            per_socket.record(&[Some(mhz)], &m);
+        }
+        assert_eq!(
+            per_socket.report(),
+            vec!["socket 1: frequency 2500 MHz ± 21.1%, correlation with the package power 1.00 (10 samples)"]
+        );
+    }
+}
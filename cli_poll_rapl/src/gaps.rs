@@ -0,0 +1,51 @@
+use std::time::{Duration, SystemTime};
+
+/// Detects the ticks of the polling timer that have been missed, from the timestamps of the measurements.
+///
+/// A tick is missed when the time between two measurements is closer to two periods (or more) than to one period,
+/// for instance because a poll was too slow.
+pub struct MissedTicks {
+    period: Duration,
+    previous: Option<SystemTime>,
+}
+
+impl MissedTicks {
+    /// `period` must not be zero.
+    pub fn new(period: Duration) -> MissedTicks {
+        assert!(!period.is_zero(), "the period must not be zero to detect the missed ticks");
+        MissedTicks { period, previous: None }
+    }
+
+    /// Returns the expected times of the ticks that have been missed between the previous measurement and this one.
+    pub fn check(&mut self, timestamp: SystemTime) -> Vec<SystemTime> {
+        let previous = self.previous.replace(timestamp);
+        let Some(elapsed) = previous.and_then(|p| timestamp.duration_since(p).ok()) else {
+            return Vec::new();
+        };
+        // round to the nearest number of periods, to tolerate the jitter of the timer
+        let periods = ((elapsed.as_secs_f64() / self.period.as_secs_f64()).round() as u32).max(1);
+        let previous = previous.unwrap();
+        (1..periods).map(|i| previous + self.period * i).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::MissedTicks;
+
+    #[test]
+    fn test_missed_ticks() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let ms = Duration::from_millis;
+        let mut ticks = MissedTicks::new(ms(100));
+        assert!(ticks.check(t0).is_empty());
+        // jitter is not a gap
+        assert!(ticks.check(t0 + ms(130)).is_empty());
+        assert!(ticks.check(t0 + ms(200)).is_empty());
+        // two ticks are missed
+        assert_eq!(ticks.check(t0 + ms(510)), vec![t0 + ms(300), t0 + ms(400)]);
+        assert!(ticks.check(t0 + ms(610)).is_empty());
+    }
+}
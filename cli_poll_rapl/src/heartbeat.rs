@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use rapl_probes::units::format_power;
+use rapl_probes::RaplDomainType;
+
+use crate::main_optimized::MeasurementsMessage;
+
+/// Periodic one-line power summary, to show that a long measurement is alive.
+pub struct Heartbeat {
+    interval: Duration,
+    /// Timestamp of the first message.
+    start: Option<SystemTime>,
+    /// Beginning of the current summary window.
+    window_start: Option<SystemTime>,
+    /// Energy consumed during the current window.
+    energy: HashMap<(u32, RaplDomainType), f64>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration) -> Heartbeat {
+        Heartbeat {
+            interval,
+            start: None,
+            window_start: None,
+            energy: HashMap::new(),
+        }
+    }
+
+    /// Adds the energy of a message to the current window.
+    /// Returns the summary lines (one per socket) when the interval has elapsed.
+    pub fn push(&mut self, msg: &MeasurementsMessage) -> Option<Vec<String>> {
+        let start = *self.start.get_or_insert(msg.timestamp);
+        let window_start = *self.window_start.get_or_insert(msg.timestamp);
+
+        for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+            for (domain, counter) in domains_of_socket {
+                if let Some(joules) = counter.joules {
+                    *self.energy.entry((socket_id as u32, domain)).or_insert(0.0) += joules;
+                }
+            }
+        }
+
+        let window = msg.timestamp.duration_since(window_start).unwrap_or(Duration::ZERO);
+        if window < self.interval || window.is_zero() {
+            return None;
+        }
+
+        // the window is complete, compute the mean power of each domain
+        let since_start = msg.timestamp.duration_since(start).unwrap_or(Duration::ZERO);
+        let mut sockets: Vec<u32> = self.energy.keys().map(|(s, _)| *s).collect();
+        sockets.sort_unstable();
+        sockets.dedup();
+        let lines = sockets
+            .into_iter()
+            .map(|socket| {
+                let powers: Vec<(RaplDomainType, f64)> = RaplDomainType::ALL
+                    .into_iter()
+                    .filter_map(|d| {
+                        let joules = self.energy.get(&(socket, d))?;
+                        Some((d, joules / window.as_secs_f64()))
+                    })
+                    .collect();
+                format_heartbeat(since_start, socket, &powers)
+            })
+            .collect();
+
+        self.energy.clear();
+        self.window_start = Some(msg.timestamp);
+        Some(lines)
+    }
+}
+
+/// Formats a summary line like `[t+12s] pkg: 45.2 W dram: 6.1 W (socket0)`.
+pub fn format_heartbeat(since_start: Duration, socket: u32, powers: &[(RaplDomainType, f64)]) -> String {
+    let mut line = format!("[t+{}s]", since_start.as_secs());
+    for (domain, watts) in powers {
+        let name = match domain {
+            RaplDomainType::Package => "pkg",
+            RaplDomainType::PP0 => "core",
+            RaplDomainType::PP1 => "uncore",
+            RaplDomainType::Dram => "dram",
+            RaplDomainType::Platform => "psys",
+        };
+        line.push_str(&format!(" {name}: {}", format_power(*watts)));
+    }
+    line.push_str(&format!(" (socket{socket})"));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::{format_heartbeat, Heartbeat};
+    use crate::main_optimized::MeasurementsMessage;
+
+    #[test]
+    fn test_format_heartbeat() {
+        let line = format_heartbeat(
+            Duration::from_millis(12_400),
+            0,
+            &[(RaplDomainType::Package, 45.2), (RaplDomainType::Dram, 6.1)],
+        );
+        assert_eq!(line, "[t+12s] pkg: 45.2 W dram: 6.1 W (socket0)");
+        assert_eq!(format_heartbeat(Duration::ZERO, 1, &[]), "[t+0s] (socket1)");
+    }
+
+    #[test]
+    fn test_heartbeat_interval() {
+        let mut heartbeat = Heartbeat::new(Duration::from_secs(1));
+        let mut measurements = EnergyMeasurements::new(1);
+        let mut lines = Vec::new();
+        // 5 J every 250 ms, i.e. 20 W
+        for i in 0..9 {
+            measurements.push(0, RaplDomainType::Package, 5 * i, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(250 * i),
+                measurements: measurements.clone(),
+                source: None,
+            };
+            lines.extend(heartbeat.push(&msg).into_iter().flatten());
+        }
+        assert_eq!(lines, vec!["[t+1s] pkg: 20 W (socket0)", "[t+2s] pkg: 20 W (socket0)"]);
+    }
+}
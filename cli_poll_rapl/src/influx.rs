@@ -0,0 +1,103 @@
+//! Output in the [line protocol](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/) of InfluxDB,
+//! for instance to send the measurements to the socket listener of Telegraf.
+//!
+//! Each measured domain of each socket gives one line:
+//! ```text
+//! rapl,socket=0,domain=package joules=1.23,overflow=false 1732110377500000000
+//! ```
+//! The timestamp is in nanoseconds since the Unix epoch (the default precision of InfluxDB).
+
+use std::io::Write;
+use std::time::SystemTime;
+
+use rapl_probes::RaplDomainType;
+
+use crate::main_optimized::MeasurementsMessage;
+use crate::output::MeasurementsOutput;
+
+/// Name of the InfluxDB measurement.
+const MEASUREMENT: &str = "rapl";
+
+/// Writes the measurements in the InfluxDB line protocol.
+pub struct InfluxLineOutput {
+    writer: Box<dyn Write + Send>,
+}
+
+impl InfluxLineOutput {
+    pub fn new(writer: Box<dyn Write + Send>) -> InfluxLineOutput {
+        InfluxLineOutput { writer }
+    }
+}
+
+impl MeasurementsOutput for InfluxLineOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        write_influx_lines(&mut self.writer, msg)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes one line per measured domain of each socket.
+pub(crate) fn write_influx_lines(writer: &mut impl Write, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+    let timestamp_ns = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_nanos();
+
+    for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+        for domain in RaplDomainType::ALL {
+            let counter = &domains_of_socket[domain];
+            if let Some(joules) = counter.joules {
+                let domain = domain.canonical_name();
+                let overflow = counter.overflowed;
+                writeln!(
+                    writer,
+                    "{MEASUREMENT},socket={socket_id},domain={domain} joules={joules},overflow={overflow} {timestamp_ns}"
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::write_influx_lines;
+    use crate::main_optimized::MeasurementsMessage;
+
+    #[test]
+    fn test_line_format() -> anyhow::Result<()> {
+        let mut m = EnergyMeasurements::new(2);
+        for (socket, domain, joules) in [
+            (0, RaplDomainType::Package, 123),
+            (0, RaplDomainType::PP0, 50),
+            (1, RaplDomainType::Dram, 7),
+        ] {
+            m.push(socket, domain, 0, u32::MAX as u64, 0.01);
+            m.push(socket, domain, joules, u32::MAX as u64, 0.01);
+        }
+        // overflow of the package of socket 1
+        m.push(1, RaplDomainType::Package, 4_000_000_000, u32::MAX as u64, 1.0);
+        m.push(1, RaplDomainType::Package, 4, u32::MAX as u64, 1.0);
+
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_nanos(1_732_110_377_500_000_001),
+            measurements: m,
+            source: None,
+        };
+        let mut out = Vec::new();
+        write_influx_lines(&mut out, &msg)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "rapl,socket=0,domain=package joules=1.23,overflow=false 1732110377500000001\n\
+             rapl,socket=0,domain=core joules=0.5,overflow=false 1732110377500000001\n\
+             rapl,socket=1,domain=package joules=294967300,overflow=true 1732110377500000001\n\
+             rapl,socket=1,domain=dram joules=0.07,overflow=false 1732110377500000001\n"
+        );
+        Ok(())
+    }
+}
@@ -1,25 +1,70 @@
+use rapl_probes::cpufreq::CpuFreqSampler;
+use rapl_probes::hwmon::HwmonSensor;
+use rapl_probes::iostats::IoOpsSampler;
+use rapl_probes::multi::MultiProbe;
 use rapl_probes::perf_event::PowerEvent;
 use rapl_probes::powercap::{PowerZone, PowerZoneHierarchy};
+use rapl_probes::stats::EnergyStats;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use clap::Parser;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
-use cli::{Cli, Commands, OutputType, ProbeType};
+use alert::PowerAlert;
+use calibration::FrequencyCheck;
+use chrome_trace::ChromeTraceOutput;
+use cli::{Cli, Commands, CsvColumns, DownsampleAgg, MonitorOptions, OutputType, ProbeType};
+use downsampling::Downsampler;
+use freq_variance::FrequencyVariance;
+use heartbeat::Heartbeat;
+use influx::InfluxLineOutput;
+use main_optimized::PolledProbe;
+use output::{CsvOutput, JsonOutput, MeasurementsOutput, RawOutput};
+use prometheus::PrometheusOutput;
+use scaphandre::ScaphandreOutput;
+use table::Table;
 use log::{info, warn};
 #[cfg(feature = "enable_ebpf")]
 use rapl_probes::ebpf;
 use rapl_probes::{
+    hwmon,
     msr::{self, RaplVendor},
-    perf_event, powercap, EnergyProbe, RaplDomainType,
+    perf_event, powercap, CpuId, DomainAvailability, EnergyProbe, ProbeCapability, RaplDomainType,
 };
 
+mod adaptive;
+mod alert;
+mod backpressure;
+mod budget;
+mod capabilities;
+mod calibration;
+mod chrome_trace;
 mod cli;
+mod csv_writer;
+mod downsampling;
+mod freq_variance;
+mod gaps;
+mod heartbeat;
+mod influx;
 mod main_optimized;
+mod markers;
+#[cfg(feature = "otel")]
+mod otel;
+mod output;
+mod poll_rate;
+mod prometheus;
+mod realtime;
+mod scaphandre;
+mod smoothing;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod table;
+mod workload;
 #[cfg(any(feature = "bad_sleep", feature = "bad_sleep_singlethread"))]
 mod main_bad;
 
@@ -36,41 +81,153 @@ async fn main() -> Result<(), anyhow::Error> {
     // parse CLI arguments
     let cli = Cli::parse();
 
+    // the analysis of existing files does not need the RAPL interfaces
+    if let Commands::Diff { a, b, bucket_ms } = &cli.command {
+        return print_diff(a, b, Duration::from_millis(*bucket_ms));
+    }
+
     // get cpu info, accessible perf events and power zones
     let all_cpus = rapl_probes::online_cpus()?;
     let socket_cpus = rapl_probes::cpus_to_monitor()?;
     let perf_events = rapl_probes::perf_event::all_power_events()?;
-    let power_zones = rapl_probes::powercap::all_power_zones()?;
+    let mut power_zones = rapl_probes::powercap::all_power_zones()?;
 
     let n_sockets = socket_cpus.len();
     let n_cpu_cores = all_cpus.len();
     info!("{n_sockets}/{n_cpu_cores} monitorable CPU (cores) found: {socket_cpus:?}");
 
     // check the consistency of the RAPL interfaces
-    let available_domains = check_domains_consistency(&perf_events, &power_zones);
+    let availability = DomainAvailability::from_discovery(&perf_events, &power_zones);
+    check_domains_consistency(&availability);
+    let available_domains = availability.most_complete().to_vec();
 
     // run the command
     match cli.command {
         Commands::Info => {
+            let color = table::stdout_supports_color();
+
+            match msr::cpu_model_info() {
+                Ok(info) => println!("\nCPU model: {info}"),
+                Err(e) => println!("\nCPU model: unknown ({e:#})"),
+            }
+
             println!("\nFound RAPL perf events:");
+            let mut events_table = Table::new(&["name", "domain", "code", "unit", "scale"]);
             for evt in &perf_events {
-                println!("- {evt:?}");
+                events_table.push(vec![
+                    evt.name.clone(),
+                    format!("{:?}", evt.domain),
+                    format!("{:#x}", evt.code),
+                    evt.unit.clone(),
+                    format!("{:e}", evt.scale),
+                ]);
             }
+            print!("{}", events_table.render(color));
 
             println!("\nFound powercap zones:");
+            let mut zones_table = Table::new(&["zone", "domain", "socket", "path"]);
+            fn push_zone_rec(table: &mut Table, zone: &PowerZone, level: usize) {
+                table.push(vec![
+                    format!("{}{}", "  ".repeat(level), zone.name),
+                    format!("{:?}", zone.domain),
+                    zone.socket_id.map(|s| s.to_string()).unwrap_or_default(),
+                    zone.path.to_string_lossy().into_owned(),
+                ]);
+                for child in &zone.children {
+                    push_zone_rec(table, child, level + 1);
+                }
+            }
             for zone in &power_zones.top {
-                println!("{zone}");
+                push_zone_rec(&mut zones_table, zone, 0);
             }
+            print!("{}", zones_table.render(color));
 
             println!("\nAll available RAPL domains: {}", mkstring(&available_domains, ", "));
+
+            let caps = rapl_probes::probe_capabilities();
+            println!("\nProbes that are likely usable by the current user:");
+            let mut caps_table = Table::new(&["probe", "status", "details"]);
+            for (probe, cap) in [
+                (ProbeType::PowercapSysfs, &caps.powercap),
+                (ProbeType::PerfEvent, &caps.perf_event),
+                (ProbeType::Ebpf, &caps.ebpf),
+                (ProbeType::Msr, &caps.msr),
+                (ProbeType::Hwmon, &caps.hwmon),
+            ] {
+                let (status, details) = match cap {
+                    ProbeCapability::Usable => ("usable", ""),
+                    ProbeCapability::Unavailable(reason) => ("unavailable", reason.as_str()),
+                    ProbeCapability::PermissionDenied(missing) => ("permission denied", missing.as_str()),
+                };
+                caps_table.push(vec![probe.to_string(), status.to_owned(), details.to_owned()]);
+            }
+            print!("{}", caps_table.render(color));
+        }
+        Commands::Capabilities => {
+            let vendor = match msr::cpu_vendor() {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    warn!("Could not determine the cpu vendor, the MSR addresses will be unknown: {e:?}");
+                    None
+                }
+            };
+            let discovery = capabilities::Discovery {
+                vendor,
+                socket_cpus: &socket_cpus,
+                perf_events: &perf_events,
+                power_zones: &power_zones,
+                probes: &rapl_probes::probe_capabilities(),
+            };
+            println!("{}", serde_json::to_string_pretty(&discovery.to_json())?);
         }
         Commands::Poll {
             probe,
+            also_probe,
             domains,
             frequency,
+            exclude_zone,
+            sockets,
+            workload_cpus,
+            allow_unattainable_frequency,
             output,
             output_file,
+            sqlite_path,
+            prometheus_listen,
+            otel_endpoint,
+            csv: csv_columns,
+            monitors: monitor_options,
+            emit_every,
+            warmup_samples,
+            samples,
+            downsample_agg,
+            batch_size,
+            max_duration,
+            realtime,
+            adaptive,
+            force,
+            append,
+            overflow_policy,
         } => {
+            let CsvColumns {
+                csv_delimiter,
+                with_cumulative,
+                with_elapsed,
+                domain_order,
+                emit_totals,
+                totals_include_platform,
+                with_frequency,
+                with_io_ops,
+                smooth_alpha,
+                markers_file,
+                emit_gaps,
+                with_cpu_model,
+            } = *csv_columns;
+            let MonitorOptions {
+                heartbeat,
+                power_alert_watts,
+                summary,
+                frequency_variance,
+            } = *monitor_options;
             // compute the polling period, or stop if zero
             let polling_period = Duration::from_secs_f64({
                 if frequency == 0.0 {
@@ -84,98 +241,483 @@ async fn main() -> Result<(), anyhow::Error> {
                 }
             });
 
-            // filter the domains according to the command-line arguments
-            if !domains.iter().all(|d| available_domains.contains(d)) {
-                return Err(anyhow!("Invalid selected domains: {}", mkstring(&domains, ", ")));
+            // create the RAPL probe
+            let socket_cpus = selected_socket_cpus(&socket_cpus, sockets.as_deref())?;
+            if let Some(workload) = &workload_cpus {
+                workload.check(&socket_cpus, rapl_probes::package_of);
+            }
+            power_zones.exclude(&exclude_zone)?;
+            let mut probe: PolledProbe = match probe {
+                _ if !also_probe.is_empty() => {
+                    if !matches!(output, OutputType::None | OutputType::Stdout | OutputType::File) {
+                        return Err(anyhow!("--also-probe is only supported by the CSV output, not by the {output} output"));
+                    }
+                    let mut multi = MultiProbe::new();
+                    for p in std::iter::once(probe.clone()).chain(also_probe.iter().cloned()) {
+                        if p == ProbeType::Ebpf {
+                            return Err(anyhow!("--also-probe does not support the eBPF probe"));
+                        }
+                        let label = p.to_string();
+                        let created = create_probe(p, &domains, &available_domains, &socket_cpus, &perf_events, &power_zones, frequency)?;
+                        multi = multi.with_probe(label, created);
+                    }
+                    PolledProbe::Multi(multi)
+                }
+                // the optimized version awaits the events, the bad versions poll the probe like the others
+                #[cfg(all(feature = "enable_ebpf", not(any(feature = "bad_sleep", feature = "bad_sleep_singlethread"))))]
+                ProbeType::Ebpf => {
+                    let (filtered_events, _) = filter_domains(&domains, &available_domains, &perf_events, &power_zones)?;
+                    let p = ebpf::AsyncEbpfProbe::new(&socket_cpus, &filtered_events, frequency as u64, ebpf::DEFAULT_BUF_PAGE_COUNT)?;
+                    PolledProbe::Ebpf(p)
+                }
+                _ => {
+                    let p = create_probe(probe, &domains, &available_domains, &socket_cpus, &perf_events, &power_zones, frequency)?;
+                    PolledProbe::Periodic(p)
+                }
+            };
+
+            // check that the probe is fast enough for the requested frequency
+            // (the eBPF probe is not polled by us: its frequency is guaranteed by the kernel)
+            let latency = match &mut probe {
+                PolledProbe::Periodic(p) => Some(calibration::measure_poll_latency(p.as_mut(), calibration::CALIBRATION_POLLS)?),
+                PolledProbe::Multi(p) => Some(calibration::measure_poll_latency(p, calibration::CALIBRATION_POLLS)?),
+                #[cfg(feature = "enable_ebpf")]
+                PolledProbe::Ebpf(_) => None,
+            };
+            let check = latency.map(|latency| (latency, calibration::check_frequency(latency, polling_period)));
+            if let Some((latency, FrequencyCheck::Unattainable { max_frequency })) = check {
+                let msg = format!(
+                    "The frequency {frequency} Hz is unattainable with this probe: poll() takes {latency:?}, which allows at most {max_frequency:.0} Hz. Use a lower frequency or a cheaper probe (perf-event is usually the fastest)."
+                );
+                if allow_unattainable_frequency {
+                    warn!("{msg}");
+                } else {
+                    return Err(anyhow!("{msg} Use --allow-unattainable-frequency to ignore this error."));
+                }
             }
 
-            let filtered_events: Vec<&PowerEvent> =
-                perf_events.iter().filter(|e| domains.contains(&e.domain)).collect();
+            let existing_file = if append {
+                ExistingFile::Append
+            } else if force {
+                ExistingFile::Overwrite
+            } else {
+                ExistingFile::Refuse
+            };
+
+            let max_duration = match max_duration {
+                Some(secs) if secs > 0.0 => Some(Duration::from_secs_f64(secs)),
+                Some(secs) => return Err(anyhow!("Invalid maximum duration: {secs}")),
+                None => None,
+            };
 
-            // the powercap zones are organized in a hierarchy, we need to explore them recursively
-            let filtered_zones: Vec<&PowerZone> = power_zones
-                .flat
-                .iter()
-                .filter(|z| domains.contains(&z.domain))
-                .collect();
+            if samples == Some(0) {
+                return Err(anyhow!("Invalid number of samples: 0"));
+            }
 
-            // create the RAPL probe
-            let probe: Box<dyn EnergyProbe> = match probe {
-                ProbeType::PowercapSysfs => {
-                    let p = powercap::PowercapProbe::<true>::new(&socket_cpus, &filtered_zones)?;
-                    Box::new(p)
+            let power_alert_watts = match power_alert_watts {
+                Some(watts) if watts > 0.0 => Some(watts),
+                Some(watts) => return Err(anyhow!("Invalid power alert threshold: {watts}")),
+                None => None,
+            };
+
+            let heartbeat = match heartbeat {
+                Some(secs) if secs > 0.0 => Some(Heartbeat::new(Duration::from_secs_f64(secs))),
+                Some(secs) => return Err(anyhow!("Invalid heartbeat interval: {secs}")),
+                None => None,
+            };
+
+            // combine the polls if requested
+            let downsampler = Downsampler::new(emit_every, downsample_agg);
+            let value_column = match downsample_agg {
+                DownsampleAgg::EnergySum => "joules",
+                DownsampleAgg::PowerMean | DownsampleAgg::PowerMax => {
+                    if with_cumulative
+                        || smooth_alpha.is_some()
+                        || !matches!(output, OutputType::None | OutputType::Stdout | OutputType::File)
+                    {
+                        return Err(anyhow!(
+                            "--downsample-agg {downsample_agg} is incompatible with --with-cumulative, --smooth-alpha and with the {output} output"
+                        ));
+                    }
+                    "watts"
+                }
+            };
+
+            // prepare the output, if any
+            #[cfg(not(feature = "bad_sleep_singlethread"))]
+            let output: Box<dyn MeasurementsOutput> = match output {
+                OutputType::Sqlite => {
+                    #[cfg(feature = "sqlite")]
+                    {
+                        let path = sqlite_path.ok_or(anyhow!("--sqlite-path is required when the output is sqlite"))?;
+                        Box::new(sqlite::SqliteOutput::open(Path::new(&path))?)
+                    }
+                    #[cfg(not(feature = "sqlite"))]
+                    {
+                        let _ = sqlite_path;
+                        panic!("Invalid output type 'sqlite': the sqlite feature has not been enabled during the compilation of the tool. Recompile with `--features sqlite` to enable.")
+                    }
                 }
-                ProbeType::PerfEvent => {
-                    let p = perf_event::PerfEventProbe::new(&socket_cpus, &filtered_events)?;
-                    Box::new(p)
+                OutputType::ScaphandreJson => {
+                    let (writer, _) = open_writer(output, output_file, existing_file)?;
+                    Box::new(ScaphandreOutput::new(writer))
                 }
-                ProbeType::Ebpf => {
-                    #[cfg(feature = "enable_ebpf")]
+                OutputType::Json => {
+                    let (writer, _) = open_writer(output, output_file, existing_file)?;
+                    Box::new(JsonOutput::new(writer))
+                }
+                OutputType::Prometheus => Box::new(PrometheusOutput::bind(&prometheus_listen)?),
+                OutputType::Otel => {
+                    #[cfg(feature = "otel")]
                     {
-                    let p = ebpf::EbpfProbe::new(&socket_cpus, &filtered_events, frequency as u64)?;
-                    Box::new(p)
+                        Box::new(otel::OtelOutput::connect(&otel_endpoint)?)
                     }
-                    #[cfg(not(feature = "enable_ebpf"))]
+                    #[cfg(not(feature = "otel"))]
                     {
-                        panic!("Invalid probe type 'ebpf': the ebpf feature has not been enabled during the compilation of the tool. Recompile with `--features enable_ebpf` to enable.")
+                        let _ = otel_endpoint;
+                        panic!("Invalid output type 'otel': the otel feature has not been enabled during the compilation of the tool. Recompile with `--features otel` to enable.")
                     }
                 }
-                ProbeType::Msr => {
-                    let p = msr::MsrProbe::new(&socket_cpus, &domains)?;
-                    Box::new(p)
+                OutputType::ChromeTrace => {
+                    let (writer, _) = open_writer(output, output_file, existing_file)?;
+                    Box::new(ChromeTraceOutput::new(writer))
                 }
-            };
-
-            // prepare the output, if any
-            let writer: Box<dyn Write + Send> = match output {
-                OutputType::None => Box::new(std::io::sink()),
-                OutputType::Stdout => Box::new(BufWriter::with_capacity(WRITER_BUFFER_CAPACITY, std::io::stdout())),
-                OutputType::File => {
-                    let filename = if let Some(f) = output_file {
-                        f
-                    } else {
-                        // create the csv file
-                        let now = OffsetDateTime::now_utc().format(&Rfc3339)?;
-                        format!("poll-{now}.csv")
-                    };
-                    let file = File::create(filename)?;
-                    let writer = BufWriter::with_capacity(WRITER_BUFFER_CAPACITY, file);
-                    // return the writer
-                    Box::new(writer)
+                OutputType::InfluxLine => {
+                    let (writer, _) = open_writer(output, output_file, existing_file)?;
+                    Box::new(InfluxLineOutput::new(writer))
+                }
+                OutputType::Raw => {
+                    // the downsampling would combine the polls, which have one raw value each
+                    if emit_every > 1 {
+                        return Err(anyhow!("--emit-every is incompatible with the raw output"));
+                    }
+                    let (writer, has_content) = open_writer(output, output_file, existing_file)?;
+                    let mut raw = RawOutput::new(writer);
+                    if !has_content {
+                        raw.write_header()?;
+                    }
+                    Box::new(raw)
+                }
+                _ => {
+                    let (writer, has_content) = open_writer(output, output_file, existing_file)?;
+                    let mut csv = CsvOutput::new(writer, value_column, with_cumulative)
+                        .with_delimiter(csv_delimiter)
+                        .with_domain_order(domain_order.domains());
+                    if emit_totals {
+                        csv = csv.with_totals(totals_include_platform);
+                    }
+                    if with_elapsed {
+                        csv = csv.with_elapsed();
+                    }
+                    if with_frequency {
+                        csv = csv.with_frequency(CpuFreqSampler::for_sockets(&socket_cpus)?);
+                    }
+                    if let Some(source) = with_io_ops {
+                        // the operations are counted when a message is written, thus a batch would get them all
+                        if batch_size > 1 {
+                            return Err(anyhow!("--with-io-ops is incompatible with --batch-size > 1"));
+                        }
+                        csv = csv.with_io_ops(IoOpsSampler::new(source)?);
+                    }
+                    if let Some(alpha) = smooth_alpha {
+                        csv = csv.with_smoothing(alpha);
+                    }
+                    if !also_probe.is_empty() {
+                        csv = csv.with_source();
+                    }
+                    if let Some(path) = markers_file {
+                        csv = csv.with_markers(markers::MarkersFile::new(PathBuf::from(path)));
+                    }
+                    if emit_gaps {
+                        if polling_period.is_zero() {
+                            return Err(anyhow!("--emit-gaps requires a positive frequency"));
+                        }
+                        // the gaps are detected after the downsampling
+                        csv = csv.with_gaps(polling_period * emit_every as u32);
+                    }
+                    if with_cpu_model {
+                        match msr::cpu_model_info() {
+                            Ok(info) => csv = csv.with_comment(format!("cpu: {info}")),
+                            Err(e) => warn!("Failed to detect the cpu model, it will not be written in the output. {e:#}"),
+                        }
+                    }
+                    if !has_content {
+                        csv.write_header()?;
+                    }
+                    Box::new(csv)
                 }
             };
 
             #[cfg(not(any(feature = "bad_sleep", feature = "bad_sleep_singlethread")))]
-            main_optimized::run(writer, probe, polling_period, MEASUREMENTS_FLUSH_INTERVAL).await?;
+            {
+                let polling = main_optimized::PollingOptions {
+                    period: polling_period,
+                    batch_size,
+                    max_duration,
+                    realtime,
+                    adaptive,
+                    overflow_policy,
+                    warmup_samples,
+                    samples,
+                };
+                let monitors = main_optimized::Monitors {
+                    heartbeat,
+                    stats: summary.then(EnergyStats::new),
+                    power_alert: power_alert_watts.map(|watts| PowerAlert::new(watts, polling_period)),
+                    frequency_variance: if frequency_variance {
+                        Some(FrequencyVariance::new(&socket_cpus)?)
+                    } else {
+                        None
+                    },
+                };
+                main_optimized::run(output, probe, downsampler, polling, MEASUREMENTS_FLUSH_INTERVAL, monitors).await?;
+            }
+
+            #[cfg(any(feature = "bad_sleep", feature = "bad_sleep_singlethread"))]
+            let probe = match probe {
+                PolledProbe::Periodic(p) => p,
+                // only the measurements of the first probe are written
+                PolledProbe::Multi(p) => Box::new(p),
+                #[cfg(feature = "enable_ebpf")]
+                PolledProbe::Ebpf(_) => unreachable!("the bad versions only use periodic probes"),
+            };
 
             #[cfg(feature = "bad_sleep")]
-            main_bad::run_bad_sleep(writer, probe, polling_period, MEASUREMENTS_FLUSH_INTERVAL).await?;
+            main_bad::run_bad_sleep(output, probe, polling_period, MEASUREMENTS_FLUSH_INTERVAL).await?;
 
             #[cfg(feature = "bad_sleep_singlethread")]
-            main_bad::run_bad_sleep_singlethread(writer, probe, polling_period, MEASUREMENTS_FLUSH_INTERVAL)?;
+            main_bad::run_bad_sleep_singlethread(open_writer(output, output_file, existing_file)?.0, probe, polling_period, MEASUREMENTS_FLUSH_INTERVAL)?;
+        }
+        Commands::Budget {
+            probe,
+            domains,
+            target_watts,
+            frequency,
+            exclude_zone,
+            sockets,
+            max_duration,
+        } => {
+            if !(target_watts > 0.0 && frequency > 0.0) {
+                return Err(anyhow!("The target power and the frequency must be positive"));
+            }
+            // the consumption is the total of package and dram, the other domains would never give a value
+            if !domains.iter().any(|d| matches!(d, RaplDomainType::Package | RaplDomainType::Dram)) {
+                return Err(anyhow!(
+                    "The budget is computed from the package and dram domains, at least one of them must be measured"
+                ));
+            }
+            let max_duration = match max_duration {
+                Some(secs) if secs > 0.0 => Some(Duration::from_secs_f64(secs)),
+                Some(secs) => return Err(anyhow!("Invalid maximum duration: {secs}")),
+                None => None,
+            };
+            let socket_cpus = selected_socket_cpus(&socket_cpus, sockets.as_deref())?;
+            power_zones.exclude(&exclude_zone)?;
+            let probe = create_probe(probe, &domains, &available_domains, &socket_cpus, &perf_events, &power_zones, frequency)?;
+            budget::run_budget(probe, target_watts, Duration::from_secs_f64(1.0 / frequency), max_duration).await?;
         }
+        Commands::Measure {
+            probe,
+            domains,
+            duration,
+            period,
+            exclude_zone,
+            sockets,
+        } => {
+            if !(duration >= 0.0 && period > 0.0) {
+                return Err(anyhow!("Invalid duration {duration} or period {period}: the period must be positive"));
+            }
+            let socket_cpus = selected_socket_cpus(&socket_cpus, sockets.as_deref())?;
+            power_zones.exclude(&exclude_zone)?;
+            // the eBPF program needs an integer frequency, of at least 1 Hz
+            let frequency = (1.0 / period).ceil();
+            let mut probe = create_probe(probe, &domains, &available_domains, &socket_cpus, &perf_events, &power_zones, frequency)?;
+            let (duration, period) = (Duration::from_secs_f64(duration), Duration::from_secs_f64(period));
+            let stats = tokio::task::spawn_blocking(move || rapl_probes::stats::measure_for(&mut *probe, duration, period)).await??;
+            print!("{}", stats.summary());
+        }
+        Commands::Diff { .. } => unreachable!("the diff command does not use the RAPL interfaces, it has been handled before"),
     }
 
     Ok(())
 }
 
-fn check_domains_consistency(perf_events: &[PowerEvent], power_zones: &PowerZoneHierarchy) -> Vec<RaplDomainType> {
-    // get all the domains available via perf-events
-    let mut perf_rapl_domains: Vec<RaplDomainType> = perf_events.iter().map(|e| e.domain).collect();
-    perf_rapl_domains.sort_by_key(|k| k.to_string());
-    perf_rapl_domains.dedup_by_key(|k| k.to_string());
+/// Compares the CSV files `a` and `b`, and prints the result as a table.
+fn print_diff(a: &Path, b: &Path, bucket: Duration) -> anyhow::Result<()> {
+    let open = |path: &Path| -> anyhow::Result<BufReader<File>> {
+        let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+        Ok(BufReader::new(file))
+    };
+    let read = |path: &Path| rapl_probes::compare::read_buckets(open(path)?, bucket).with_context(|| format!("failed to read {path:?}"));
+    let diffs = rapl_probes::compare::compare(&read(a)?, &read(b)?);
+    if diffs.is_empty() {
+        return Err(anyhow!("no measurement in {a:?} nor in {b:?}"));
+    }
+
+    let mut table = Table::new(&["domain", "pairs", "mean |a - b| (J)", "mean a (J)", "correlation", "unmatched"]);
+    for diff in diffs {
+        table.push(vec![
+            diff.domain.canonical_name().to_owned(),
+            diff.pairs.to_string(),
+            format!("{:.6}", diff.mean_abs_diff),
+            format!("{:.6}", diff.mean_a),
+            diff.correlation.map(|r| format!("{r:.4}")).unwrap_or_else(|| String::from("-")),
+            diff.unmatched.to_string(),
+        ]);
+    }
+    print!("{}", table.render(table::stdout_supports_color()));
+    Ok(())
+}
+
+/// Keeps the cpus of the selected `sockets`, or all of them if no socket is selected.
+fn selected_socket_cpus(socket_cpus: &[CpuId], sockets: Option<&[u32]>) -> anyhow::Result<Vec<CpuId>> {
+    match sockets {
+        Some(sockets) => {
+            let selected = rapl_probes::select_sockets(socket_cpus, sockets)?;
+            info!("Monitoring the sockets {}: {selected:?}", mkstring(sockets, ","));
+            Ok(selected)
+        }
+        None => Ok(socket_cpus.to_vec()),
+    }
+}
+
+/// Checks that the selected `domains` are available, and returns the perf events and the powercap zones that measure them.
+fn filter_domains<'a>(
+    domains: &[RaplDomainType],
+    available_domains: &[RaplDomainType],
+    perf_events: &'a [PowerEvent],
+    power_zones: &'a PowerZoneHierarchy,
+) -> anyhow::Result<(Vec<&'a PowerEvent>, Vec<&'a PowerZone>)> {
+    if !domains.iter().all(|d| available_domains.contains(d)) {
+        return Err(anyhow!("Invalid selected domains: {}", mkstring(domains, ", ")));
+    }
+
+    let filtered_events: Vec<&PowerEvent> = perf_events.iter().filter(|e| domains.contains(&e.domain)).collect();
+
+    // the powercap zones are organized in a hierarchy, we need to explore them recursively
+    let filtered_zones: Vec<&PowerZone> = power_zones
+        .flat
+        .iter()
+        .filter(|z| domains.contains(&z.domain))
+        .collect();
+    Ok((filtered_events, filtered_zones))
+}
+
+/// Creates a RAPL probe that is polled periodically, for the selected domains.
+fn create_probe(
+    probe: ProbeType,
+    domains: &[RaplDomainType],
+    available_domains: &[RaplDomainType],
+    socket_cpus: &[CpuId],
+    perf_events: &[PowerEvent],
+    power_zones: &PowerZoneHierarchy,
+    frequency: f64,
+) -> anyhow::Result<Box<dyn EnergyProbe>> {
+    let (filtered_events, filtered_zones) = filter_domains(domains, available_domains, perf_events, power_zones)?;
+    let probe: Box<dyn EnergyProbe> = match probe {
+        ProbeType::PowercapSysfs => Box::new(powercap::PowercapProbe::<true>::new(socket_cpus, &filtered_zones)?),
+        ProbeType::PerfEvent => Box::new(perf_event::PerfEventProbe::new(socket_cpus, &filtered_events)?),
+        ProbeType::Ebpf => {
+            #[cfg(feature = "enable_ebpf")]
+            {
+                Box::new(ebpf::EbpfProbe::new(socket_cpus, &filtered_events, frequency as u64, ebpf::DEFAULT_BUF_PAGE_COUNT)?)
+            }
+            #[cfg(not(feature = "enable_ebpf"))]
+            {
+                let _ = frequency;
+                panic!("Invalid probe type 'ebpf': the ebpf feature has not been enabled during the compilation of the tool. Recompile with `--features enable_ebpf` to enable.")
+            }
+        }
+        ProbeType::Msr => Box::new(msr::MsrProbe::new(socket_cpus, domains)?),
+        ProbeType::Hwmon => {
+            let sensors = hwmon::all_hwmon_energy_sensors()?;
+            let filtered_sensors: Vec<&HwmonSensor> = sensors.iter().filter(|s| domains.contains(&s.domain)).collect();
+            Box::new(hwmon::HwmonProbe::new(socket_cpus, &filtered_sensors)?)
+        }
+    };
+    Ok(probe)
+}
+
+/// What to do when the output file already exists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExistingFile {
+    /// Return an error, to avoid destroying a previous capture.
+    Refuse,
+    /// Truncate the file.
+    Overwrite,
+    /// Write at the end of the file.
+    Append,
+}
 
-    // get all the domains available via Powercap
-    let mut powercap_rapl_domains: Vec<RaplDomainType> = power_zones.flat.iter().map(|z| z.domain).collect();
-    powercap_rapl_domains.sort_by_key(|k| k.to_string());
-    powercap_rapl_domains.dedup_by_key(|k| k.to_string());
+/// Opens the text output that corresponds to `output`.
+/// Also returns `true` if the output already contains some data (in append mode).
+fn open_writer(
+    output: OutputType,
+    output_file: Option<String>,
+    existing_file: ExistingFile,
+) -> anyhow::Result<(Box<dyn Write + Send>, bool)> {
+    let writer: Box<dyn Write + Send> = match output {
+        OutputType::None => Box::new(std::io::sink()),
+        OutputType::Stdout => Box::new(BufWriter::with_capacity(WRITER_BUFFER_CAPACITY, std::io::stdout())),
+        OutputType::File
+        | OutputType::Json
+        | OutputType::ScaphandreJson
+        | OutputType::ChromeTrace
+        | OutputType::InfluxLine
+        | OutputType::Raw => {
+            let filename = if let Some(f) = output_file {
+                f
+            } else if output == OutputType::File {
+                // create the csv file
+                let now = OffsetDateTime::now_utc().format(&Rfc3339)?;
+                format!("poll-{now}.csv")
+            } else {
+                // other formats are written to stdout by default
+                return Ok((
+                    Box::new(BufWriter::with_capacity(WRITER_BUFFER_CAPACITY, std::io::stdout())),
+                    false,
+                ));
+            };
+            let file = open_output_file(Path::new(&filename), existing_file)?;
+            let has_content = file.metadata()?.len() > 0;
+            return Ok((Box::new(BufWriter::with_capacity(WRITER_BUFFER_CAPACITY, file)), has_content));
+        }
+        OutputType::Sqlite | OutputType::Prometheus | OutputType::Otel => {
+            return Err(anyhow!("Output type {output} cannot be written as text"))
+        }
+    };
+    Ok((writer, false))
+}
 
-    if perf_rapl_domains != powercap_rapl_domains {
+/// Opens the output file for writing, according to `existing_file`.
+fn open_output_file(path: &Path, existing_file: ExistingFile) -> anyhow::Result<File> {
+    let mut options = OpenOptions::new();
+    match existing_file {
+        ExistingFile::Refuse => options.write(true).create_new(true),
+        ExistingFile::Overwrite => options.write(true).create(true).truncate(true),
+        ExistingFile::Append => options.append(true).create(true),
+    };
+    options.open(path).map_err(|e| {
+        if e.kind() == ErrorKind::AlreadyExists {
+            anyhow!(
+                "Output file {} already exists. Use --force to overwrite it, or --append to add measurements to it.",
+                path.display()
+            )
+        } else {
+            anyhow::Error::new(e).context(format!("failed to open output file {}", path.display()))
+        }
+    })
+}
+
+fn check_domains_consistency(availability: &DomainAvailability) {
+    let perf_rapl_domains = &availability.perf_event;
+    let powercap_rapl_domains = &availability.powercap;
+
+    if !availability.is_consistent() {
         warn!("Powercap and perf-event don't report the same RAPL domains. This may be due to a bug in powercap or in perf-event.");
         warn!("Upgrading to a newer kernel could fix the problem.");
-        warn!("Perf-event: {}", mkstring(&perf_rapl_domains, ", "));
-        warn!("Powercap:   {}", mkstring(&powercap_rapl_domains, ", "));
-
+        warn!("Perf-event: {}", mkstring(perf_rapl_domains, ", "));
+        warn!("Powercap:   {}", mkstring(powercap_rapl_domains, ", "));
         match rapl_probes::msr::cpu_vendor() {
             Ok(RaplVendor::Amd) =>
                 warn!(
@@ -199,13 +741,7 @@ fn check_domains_consistency(perf_events: &[PowerEvent], power_zones: &PowerZone
                 ),
         };
     } else {
-        info!("Available RAPL domains: {}", mkstring(&perf_rapl_domains, ", "));
-    }
-
-    if perf_rapl_domains.len() >= powercap_rapl_domains.len() {
-        perf_rapl_domains
-    } else {
-        powercap_rapl_domains
+        info!("Available RAPL domains: {}", mkstring(perf_rapl_domains, ", "));
     }
 }
 
@@ -216,3 +752,30 @@ fn mkstring<A: ToString>(elems: &[A], sep: &str) -> String {
 
 #[cfg(all(feature = "bad_sleep", feature = "bad_sleep_singlethread"))]
 compile_error!("features \"bad_sleep\" and \"bad_sleep_singlethread\" cannot be enabled at the same time");
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{open_output_file, ExistingFile};
+
+    #[test]
+    fn test_refuse_existing_output_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("cli_poll_rapl-test-{}.csv", std::process::id()));
+        std::fs::write(&path, "previous capture\n")?;
+
+        let err = open_output_file(&path, ExistingFile::Refuse).expect_err("the file should not be overwritten");
+        assert!(err.to_string().contains("already exists"), "unexpected error: {err}");
+        assert_eq!(std::fs::read_to_string(&path)?, "previous capture\n");
+
+        let mut file = open_output_file(&path, ExistingFile::Append)?;
+        file.write_all(b"more\n")?;
+        assert_eq!(std::fs::read_to_string(&path)?, "previous capture\nmore\n");
+
+        open_output_file(&path, ExistingFile::Overwrite)?;
+        assert_eq!(std::fs::read_to_string(&path)?, "");
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use super::main_optimized::print_measurements as print_measurements_message;
 use super::main_optimized::MeasurementsMessage;
+use super::output::MeasurementsOutput;
+use super::poll_rate::PollRate;
 
 use rapl_probes::{EnergyMeasurements, EnergyProbe};
 
@@ -8,6 +9,18 @@ use std::io::Write;
 use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::{self, Sender};
 
+/// These variants poll until they are killed: the achieved frequency is logged periodically, not at the end.
+const RATE_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Records the timestamp of a poll, and logs the achieved frequency (since the start) every [`RATE_REPORT_INTERVAL`].
+fn record_poll(rate: &mut PollRate, last_report: &mut SystemTime, timestamp: SystemTime, period: Duration) {
+    rate.record(timestamp);
+    if timestamp.duration_since(*last_report).unwrap_or(Duration::ZERO) >= RATE_REPORT_INTERVAL {
+        *last_report = timestamp;
+        rate.log_report(Some(period));
+    }
+}
+
 #[cfg(feature = "bad_sleep_singlethread")]
 pub fn run_bad_sleep_singlethread(
     mut writer: Box<dyn Write + Send>,
@@ -16,9 +29,11 @@ pub fn run_bad_sleep_singlethread(
     measurement_flush_interval: Duration,
 ) -> anyhow::Result<()> {
     let mut previous_timestamp: SystemTime = SystemTime::now();
+    let mut rate = PollRate::new();
+    let mut last_report = SystemTime::now();
 
     // write the csv header
-    writer.write("timestamp_ms;socket;domain;overflow;joules\n".as_bytes())?;
+    writer.write_all("timestamp_ms;socket;domain;overflow;joules\n".as_bytes())?;
 
     loop {
         // wait for the polling period, CAVEAT: actually, this is very unprecise
@@ -27,7 +42,8 @@ pub fn run_bad_sleep_singlethread(
         probe.poll().context("refreshing measurements")?;
         let m = probe.measurements();
 
-        let timestamp = SystemTime::now();
+        let timestamp = m.timestamp().unwrap_or_else(SystemTime::now);
+        record_poll(&mut rate, &mut last_report, timestamp, polling_period);
         print_measurements_direct(&mut writer, &m, timestamp)?;
 
         let time_since_last_flush = timestamp.duration_since(previous_timestamp).unwrap_or(Duration::ZERO);
@@ -40,7 +56,7 @@ pub fn run_bad_sleep_singlethread(
 
 #[cfg(feature = "bad_sleep")]
 pub async fn run_bad_sleep(
-    mut writer: Box<dyn Write + Send>,
+    mut output: Box<dyn MeasurementsOutput>,
     mut probe: Box<dyn EnergyProbe>,
     polling_period: Duration,
     measurement_flush_interval: Duration,
@@ -53,10 +69,8 @@ pub async fn run_bad_sleep(
     let handle = tokio::spawn(async move {
         let mut previous_timestamp: SystemTime = SystemTime::now();
 
-        // write the csv header
-        writer.write("timestamp_ms;socket;domain;overflow;joules\n".as_bytes())?;
         while let Some(msg) = rx.recv().await {
-            print_measurements_message(&mut writer, &msg)?;
+            output.write(&msg)?;
 
             let time_since_last_flush = msg
                 .timestamp
@@ -65,7 +79,7 @@ pub async fn run_bad_sleep(
 
             if time_since_last_flush >= measurement_flush_interval {
                 previous_timestamp = msg.timestamp;
-                writer.flush()?;
+                output.flush()?;
             }
         }
 
@@ -88,6 +102,8 @@ async fn poll_energy_probe_badly(
     period: Duration,
     tx: Sender<MeasurementsMessage>,
 ) -> anyhow::Result<()> {
+    let mut rate = PollRate::new();
+    let mut last_report = SystemTime::now();
     loop {
         // wait for the next period
         std::thread::sleep(period);
@@ -97,12 +113,15 @@ async fn poll_energy_probe_badly(
         let m = probe.measurements();
 
         // // send the values to the writer task through the channel
-        let timestamp = SystemTime::now();
+        // use the time at which the probe has read the counters, not the time at which poll() returned
+        let timestamp = m.timestamp().unwrap_or_else(SystemTime::now);
         let measurements = m.clone();
+        record_poll(&mut rate, &mut last_report, timestamp, period);
 
         tx.send(MeasurementsMessage {
             timestamp,
             measurements,
+            source: None,
         })
         .await
         .expect("failed to send measurement through channel");
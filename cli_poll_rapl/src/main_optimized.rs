@@ -1,30 +1,242 @@
-use rapl_probes::{EnergyMeasurements, EnergyProbe};
+use crate::adaptive::{AdaptivePeriod, MIN_ADAPTIVE_PERIOD, RELAX_WINDOW};
+use crate::alert::PowerAlert;
+use crate::backpressure::{MeasurementsSender, CHANNEL_CAPACITY};
+use crate::cli::OverflowPolicy;
+use crate::csv_writer::CsvWriter;
+use crate::downsampling::Downsampler;
+use crate::freq_variance::FrequencyVariance;
+use crate::heartbeat::Heartbeat;
+use crate::output::MeasurementsOutput;
+use crate::poll_rate::PollRate;
+use crate::realtime;
+use crate::smoothing::EwmaFilter;
+use rapl_probes::iostats::joules_per_op;
+use rapl_probes::multi::MultiProbe;
+use rapl_probes::stats::EnergyStats;
+use rapl_probes::{EnergyMeasurements, EnergyProbe, RaplDomainType};
 
 use anyhow::Context;
 use futures::stream::StreamExt;
+use serde_json::json;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
 use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::mpsc;
 use tokio_timerfd::Interval;
 
+/// How to poll the probe.
+pub struct PollingOptions {
+    pub period: Duration,
+    /// Number of polls sent at once to the writer task.
+    pub batch_size: usize,
+    /// Stops the polling after this duration, `None` to poll until Ctrl-C.
+    pub max_duration: Option<Duration>,
+    /// Polls on a dedicated thread with a real-time priority, see [`crate::realtime`].
+    pub realtime: bool,
+    /// Reduces the period when the counters overflow, see [`AdaptivePeriod`].
+    pub adaptive: bool,
+    /// What to do when the writer task cannot keep up with the polling.
+    pub overflow_policy: OverflowPolicy,
+    /// Number of polls that the writer task discards at the beginning, before writing and monitoring the measurements.
+    /// This includes the first poll, which has no value.
+    pub warmup_samples: usize,
+    /// Stops the polling after this number of samples (after the warm-up), see [`SampleLimit`].
+    pub samples: Option<usize>,
+}
+
+/// Counts the samples produced by the polling loop, to stop it after a fixed number of samples.
+///
+/// A sample is a poll that gives at least one energy value: the first poll (which has no value) does not count.
+/// The warm-up polls, which the writer task discards, do not count either.
+pub(crate) struct SampleLimit {
+    /// Number of polls to ignore, for the warm-up.
+    to_skip: usize,
+    remaining: usize,
+}
+
+impl SampleLimit {
+    pub fn new(samples: usize, warmup_polls: usize) -> SampleLimit {
+        SampleLimit {
+            to_skip: warmup_polls,
+            remaining: samples,
+        }
+    }
+
+    /// Counts a poll, and returns `true` when it is the last sample.
+    /// `has_value` is `true` if the poll has given at least one energy value.
+    pub fn count(&mut self, has_value: bool) -> bool {
+        if self.to_skip > 0 {
+            self.to_skip -= 1;
+        } else if has_value {
+            self.remaining = self.remaining.saturating_sub(1);
+        }
+        self.remaining == 0
+    }
+}
+
+/// Returns `true` if `limit` is set and `has_value` completes it.
+pub(crate) fn is_last_sample(limit: &mut Option<SampleLimit>, has_value: bool) -> bool {
+    limit.as_mut().is_some_and(|l| l.count(has_value))
+}
+
+/// What the writer task computes from the measurements, besides the output.
+#[derive(Default)]
+pub struct Monitors {
+    /// Prints a one-line power summary on stderr periodically.
+    pub heartbeat: Option<Heartbeat>,
+    /// Prints the statistics of each domain on stderr at the end.
+    pub stats: Option<EnergyStats>,
+    /// Warns when the power of a package exceeds a threshold.
+    pub power_alert: Option<PowerAlert>,
+    /// Prints the correlation between the frequency and the power of each socket on stderr at the end.
+    pub frequency_variance: Option<FrequencyVariance>,
+}
+
+/// The probe to poll.
+pub enum PolledProbe {
+    /// A probe that is polled periodically.
+    Periodic(Box<dyn EnergyProbe>),
+    /// The eBPF probe, whose program is triggered periodically by the kernel: its events are awaited.
+    #[cfg(feature = "enable_ebpf")]
+    Ebpf(rapl_probes::ebpf::AsyncEbpfProbe),
+    /// Several probes that are polled together periodically: each poll gives one message per probe, tagged with its label.
+    Multi(MultiProbe),
+}
+
 pub async fn run(
-    mut writer: Box<dyn Write + Send>,
-    mut probe: Box<dyn EnergyProbe>,
-    polling_period: Duration,
+    mut output: Box<dyn MeasurementsOutput>,
+    probe: PolledProbe,
+    downsampler: Downsampler,
+    polling: PollingOptions,
     measurement_flush_interval: Duration,
+    mut monitors: Monitors,
 ) -> anyhow::Result<()> {
     // open a Channel to write to the output in another thread
-    let (tx, mut rx) = mpsc::channel::<MeasurementsMessage>(4096);
+    let (tx, rx) = mpsc::channel::<Vec<MeasurementsMessage>>(CHANNEL_CAPACITY);
+    let tx = MeasurementsSender::new(tx, polling.overflow_policy);
+
+    if polling.warmup_samples > 0 {
+        log::info!("Discarding the first {} polls (warm-up)", polling.warmup_samples);
+    }
 
     // Start the writer task, which will receive the data from the channel and write
     // it to the selected output.
+    let messages_per_poll = match &probe {
+        PolledProbe::Multi(p) => p.len(),
+        _ => 1,
+    };
+    let warmup_messages = polling.warmup_samples * messages_per_poll;
     let handle = tokio::spawn(async move {
-        let mut previous_timestamp: SystemTime = SystemTime::now();
+        write_measurements(rx, output.as_mut(), downsampler, measurement_flush_interval, &mut monitors, warmup_messages).await?;
+        if let Some(stats) = monitors.stats {
+            eprint!("{}", stats.summary());
+        }
+        if let Some(variance) = monitors.frequency_variance {
+            for line in variance.report() {
+                eprintln!("{line}");
+            }
+        }
+        anyhow::Ok(())
+    });
+
+    // Start the polling task, which will poll the RAPL counters at regular intervals
+    // and send the data to the writer task, through the channel.
+    // It stops on Ctrl-C, after `max_duration` or after the requested number of samples, and closes the channel.
+    let shutdown = shutdown_signal(polling.max_duration);
+    let limit = polling.samples.map(|n| SampleLimit::new(n, polling.warmup_samples));
+    match probe {
+        PolledProbe::Periodic(probe) if polling.realtime => {
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = realtime::spawn_poll_thread(probe, polling.period, polling.batch_size, limit, tx, stop.clone())?;
+            // the thread stops by itself after the last sample
+            let mut join = tokio::task::spawn_blocking(move || thread.join());
+            let joined = tokio::select! {
+                res = &mut join => res,
+                _ = shutdown => {
+                    stop.store(true, Ordering::Relaxed);
+                    join.await
+                }
+            };
+            joined?.expect("polling thread panicked").expect("probe error");
+        }
+        PolledProbe::Periodic(mut probe) => {
+            let adaptive = polling
+                .adaptive
+                .then(|| AdaptivePeriod::new(polling.period, MIN_ADAPTIVE_PERIOD, RELAX_WINDOW));
+            poll_energy_probe(probe.as_mut(), polling.period, adaptive, polling.batch_size, limit, tx, shutdown)
+                .await
+                .expect("probe error");
+        }
+        PolledProbe::Multi(mut probe) => {
+            poll_multi_probe(&mut probe, polling.period, polling.batch_size, limit, tx, shutdown)
+                .await
+                .expect("probe error");
+        }
+        #[cfg(feature = "enable_ebpf")]
+        PolledProbe::Ebpf(mut probe) => {
+            if polling.realtime {
+                log::warn!("--realtime has no effect with the eBPF probe, which is driven by the kernel");
+            }
+            if polling.adaptive {
+                log::warn!("--adaptive has no effect with the eBPF probe, whose frequency is set when it is loaded");
+            }
+            poll_async_energy_probe(&mut probe, polling.batch_size, limit, tx, shutdown)
+                .await
+                .expect("probe error");
+        }
+    }
+
+    handle.await?.expect("writer task error");
+
+    Ok(())
+}
 
-        // write the csv header
-        writer.write("timestamp_ms;socket;domain;overflow;joules\n".as_bytes())?;
-        while let Some(msg) = rx.recv().await {
-            print_measurements(&mut writer, &msg)?;
+/// The loop of the writer task: receives the measurements until the channel is closed,
+/// updates the monitors and writes the (downsampled) measurements to the output.
+///
+/// The first `warmup_messages` messages are discarded: they are neither monitored nor written.
+async fn write_measurements(
+    mut rx: mpsc::Receiver<Vec<MeasurementsMessage>>,
+    output: &mut dyn MeasurementsOutput,
+    mut downsampler: Downsampler,
+    measurement_flush_interval: Duration,
+    monitors: &mut Monitors,
+    warmup_messages: usize,
+) -> anyhow::Result<()> {
+    let mut previous_timestamp: SystemTime = SystemTime::now();
+    let mut to_discard = warmup_messages;
+    let mut baseline = CumulativeBaseline::default();
+
+    while let Some(batch) = rx.recv().await {
+        for mut msg in batch {
+            if to_discard > 0 {
+                to_discard -= 1;
+                continue;
+            }
+            baseline.apply(&mut msg);
+            // the summary is written to stderr, so that it does not mix with the measurements
+            if let Some(lines) = monitors.heartbeat.as_mut().and_then(|h| h.push(&msg)) {
+                for line in lines {
+                    eprintln!("{line}");
+                }
+            }
+            if let Some(stats) = monitors.stats.as_mut() {
+                stats.record(&msg.measurements);
+            }
+            if let Some(alert) = monitors.power_alert.as_mut() {
+                alert.warn(&msg);
+            }
+            if let Some(variance) = monitors.frequency_variance.as_mut() {
+                variance.record(&msg);
+            }
+            let Some(msg) = downsampler.push(msg) else {
+                continue;
+            };
+            output.write(&msg)?;
 
             let time_since_last_flush = msg
                 .timestamp
@@ -33,71 +245,768 @@ pub async fn run(
 
             if time_since_last_flush >= measurement_flush_interval {
                 previous_timestamp = msg.timestamp;
-                writer.flush()?;
+                output.flush()?;
             }
         }
+    }
 
-        anyhow::Ok(())
-    });
+    // the channel has been closed by the polling task, write the remaining measurements
+    output.flush()?;
+    Ok(())
+}
 
-    // Start the polling task, which will poll the RAPL counters at regular intervals
-    // and send the data to the writer task, through the channel.
-    poll_energy_probe(probe.as_mut(), polling_period, tx)
-        .await
-        .expect("probe error");
+/// Makes the cumulative energy of the written messages start at the first written message.
+///
+/// The cumulative energy of the counters starts at the first poll: it includes the energy of the warm-up polls,
+/// which are discarded by the writer task, and of the measurements dropped by the polling task.
+/// This energy is subtracted, so that the cumulative energy of each counter is the running sum of its written energy.
+#[derive(Default)]
+pub(crate) struct CumulativeBaseline {
+    /// The baseline of each (source, socket, domain).
+    counters: HashMap<(Option<Arc<str>>, usize, RaplDomainType), Baseline>,
+}
 
-    handle.await?.expect("writer task error");
+struct Baseline {
+    /// The energy that has not been written, in Joules.
+    not_written: f64,
+    /// The cumulative energy of the counter in the previous written message.
+    previous_cumulative: f64,
+}
 
-    Ok(())
+impl CumulativeBaseline {
+    /// Subtracts the baseline from the cumulative energy of the counters of `msg`, which is about to be written.
+    pub fn apply(&mut self, msg: &mut MeasurementsMessage) {
+        for (socket, domains) in msg.measurements.per_socket.iter_mut().enumerate() {
+            for (domain, counter) in domains.iter_mut() {
+                let Some(joules) = counter.joules else {
+                    continue;
+                };
+                let cumulative = counter.cumulative_joules;
+                let baseline = match self.counters.entry((msg.source.clone(), socket, domain)) {
+                    Entry::Vacant(e) => e.insert(Baseline {
+                        not_written: cumulative - joules,
+                        previous_cumulative: cumulative,
+                    }),
+                    Entry::Occupied(e) => {
+                        // the energy between the two messages that is not in `joules` has been dropped
+                        let baseline = e.into_mut();
+                        baseline.not_written += cumulative - baseline.previous_cumulative - joules;
+                        baseline.previous_cumulative = cumulative;
+                        baseline
+                    }
+                };
+                counter.cumulative_joules = cumulative - baseline.not_written;
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct MeasurementsMessage {
     pub timestamp: SystemTime,
     pub measurements: EnergyMeasurements,
+    /// The label of the probe, when several probes are polled together (see [`MultiProbe`]).
+    pub source: Option<Arc<str>>,
+}
+
+/// Groups several messages, to send them through the channel at once.
+/// At high frequencies, this reduces the overhead of the channel.
+pub(crate) struct MessageBatch {
+    size: usize,
+    messages: Vec<MeasurementsMessage>,
+}
+
+impl MessageBatch {
+    pub fn new(size: usize) -> MessageBatch {
+        let size = size.max(1);
+        MessageBatch {
+            size,
+            messages: Vec::with_capacity(size),
+        }
+    }
+
+    /// Adds a message to the batch, and returns the whole batch if it is full.
+    pub fn push(&mut self, msg: MeasurementsMessage) -> Option<Vec<MeasurementsMessage>> {
+        self.messages.push(msg);
+        if self.messages.len() >= self.size {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the messages of the batch, even if it is not full, and empties it.
+    pub fn take(&mut self) -> Vec<MeasurementsMessage> {
+        std::mem::replace(&mut self.messages, Vec::with_capacity(self.size))
+    }
+}
+
+/// Completes when the user presses Ctrl-C, or after `max_duration` if it is set.
+pub(crate) async fn shutdown_signal(max_duration: Option<Duration>) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::warn!("Failed to listen for Ctrl-C, the measurement cannot be interrupted cleanly: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    match max_duration {
+        Some(d) => {
+            tokio::select! {
+                _ = ctrl_c => (),
+                _ = tokio::time::sleep(d) => (),
+            }
+        }
+        None => ctrl_c.await,
+    }
 }
 
+/// Awaits the new values of the probe until `shutdown` completes or the last sample of `limit` is produced,
+/// then sends the incomplete batch and closes the channel.
+///
+/// Unlike [`poll_energy_probe`], there is no timer: the probe determines the frequency.
+#[cfg(any(feature = "enable_ebpf", test))]
+async fn poll_async_energy_probe(
+    probe: &mut impl rapl_probes::async_probe::AsyncEnergyProbe,
+    batch_size: usize,
+    mut limit: Option<SampleLimit>,
+    mut tx: MeasurementsSender,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let mut batch = MessageBatch::new(batch_size);
+    let mut rate = PollRate::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            res = probe.poll() => res.context("refreshing measurements")?,
+        }
+        let m = probe.measurements();
+        let msg = MeasurementsMessage {
+            timestamp: m.timestamp().unwrap_or_else(SystemTime::now),
+            measurements: m.clone(),
+            source: None,
+        };
+        rate.record(msg.timestamp);
+        let last = is_last_sample(&mut limit, m.iter_measured().next().is_some());
+        if let Some(full_batch) = batch.push(msg) {
+            tx.send(full_batch).await;
+        }
+        if last {
+            break;
+        }
+    }
+
+    let remaining = batch.take();
+    if !remaining.is_empty() {
+        tx.send(remaining).await;
+    }
+    tx.finish().await;
+    rate.log_report(None);
+    Ok(())
+}
+
+/// Polls the probe until `shutdown` completes or the last sample of `limit` is produced,
+/// then sends the incomplete batch and closes the channel.
+/// If `adaptive` is set, it changes the period according to the measurements.
 async fn poll_energy_probe(
     probe: &mut dyn EnergyProbe,
-    period: Duration,
-    tx: Sender<MeasurementsMessage>,
+    mut period: Duration,
+    mut adaptive: Option<AdaptivePeriod>,
+    batch_size: usize,
+    mut limit: Option<SampleLimit>,
+    mut tx: MeasurementsSender,
+    shutdown: impl Future<Output = ()>,
 ) -> anyhow::Result<()> {
     // Underneath, this uses a periodic timer from timerfd, which has a higher resolution than std::time::sleep and tokio::time::sleep
     // Also, using an interval is better than using a `Delay` by hand
     // (for 1000Hz, we get close to 999Hz with the Interval but only around 860Hz with the Delay).
     let mut interval = Interval::new_interval(period)?;
+    let mut batch = MessageBatch::new(batch_size);
+    // with the adaptive period, the achieved frequency is compared to the initial one
+    let requested = period;
+    let mut rate = PollRate::new();
+    tokio::pin!(shutdown);
 
     loop {
-        // wait for the next tick of the periodic timer
-        interval.next().await;
+        // wait for the next tick of the periodic timer, unless we must stop
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            _ = interval.next() => (),
+        }
 
         // poll the new values from the probe
         probe.poll().context("refreshing measurements")?;
         let m = probe.measurements();
 
         // // send the values to the writer task through the channel
-        let timestamp = SystemTime::now();
+        // use the time at which the probe has read the counters, not the time at which poll() returned
+        let timestamp = m.timestamp().unwrap_or_else(SystemTime::now);
         let measurements = m.clone();
+        rate.record(timestamp);
+        let last = is_last_sample(&mut limit, m.iter_measured().next().is_some());
+
+        if let Some(adaptive) = &mut adaptive {
+            let next_period = adaptive.update(m);
+            if next_period != period {
+                period = next_period;
+                interval = Interval::new_interval(period)?;
+            }
+        }
 
-        tx.send(MeasurementsMessage {
+        let msg = MeasurementsMessage {
             timestamp,
             measurements,
-        })
-        .await
-        .expect("failed to send measurement through channel");
+            source: None,
+        };
+        if let Some(full_batch) = batch.push(msg) {
+            tx.send(full_batch).await;
+        }
+        if last {
+            break;
+        }
+    }
+
+    let remaining = batch.take();
+    if !remaining.is_empty() {
+        tx.send(remaining).await;
+    }
+    tx.finish().await;
+    // dropping tx (in finish) closes the channel, which stops the writer task
+    rate.log_report(Some(requested));
+    Ok(())
+}
+
+/// Like [`poll_energy_probe`], but sends the measurements of each probe of `probe`, with its label as the source.
+/// The messages of a poll are consecutive in the channel.
+async fn poll_multi_probe(
+    probe: &mut MultiProbe,
+    period: Duration,
+    batch_size: usize,
+    mut limit: Option<SampleLimit>,
+    mut tx: MeasurementsSender,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let labels: Vec<Arc<str>> = probe.sources().map(|(label, _)| Arc::from(label)).collect();
+    let mut interval = Interval::new_interval(period)?;
+    let mut batch = MessageBatch::new(batch_size);
+    let mut rate = PollRate::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            _ = interval.next() => (),
+        }
+
+        probe.poll().context("refreshing measurements")?;
+        rate.record(probe.measurements().timestamp().unwrap_or_else(SystemTime::now));
+        for ((_, m), label) in probe.sources().zip(&labels) {
+            let msg = MeasurementsMessage {
+                timestamp: m.timestamp().unwrap_or_else(SystemTime::now),
+                measurements: m.clone(),
+                source: Some(label.clone()),
+            };
+            if let Some(full_batch) = batch.push(msg) {
+                tx.send(full_batch).await;
+            }
+        }
+        // a sample is a poll of all the probes
+        let has_value = probe.sources().any(|(_, m)| m.iter_measured().next().is_some());
+        if is_last_sample(&mut limit, has_value) {
+            break;
+        }
+    }
+
+    let remaining = batch.take();
+    if !remaining.is_empty() {
+        tx.send(remaining).await;
     }
+    tx.finish().await;
+    rate.log_report(Some(period));
+    Ok(())
+}
+
+/// Computes the energy of the synthetic `total` row, or `None` if no domain of the total has been measured.
+///
+/// The total is the sum of the `Package` and `Dram` domains of all the sockets (`PP0` and `PP1` are part of the package).
+/// `Platform` overlaps the package, so it is only added if `include_platform` is `true`.
+pub(crate) fn total_joules(measurements: &EnergyMeasurements, include_platform: bool) -> Option<f64> {
+    let mut domains = vec![RaplDomainType::Package, RaplDomainType::Dram];
+    if include_platform {
+        domains.push(RaplDomainType::Platform);
+    }
+    domains
+        .into_iter()
+        .filter_map(|d| measurements.total_for_domain(d))
+        .reduce(|a, b| a + b)
+}
+
+/// Optional columns of the CSV rows, written after the `cumulative` one, in this order.
+#[derive(Default, Clone, Copy)]
+pub(crate) struct ExtraColumns<'a> {
+    /// The average frequency of each socket (in MHz), written in a column which is empty for the sockets without cpufreq.
+    pub frequencies: Option<&'a [Option<f64>]>,
+    /// The number of IO operations, written with the energy per operation in two columns.
+    pub io_ops: Option<u64>,
+    /// The smoothed power, written with the raw power in two columns (`watts` and `watts_smoothed`).
+    /// The filter must have been updated with the measurements.
+    pub smoothing: Option<&'a EwmaFilter>,
 }
 
-pub(crate) fn print_measurements(writer: &mut dyn Write, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+/// Writes the measurements as CSV lines.
+/// If `cumulative` is `true`, the running total of each (socket, domain), [`rapl_probes::EnergyCounter::cumulative_joules`],
+/// is written in an additional column.
+/// The other optional columns are given by `extra`.
+/// `suffix` is appended to each row, it contains the last columns (label, gap).
+/// The rows of each socket are written in the order of `domain_order`, the domains that are not in this list are skipped.
+pub(crate) fn print_measurements(
+    out: &mut CsvWriter<impl Write>,
+    msg: &MeasurementsMessage,
+    cumulative: bool,
+    extra: &ExtraColumns,
+    suffix: &[String],
+    domain_order: &[RaplDomainType],
+) -> anyhow::Result<()> {
     let timestamp_ms = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_millis();
 
     for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
-        for (domain, counter) in domains_of_socket {
+        for &domain in domain_order {
+            let counter = &domains_of_socket[domain];
             if let Some(consumed) = counter.joules {
                 let overflow = counter.overflowed;
-                writeln!(writer, "{timestamp_ms};{socket_id};{domain:?};{overflow};{consumed}")?;
+                let mut row = vec![
+                    timestamp_ms.to_string(),
+                    socket_id.to_string(),
+                    format!("{domain:?}"),
+                    overflow.to_string(),
+                    consumed.to_string(),
+                ];
+                if cumulative {
+                    row.push(counter.cumulative_joules.to_string());
+                }
+                if let Some(frequencies) = extra.frequencies {
+                    match frequencies.get(socket_id).copied().flatten() {
+                        Some(mhz) => row.push(format!("{mhz:.0}")),
+                        None => row.push(String::new()),
+                    }
+                }
+                if let Some(ops) = extra.io_ops {
+                    row.extend(io_ops_fields(consumed, ops));
+                }
+                if let Some(filter) = extra.smoothing {
+                    let optional = |watts: Option<f64>| watts.map(|w| w.to_string()).unwrap_or_default();
+                    row.push(optional(counter.watts()));
+                    row.push(optional(filter.smoothed(socket_id as u32, domain)));
+                }
+                row.extend_from_slice(suffix);
+                out.write_row(&row)?;
             }
         }
     }
     Ok(())
 }
+
+/// The values of the `io_ops` and `joules_per_io_op` columns.
+pub(crate) fn io_ops_fields(joules: f64, ops: u64) -> [String; 2] {
+    match joules_per_op(joules, ops) {
+        Some(per_op) => [ops.to_string(), per_op.to_string()],
+        None => [ops.to_string(), String::new()],
+    }
+}
+
+/// Writes the measurements as JSON Lines, one object per (socket, domain).
+pub(crate) fn print_measurements_json(writer: &mut dyn Write, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+    let timestamp_ms = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_millis();
+
+    for (socket_id, domain, counter) in msg.measurements.iter_measured() {
+        let line = json!({
+            "timestamp_ms": timestamp_ms as u64,
+            "socket": socket_id,
+            "domain": domain.canonical_name(),
+            "overflow": counter.overflowed,
+            "joules": counter.joules,
+        });
+        serde_json::to_writer(&mut *writer, &line)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rapl_probes::mock::MockProbe;
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+    use tokio::sync::mpsc;
+
+    use crate::backpressure::MeasurementsSender;
+    use crate::cli::{DomainOrder, DownsampleAgg, OverflowPolicy};
+    use crate::csv_writer::CsvWriter;
+    use crate::downsampling::Downsampler;
+    use crate::output::MeasurementsOutput;
+    use rapl_probes::stats::EnergyStats;
+
+    use rapl_probes::async_probe::BlockingProbe;
+
+    use super::{
+        poll_async_energy_probe, poll_energy_probe, print_measurements, print_measurements_json, total_joules, write_measurements,
+        ExtraColumns, MeasurementsMessage, MessageBatch, Monitors, SampleLimit,
+    };
+
+    #[test]
+    fn test_total_excludes_platform() {
+        let mut m = EnergyMeasurements::new(2);
+        for (socket, domain, joules) in [
+            (0, RaplDomainType::Package, 10),
+            (0, RaplDomainType::PP0, 6),
+            (0, RaplDomainType::Dram, 2),
+            (0, RaplDomainType::Platform, 15),
+            (1, RaplDomainType::Package, 8),
+        ] {
+            m.push(socket, domain, 0, u32::MAX as u64, 1.0);
+            m.push(socket, domain, joules, u32::MAX as u64, 1.0);
+        }
+        assert_eq!(total_joules(&m, false), Some(20.0));
+        assert_eq!(total_joules(&m, true), Some(35.0));
+        assert_eq!(total_joules(&EnergyMeasurements::new(1), false), None);
+    }
+
+    #[test]
+    fn test_domain_order() -> anyhow::Result<()> {
+        let mut measurements = EnergyMeasurements::new(1);
+        for domain in RaplDomainType::ALL {
+            measurements.push(0, domain, 0, u32::MAX as u64, 1.0);
+            measurements.push(0, domain, 1, u32::MAX as u64, 1.0);
+        }
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH,
+            measurements,
+            source: None,
+        };
+        let rows_order = |order: DomainOrder| -> anyhow::Result<Vec<String>> {
+            let mut out: Vec<u8> = Vec::new();
+            print_measurements(&mut CsvWriter::new(&mut out), &msg, false, &ExtraColumns::default(), &[], &order.domains())?;
+            let out = String::from_utf8(out)?;
+            Ok(out.lines().map(|l| l.split(';').nth(2).unwrap().to_owned()).collect())
+        };
+        assert_eq!(rows_order(DomainOrder::Declared)?, ["Package", "PP0", "PP1", "Dram", "Platform"]);
+        assert_eq!(rows_order(DomainOrder::Addr)?, ["Package", "Dram", "PP0", "PP1", "Platform"]);
+        assert_eq!(rows_order(DomainOrder::Name)?, ["Dram", "PP0", "PP1", "Package", "Platform"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_json() -> anyhow::Result<()> {
+        let mut measurements = EnergyMeasurements::new(1);
+        measurements.push(0, RaplDomainType::PP0, 0, u32::MAX as u64, 0.5);
+        measurements.push(0, RaplDomainType::PP0, 3, u32::MAX as u64, 0.5);
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1234),
+            measurements,
+            source: None,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        print_measurements_json(&mut out, &msg)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "{\"domain\":\"core\",\"joules\":1.5,\"overflow\":false,\"socket\":0,\"timestamp_ms\":1234}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_order_and_count() {
+        let mut batch = MessageBatch::new(3);
+        let mut received = Vec::new();
+        let mut n_batches = 0;
+        for i in 0..10 {
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(i),
+                measurements: EnergyMeasurements::new(1),
+                source: None,
+            };
+            if let Some(full) = batch.push(msg) {
+                assert_eq!(full.len(), 3);
+                received.extend(full);
+                n_batches += 1;
+            }
+        }
+        // the last message stays in the incomplete batch
+        assert_eq!(n_batches, 3);
+        let timestamps: Vec<SystemTime> = received.iter().map(|m| m.timestamp).collect();
+        let expected: Vec<SystemTime> = (0..9)
+            .map(|i| SystemTime::UNIX_EPOCH + Duration::from_millis(i))
+            .collect();
+        assert_eq!(timestamps, expected);
+
+        // a batch of size 1 sends every message immediately
+        let mut batch = MessageBatch::new(1);
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH,
+            measurements: EnergyMeasurements::new(1),
+            source: None,
+        };
+        assert_eq!(batch.push(msg).map(|b| b.len()), Some(1));
+    }
+
+    /// Keeps the timestamps of the written messages.
+    #[derive(Default)]
+    struct WrittenTimestamps(Vec<SystemTime>);
+
+    impl MeasurementsOutput for WrittenTimestamps {
+        fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+            self.0.push(msg.timestamp);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup_samples() -> anyhow::Result<()> {
+        // 1 J per poll, except the third poll which is noisy (100 J)
+        let (tx, rx) = mpsc::channel(16);
+        let mut measurements = EnergyMeasurements::new(1);
+        let mut batch = Vec::new();
+        for (i, value) in [0, 1, 101, 102, 103, 104].into_iter().enumerate() {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            batch.push(MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(i as u64),
+                measurements: measurements.clone(),
+                source: None,
+            });
+        }
+        // the warm-up spans several batches
+        let second = batch.split_off(2);
+        tx.send(batch).await?;
+        tx.send(second).await?;
+        drop(tx);
+
+        let mut output = WrittenTimestamps::default();
+        let mut monitors = Monitors {
+            stats: Some(EnergyStats::new()),
+            ..Default::default()
+        };
+        let downsampler = Downsampler::new(1, DownsampleAgg::EnergySum);
+        write_measurements(rx, &mut output, downsampler, Duration::from_secs(1), &mut monitors, 3).await?;
+
+        let written: Vec<u64> = output.0.iter().map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()).collect();
+        assert_eq!(written, [3, 4, 5]);
+        let stats = monitors.stats.unwrap();
+        let package = stats.get(0, RaplDomainType::Package).unwrap();
+        assert_eq!(package.joules, 3.0);
+        assert_eq!(package.samples, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cumulative_column() -> anyhow::Result<()> {
+        let mut measurements = EnergyMeasurements::new(2);
+        let mut out: Vec<u8> = Vec::new();
+        let mut csv = CsvWriter::new(&mut out);
+        for (i, value) in [0, 10, 25, 26, 60].into_iter().enumerate() {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            measurements.push(1, RaplDomainType::Package, value * 2, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(i as u64),
+                measurements: measurements.clone(),
+                source: None,
+            };
+            print_measurements(&mut csv, &msg, true, &ExtraColumns::default(), &[], &RaplDomainType::ALL)?;
+        }
+
+        let out = String::from_utf8(out)?;
+        let mut running_sums = [0.0; 2];
+        let mut n_lines = 0;
+        for line in out.lines() {
+            let fields: Vec<&str> = line.split(';').collect();
+            let socket: usize = fields[1].parse()?;
+            let joules: f64 = fields[4].parse()?;
+            let cumulative: f64 = fields[5].parse()?;
+            running_sums[socket] += joules;
+            assert_eq!(cumulative, running_sums[socket], "wrong cumulative value in {line}");
+            n_lines += 1;
+        }
+        assert_eq!(n_lines, 8);
+        assert_eq!(running_sums, [60.0, 120.0]);
+
+        // with a warm-up, the total starts at the first written measurement
+        let (tx, rx) = mpsc::channel(16);
+        let mut measurements = EnergyMeasurements::new(1);
+        let mut batch = Vec::new();
+        for (i, value) in [0, 10, 25, 26, 60].into_iter().enumerate() {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            batch.push(MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(i as u64),
+                measurements: measurements.clone(),
+                source: None,
+            });
+        }
+        tx.try_send(batch)?;
+        drop(tx);
+
+        let mut output = WrittenCumulative::default();
+        let downsampler = Downsampler::new(1, DownsampleAgg::EnergySum);
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        runtime.block_on(write_measurements(rx, &mut output, downsampler, Duration::from_secs(1), &mut Monitors::default(), 2))?;
+        assert_eq!(output.0, [(15.0, 15.0), (1.0, 16.0), (34.0, 50.0)]);
+        Ok(())
+    }
+
+    /// Keeps the energy and the cumulative energy of the package of socket 0, for each written message.
+    #[derive(Default)]
+    struct WrittenCumulative(Vec<(f64, f64)>);
+
+    impl MeasurementsOutput for WrittenCumulative {
+        fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+            let counter = &msg.measurements.per_socket[0][RaplDomainType::Package];
+            self.0.push((counter.joules.unwrap(), counter.cumulative_joules));
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_frequency_column() -> anyhow::Result<()> {
+        let mut measurements = EnergyMeasurements::new(2);
+        for value in [0, 10] {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            measurements.push(1, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+        }
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH,
+            measurements,
+            source: None,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        // socket 1 has no cpufreq
+        let frequencies = [Some(2450.4), None];
+        let suffix = [String::from("io")];
+        let extra = ExtraColumns {
+            frequencies: Some(&frequencies),
+            ..Default::default()
+        };
+        print_measurements(&mut CsvWriter::new(&mut out), &msg, false, &extra, &suffix, &RaplDomainType::ALL)?;
+        assert_eq!(String::from_utf8(out)?, "0;0;Package;false;10;2450;io\n0;1;Package;false;10;;io\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_io_ops_columns() -> anyhow::Result<()> {
+        let mut measurements = EnergyMeasurements::new(1);
+        for value in [0, 10] {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+        }
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH,
+            measurements,
+            source: None,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        let mut csv = CsvWriter::new(&mut out);
+        let extra = ExtraColumns {
+            io_ops: Some(4),
+            ..Default::default()
+        };
+        print_measurements(&mut csv, &msg, false, &extra, &[], &RaplDomainType::ALL)?;
+        let extra = ExtraColumns {
+            io_ops: Some(0),
+            ..Default::default()
+        };
+        print_measurements(&mut csv, &msg, false, &extra, &[], &RaplDomainType::ALL)?;
+        assert_eq!(String::from_utf8(out)?, "0;0;Package;false;10;4;2.5\n0;0;Package;false;10;0;\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_until_shutdown() -> anyhow::Result<()> {
+        let values: Vec<u64> = (0..100_000).collect();
+        let mut probe = MockProbe::new(1).with_counter(0, RaplDomainType::Package, values, u32::MAX as u64, 1.0);
+        let (tx, mut rx) = mpsc::channel(4096);
+
+        // the batches are larger than the number of polls: the incomplete batch must be sent on shutdown
+        let shutdown = tokio::time::sleep(Duration::from_millis(50));
+        let tx = MeasurementsSender::new(tx, OverflowPolicy::Block);
+        poll_energy_probe(&mut probe, Duration::from_millis(1), None, 1_000_000, None, tx, shutdown).await?;
+
+        let mut received = 0;
+        while let Some(batch) = rx.recv().await {
+            received += batch.len();
+        }
+        // recv() returned None: the channel has been closed
+        assert!(received > 0);
+        assert_eq!(received, probe.poll_count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_samples() -> anyhow::Result<()> {
+        for (samples, warmup, expected_polls) in [(5, 0, 6), (5, 3, 8), (1, 1, 2)] {
+            let values: Vec<u64> = (0..1000).collect();
+            let mut probe = MockProbe::new(1).with_counter(0, RaplDomainType::Package, values, u32::MAX as u64, 1.0);
+            let (tx, mut rx) = mpsc::channel(4096);
+
+            // only the number of samples stops the loop
+            let limit = Some(SampleLimit::new(samples, warmup));
+            let tx = MeasurementsSender::new(tx, OverflowPolicy::Block);
+            poll_energy_probe(&mut probe, Duration::from_millis(1), None, 4, limit, tx, std::future::pending()).await?;
+
+            let mut received = Vec::new();
+            while let Some(batch) = rx.recv().await {
+                received.extend(batch);
+            }
+            assert_eq!(probe.poll_count(), expected_polls);
+            assert_eq!(received.len(), expected_polls);
+            let with_value = received[warmup..]
+                .iter()
+                .filter(|msg| msg.measurements.iter_measured().next().is_some())
+                .count();
+            assert_eq!(with_value, samples);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_limit() {
+        let mut limit = SampleLimit::new(2, 1);
+        // warm-up, then a poll without value (e.g. a stale counter)
+        assert!(!limit.count(true));
+        assert!(!limit.count(false));
+        assert!(!limit.count(true));
+        assert!(limit.count(true));
+    }
+
+    #[tokio::test]
+    async fn test_poll_async_until_shutdown() -> anyhow::Result<()> {
+        let values: Vec<u64> = (0..1_000_000).collect();
+        let mock = MockProbe::new(1).with_counter(0, RaplDomainType::Package, values, u32::MAX as u64, 1.0);
+        let mut probe = BlockingProbe::new(mock);
+        let (tx, mut rx) = mpsc::channel(4096);
+
+        // no timer: the probe is polled as soon as the previous poll completes, until shutdown
+        let shutdown = tokio::time::sleep(Duration::from_millis(20));
+        let tx = MeasurementsSender::new(tx, OverflowPolicy::Block);
+        poll_async_energy_probe(&mut probe, 1_000_000, None, tx, shutdown).await?;
+
+        let mut received = Vec::new();
+        while let Some(batch) = rx.recv().await {
+            received.extend(batch);
+        }
+        assert!(!received.is_empty());
+        // the first poll gives no energy value, the next ones do
+        for msg in &received[1..] {
+            assert_eq!(msg.measurements.per_socket[0][RaplDomainType::Package].joules, Some(1.0));
+        }
+        Ok(())
+    }
+}
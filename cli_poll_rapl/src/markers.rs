@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use log::warn;
+
+/// The beginning of a phase of the measured application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    pub timestamp_ms: u128,
+    pub label: String,
+}
+
+/// Reads the phase markers that the measured application appends to a file, like `tail -f`.
+///
+/// Each line of the file is `<timestamp_ms> <label>`, where `timestamp_ms` is the number of milliseconds
+/// since the Unix epoch at which the phase starts. The lines must be written in chronological order.
+pub struct MarkersFile {
+    path: PathBuf,
+    /// `None` until the file exists.
+    reader: Option<BufReader<File>>,
+    /// Incomplete line, which is being written by the application.
+    partial: String,
+    markers: Vec<Marker>,
+}
+
+impl MarkersFile {
+    pub fn new(path: PathBuf) -> MarkersFile {
+        MarkersFile {
+            path,
+            reader: None,
+            partial: String::new(),
+            markers: Vec::new(),
+        }
+    }
+
+    /// Reads the lines that have been added to the file since the last call.
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        if self.reader.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => self.reader = Some(BufReader::new(file)),
+                // the application has not created the file yet
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let reader = self.reader.as_mut().unwrap();
+        while reader.read_line(&mut self.partial)? > 0 {
+            if !self.partial.ends_with('\n') {
+                // wait for the end of the line
+                break;
+            }
+            match parse_marker(&self.partial) {
+                Some(marker) => self.markers.push(marker),
+                None if self.partial.trim().is_empty() => (),
+                None => warn!("Invalid marker line in {:?}: {}", self.path, self.partial.trim_end()),
+            }
+            self.partial.clear();
+        }
+        Ok(())
+    }
+
+    /// The label of the phase that is active at `timestamp_ms`.
+    pub fn active_label(&self, timestamp_ms: u128) -> Option<&str> {
+        active_label(&self.markers, timestamp_ms)
+    }
+}
+
+/// Parses a line `<timestamp_ms> <label>`.
+fn parse_marker(line: &str) -> Option<Marker> {
+    let (timestamp, label) = line.trim().split_once(char::is_whitespace)?;
+    Some(Marker {
+        timestamp_ms: timestamp.parse().ok()?,
+        label: label.trim().to_owned(),
+    })
+}
+
+/// Returns the label of the last marker that starts before or at `timestamp_ms`.
+/// The markers must be sorted by timestamp.
+pub fn active_label(markers: &[Marker], timestamp_ms: u128) -> Option<&str> {
+    let n_started = markers.partition_point(|m| m.timestamp_ms <= timestamp_ms);
+    n_started.checked_sub(1).map(|i| markers[i].label.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{active_label, parse_marker, Marker, MarkersFile};
+
+    fn marker(timestamp_ms: u128, label: &str) -> Marker {
+        Marker {
+            timestamp_ms,
+            label: label.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_active_label() {
+        let markers = vec![marker(1000, "init"), marker(2000, "compute"), marker(3500, "io")];
+        let labels: Vec<Option<&str>> = [0, 999, 1000, 1500, 2000, 3499, 3500, 10_000]
+            .into_iter()
+            .map(|t| active_label(&markers, t))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                None,
+                None,
+                Some("init"),
+                Some("init"),
+                Some("compute"),
+                Some("compute"),
+                Some("io"),
+                Some("io")
+            ]
+        );
+        assert_eq!(active_label(&[], 1000), None);
+    }
+
+    #[test]
+    fn test_parse_marker() {
+        assert_eq!(parse_marker("1000 warm up\n"), Some(marker(1000, "warm up")));
+        assert_eq!(parse_marker("1000\tio"), Some(marker(1000, "io")));
+        assert_eq!(parse_marker("abc io"), None);
+        assert_eq!(parse_marker("1000"), None);
+    }
+
+    #[test]
+    fn test_tail_markers_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("cli_poll_rapl-markers-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut markers = MarkersFile::new(path.clone());
+        markers.refresh()?; // the file does not exist yet
+
+        let mut file = std::fs::File::create(&path)?;
+        write!(file, "1000 init\n2000 comp")?;
+        markers.refresh()?;
+        assert_eq!(markers.active_label(2500), Some("init"), "incomplete lines must be ignored");
+
+        writeln!(file, "ute")?;
+        markers.refresh()?;
+        assert_eq!(markers.active_label(2500), Some("compute"));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+//! OpenTelemetry output: exports the measurements as metrics to a collector, with OTLP over HTTP.
+//!
+//! Two metrics are exported for each domain of each socket, with the attributes `socket` and `domain`:
+//! - `rapl.energy`, a counter: the energy consumed since the start of the measurement, in Joules;
+//! - `rapl.power`, a gauge: the average power during the last poll, in Watts.
+//!
+//! The metrics are exported periodically by the OpenTelemetry SDK, every 60 seconds by default.
+//! Set `OTEL_METRIC_EXPORT_INTERVAL` (in milliseconds) to change it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use log::info;
+use opentelemetry::metrics::{MeterProvider, ObservableCounter, ObservableGauge};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use rapl_probes::RaplDomainType;
+
+use crate::main_optimized::MeasurementsMessage;
+use crate::output::MeasurementsOutput;
+
+/// The latest values of a (socket, domain).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DomainValues {
+    /// Energy consumed since the start of the measurement, in Joules.
+    cumulative_joules: f64,
+    /// Average power during the last poll, in Watts, `None` if the duration of the poll is unknown.
+    watts: Option<f64>,
+}
+
+type LatestValues = Arc<Mutex<HashMap<(u32, RaplDomainType), DomainValues>>>;
+
+/// Updates the metrics, which are collected and exported by the OpenTelemetry SDK.
+pub struct OtelOutput {
+    /// The latest values, read by the callbacks of the instruments when the SDK collects the metrics.
+    latest: LatestValues,
+    /// Exports the remaining metrics when dropped.
+    _provider: SdkMeterProvider,
+    // the callbacks are registered as long as the instruments exist
+    _energy: ObservableCounter<f64>,
+    _power: ObservableGauge<f64>,
+}
+
+impl OtelOutput {
+    /// Exports the metrics to the OTLP/HTTP endpoint of a collector, e.g. `http://localhost:4318/v1/metrics`.
+    pub fn connect(endpoint: &str) -> anyhow::Result<OtelOutput> {
+        let exporter = opentelemetry_otlp::MetricExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .with_context(|| format!("failed to create the OTLP exporter for {endpoint}"))?;
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter).build())
+            .build();
+        info!("Exporting the OpenTelemetry metrics to {endpoint}");
+        Ok(OtelOutput::with_provider(provider))
+    }
+
+    /// Registers the metrics in `provider`, which exports them with its readers.
+    pub fn with_provider(provider: SdkMeterProvider) -> OtelOutput {
+        let meter = provider.meter("cli_poll_rapl");
+        let latest = LatestValues::default();
+
+        let values = latest.clone();
+        let energy = meter
+            .f64_observable_counter("rapl.energy")
+            .with_description("Energy consumed since the start of the measurement")
+            .with_unit("J")
+            .with_callback(move |observer| {
+                for (&(socket, domain), v) in values.lock().unwrap().iter() {
+                    observer.observe(v.cumulative_joules, &attributes(socket, domain));
+                }
+            })
+            .build();
+
+        let values = latest.clone();
+        let power = meter
+            .f64_observable_gauge("rapl.power")
+            .with_description("Average power during the last poll")
+            .with_unit("W")
+            .with_callback(move |observer| {
+                for (&(socket, domain), v) in values.lock().unwrap().iter() {
+                    if let Some(watts) = v.watts {
+                        observer.observe(watts, &attributes(socket, domain));
+                    }
+                }
+            })
+            .build();
+
+        OtelOutput {
+            latest,
+            _provider: provider,
+            _energy: energy,
+            _power: power,
+        }
+    }
+}
+
+/// The attributes of the data points of a (socket, domain).
+fn attributes(socket: u32, domain: RaplDomainType) -> [KeyValue; 2] {
+    [
+        KeyValue::new("socket", i64::from(socket)),
+        KeyValue::new("domain", domain.canonical_name()),
+    ]
+}
+
+impl MeasurementsOutput for OtelOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        let mut latest = self.latest.lock().unwrap();
+        for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+            let socket = socket_id as u32;
+            for (domain, counter) in domains_of_socket {
+                if let Some(joules) = counter.joules {
+                    let cumulative_joules = counter.cumulative_joules;
+                    let watts = counter.elapsed.filter(|e| !e.is_zero()).map(|e| joules / e.as_secs_f64());
+                    latest.insert((socket, domain), DomainValues { cumulative_joules, watts });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Does nothing: the metrics are exported periodically by the SDK.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+    use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader, SdkMeterProvider};
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::OtelOutput;
+    use crate::main_optimized::MeasurementsMessage;
+    use crate::output::MeasurementsOutput;
+
+    /// The value of each data point of each metric, by (metric name, socket, domain).
+    type DataPoints = HashMap<(String, String, String), f64>;
+
+    fn exported_data_points(exporter: &InMemoryMetricExporter) -> anyhow::Result<DataPoints> {
+        let mut points = DataPoints::new();
+        let exported = exporter.get_finished_metrics()?;
+        let last_export = exported.last().expect("no metrics exported");
+        for metric in last_export.scope_metrics().flat_map(|s| s.metrics()) {
+            let values: Vec<_> = match metric.data() {
+                AggregatedMetrics::F64(MetricData::Sum(sum)) => {
+                    assert!(sum.is_monotonic());
+                    sum.data_points().map(|p| (p.attributes().cloned().collect::<Vec<_>>(), p.value())).collect()
+                }
+                AggregatedMetrics::F64(MetricData::Gauge(gauge)) => {
+                    gauge.data_points().map(|p| (p.attributes().cloned().collect::<Vec<_>>(), p.value())).collect()
+                }
+                other => panic!("unexpected metric data for {}: {other:?}", metric.name()),
+            };
+            for (attributes, value) in values {
+                let attribute = |key: &str| {
+                    let kv = attributes.iter().find(|kv| kv.key.as_str() == key).expect("missing attribute");
+                    kv.value.to_string()
+                };
+                assert_eq!(attributes.len(), 2);
+                points.insert((metric.name().to_owned(), attribute("socket"), attribute("domain")), value);
+            }
+        }
+        Ok(points)
+    }
+
+    #[test]
+    fn test_data_points() -> anyhow::Result<()> {
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone()).build())
+            .build();
+        let mut output = OtelOutput::with_provider(provider.clone());
+
+        let mut m = EnergyMeasurements::new(2);
+        let t0 = Instant::now();
+        for (secs, pkg0, dram0, pkg1) in [(0, 0, 0, 0), (1, 20, 5, 30), (3, 60, 9, 90)] {
+            let t = t0 + Duration::from_secs(secs);
+            m.push_at(0, RaplDomainType::Package, pkg0, u32::MAX as u64, 1.0, t);
+            m.push_at(0, RaplDomainType::Dram, dram0, u32::MAX as u64, 1.0, t);
+            m.push_at(1, RaplDomainType::Package, pkg1, u32::MAX as u64, 1.0, t);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+        provider.force_flush()?;
+
+        let point = |name: &str, socket: &str, domain: &str| (name.to_owned(), socket.to_owned(), domain.to_owned());
+        let expected = DataPoints::from([
+            (point("rapl.energy", "0", "package"), 60.0),
+            (point("rapl.energy", "0", "dram"), 9.0),
+            (point("rapl.energy", "1", "package"), 90.0),
+            (point("rapl.power", "0", "package"), 20.0),
+            (point("rapl.power", "0", "dram"), 2.0),
+            (point("rapl.power", "1", "package"), 30.0),
+        ]);
+        assert_eq!(exported_data_points(&exporter)?, expected);
+        Ok(())
+    }
+}
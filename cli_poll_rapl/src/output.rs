@@ -0,0 +1,631 @@
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use rapl_probes::cpufreq::CpuFreqSampler;
+use rapl_probes::iostats::IoOpsSampler;
+use rapl_probes::replay::{write_raw, RAW_HEADER};
+use rapl_probes::RaplDomainType;
+
+use crate::csv_writer::CsvWriter;
+use crate::main_optimized::{
+    io_ops_fields, print_measurements, print_measurements_json, total_joules, ExtraColumns,
+    MeasurementsMessage,
+};
+use crate::gaps::MissedTicks;
+use crate::markers::MarkersFile;
+use crate::smoothing::EwmaFilter;
+
+/// Destination of the measurements, used by the writer task.
+pub trait MeasurementsOutput: Send {
+    /// Writes (or buffers) the content of a message.
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()>;
+
+    /// Flushes the buffered measurements, if any.
+    fn flush(&mut self) -> anyhow::Result<()>;
+}
+
+/// Writes the measurements as CSV.
+pub struct CsvOutput {
+    writer: CsvWriter<Box<dyn Write + Send>>,
+    /// Name of the column that contains the measured values.
+    value_column: String,
+    /// `true` if the `cumulative_joules` column is enabled.
+    cumulative: bool,
+    /// Set if the `total` rows are enabled, `true` if they include the platform domain.
+    totals: Option<bool>,
+    /// Set if the average frequency of each socket is written in a `freq_mhz` column.
+    frequency: Option<CpuFreqSampler>,
+    /// Set if the IO operations are written in the `io_ops` and `joules_per_io_op` columns.
+    io_ops: Option<IoOpsSampler>,
+    /// Set if the power is written in the `watts` and `watts_smoothed` columns.
+    smoothing: Option<EwmaFilter>,
+    /// Set if the rows are tagged with the phase markers of the application, in a `label` column.
+    markers: Option<MarkersFile>,
+    /// Order of the domains in the rows of each socket.
+    domain_order: Vec<RaplDomainType>,
+    /// Set if the missed ticks are written as gap rows, with a `gap` column.
+    gaps: Option<MissedTicks>,
+    /// Set if the time since the first message is written in an `elapsed_ms` column.
+    elapsed: Option<Elapsed>,
+    /// `true` if the label of the probe is written in a `source` column.
+    source: bool,
+    /// Comment lines written before the header.
+    comments: Vec<String>,
+}
+
+/// Milliseconds since the timestamp of the first message.
+#[derive(Default)]
+struct Elapsed {
+    start: Option<SystemTime>,
+}
+
+impl Elapsed {
+    /// The first call sets the start, even if the message has no value (e.g. the first poll).
+    fn millis_since_start(&mut self, timestamp: SystemTime) -> u128 {
+        let start = *self.start.get_or_insert(timestamp);
+        timestamp.duration_since(start).unwrap_or_default().as_millis()
+    }
+}
+
+impl CsvOutput {
+    /// Creates a new CSV output.
+    ///
+    /// `value_column` is the name of the last column, usually `joules`.
+    pub fn new(writer: Box<dyn Write + Send>, value_column: &str, with_cumulative: bool) -> CsvOutput {
+        CsvOutput {
+            writer: CsvWriter::new(writer),
+            value_column: value_column.to_owned(),
+            cumulative: with_cumulative,
+            totals: None,
+            frequency: None,
+            io_ops: None,
+            smoothing: None,
+            markers: None,
+            domain_order: RaplDomainType::ALL.to_vec(),
+            gaps: None,
+            elapsed: None,
+            source: false,
+            comments: Vec::new(),
+        }
+    }
+
+    /// Separates the columns with `delimiter` instead of `;`.
+    pub fn with_delimiter(mut self, delimiter: char) -> CsvOutput {
+        self.writer = self.writer.with_delimiter(delimiter);
+        self
+    }
+
+    /// Sets the order of the domains in the rows of each socket.
+    pub fn with_domain_order(mut self, domain_order: Vec<RaplDomainType>) -> CsvOutput {
+        self.domain_order = domain_order;
+        self
+    }
+
+    /// Adds a `freq_mhz` column, with the average current frequency of the cpus of the socket.
+    ///
+    /// The frequency is sampled when the rows are written, which can be slightly after the poll.
+    pub fn with_frequency(mut self, sampler: CpuFreqSampler) -> CsvOutput {
+        self.frequency = Some(sampler);
+        self
+    }
+
+    /// Adds the `io_ops` and `joules_per_io_op` columns, with the number of IO operations since the previous row
+    /// and the energy of the row divided by this number (empty if there has been no operation).
+    ///
+    /// Like the frequency, the operations are counted when the rows are written.
+    pub fn with_io_ops(mut self, sampler: IoOpsSampler) -> CsvOutput {
+        self.io_ops = Some(sampler);
+        self
+    }
+
+    /// Adds the `watts` and `watts_smoothed` columns, with the power of the row and its exponentially weighted moving average,
+    /// see [`EwmaFilter`]. The smoothed power of a (socket, domain) restarts when its counter is reset.
+    pub fn with_smoothing(mut self, alpha: f64) -> CsvOutput {
+        self.smoothing = Some(EwmaFilter::new(alpha));
+        self
+    }
+
+    /// Adds a `label` column, with the label of the phase that is active at the time of each row.
+    pub fn with_markers(mut self, markers: MarkersFile) -> CsvOutput {
+        self.markers = Some(markers);
+        self
+    }
+
+    /// Adds a `gap` column, and writes a gap row for each tick of the polling timer that has been missed
+    /// (the time between two polls is at least two periods).
+    ///
+    /// A gap row has the time of the missed tick, `true` in the `gap` column and no other value.
+    pub fn with_gaps(mut self, period: Duration) -> CsvOutput {
+        self.gaps = Some(MissedTicks::new(period));
+        self
+    }
+
+    /// Adds an `elapsed_ms` column, with the number of milliseconds since the first message, for plotting.
+    /// The absolute `timestamp_ms` is kept.
+    pub fn with_elapsed(mut self) -> CsvOutput {
+        self.elapsed = Some(Elapsed::default());
+        self
+    }
+
+    /// Adds a `source` column, with the label of the probe that has measured the row, when several probes are polled together.
+    /// The `total` rows are computed for each source.
+    pub fn with_source(mut self) -> CsvOutput {
+        self.source = true;
+        self
+    }
+
+    /// Enables the synthetic `total` rows, which sum the energy of all the sockets.
+    /// See [`total_joules`] for the domains that are included.
+    pub fn with_totals(mut self, include_platform: bool) -> CsvOutput {
+        self.totals = Some(include_platform);
+        self
+    }
+
+    /// Writes a comment line (`# <comment>`) before the header, for instance to describe the machine.
+    /// The readers of the CSV must skip the lines that start with `#`.
+    pub fn with_comment(mut self, comment: impl Into<String>) -> CsvOutput {
+        self.comments.push(comment.into());
+        self
+    }
+
+    /// Writes the comments and the csv header.
+    pub fn write_header(&mut self) -> anyhow::Result<()> {
+        for comment in &self.comments {
+            self.writer.write_comment(comment)?;
+        }
+        let value_column = &self.value_column;
+        let mut header = vec![
+            String::from("timestamp_ms"),
+            String::from("socket"),
+            String::from("domain"),
+            String::from("overflow"),
+            value_column.clone(),
+        ];
+        if self.cumulative {
+            header.push(format!("cumulative_{value_column}"));
+        }
+        if self.frequency.is_some() {
+            header.push(String::from("freq_mhz"));
+        }
+        if self.io_ops.is_some() {
+            header.extend([String::from("io_ops"), String::from("joules_per_io_op")]);
+        }
+        if self.smoothing.is_some() {
+            header.extend([String::from("watts"), String::from("watts_smoothed")]);
+        }
+        if self.source {
+            header.push(String::from("source"));
+        }
+        if self.elapsed.is_some() {
+            header.push(String::from("elapsed_ms"));
+        }
+        if self.markers.is_some() {
+            header.push(String::from("label"));
+        }
+        if self.gaps.is_some() {
+            header.push(String::from("gap"));
+        }
+        self.writer.write_row(&header)?;
+        Ok(())
+    }
+
+    /// Writes a row without values, for a missed tick.
+    fn write_gap_row(&mut self, timestamp: SystemTime) -> anyhow::Result<()> {
+        let timestamp_ms = timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_millis();
+        // socket, domain, overflow and value
+        let empty_columns = 4
+            + usize::from(self.cumulative)
+            + usize::from(self.frequency.is_some())
+            + 2 * usize::from(self.io_ops.is_some())
+            + 2 * usize::from(self.smoothing.is_some())
+            + usize::from(self.source);
+        let mut row = vec![timestamp_ms.to_string()];
+        row.resize(1 + empty_columns, String::new());
+        if let Some(elapsed) = &mut self.elapsed {
+            row.push(elapsed.millis_since_start(timestamp).to_string());
+        }
+        if self.markers.is_some() {
+            row.push(String::new());
+        }
+        row.push(String::from("true"));
+        self.writer.write_row(&row)?;
+        Ok(())
+    }
+}
+
+impl MeasurementsOutput for CsvOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        if let Some(gaps) = &mut self.gaps {
+            for missed in gaps.check(msg.timestamp) {
+                self.write_gap_row(missed)?;
+            }
+        }
+
+        let timestamp_ms = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_millis();
+        let mut suffix = Vec::new();
+        if self.source {
+            suffix.push(msg.source.as_deref().unwrap_or("").to_owned());
+        }
+        if let Some(elapsed) = &mut self.elapsed {
+            suffix.push(elapsed.millis_since_start(msg.timestamp).to_string());
+        }
+        if let Some(markers) = &mut self.markers {
+            markers.refresh()?;
+            suffix.push(markers.active_label(timestamp_ms).unwrap_or("").to_owned());
+        }
+        if self.gaps.is_some() {
+            suffix.push(String::from("false"));
+        }
+        let frequencies = self.frequency.as_ref().map(CpuFreqSampler::sample);
+        let io_ops = self.io_ops.as_mut().map(IoOpsSampler::sample).transpose()?;
+        if let Some(filter) = &mut self.smoothing {
+            filter.update(&msg.measurements);
+        }
+        let extra = ExtraColumns {
+            frequencies: frequencies.as_deref(),
+            io_ops,
+            smoothing: self.smoothing.as_ref(),
+        };
+        print_measurements(
+            &mut self.writer,
+            msg,
+            self.cumulative,
+            &extra,
+            &suffix,
+            &self.domain_order,
+        )?;
+        if let Some(include_platform) = self.totals {
+            if let Some(total) = total_joules(&msg.measurements, include_platform) {
+                let overflow = msg.measurements.per_socket.iter().any(|s| s.values().any(|c| c.overflowed));
+                let mut row = vec![
+                    timestamp_ms.to_string(),
+                    String::from("all"),
+                    String::from("total"),
+                    overflow.to_string(),
+                    total.to_string(),
+                ];
+                if self.cumulative {
+                    row.push(String::new());
+                }
+                if self.frequency.is_some() {
+                    row.push(String::new());
+                }
+                if let Some(ops) = io_ops {
+                    row.extend(io_ops_fields(total, ops));
+                }
+                if self.smoothing.is_some() {
+                    row.extend([String::new(), String::new()]);
+                }
+                row.extend(suffix);
+                self.writer.write_row(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the measurements as JSON Lines, without header.
+pub struct JsonOutput {
+    writer: Box<dyn Write + Send>,
+}
+
+impl JsonOutput {
+    pub fn new(writer: Box<dyn Write + Send>) -> JsonOutput {
+        JsonOutput { writer }
+    }
+}
+
+impl MeasurementsOutput for JsonOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        print_measurements_json(&mut self.writer, msg)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the raw values of the counters, to replay them with [`rapl_probes::replay::ReplayProbe`].
+pub struct RawOutput {
+    writer: Box<dyn Write + Send>,
+}
+
+impl RawOutput {
+    pub fn new(writer: Box<dyn Write + Send>) -> RawOutput {
+        RawOutput { writer }
+    }
+
+    /// Writes the header of the raw format.
+    pub fn write_header(&mut self) -> anyhow::Result<()> {
+        writeln!(self.writer, "{RAW_HEADER}")?;
+        Ok(())
+    }
+}
+
+impl MeasurementsOutput for RawOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        write_raw(&mut self.writer, msg.timestamp, &msg.measurements)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::{CsvOutput, MeasurementsOutput, RawOutput};
+    use crate::main_optimized::MeasurementsMessage;
+    use crate::markers::MarkersFile;
+
+    /// A writer whose content can be read after the output has been dropped.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_gap_rows() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = CsvOutput::new(Box::new(buffer.clone()), "joules", true).with_gaps(Duration::from_millis(100));
+        output.write_header()?;
+
+        // the poll of 1200 ms has been skipped
+        let mut m = EnergyMeasurements::new(1);
+        for (millis, value) in [(1000, 0), (1100, 10), (1300, 30)] {
+            m.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            csv,
+            "timestamp_ms;socket;domain;overflow;joules;cumulative_joules;gap\n\
+             1100;0;Package;false;10;10;false\n\
+             1200;;;;;;true\n\
+             1300;0;Package;false;20;30;false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_delimiter() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let markers_path = std::env::temp_dir().join(format!("cli_poll_rapl-test-delimiter-{}.markers", std::process::id()));
+        std::fs::write(&markers_path, "1000 phase 1, warmup\n")?;
+        let mut output = CsvOutput::new(Box::new(buffer.clone()), "joules", true)
+            .with_delimiter(',')
+            .with_totals(false)
+            .with_markers(MarkersFile::new(markers_path.clone()));
+        output.write_header()?;
+
+        let mut m = EnergyMeasurements::new(1);
+        for (millis, value) in [(1000, 0), (1100, 10)] {
+            m.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+        std::fs::remove_file(&markers_path)?;
+
+        // the label contains the delimiter, thus it is quoted
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            csv,
+            "timestamp_ms,socket,domain,overflow,joules,cumulative_joules,label\n\
+             1100,0,Package,false,10,10,\"phase 1, warmup\"\n\
+             1100,all,total,false,10,,\"phase 1, warmup\"\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_source() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = CsvOutput::new(Box::new(buffer.clone()), "joules", false)
+            .with_source()
+            .with_totals(false)
+            .with_gaps(Duration::from_millis(100));
+        output.write_header()?;
+
+        // two probes polled together, which do not measure the same energy; the poll of 1200 ms has been skipped
+        let mut powercap = EnergyMeasurements::new(1);
+        let mut perf_event = EnergyMeasurements::new(1);
+        let powercap_label: Arc<str> = Arc::from("powercap-sysfs");
+        let perf_event_label: Arc<str> = Arc::from("perf-event");
+        for (millis, value) in [(1000, 0), (1100, 10), (1300, 30)] {
+            powercap.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            perf_event.push(0, RaplDomainType::Package, value * 2, u32::MAX as u64, 1.0);
+            for (m, label) in [(&powercap, &powercap_label), (&perf_event, &perf_event_label)] {
+                let msg = MeasurementsMessage {
+                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+                    measurements: m.clone(),
+                    source: Some(label.clone()),
+                };
+                output.write(&msg)?;
+            }
+        }
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            csv,
+            "timestamp_ms;socket;domain;overflow;joules;source;gap\n\
+             1100;0;Package;false;10;powercap-sysfs;false\n\
+             1100;all;total;false;10;powercap-sysfs;false\n\
+             1100;0;Package;false;20;perf-event;false\n\
+             1100;all;total;false;20;perf-event;false\n\
+             1200;;;;;;true\n\
+             1300;0;Package;false;20;powercap-sysfs;false\n\
+             1300;all;total;false;20;powercap-sysfs;false\n\
+             1300;0;Package;false;40;perf-event;false\n\
+             1300;all;total;false;40;perf-event;false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_smoothing() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = CsvOutput::new(Box::new(buffer.clone()), "joules", false)
+            .with_smoothing(0.5)
+            .with_totals(false)
+            .with_gaps(Duration::from_secs(1));
+        output.write_header()?;
+
+        // 10 W, then 30 W; the poll of 3000 ms has been skipped
+        let start = Instant::now();
+        let mut m = EnergyMeasurements::new(1);
+        for (secs, value) in [(1, 0), (2, 10), (4, 70)] {
+            m.push_at(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0, start + Duration::from_secs(secs));
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            csv,
+            "timestamp_ms;socket;domain;overflow;joules;watts;watts_smoothed;gap\n\
+             2000;0;Package;false;10;10;10;false\n\
+             2000;all;total;false;10;;;false\n\
+             3000;;;;;;;true\n\
+             4000;0;Package;false;60;30;20;false\n\
+             4000;all;total;false;60;;;false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = RawOutput::new(Box::new(buffer.clone()));
+        output.write_header()?;
+
+        // the first poll is written too, and the wrap is not corrected
+        let mut m = EnergyMeasurements::new(1);
+        for (millis, value) in [(1000, 998), (1100, 3)] {
+            m.push(0, RaplDomainType::Package, value, 999, 0.25);
+            m.push(0, RaplDomainType::Dram, value / 2, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+
+        let raw = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            raw,
+            "timestamp_us;socket;domain;raw_counter;max_value;energy_unit\n\
+             1000000;0;package;998;999;0.25\n\
+             1000000;0;dram;499;4294967295;1\n\
+             1100000;0;package;3;999;0.25\n\
+             1100000;0;dram;1;4294967295;1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_comment() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = CsvOutput::new(Box::new(buffer.clone()), "joules", false)
+            .with_comment("cpu: GenuineIntel family 6 model 0x3f stepping 2 (Haswell)")
+            .with_comment("two\nlines");
+        output.write_header()?;
+
+        let mut m = EnergyMeasurements::new(1);
+        for value in [0, 10] {
+            m.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1000),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            csv,
+            "# cpu: GenuineIntel family 6 model 0x3f stepping 2 (Haswell)\n\
+             # two lines\n\
+             timestamp_ms;socket;domain;overflow;joules\n\
+             1000;0;Package;false;10\n"
+        );
+        // the comments are skipped when the file is read back
+        let snapshots: Vec<_> = rapl_probes::io::parse_csv(csv.as_bytes()).collect::<anyhow::Result<_>>()?;
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].1.per_socket[0][RaplDomainType::Package].joules, Some(10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_elapsed() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let mut output = CsvOutput::new(Box::new(buffer.clone()), "joules", false)
+            .with_elapsed()
+            .with_totals(false)
+            .with_gaps(Duration::from_millis(100));
+        output.write_header()?;
+
+        // the first poll has no value, but it is the start of the measurement; the polls at +200 and +400 ms are missed
+        let mut m = EnergyMeasurements::new(1);
+        for (millis, value) in [(1_700_000_000_000, 0), (1_700_000_000_100, 10), (1_700_000_000_300, 30), (1_700_000_000_500, 35)] {
+            m.push(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            csv,
+            "timestamp_ms;socket;domain;overflow;joules;elapsed_ms;gap\n\
+             1700000000100;0;Package;false;10;100;false\n\
+             1700000000100;all;total;false;10;100;false\n\
+             1700000000200;;;;;200;true\n\
+             1700000000300;0;Package;false;20;300;false\n\
+             1700000000300;all;total;false;20;300;false\n\
+             1700000000400;;;;;400;true\n\
+             1700000000500;0;Package;false;5;500;false\n\
+             1700000000500;all;total;false;5;500;false\n"
+        );
+        Ok(())
+    }
+}
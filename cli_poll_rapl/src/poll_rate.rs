@@ -0,0 +1,127 @@
+//! Measurement of the polling frequency that is actually achieved, which is lower than the requested one
+//! when the timer is late or when a poll takes too long.
+
+use std::time::{Duration, SystemTime};
+
+/// Statistics of the intervals between the polls, fed with the timestamp of each poll.
+///
+/// The moments are updated with Welford's algorithm, thus the intervals are not stored.
+#[derive(Debug, Default)]
+pub struct PollRate {
+    previous: Option<SystemTime>,
+    intervals: usize,
+    mean_secs: f64,
+    m2: f64,
+    max: Duration,
+}
+
+impl PollRate {
+    pub fn new() -> PollRate {
+        PollRate::default()
+    }
+
+    /// Records the timestamp of a poll. The timestamps that go backwards (the clock has been changed) are ignored.
+    pub fn record(&mut self, timestamp: SystemTime) {
+        let previous = self.previous.replace(timestamp);
+        if let Some(interval) = previous.and_then(|p| timestamp.duration_since(p).ok()) {
+            self.push_interval(interval);
+        }
+    }
+
+    fn push_interval(&mut self, interval: Duration) {
+        let secs = interval.as_secs_f64();
+        self.intervals += 1;
+        let delta = secs - self.mean_secs;
+        self.mean_secs += delta / self.intervals as f64;
+        self.m2 += delta * (secs - self.mean_secs);
+        self.max = self.max.max(interval);
+    }
+
+    /// The number of intervals, i.e. the number of polls minus one.
+    pub fn intervals(&self) -> usize {
+        self.intervals
+    }
+
+    /// The mean interval between two polls, `None` before the second poll.
+    pub fn mean_interval(&self) -> Option<Duration> {
+        (self.intervals > 0).then(|| Duration::from_secs_f64(self.mean_secs))
+    }
+
+    /// The sample standard deviation of the intervals (the jitter), `None` before the third poll.
+    pub fn interval_stddev(&self) -> Option<Duration> {
+        (self.intervals > 1).then(|| Duration::from_secs_f64((self.m2 / (self.intervals - 1) as f64).sqrt()))
+    }
+
+    /// The longest interval between two polls, `None` before the second poll.
+    pub fn max_interval(&self) -> Option<Duration> {
+        (self.intervals > 0).then_some(self.max)
+    }
+
+    /// The achieved frequency, in Hertz: the number of intervals divided by their total duration.
+    pub fn mean_frequency(&self) -> Option<f64> {
+        (self.intervals > 0 && self.mean_secs > 0.0).then(|| 1.0 / self.mean_secs)
+    }
+
+    /// Logs the [`PollRate::report`], if any.
+    pub fn log_report(&self, requested: Option<Duration>) {
+        if let Some(report) = self.report(requested) {
+            log::info!("{report}");
+        }
+    }
+
+    /// Describes the achieved frequency, compared to the `requested` period (zero for continuous polling, `None` if the
+    /// period is not chosen by the tool). Returns `None` if there are not enough polls.
+    pub fn report(&self, requested: Option<Duration>) -> Option<String> {
+        let frequency = self.mean_frequency()?;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let requested = match requested {
+            Some(period) if period.is_zero() => String::from(" (requested: continuous)"),
+            Some(period) => format!(" (requested: {:.1} Hz)", 1.0 / period.as_secs_f64()),
+            None => String::new(),
+        };
+        let stddev = self.interval_stddev().map(|s| format!(" ± {:.3}", ms(s))).unwrap_or_default();
+        Some(format!(
+            "Achieved polling frequency: {frequency:.1} Hz{requested}, interval {:.3}{stddev} ms, worst gap {:.3} ms over {} polls",
+            ms(self.mean_interval()?),
+            ms(self.max_interval()?),
+            self.intervals() + 1,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::PollRate;
+
+    #[test]
+    fn test_poll_rate() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let ms = Duration::from_millis;
+        let mut rate = PollRate::new();
+        assert_eq!(rate.report(Some(ms(10))), None);
+        rate.record(t0);
+        assert_eq!(rate.mean_interval(), None);
+
+        // intervals of 8, 12, 10 and 30 ms
+        for t in [8, 20, 30, 60] {
+            rate.record(t0 + ms(t));
+        }
+        // a timestamp in the past is ignored, and the next interval starts from it
+        rate.record(t0);
+        rate.record(t0 + ms(10));
+        assert_eq!(rate.intervals(), 5);
+        let mean = rate.mean_interval().unwrap().as_secs_f64();
+        assert!((mean - 0.014).abs() < 1e-9, "{mean}");
+        assert!((rate.mean_frequency().unwrap() - 1.0 / 0.014).abs() < 1e-6);
+        assert_eq!(rate.max_interval(), Some(ms(30)));
+        // intervals 8, 12, 10, 30, 10 ms: sum of the squared deviations = 36 + 4 + 16 + 256 + 16 = 328
+        let stddev = rate.interval_stddev().unwrap().as_secs_f64() * 1000.0;
+        assert!((stddev - (328.0f64 / 4.0).sqrt()).abs() < 1e-6, "{stddev}");
+
+        let report = rate.report(Some(ms(10))).unwrap();
+        assert!(report.starts_with("Achieved polling frequency: 71.4 Hz (requested: 100.0 Hz)"), "{report}");
+        assert!(report.ends_with("worst gap 30.000 ms over 6 polls"), "{report}");
+    }
+}
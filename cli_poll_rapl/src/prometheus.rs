@@ -0,0 +1,193 @@
+//! Prometheus exporter: serves the latest measurements over HTTP, in the
+//! [text exposition format](https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format).
+//!
+//! The energy is exposed as a counter, which is the cumulative energy since the start of the measurement:
+//! ```text
+//! # HELP rapl_energy_joules_total Energy consumed since the start of the measurement, in Joules.
+//! # TYPE rapl_energy_joules_total counter
+//! rapl_energy_joules_total{socket="0",domain="package"} 1520.3
+//! rapl_energy_joules_total{socket="0",domain="dram"} 201.7
+//! ```
+//! Use `rate(rapl_energy_joules_total[1m])` to get the power in Watts.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use enum_map::EnumMap;
+use log::{info, warn};
+use rapl_probes::RaplDomainType;
+
+use crate::main_optimized::MeasurementsMessage;
+use crate::output::MeasurementsOutput;
+
+/// How long the HTTP thread waits for a client to send its request or to receive the response.
+/// The requests are answered one at a time: without a timeout, an idle client would block the others.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Updates the metrics, which are served by an HTTP thread.
+pub struct PrometheusOutput {
+    /// The cumulative energy of each domain of each socket, `None` if the domain has not been measured.
+    totals: Vec<EnumMap<RaplDomainType, Option<f64>>>,
+    /// The rendered metrics, shared with the HTTP thread.
+    metrics: Arc<Mutex<String>>,
+}
+
+impl PrometheusOutput {
+    /// Starts an HTTP server on `listen_address`, which serves the metrics at `/metrics`.
+    pub fn bind(listen_address: &str) -> anyhow::Result<PrometheusOutput> {
+        let listener =
+            TcpListener::bind(listen_address).with_context(|| format!("failed to listen on {listen_address}"))?;
+        PrometheusOutput::serve(listener)
+    }
+
+    /// Serves the metrics at `/metrics`, with an HTTP server that accepts the connections of `listener`.
+    pub fn serve(listener: TcpListener) -> anyhow::Result<PrometheusOutput> {
+        info!("Serving the Prometheus metrics on http://{}/metrics", listener.local_addr()?);
+
+        let metrics = Arc::new(Mutex::new(render_metrics(&[])));
+        let shared = metrics.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.map_err(anyhow::Error::from).and_then(|s| handle_request(s, &shared));
+                if let Err(e) = result {
+                    warn!("Failed to answer a Prometheus request: {e:?}");
+                }
+            }
+        });
+
+        Ok(PrometheusOutput {
+            totals: Vec::new(),
+            metrics,
+        })
+    }
+}
+
+impl MeasurementsOutput for PrometheusOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        let per_socket = &msg.measurements.per_socket;
+        if self.totals.len() < per_socket.len() {
+            self.totals.resize(per_socket.len(), EnumMap::default());
+        }
+        for (totals, domains_of_socket) in self.totals.iter_mut().zip(per_socket) {
+            for (domain, counter) in domains_of_socket {
+                if counter.joules.is_some() {
+                    totals[domain] = Some(counter.cumulative_joules);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes the new metrics.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let rendered = render_metrics(&self.totals);
+        *self.metrics.lock().unwrap() = rendered;
+        Ok(())
+    }
+}
+
+/// Renders the cumulative energy of each (socket, domain) in the Prometheus text format.
+pub(crate) fn render_metrics(totals: &[EnumMap<RaplDomainType, Option<f64>>]) -> String {
+    let mut out = String::new();
+    out.push_str("# HELP rapl_energy_joules_total Energy consumed since the start of the measurement, in Joules.\n");
+    out.push_str("# TYPE rapl_energy_joules_total counter\n");
+    for (socket, domains_of_socket) in totals.iter().enumerate() {
+        for (domain, joules) in domains_of_socket {
+            if let Some(joules) = joules {
+                let domain = domain.canonical_name();
+                writeln!(out, "rapl_energy_joules_total{{socket=\"{socket}\",domain=\"{domain}\"}} {joules}").unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// Answers one HTTP request: the metrics for `GET /metrics`, 404 otherwise.
+fn handle_request(mut stream: TcpStream, metrics: &Mutex<String>) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line != "\r\n" && line != "\n" {
+        line.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if request_line.starts_with("GET ") && path == "/metrics" {
+        ("200 OK", metrics.lock().unwrap().clone())
+    } else {
+        ("404 Not Found", String::from("not found, the metrics are at /metrics\n"))
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::PrometheusOutput;
+    use crate::main_optimized::MeasurementsMessage;
+    use crate::output::MeasurementsOutput;
+
+    fn http_get(address: SocketAddr, path: &str) -> anyhow::Result<String> {
+        let mut stream = TcpStream::connect(address)?;
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: {address}\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn test_prometheus_output() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let mut output = PrometheusOutput::serve(listener)?;
+
+        let mut m = EnergyMeasurements::new(2);
+        let t0 = Instant::now();
+        for (i, value) in [0, 100, 250].into_iter().enumerate() {
+            let t = t0 + Duration::from_millis(100 * i as u64);
+            m.push_at(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0, t);
+            m.push_at(1, RaplDomainType::Dram, value / 10, u32::MAX as u64, 1.0, t);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::now(),
+                measurements: m.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+        output.flush()?;
+
+        let response = http_get(address, "/metrics")?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        assert_eq!(
+            body,
+            "# HELP rapl_energy_joules_total Energy consumed since the start of the measurement, in Joules.\n\
+             # TYPE rapl_energy_joules_total counter\n\
+             rapl_energy_joules_total{socket=\"0\",domain=\"package\"} 250\n\
+             rapl_energy_joules_total{socket=\"1\",domain=\"dram\"} 25\n"
+        );
+
+        let response = http_get(address, "/")?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{response}");
+        Ok(())
+    }
+}
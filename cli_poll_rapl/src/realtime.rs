@@ -0,0 +1,153 @@
+//! Polling on a dedicated OS thread with a real-time priority, for the lowest jitter.
+//!
+//! Unlike the tokio task of [`crate::main_optimized`], the thread sleeps until absolute deadlines
+//! with `clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME)`, thus the period does not drift.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use log::warn;
+use rapl_probes::EnergyProbe;
+use crate::backpressure::MeasurementsSender;
+use crate::main_optimized::{is_last_sample, MeasurementsMessage, MessageBatch, SampleLimit};
+use crate::poll_rate::PollRate;
+
+/// Priority of the polling thread, in the SCHED_FIFO range (1-99).
+/// It stays below the threaded interrupt handlers of the kernel, which run at 50.
+const REALTIME_PRIORITY: i32 = 49;
+
+/// Spawns a thread that polls the probe every `period`, until `stop` is set or the last sample of `limit` is produced.
+/// The measurements are sent to the writer task through `tx`, which is closed at the end.
+pub fn spawn_poll_thread(
+    mut probe: Box<dyn EnergyProbe>,
+    period: Duration,
+    batch_size: usize,
+    mut limit: Option<SampleLimit>,
+    mut tx: MeasurementsSender,
+    stop: Arc<AtomicBool>,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    let handle = std::thread::Builder::new()
+        .name(String::from("rapl-poll"))
+        .spawn(move || {
+            set_realtime_priority();
+            poll_loop(probe.as_mut(), period, batch_size, &mut limit, &mut tx, &stop)?;
+            tx.blocking_finish();
+            Ok(())
+        })?;
+    Ok(handle)
+}
+
+/// Sets the SCHED_FIFO policy on the current thread, or warns if it is not possible.
+fn set_realtime_priority() {
+    // sched_param has more fields on some libc (e.g. musl), zero them
+    let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+    param.sched_priority = REALTIME_PRIORITY;
+    // pid 0 means the calling thread
+    let res = unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) };
+    if res != 0 {
+        let err = std::io::Error::last_os_error();
+        warn!("Failed to set the SCHED_FIFO priority of the polling thread, the jitter may be higher (CAP_SYS_NICE is required): {err}");
+    }
+}
+
+fn poll_loop(
+    probe: &mut dyn EnergyProbe,
+    period: Duration,
+    batch_size: usize,
+    limit: &mut Option<SampleLimit>,
+    tx: &mut MeasurementsSender,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut batch = MessageBatch::new(batch_size);
+    let mut rate = PollRate::new();
+    let mut deadline = monotonic_now();
+
+    while !stop.load(Ordering::Relaxed) {
+        if !period.is_zero() {
+            deadline = next_deadline(deadline, period, monotonic_now());
+            sleep_until(deadline);
+        }
+
+        probe.poll().context("refreshing measurements")?;
+        let m = probe.measurements();
+        let msg = MeasurementsMessage {
+            timestamp: m.timestamp().unwrap_or_else(SystemTime::now),
+            measurements: m.clone(),
+            source: None,
+        };
+        rate.record(msg.timestamp);
+        let last = is_last_sample(limit, m.iter_measured().next().is_some());
+        if let Some(full_batch) = batch.push(msg) {
+            tx.blocking_send(full_batch);
+        }
+        if last {
+            break;
+        }
+    }
+
+    let remaining = batch.take();
+    if !remaining.is_empty() {
+        tx.blocking_send(remaining);
+    }
+    rate.log_report(Some(period));
+    Ok(())
+}
+
+/// Computes the next deadline of the loop, on the monotonic clock.
+///
+/// The deadlines are multiples of `period` after the first one, so that the period does not drift.
+/// If the polling took too long and some deadlines have already passed, they are skipped:
+/// the next deadline is the first one that is after `now`.
+pub(crate) fn next_deadline(previous: Duration, period: Duration, now: Duration) -> Duration {
+    let next = previous + period;
+    if next > now {
+        next
+    } else {
+        let missed = (now - previous).as_nanos() / period.as_nanos();
+        let periods = u32::try_from(missed + 1).unwrap_or(u32::MAX);
+        previous + period * periods
+    }
+}
+
+fn monotonic_now() -> Duration {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Sleeps until `deadline` on the monotonic clock.
+fn sleep_until(deadline: Duration) {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    ts.tv_sec = deadline.as_secs() as _;
+    ts.tv_nsec = deadline.subsec_nanos() as _;
+    loop {
+        let res = unsafe {
+            libc::clock_nanosleep(libc::CLOCK_MONOTONIC, libc::TIMER_ABSTIME, &ts, std::ptr::null_mut())
+        };
+        // with TIMER_ABSTIME, the sleep can simply be restarted after an interruption
+        if res != libc::EINTR {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::next_deadline;
+
+    #[test]
+    fn test_next_deadline() {
+        let ms = Duration::from_millis;
+        // on time: the next deadline is one period later, regardless of the time spent polling
+        assert_eq!(next_deadline(ms(100), ms(10), ms(100)), ms(110));
+        assert_eq!(next_deadline(ms(100), ms(10), ms(107)), ms(110));
+        // late: the missed deadlines are skipped, the phase is kept
+        assert_eq!(next_deadline(ms(100), ms(10), ms(110)), ms(120));
+        assert_eq!(next_deadline(ms(100), ms(10), ms(135)), ms(140));
+    }
+}
@@ -0,0 +1,185 @@
+//! Output compatible with a subset of the JSON format of [Scaphandre](https://github.com/hubblo-org/scaphandre),
+//! so that the measurements can be fed into tools that already understand it.
+//!
+//! Each message is written as one JSON object per line:
+//! ```json
+//! {
+//!   "host": {"consumption": 52000000.0, "timestamp": 1732110377.71},
+//!   "sockets": [
+//!     {
+//!       "id": 0, "consumption": 45000000.0, "timestamp": 1732110377.71,
+//!       "domains": [{"name": "dram", "consumption": 7000000.0, "timestamp": 1732110377.71}]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Field mapping:
+//! - `consumption` is a power in **microwatts**, like in Scaphandre, computed from the joules and the polling interval.
+//! - `timestamp` is the time of the measurement, in seconds since the Unix epoch.
+//! - `sockets[].consumption` is the power of the `Package` domain.
+//! - `sockets[].domains` contains the other domains of the socket: `PP0` is named `core`, `PP1` is `uncore`,
+//!   `Dram` is `dram`.
+//! - `host.consumption` is the power of the `Platform` (psys) domain if it is measured,
+//!   otherwise the sum of the package power of all the sockets (this is what Scaphandre does).
+//!
+//! Unlike Scaphandre, this output has no `consumers` (processes) and no `components` (disks).
+
+use std::io::Write;
+use std::time::SystemTime;
+
+use rapl_probes::RaplDomainType;
+use serde_json::{json, Value};
+
+use crate::main_optimized::MeasurementsMessage;
+use crate::output::MeasurementsOutput;
+
+const MICROWATTS_PER_WATT: f64 = 1_000_000.0;
+
+/// Writes the measurements in the Scaphandre JSON format, one object per line.
+pub struct ScaphandreOutput {
+    writer: Box<dyn Write + Send>,
+}
+
+impl ScaphandreOutput {
+    pub fn new(writer: Box<dyn Write + Send>) -> ScaphandreOutput {
+        ScaphandreOutput { writer }
+    }
+}
+
+impl MeasurementsOutput for ScaphandreOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        if let Some(record) = scaphandre_record(msg)? {
+            serde_json::to_writer(&mut self.writer, &record)?;
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The name of a domain in Scaphandre, or `None` if it is not a sub-domain of the socket.
+fn scaphandre_domain_name(domain: RaplDomainType) -> Option<&'static str> {
+    match domain {
+        RaplDomainType::PP0 => Some("core"),
+        RaplDomainType::PP1 => Some("uncore"),
+        RaplDomainType::Dram => Some("dram"),
+        RaplDomainType::Package | RaplDomainType::Platform => None,
+    }
+}
+
+/// Builds the JSON record of a message, or returns `None` if it contains no power measurement.
+pub(crate) fn scaphandre_record(msg: &MeasurementsMessage) -> anyhow::Result<Option<Value>> {
+    let timestamp = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+
+    let mut sockets = Vec::new();
+    let mut packages_uw: Option<f64> = None;
+    let mut platform_uw: Option<f64> = None;
+    for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+        let mut socket_uw = None;
+        let mut domains = Vec::new();
+        for (domain, counter) in domains_of_socket {
+            let Some(microwatts) = counter.watts().map(|w| w * MICROWATTS_PER_WATT) else {
+                continue;
+            };
+            match domain {
+                RaplDomainType::Package => socket_uw = Some(microwatts),
+                RaplDomainType::Platform => *platform_uw.get_or_insert(0.0) += microwatts,
+                _ => {
+                    let name = scaphandre_domain_name(domain).unwrap();
+                    domains.push(json!({"name": name, "consumption": microwatts, "timestamp": timestamp}));
+                }
+            }
+        }
+        if socket_uw.is_none() && domains.is_empty() {
+            continue;
+        }
+        if let Some(uw) = socket_uw {
+            *packages_uw.get_or_insert(0.0) += uw;
+        }
+        sockets.push(json!({
+            "id": socket_id,
+            "consumption": socket_uw.unwrap_or(0.0),
+            "timestamp": timestamp,
+            "domains": domains,
+        }));
+    }
+
+    if sockets.is_empty() && platform_uw.is_none() {
+        return Ok(None);
+    }
+    let host_uw = platform_uw.or(packages_uw).unwrap_or(0.0);
+    Ok(Some(json!({
+        "host": {"consumption": host_uw, "timestamp": timestamp},
+        "sockets": sockets,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+    use serde_json::json;
+
+    use super::scaphandre_record;
+    use crate::main_optimized::MeasurementsMessage;
+
+    #[test]
+    fn test_scaphandre_record() -> anyhow::Result<()> {
+        let mut m = EnergyMeasurements::new(2);
+        let t0 = Instant::now();
+        let t1 = t0 + Duration::from_millis(500);
+        let unit = 0.5_f64.powi(10);
+        for (socket, pkg_joules, dram_joules) in [(0, 20.0, 3.0), (1, 10.0, 1.0)] {
+            for (domain, joules) in [(RaplDomainType::Package, pkg_joules), (RaplDomainType::Dram, dram_joules)] {
+                m.push_at(socket, domain, 1000, u32::MAX as u64, unit, t0);
+                let counter_value = 1000 + (joules / unit) as u64;
+                m.push_at(socket, domain, counter_value, u32::MAX as u64, unit, t1);
+            }
+        }
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_732_110_377_500),
+            measurements: m,
+            source: None,
+        };
+
+        let record = scaphandre_record(&msg)?.expect("the record should not be empty");
+        let expected = json!({
+            "host": {"consumption": 60_000_000.0, "timestamp": 1_732_110_377.5},
+            "sockets": [
+                {
+                    "id": 0,
+                    "consumption": 40_000_000.0,
+                    "timestamp": 1_732_110_377.5,
+                    "domains": [{"name": "dram", "consumption": 6_000_000.0, "timestamp": 1_732_110_377.5}]
+                },
+                {
+                    "id": 1,
+                    "consumption": 20_000_000.0,
+                    "timestamp": 1_732_110_377.5,
+                    "domains": [{"name": "dram", "consumption": 2_000_000.0, "timestamp": 1_732_110_377.5}]
+                }
+            ]
+        });
+        assert_eq!(record, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_poll_is_skipped() -> anyhow::Result<()> {
+        let mut m = EnergyMeasurements::new(1);
+        m.push(0, RaplDomainType::Package, 1000, u32::MAX as u64, 1.0);
+        let msg = MeasurementsMessage {
+            timestamp: SystemTime::now(),
+            measurements: m,
+            source: None,
+        };
+        assert_eq!(scaphandre_record(&msg)?, None);
+        Ok(())
+    }
+}
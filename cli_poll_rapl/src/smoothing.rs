@@ -0,0 +1,129 @@
+//! Smoothing of the power, which is jumpy at high frequencies.
+
+use std::collections::HashMap;
+
+use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+/// Exponentially weighted moving average of the power of each (socket, domain).
+///
+/// Each new value `x` gives `smoothed = alpha * x + (1 - alpha) * smoothed`: with a small alpha, the average is smoother
+/// but slower to follow the changes. The first value is taken as is.
+pub struct EwmaFilter {
+    alpha: f64,
+    smoothed: HashMap<(u32, RaplDomainType), f64>,
+}
+
+impl EwmaFilter {
+    /// Creates a filter, `alpha` must be in `(0, 1]` (see [`parse_alpha`]).
+    pub fn new(alpha: f64) -> EwmaFilter {
+        assert!(alpha > 0.0 && alpha <= 1.0, "invalid EWMA alpha {alpha}");
+        EwmaFilter {
+            alpha,
+            smoothed: HashMap::new(),
+        }
+    }
+
+    /// Adds a value of the power of `domain` on `socket`, in Watts, and returns the new smoothed value.
+    pub fn push(&mut self, socket: u32, domain: RaplDomainType, watts: f64) -> f64 {
+        let alpha = self.alpha;
+        let smoothed = self
+            .smoothed
+            .entry((socket, domain))
+            .and_modify(|s| *s = alpha * watts + (1.0 - alpha) * *s)
+            .or_insert(watts);
+        *smoothed
+    }
+
+    /// Adds the power of each counter of `measurements`.
+    ///
+    /// The counters without value (the first poll, or the first poll after [`rapl_probes::EnergyProbe::reset`])
+    /// forget their smoothed value, so that the average restarts with the measurement.
+    pub fn update(&mut self, measurements: &EnergyMeasurements) {
+        for (socket, domains) in measurements.per_socket.iter().enumerate() {
+            for (domain, counter) in domains {
+                let socket = socket as u32;
+                if counter.joules.is_none() {
+                    self.smoothed.remove(&(socket, domain));
+                } else if let Some(watts) = counter.watts() {
+                    self.push(socket, domain, watts);
+                }
+            }
+        }
+    }
+
+    /// The current smoothed power of `domain` on `socket`, in Watts.
+    pub fn smoothed(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.smoothed.get(&(socket, domain)).copied()
+    }
+}
+
+/// Parses the value of `--smooth-alpha`, which must be in `(0, 1]`.
+pub fn parse_alpha(s: &str) -> Result<f64, String> {
+    let alpha: f64 = s.parse().map_err(|e| format!("invalid number '{s}': {e}"))?;
+    if alpha > 0.0 && alpha <= 1.0 {
+        Ok(alpha)
+    } else {
+        Err(format!("the smoothing factor must be in (0, 1], not {alpha}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+
+    use super::{parse_alpha, EwmaFilter};
+
+    #[test]
+    fn test_converges_to_constant() {
+        let mut filter = EwmaFilter::new(0.2);
+        assert_eq!(filter.push(0, RaplDomainType::Package, 50.0), 50.0);
+        let mut smoothed = 0.0;
+        for _ in 0..100 {
+            smoothed = filter.push(0, RaplDomainType::Package, 50.0);
+        }
+        assert!((smoothed - 50.0).abs() < 1e-9);
+        // the domains are independent
+        assert_eq!(filter.smoothed(0, RaplDomainType::Dram), None);
+        assert_eq!(filter.push(1, RaplDomainType::Package, 10.0), 10.0);
+    }
+
+    #[test]
+    fn test_step_response() {
+        let mut filter = EwmaFilter::new(0.5);
+        filter.push(0, RaplDomainType::Package, 10.0);
+        // step from 10 W to 30 W: the gap is halved at each value
+        let responses: Vec<f64> = (0..4).map(|_| filter.push(0, RaplDomainType::Package, 30.0)).collect();
+        assert_eq!(responses, [20.0, 25.0, 27.5, 28.75]);
+
+        // with alpha = 1, there is no smoothing
+        let mut raw = EwmaFilter::new(1.0);
+        raw.push(0, RaplDomainType::Package, 10.0);
+        assert_eq!(raw.push(0, RaplDomainType::Package, 30.0), 30.0);
+    }
+
+    #[test]
+    fn test_update_and_reset() {
+        let mut filter = EwmaFilter::new(0.5);
+        let mut m = EnergyMeasurements::new(1);
+        let t0 = Instant::now();
+        for (i, value) in [0, 10, 40].into_iter().enumerate() {
+            m.push_at(0, RaplDomainType::Package, value, u32::MAX as u64, 1.0, t0 + Duration::from_secs(i as u64));
+            filter.update(&m);
+        }
+        // 10 W, then 30 W
+        assert_eq!(filter.smoothed(0, RaplDomainType::Package), Some(20.0));
+
+        // after a reset, the first poll has no value: the average restarts
+        m.clear();
+        m.push_at(0, RaplDomainType::Package, 100, u32::MAX as u64, 1.0, t0 + Duration::from_secs(3));
+        filter.update(&m);
+        assert_eq!(filter.smoothed(0, RaplDomainType::Package), None);
+
+        assert!(parse_alpha("0.1").is_ok());
+        assert!(parse_alpha("0").is_err());
+        assert!(parse_alpha("1.5").is_err());
+        assert!(parse_alpha("abc").is_err());
+    }
+}
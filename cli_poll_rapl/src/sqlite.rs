@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use rapl_probes::RaplDomainType;
+use rusqlite::{params, Connection};
+
+use crate::main_optimized::MeasurementsMessage;
+use crate::output::MeasurementsOutput;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS measurements (
+    timestamp_ms INTEGER NOT NULL,
+    socket INTEGER NOT NULL,
+    domain TEXT NOT NULL,
+    overflow INTEGER NOT NULL,
+    joules REAL NOT NULL
+)";
+
+const INSERT_ROW: &str = "INSERT INTO measurements (timestamp_ms, socket, domain, overflow, joules) VALUES (?1, ?2, ?3, ?4, ?5)";
+
+/// Writes the measurements to a SQLite database.
+///
+/// The rows are buffered in memory and inserted in a single transaction on each flush,
+/// because one transaction per row would be way too slow at high frequencies.
+pub struct SqliteOutput {
+    conn: Connection,
+    pending: Vec<Row>,
+}
+
+struct Row {
+    timestamp_ms: i64,
+    socket: u32,
+    domain: RaplDomainType,
+    overflow: bool,
+    joules: f64,
+}
+
+impl SqliteOutput {
+    /// Opens (or creates) the database at the given path.
+    pub fn open(path: &Path) -> anyhow::Result<SqliteOutput> {
+        let conn = Connection::open(path).with_context(|| format!("open sqlite database {path:?}"))?;
+        SqliteOutput::new(conn)
+    }
+
+    /// Creates the `measurements` table in the database, if it does not exist.
+    pub fn new(conn: Connection) -> anyhow::Result<SqliteOutput> {
+        conn.execute(CREATE_TABLE, []).context("create table measurements")?;
+        Ok(SqliteOutput {
+            conn,
+            pending: Vec::new(),
+        })
+    }
+}
+
+impl MeasurementsOutput for SqliteOutput {
+    fn write(&mut self, msg: &MeasurementsMessage) -> anyhow::Result<()> {
+        let timestamp_ms = msg.timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as i64;
+
+        for (socket_id, domains_of_socket) in msg.measurements.per_socket.iter().enumerate() {
+            for (domain, counter) in domains_of_socket {
+                if let Some(joules) = counter.joules {
+                    self.pending.push(Row {
+                        timestamp_ms,
+                        socket: socket_id as u32,
+                        domain,
+                        overflow: counter.overflowed,
+                        joules,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction()?;
+        {
+            // the statement is cached by the connection, it is only prepared once
+            let mut stmt = tx.prepare_cached(INSERT_ROW)?;
+            for row in &self.pending {
+                let domain = row.domain.to_string();
+                stmt.execute(params![row.timestamp_ms, row.socket, domain, row.overflow, row.joules])?;
+            }
+        }
+        tx.commit()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use rapl_probes::{EnergyMeasurements, RaplDomainType};
+    use rusqlite::Connection;
+
+    use super::SqliteOutput;
+    use crate::main_optimized::MeasurementsMessage;
+    use crate::output::MeasurementsOutput;
+
+    #[test]
+    fn test_insert_and_query() -> anyhow::Result<()> {
+        let mut output = SqliteOutput::new(Connection::open_in_memory()?)?;
+
+        let mut measurements = EnergyMeasurements::new(2);
+        for (i, value) in [100, 300].into_iter().enumerate() {
+            measurements.push(0, RaplDomainType::Package, value, u32::MAX as u64, 0.5);
+            measurements.push(1, RaplDomainType::Dram, value * 2, u32::MAX as u64, 0.5);
+            let msg = MeasurementsMessage {
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1000 + i as u64),
+                measurements: measurements.clone(),
+                source: None,
+            };
+            output.write(&msg)?;
+        }
+        // nothing is inserted before the flush
+        let count: i64 = output.conn.query_row("SELECT COUNT(*) FROM measurements", [], |r| r.get(0))?;
+        assert_eq!(count, 0);
+
+        output.flush()?;
+        let mut stmt = output
+            .conn
+            .prepare("SELECT timestamp_ms, socket, domain, overflow, joules FROM measurements ORDER BY socket")?;
+        let rows: Vec<(i64, u32, String, bool, f64)> = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)))?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                (1001, 0, String::from("Package"), false, 100.0),
+                (1001, 1, String::from("Dram"), false, 200.0),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Aligned tables for the `info` command, with optional ANSI colors.
+
+use std::fmt::Write as _;
+
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Space between two columns.
+const COLUMN_GAP: usize = 2;
+
+/// A table whose columns are aligned on the widest cell.
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Table {
+        Table {
+            header: header.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row. It should have as many cells as the header.
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// Renders the table. If `color` is true, the header is bold and the first column is cyan.
+    pub fn render(&self, color: bool) -> String {
+        let widths = column_widths(&self.header, &self.rows);
+        let mut out = String::new();
+        write_row(&mut out, &self.header, &widths, color.then_some(BOLD), color.then_some(BOLD));
+        for row in &self.rows {
+            write_row(&mut out, row, &widths, color.then_some(CYAN), None);
+        }
+        out
+    }
+}
+
+/// Computes the width of each column, i.e. the number of characters of its widest cell.
+pub(crate) fn column_widths(header: &[String], rows: &[Vec<String>]) -> Vec<usize> {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        if widths.len() < row.len() {
+            widths.resize(row.len(), 0);
+        }
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    widths
+}
+
+/// Writes the cells, padded to the widths of the columns. The padding is computed on the text without colors.
+/// The last cell is not padded, to avoid trailing spaces.
+fn write_row(out: &mut String, cells: &[String], widths: &[usize], first_color: Option<&str>, color: Option<&str>) {
+    for (i, cell) in cells.iter().enumerate() {
+        let style = if i == 0 { first_color } else { color };
+        match style {
+            Some(style) => write!(out, "{style}{cell}{RESET}").unwrap(),
+            None => out.push_str(cell),
+        }
+        if i + 1 < cells.len() {
+            let padding = widths[i] - cell.chars().count() + COLUMN_GAP;
+            out.extend(std::iter::repeat_n(' ', padding));
+        }
+    }
+    out.push('\n');
+}
+
+/// Returns `true` if the colors should be used on stdout: it must be a terminal, and `NO_COLOR` must not be set.
+pub fn stdout_supports_color() -> bool {
+    let is_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    is_tty && std::env::var_os("NO_COLOR").is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{column_widths, Table};
+
+    fn strings(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_column_widths() {
+        let header = strings(&["name", "domain", "scale"]);
+        let rows = vec![
+            strings(&["energy-pkg", "Package", "2.3283064e-10"]),
+            strings(&["energy-ram", "Dram", "1.5e-5"]),
+        ];
+        assert_eq!(column_widths(&header, &rows), vec![10, 7, 13]);
+        assert_eq!(column_widths(&header, &[]), vec![4, 6, 5]);
+        // the width is in characters, not bytes
+        assert_eq!(column_widths(&strings(&["é"]), &[]), vec![1]);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut table = Table::new(&["probe", "status"]);
+        table.push(strings(&["powercap", "Usable"]));
+        table.push(strings(&["msr", "PermissionDenied"]));
+        assert_eq!(
+            table.render(false),
+            "probe     status\npowercap  Usable\nmsr       PermissionDenied\n"
+        );
+        assert_eq!(
+            table.render(true).lines().nth(2),
+            Some("\x1b[36mmsr\x1b[0m       PermissionDenied")
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! The cpus of the measured workload (e.g. pinned with `taskset`), to attribute the energy of the sockets.
+
+use std::str::FromStr;
+
+use log::{info, warn};
+use rapl_probes::CpuId;
+
+use crate::mkstring;
+
+/// The cpus that the workload runs on, e.g. `0-15`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkloadCpus {
+    pub cpus: Box<[u32]>,
+}
+
+impl FromStr for WorkloadCpus {
+    type Err = String;
+
+    /// Parses a list of cpus in the format of `taskset --cpu-list`, e.g. `0-3,8,10-11`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cpus = rapl_probes::parse_cpu_list(s).map_err(|e| format!("invalid cpu list '{s}': {e}"))?;
+        Ok(WorkloadCpus { cpus: cpus.into() })
+    }
+}
+
+impl WorkloadCpus {
+    /// The sockets that contain the cpus of the workload, given by `package_of`, sorted and without duplicates.
+    /// Returns `None` if the socket of a cpu is unknown.
+    pub fn sockets(&self, package_of: impl Fn(u32) -> Option<u32>) -> Option<Vec<u32>> {
+        let mut sockets = self.cpus.iter().map(|&cpu| package_of(cpu)).collect::<Option<Vec<u32>>>()?;
+        sockets.sort_unstable();
+        sockets.dedup();
+        Some(sockets)
+    }
+
+    /// Logs the cpus and the sockets of the workload, and warns if the energy of the monitored sockets
+    /// cannot be attributed to the workload: when it spans several sockets, or when some of its sockets are not monitored.
+    pub fn check(&self, monitored: &[CpuId], package_of: impl Fn(u32) -> Option<u32>) {
+        let list = mkstring(&self.cpus, ",");
+        let Some(sockets) = self.sockets(package_of) else {
+            warn!("The sockets of the workload cpus {list} are unknown (cpu topology not available)");
+            return;
+        };
+        info!("Workload cpus: {list} (sockets {})", mkstring(&sockets, ","));
+        if sockets.len() > 1 {
+            warn!(
+                "The workload cpus {list} span {} sockets ({}): their energy also includes the idle cores of these sockets",
+                sockets.len(),
+                mkstring(&sockets, ",")
+            );
+        }
+        let unmonitored: Vec<u32> = sockets.iter().copied().filter(|s| !monitored.iter().any(|c| c.socket == *s)).collect();
+        if !unmonitored.is_empty() {
+            warn!(
+                "The workload runs on the sockets {}, which are not monitored",
+                mkstring(&unmonitored, ",")
+            );
+        }
+        if monitored.len() > sockets.len() {
+            info!(
+                "The other sockets do not run the workload: use `--sockets {}` to measure only the sockets of the workload",
+                mkstring(&sockets, ",")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkloadCpus;
+
+    #[test]
+    fn test_sockets() {
+        // 2 sockets of 16 cpus
+        let package_of = |cpu: u32| (cpu < 32).then_some(cpu / 16);
+
+        let one_socket: WorkloadCpus = "0-15".parse().unwrap();
+        assert_eq!(*one_socket.cpus, (0..16).collect::<Vec<u32>>());
+        assert_eq!(one_socket.sockets(package_of), Some(vec![0]));
+
+        let two_sockets: WorkloadCpus = "20,2-3,31".parse().unwrap();
+        assert_eq!(*two_sockets.cpus, [20, 2, 3, 31]);
+        assert_eq!(two_sockets.sockets(package_of), Some(vec![0, 1]));
+
+        // cpu 40 does not exist
+        let unknown: WorkloadCpus = "0,40".parse().unwrap();
+        assert_eq!(unknown.sockets(package_of), None);
+
+        assert!("0-a".parse::<WorkloadCpus>().is_err());
+        assert!("1-2-3".parse::<WorkloadCpus>().is_err());
+    }
+}
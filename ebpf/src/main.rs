@@ -71,11 +71,41 @@ fn read_and_push_counter(ctx: &PerfEventContext, cpu_id: u32, domain_id: u8) ->
     Ok(())
 }
 
+/// Maximum number of events per socket that the loop variant can read.
+///
+/// The verifier only accepts loops whose number of iterations is bounded (Linux >= 5.3),
+/// hence this constant upper bound.
+#[cfg(feature = "ebpf_loops")]
+const MAX_EVENTS: u8 = 16;
+
+#[cfg(feature = "ebpf_loops")]
+fn try_aya_start(ctx: &PerfEventContext) -> Result<(), (&str, i64)> {
+    let cpu_id = unsafe { bpf_get_smp_processor_id() };
+    let n = unsafe { N_EVENTS.get(0) }.ok_or(("N_EVENTS not set", -1))?;
+
+    #[cfg(debug_assertions)]
+    debug!(ctx, "N_EVENTS = {}", *n);
+
+    if *n == 0 || *n > MAX_EVENTS {
+        return Err(("invalid N_EVENTS, should be in 1..=MAX_EVENTS", -7));
+    }
+    // iterate up to the constant bound, so that the verifier can prove that the loop terminates
+    for domain_id in 0..MAX_EVENTS {
+        if domain_id >= *n {
+            break;
+        }
+        read_and_push_counter(ctx, cpu_id, domain_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "ebpf_loops"))]
 fn try_aya_start(ctx: &PerfEventContext) -> Result<(), (&str, i64)> {
     let cpu_id = unsafe { bpf_get_smp_processor_id() };
 
     // loops aren't available in EBPF before Linux Kernel 5.3, and we have HPC servers running on 4.8
     // For brevity, only the common cases used in our benchmarks are implemented.
+    // On recent kernels, build with the "ebpf_loops" feature to support more events.
 
     let n = unsafe { N_EVENTS.get(0) }.ok_or(("N_EVENTS not set", -1))?;
 
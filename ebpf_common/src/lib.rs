@@ -4,9 +4,82 @@
 // and the `rapl_probes` module, which implements the userspace program that communicates with the ebpf kernel program.
 
 /// The value of a RAPL energy counter.
-#[repr(align(16))] // for the ebpf verifier
+///
+/// The ebpf program (bpfel target) sends it as bytes, in little-endian with the `repr(C)` layout:
+///
+/// | offset | size | field       |
+/// |--------|------|-------------|
+/// | 0      | 4    | `cpu_id`    |
+/// | 4      | 1    | `domain_id` |
+/// | 5      | 3    | padding     |
+/// | 8      | 8    | `energy`    |
+///
+/// The userspace program decodes it with [`RaplEnergy::from_le_bytes`], which does not depend on its own endianness.
+#[repr(C, align(16))] // for the ebpf verifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RaplEnergy {
     pub cpu_id: u32,
     pub domain_id: u8,
     pub energy: u64,
 }
+
+impl RaplEnergy {
+    const CPU_ID_OFFSET: usize = 0;
+    const DOMAIN_ID_OFFSET: usize = 4;
+    const ENERGY_OFFSET: usize = 8;
+
+    /// Number of bytes of an encoded `RaplEnergy`.
+    pub const ENCODED_LEN: usize = Self::ENERGY_OFFSET + 8;
+
+    /// Decodes the bytes sent by the ebpf program, or returns `None` if there are less than [`RaplEnergy::ENCODED_LEN`] bytes.
+    pub fn from_le_bytes(bytes: &[u8]) -> Option<RaplEnergy> {
+        if bytes.len() < Self::ENCODED_LEN {
+            return None;
+        }
+        let cpu_id = u32::from_le_bytes(bytes[Self::CPU_ID_OFFSET..Self::CPU_ID_OFFSET + 4].try_into().unwrap());
+        let domain_id = bytes[Self::DOMAIN_ID_OFFSET];
+        let energy = u64::from_le_bytes(bytes[Self::ENERGY_OFFSET..Self::ENERGY_OFFSET + 8].try_into().unwrap());
+        Some(RaplEnergy {
+            cpu_id,
+            domain_id,
+            energy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::{offset_of, size_of};
+
+    use super::RaplEnergy;
+
+    #[test]
+    fn test_layout() {
+        // the offsets of the decoder must match the layout of the struct in the ebpf program
+        assert_eq!(offset_of!(RaplEnergy, cpu_id), RaplEnergy::CPU_ID_OFFSET);
+        assert_eq!(offset_of!(RaplEnergy, domain_id), RaplEnergy::DOMAIN_ID_OFFSET);
+        assert_eq!(offset_of!(RaplEnergy, energy), RaplEnergy::ENERGY_OFFSET);
+        assert_eq!(size_of::<RaplEnergy>(), 16);
+    }
+
+    #[test]
+    fn test_from_le_bytes() {
+        let bytes: [u8; 16] = [
+            0x2a, 0x01, 0x00, 0x00, // cpu_id = 298
+            0x03, // domain_id = 3
+            0xff, 0xff, 0xff, // padding, ignored
+            0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // energy
+        ];
+        let expected = RaplEnergy {
+            cpu_id: 298,
+            domain_id: 3,
+            energy: 0x1122_3344_5566_7788,
+        };
+        assert_eq!(RaplEnergy::from_le_bytes(&bytes), Some(expected));
+        // a longer buffer is fine
+        let mut longer = [0u8; 32];
+        longer[..16].copy_from_slice(&bytes);
+        assert_eq!(RaplEnergy::from_le_bytes(&longer), Some(expected));
+        assert_eq!(RaplEnergy::from_le_bytes(&bytes[..15]), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Asynchronous variant of [`EnergyProbe`], for the probes that receive their data asynchronously
+//! (e.g. the eBPF probe, whose program is triggered by the kernel).
+
+use std::future::Future;
+
+use anyhow::Context;
+use tokio::task::JoinHandle;
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplError};
+
+/// Like [`EnergyProbe`], but `poll` waits for new data instead of reading the counters immediately.
+pub trait AsyncEnergyProbe: Send {
+    /// Waits for new values, then updates the energy measurements.
+    fn poll(&mut self) -> impl Future<Output = Result<(), RaplError>> + Send;
+
+    /// Retrieves the latest measurements.
+    fn measurements(&self) -> &EnergyMeasurements;
+
+    /// Resets the measurements.
+    fn reset(&mut self);
+}
+
+/// Adapts any synchronous [`EnergyProbe`] to [`AsyncEnergyProbe`], by polling it with `spawn_blocking`.
+///
+/// `poll` is cancel-safe: if its future is dropped (e.g. in a `select!`), the blocking poll keeps running
+/// and the next call to `poll` waits for it instead of starting a new one.
+///
+/// This must be used in a tokio runtime.
+pub struct BlockingProbe {
+    /// The probe, `None` while it is being polled on the blocking thread.
+    probe: Option<Box<dyn EnergyProbe>>,
+    /// The blocking poll in progress, which gives the probe back.
+    pending: Option<PollTask>,
+}
+
+type PollTask = JoinHandle<(Box<dyn EnergyProbe>, Result<(), RaplError>)>;
+
+impl BlockingProbe {
+    pub fn new(probe: impl EnergyProbe + 'static) -> BlockingProbe {
+        BlockingProbe::from(Box::new(probe) as Box<dyn EnergyProbe>)
+    }
+
+    fn probe(&self) -> &dyn EnergyProbe {
+        self.probe.as_deref().expect(POLL_IN_PROGRESS)
+    }
+}
+
+const POLL_IN_PROGRESS: &str = "the probe is being polled: the previous poll() has been interrupted";
+
+impl From<Box<dyn EnergyProbe>> for BlockingProbe {
+    fn from(probe: Box<dyn EnergyProbe>) -> Self {
+        BlockingProbe {
+            probe: Some(probe),
+            pending: None,
+        }
+    }
+}
+
+impl AsyncEnergyProbe for BlockingProbe {
+    async fn poll(&mut self) -> Result<(), RaplError> {
+        if self.pending.is_none() {
+            let mut probe = self.probe.take().expect(POLL_IN_PROGRESS);
+            self.pending = Some(tokio::task::spawn_blocking(move || {
+                let res = probe.poll();
+                (probe, res)
+            }));
+        }
+        let handle = self.pending.as_mut().unwrap();
+        let joined = handle.await;
+        self.pending = None;
+        let (probe, res) = joined.context("the blocking poll task failed")?;
+        self.probe = Some(probe);
+        res
+    }
+
+    fn measurements(&self) -> &EnergyMeasurements {
+        self.probe().measurements()
+    }
+
+    fn reset(&mut self) {
+        self.probe.as_mut().expect(POLL_IN_PROGRESS).reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncEnergyProbe, BlockingProbe};
+    use crate::{mock::MockProbe, RaplDomainType};
+
+    #[tokio::test]
+    async fn test_blocking_probe() -> anyhow::Result<()> {
+        let mock = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![0, 10, 25], u32::MAX as u64, 1.0);
+        let mut probe = BlockingProbe::new(mock);
+        probe.poll().await?;
+        probe.poll().await?;
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, Some(10.0));
+        probe.poll().await?;
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, Some(15.0));
+
+        probe.reset();
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_poll() -> anyhow::Result<()> {
+        let mock = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![0, 10, 25], u32::MAX as u64, 1.0);
+        let mut probe = BlockingProbe::new(mock);
+        probe.poll().await?;
+
+        // drop the future before its completion: the next poll must finish the interrupted one
+        tokio::select! {
+            biased;
+            _ = std::future::ready(()) => (),
+            _ = probe.poll() => panic!("the poll should not be ready immediately"),
+        }
+        probe.poll().await?;
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, Some(10.0));
+        Ok(())
+    }
+}
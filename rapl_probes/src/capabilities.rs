@@ -0,0 +1,316 @@
+//! Cheap checks of the probes that can be used by the current user, without creating them.
+//!
+//! The checks only look at the filesystem and at the capabilities of the process: they don't open
+//! the counters, load the msr module or load the eBPF program. Therefore, the result is **advisory**:
+//! a probe that is reported as usable can still fail when it is created (e.g. because of a kernel bug),
+//! and creating the probe is the only way to be sure.
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Whether a probe is likely to be usable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeCapability {
+    /// The interface exists and the current user seems to have the required permissions.
+    Usable,
+    /// The interface is not available on this system (or in this build), with the reason.
+    Unavailable(String),
+    /// The interface exists, but the current user lacks some permissions, with the missing permissions.
+    PermissionDenied(String),
+}
+
+impl ProbeCapability {
+    pub fn is_usable(&self) -> bool {
+        *self == ProbeCapability::Usable
+    }
+}
+
+/// The capability of each probe type, see [`probe_capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeCapabilities {
+    pub powercap: ProbeCapability,
+    pub perf_event: ProbeCapability,
+    pub ebpf: ProbeCapability,
+    pub msr: ProbeCapability,
+    pub hwmon: ProbeCapability,
+}
+
+// Linux capabilities, see `man 7 capabilities`.
+const CAP_DAC_OVERRIDE: u32 = 1;
+const CAP_DAC_READ_SEARCH: u32 = 2;
+const CAP_SYS_RAWIO: u32 = 17;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+const POWERCAP_ENERGY_PATH: &str = "/sys/devices/virtual/powercap/intel-rapl/intel-rapl:0/energy_uj";
+const PERF_POWER_TYPE_PATH: &str = "/sys/devices/power/type";
+const PERF_EVENT_PARANOID_PATH: &str = "/proc/sys/kernel/perf_event_paranoid";
+const MSR_PATH: &str = "/dev/cpu/0/msr";
+const HWMON_PATH: &str = "/sys/class/hwmon";
+
+/// The information about the system that the checks need.
+/// This is a trait in order to test the checks without the real filesystem.
+pub(crate) trait SystemView {
+    fn exists(&self, path: &Path) -> bool;
+    /// Returns `true` if the current user has the permission to read the file.
+    fn readable(&self, path: &Path) -> bool;
+    fn read_to_string(&self, path: &Path) -> Option<String>;
+    fn list_dir(&self, path: &Path) -> Vec<PathBuf>;
+    /// Returns `true` if the process has the capability `cap` in its effective set.
+    fn has_capability(&self, cap: u32) -> bool;
+}
+
+/// The real system, queried through procfs and sysfs.
+struct HostSystem {
+    /// Effective capabilities of the process.
+    cap_eff: u64,
+    /// Effective user and group ids.
+    uid: u32,
+    gid: u32,
+}
+
+impl HostSystem {
+    fn new() -> HostSystem {
+        let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let field = |name: &str| status.lines().find_map(|l| l.strip_prefix(name)).map(str::trim);
+        // "Uid:" and "Gid:" contain the real, effective, saved and filesystem ids
+        let effective_id = |name: &str| field(name).and_then(|ids| ids.split_whitespace().nth(1)?.parse().ok());
+        HostSystem {
+            cap_eff: field("CapEff:").and_then(|c| u64::from_str_radix(c, 16).ok()).unwrap_or(0),
+            uid: effective_id("Uid:").unwrap_or(u32::MAX),
+            gid: effective_id("Gid:").unwrap_or(u32::MAX),
+        }
+    }
+}
+
+impl SystemView for HostSystem {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn readable(&self, path: &Path) -> bool {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        if self.has_capability(CAP_DAC_OVERRIDE) || self.has_capability(CAP_DAC_READ_SEARCH) {
+            return true;
+        }
+        let mode = metadata.mode();
+        if metadata.uid() == self.uid {
+            mode & 0o400 != 0
+        } else if metadata.gid() == self.gid {
+            mode & 0o040 != 0
+        } else {
+            mode & 0o004 != 0
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    fn list_dir(&self, path: &Path) -> Vec<PathBuf> {
+        match fs::read_dir(path) {
+            Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn has_capability(&self, cap: u32) -> bool {
+        self.cap_eff & (1 << cap) != 0
+    }
+}
+
+/// Checks, for each probe type, whether the current user can probably use it.
+///
+/// This is advisory, see the [module documentation](self).
+pub fn probe_capabilities() -> ProbeCapabilities {
+    capabilities_of(&HostSystem::new())
+}
+
+pub(crate) fn capabilities_of(system: &impl SystemView) -> ProbeCapabilities {
+    let perf_event = perf_event_capability(system);
+    ProbeCapabilities {
+        powercap: powercap_capability(system),
+        ebpf: ebpf_capability(system, &perf_event),
+        perf_event,
+        msr: msr_capability(system),
+        hwmon: hwmon_capability(system),
+    }
+}
+
+fn powercap_capability(system: &impl SystemView) -> ProbeCapability {
+    let path = Path::new(POWERCAP_ENERGY_PATH);
+    if !system.exists(path) {
+        ProbeCapability::Unavailable(format!("{POWERCAP_ENERGY_PATH} does not exist"))
+    } else if !system.readable(path) {
+        // since Linux 5.10, the energy counters are only readable by root
+        ProbeCapability::PermissionDenied(format!("{POWERCAP_ENERGY_PATH} is not readable"))
+    } else {
+        ProbeCapability::Usable
+    }
+}
+
+fn perf_event_capability(system: &impl SystemView) -> ProbeCapability {
+    if !system.exists(Path::new(PERF_POWER_TYPE_PATH)) {
+        return ProbeCapability::Unavailable(String::from("the RAPL PMU (power) is not available in perf_event"));
+    }
+    // the RAPL events are system-wide, they require perf_event_paranoid <= 0 or CAP_PERFMON
+    let paranoid: i32 = system
+        .read_to_string(Path::new(PERF_EVENT_PARANOID_PATH))
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(2);
+    if paranoid <= 0 || system.has_capability(CAP_PERFMON) || system.has_capability(CAP_SYS_ADMIN) {
+        ProbeCapability::Usable
+    } else {
+        ProbeCapability::PermissionDenied(format!(
+            "perf_event_paranoid is {paranoid}, CAP_PERFMON or perf_event_paranoid <= 0 is required"
+        ))
+    }
+}
+
+fn ebpf_capability(system: &impl SystemView, perf_event: &ProbeCapability) -> ProbeCapability {
+    if !cfg!(feature = "enable_ebpf") {
+        return ProbeCapability::Unavailable(String::from("the enable_ebpf feature has not been enabled"));
+    }
+    // the eBPF program reads the perf events
+    if !perf_event.is_usable() {
+        return perf_event.clone();
+    }
+    let can_load = system.has_capability(CAP_SYS_ADMIN)
+        || (system.has_capability(CAP_BPF) && system.has_capability(CAP_PERFMON));
+    if can_load {
+        ProbeCapability::Usable
+    } else {
+        ProbeCapability::PermissionDenied(String::from("CAP_BPF and CAP_PERFMON (or CAP_SYS_ADMIN) are required"))
+    }
+}
+
+fn msr_capability(system: &impl SystemView) -> ProbeCapability {
+    let path = Path::new(MSR_PATH);
+    if !system.exists(path) {
+        ProbeCapability::Unavailable(format!(
+            "{MSR_PATH} does not exist, the msr kernel module is probably not loaded"
+        ))
+    } else if !system.readable(path) || !system.has_capability(CAP_SYS_RAWIO) {
+        ProbeCapability::PermissionDenied(format!("reading {MSR_PATH} requires the read permission and CAP_SYS_RAWIO"))
+    } else {
+        ProbeCapability::Usable
+    }
+}
+
+fn hwmon_capability(system: &impl SystemView) -> ProbeCapability {
+    let driver_loaded = system.list_dir(Path::new(HWMON_PATH)).into_iter().any(|device| {
+        system
+            .read_to_string(&device.join("name"))
+            .is_some_and(|name| name.trim() == "amd_energy")
+    });
+    if driver_loaded {
+        ProbeCapability::Usable
+    } else {
+        ProbeCapability::Unavailable(String::from("the amd_energy hwmon driver is not loaded"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::path::{Path, PathBuf};
+
+    use super::{capabilities_of, ProbeCapability, SystemView, CAP_PERFMON, CAP_SYS_RAWIO};
+
+    /// A fake system, with the given files (and their content) and capabilities.
+    #[derive(Default)]
+    struct FakeSystem {
+        files: HashMap<PathBuf, String>,
+        unreadable: HashSet<PathBuf>,
+        caps: HashSet<u32>,
+    }
+
+    impl FakeSystem {
+        fn with_file(mut self, path: &str, content: &str) -> FakeSystem {
+            self.files.insert(PathBuf::from(path), content.to_owned());
+            self
+        }
+    }
+
+    impl SystemView for FakeSystem {
+        fn exists(&self, path: &Path) -> bool {
+            self.files.contains_key(path)
+        }
+
+        fn readable(&self, path: &Path) -> bool {
+            self.exists(path) && !self.unreadable.contains(path)
+        }
+
+        fn read_to_string(&self, path: &Path) -> Option<String> {
+            self.files.get(path).cloned()
+        }
+
+        fn list_dir(&self, path: &Path) -> Vec<PathBuf> {
+            let children: HashSet<PathBuf> = self
+                .files
+                .keys()
+                .filter_map(|p| p.strip_prefix(path).ok()?.components().next().map(|c| path.join(c)))
+                .collect();
+            children.into_iter().collect()
+        }
+
+        fn has_capability(&self, cap: u32) -> bool {
+            self.caps.contains(&cap)
+        }
+    }
+
+    fn intel_system() -> FakeSystem {
+        FakeSystem::default()
+            .with_file("/sys/devices/virtual/powercap/intel-rapl/intel-rapl:0/energy_uj", "123\n")
+            .with_file("/sys/devices/power/type", "23\n")
+            .with_file("/proc/sys/kernel/perf_event_paranoid", "2\n")
+            .with_file("/dev/cpu/0/msr", "")
+            .with_file("/sys/class/hwmon/hwmon0/name", "coretemp\n")
+    }
+
+    #[test]
+    fn test_unprivileged_user() {
+        let mut system = intel_system();
+        system
+            .unreadable
+            .insert(PathBuf::from("/sys/devices/virtual/powercap/intel-rapl/intel-rapl:0/energy_uj"));
+        system.unreadable.insert(PathBuf::from("/dev/cpu/0/msr"));
+        let caps = capabilities_of(&system);
+        assert!(matches!(caps.powercap, ProbeCapability::PermissionDenied(_)));
+        assert!(matches!(caps.perf_event, ProbeCapability::PermissionDenied(_)));
+        assert!(matches!(caps.msr, ProbeCapability::PermissionDenied(_)));
+        assert!(matches!(caps.hwmon, ProbeCapability::Unavailable(_)));
+        assert!(!caps.ebpf.is_usable());
+    }
+
+    #[test]
+    fn test_privileged_user() {
+        let mut system = intel_system();
+        system.caps.extend([CAP_PERFMON, CAP_SYS_RAWIO]);
+        let caps = capabilities_of(&system);
+        assert_eq!(caps.powercap, ProbeCapability::Usable);
+        assert_eq!(caps.perf_event, ProbeCapability::Usable);
+        assert_eq!(caps.msr, ProbeCapability::Usable);
+
+        // perf_event_paranoid <= 0 allows everyone to use the RAPL events
+        let system = intel_system().with_file("/proc/sys/kernel/perf_event_paranoid", "-1\n");
+        assert_eq!(capabilities_of(&system).perf_event, ProbeCapability::Usable);
+    }
+
+    #[test]
+    fn test_missing_interfaces() {
+        let system = FakeSystem::default()
+            .with_file("/sys/class/hwmon/hwmon0/name", "k10temp\n")
+            .with_file("/sys/class/hwmon/hwmon1/name", "amd_energy\n");
+        let caps = capabilities_of(&system);
+        assert!(matches!(caps.powercap, ProbeCapability::Unavailable(_)));
+        assert!(matches!(caps.perf_event, ProbeCapability::Unavailable(_)));
+        assert!(matches!(caps.msr, ProbeCapability::Unavailable(msg) if msg.contains("msr kernel module")));
+        assert!(matches!(caps.ebpf, ProbeCapability::Unavailable(_)));
+        assert_eq!(caps.hwmon, ProbeCapability::Usable);
+    }
+}
@@ -0,0 +1,246 @@
+//! Attribution of the energy of the sockets to a cgroup (container, service, ...).
+//!
+//! RAPL only measures whole sockets, there is no per-process counter.
+//! [`ScaledProbe`] **approximates** the energy of a cgroup by multiplying the energy of each socket
+//! by the fraction of the cpu time of this socket that the cgroup has used.
+//! This ignores the differences between the workloads (a core running AVX code consumes more than an idle-looping one),
+//! the idle power of the socket (which is attributed in proportion to the cpu time, like the rest)
+//! and the energy of the domains that do not depend on the cpu time (e.g. DRAM).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError};
+
+/// Gives the fraction of the cpu time of each socket that has been used by something (usually a cgroup).
+pub trait CpuShare: Send {
+    /// Returns, for each socket, the fraction (between 0 and 1) of the cpu time of the socket
+    /// that has been used since the previous call.
+    fn socket_fractions(&mut self) -> anyhow::Result<Vec<f64>>;
+}
+
+/// The cpu share of a cgroup, read from its accounting files.
+///
+/// With cgroup v1, `cpuacct.usage_percpu` gives the cpu time per cpu, which is summed per socket.
+/// With cgroup v2, `cpu.stat` only gives the total cpu time, which is split between the sockets
+/// in proportion to their number of cpus (another approximation).
+pub struct CgroupCpuShare {
+    cgroup: PathBuf,
+    /// The cpus of each socket.
+    socket_cpus: Vec<Vec<u32>>,
+    previous: Option<(Instant, Vec<Duration>)>,
+}
+
+impl CgroupCpuShare {
+    /// `cgroup` is the directory of the cgroup, for instance `/sys/fs/cgroup/system.slice/docker-<id>.scope`,
+    /// `socket_cpus` contains the cpus of each socket, in the order of the sockets of the probe.
+    pub fn new(cgroup: PathBuf, socket_cpus: Vec<Vec<u32>>) -> CgroupCpuShare {
+        CgroupCpuShare {
+            cgroup,
+            socket_cpus,
+            previous: None,
+        }
+    }
+
+    /// Reads the cpu time used by the cgroup on each socket.
+    fn cpu_time_per_socket(&self) -> anyhow::Result<Vec<Duration>> {
+        let percpu_path = self.cgroup.join("cpuacct.usage_percpu");
+        if percpu_path.exists() {
+            let content = fs::read_to_string(&percpu_path)?;
+            let per_cpu = parse_usage_percpu(&content).with_context(|| format!("failed to parse {percpu_path:?}"))?;
+            let per_socket = self
+                .socket_cpus
+                .iter()
+                .map(|cpus| cpus.iter().filter_map(|cpu| per_cpu.get(*cpu as usize)).sum())
+                .collect();
+            Ok(per_socket)
+        } else {
+            let stat_path = self.cgroup.join("cpu.stat");
+            let content = fs::read_to_string(&stat_path).with_context(|| format!("failed to read {stat_path:?}"))?;
+            let total = parse_cpu_stat_usage(&content).with_context(|| format!("failed to parse {stat_path:?}"))?;
+            let n_cpus: usize = self.socket_cpus.iter().map(Vec::len).sum();
+            let per_socket = self
+                .socket_cpus
+                .iter()
+                .map(|cpus| total.mul_f64(cpus.len() as f64 / n_cpus.max(1) as f64))
+                .collect();
+            Ok(per_socket)
+        }
+    }
+}
+
+impl CpuShare for CgroupCpuShare {
+    fn socket_fractions(&mut self) -> anyhow::Result<Vec<f64>> {
+        let now = Instant::now();
+        let usage = self.cpu_time_per_socket()?;
+        let fractions = match &self.previous {
+            Some((prev_time, prev_usage)) => {
+                let wall = now.saturating_duration_since(*prev_time).as_secs_f64();
+                usage
+                    .iter()
+                    .zip(prev_usage)
+                    .zip(&self.socket_cpus)
+                    .map(|((u, prev), cpus)| {
+                        let available = wall * cpus.len() as f64;
+                        if available > 0.0 {
+                            (u.saturating_sub(*prev).as_secs_f64() / available).clamp(0.0, 1.0)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            }
+            None => vec![0.0; usage.len()],
+        };
+        self.previous = Some((now, usage));
+        Ok(fractions)
+    }
+}
+
+/// Parses `cpuacct.usage_percpu`: the cpu time of each cpu, in nanoseconds, separated by spaces.
+fn parse_usage_percpu(content: &str) -> anyhow::Result<Vec<Duration>> {
+    content
+        .split_whitespace()
+        .map(|ns| Ok(Duration::from_nanos(ns.parse()?)))
+        .collect()
+}
+
+/// Parses the `usage_usec` line of `cpu.stat`.
+fn parse_cpu_stat_usage(content: &str) -> anyhow::Result<Duration> {
+    let usec = content
+        .lines()
+        .find_map(|l| l.strip_prefix("usage_usec "))
+        .ok_or_else(|| anyhow!("usage_usec not found"))?;
+    Ok(Duration::from_micros(usec.trim().parse()?))
+}
+
+/// Wraps a probe and attributes the energy of each socket to a cgroup, in proportion to its cpu time.
+///
+/// [`EnergyProbe::measurements`] returns the attributed energy, [`ScaledProbe::raw_measurements`] returns
+/// the energy of the whole sockets. This is an approximation, see the [module documentation](self).
+pub struct ScaledProbe {
+    base: Box<dyn EnergyProbe>,
+    share: Box<dyn CpuShare>,
+    attributed: EnergyMeasurements,
+}
+
+impl ScaledProbe {
+    pub fn new(base: Box<dyn EnergyProbe>, share: Box<dyn CpuShare>) -> ScaledProbe {
+        let attributed = base.measurements().clone();
+        ScaledProbe {
+            base,
+            share,
+            attributed,
+        }
+    }
+
+    /// Attributes the energy of the sockets to the cgroup at `cgroup_path`.
+    /// `socket_cpus` contains the cpus of each socket.
+    pub fn for_cgroup(base: Box<dyn EnergyProbe>, cgroup_path: &Path, socket_cpus: Vec<Vec<u32>>) -> ScaledProbe {
+        let share = CgroupCpuShare::new(cgroup_path.to_path_buf(), socket_cpus);
+        ScaledProbe::new(base, Box::new(share))
+    }
+
+    /// The energy of the whole sockets, as measured by the base probe.
+    pub fn raw_measurements(&self) -> &EnergyMeasurements {
+        self.base.measurements()
+    }
+}
+
+impl EnergyProbe for ScaledProbe {
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.base.poll()?;
+        let fractions = self.share.socket_fractions()?;
+        let raw = self.base.measurements();
+
+        let mut attributed = raw.clone();
+        for (socket_id, domains) in attributed.per_socket.iter_mut().enumerate() {
+            let fraction = fractions.get(socket_id).copied().unwrap_or(0.0);
+            for (domain, counter) in domains.iter_mut() {
+                counter.joules = counter.joules.map(|j| j * fraction);
+                let previous_total = self.attributed.per_socket.get(socket_id).map_or(0.0, |d| d[domain].cumulative_joules);
+                counter.cumulative_joules = previous_total + counter.joules.unwrap_or(0.0);
+            }
+        }
+        self.attributed = attributed;
+        Ok(())
+    }
+
+    fn measurements(&self) -> &EnergyMeasurements {
+        &self.attributed
+    }
+
+    fn reset(&mut self) {
+        self.base.reset();
+        self.attributed.clear();
+    }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.base.energy_unit_for(socket, domain)
+    }
+
+    /// Returns the raw values of the base probe: they are not attributed to the cgroup.
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        self.base.read_absolute(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    use super::{parse_cpu_stat_usage, parse_usage_percpu, CpuShare, ScaledProbe};
+    use crate::mock::MockProbe;
+    use crate::{EnergyProbe, RaplDomainType};
+
+    /// Returns scripted fractions.
+    struct SyntheticShare(VecDeque<Vec<f64>>);
+
+    impl CpuShare for SyntheticShare {
+        fn socket_fractions(&mut self) -> anyhow::Result<Vec<f64>> {
+            Ok(self.0.pop_front().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_scaled_probe() -> anyhow::Result<()> {
+        let max = u32::MAX as u64;
+        let base = MockProbe::new(2)
+            .with_counter(0, RaplDomainType::Package, vec![0, 100, 300], max, 1.0)
+            .with_counter(1, RaplDomainType::Package, vec![0, 50, 100], max, 1.0);
+        let share = SyntheticShare(VecDeque::from([vec![0.0, 0.0], vec![0.5, 0.1], vec![0.25, 1.0]]));
+        let mut probe = ScaledProbe::new(Box::new(base), Box::new(share));
+
+        probe.poll()?;
+        probe.poll()?;
+        let attributed = &probe.measurements().per_socket;
+        assert_eq!(attributed[0][RaplDomainType::Package].joules, Some(50.0));
+        assert_eq!(attributed[1][RaplDomainType::Package].joules, Some(5.0));
+        assert_eq!(probe.raw_measurements().per_socket[0][RaplDomainType::Package].joules, Some(100.0));
+
+        probe.poll()?;
+        let attributed = &probe.measurements().per_socket;
+        assert_eq!(attributed[0][RaplDomainType::Package].joules, Some(50.0));
+        assert_eq!(attributed[0][RaplDomainType::Package].cumulative_joules, 100.0);
+        assert_eq!(attributed[1][RaplDomainType::Package].joules, Some(50.0));
+        assert_eq!(attributed[1][RaplDomainType::Package].cumulative_joules, 55.0);
+        assert_eq!(probe.raw_measurements().per_socket[0][RaplDomainType::Package].cumulative_joules, 300.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cgroup_files() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_usage_percpu("1000 2000000 0 \n")?,
+            vec![Duration::from_nanos(1000), Duration::from_millis(2), Duration::ZERO]
+        );
+        let stat = "usage_usec 1500000\nuser_usec 1000000\nsystem_usec 500000\n";
+        assert_eq!(parse_cpu_stat_usage(stat)?, Duration::from_millis(1500));
+        assert!(parse_cpu_stat_usage("user_usec 1\n").is_err());
+        Ok(())
+    }
+}
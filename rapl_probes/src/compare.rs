@@ -0,0 +1,163 @@
+//! Comparison of two measurements of the same run, e.g. with the powercap probe and the perf-event probe,
+//! to quantify the discrepancies between the RAPL interfaces.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+
+use crate::io::parse_csv;
+use crate::stats::Correlation;
+use crate::RaplDomainType;
+
+/// Energy per (time bucket, socket, domain).
+///
+/// The two probes do not poll at the same instants: their values are summed per bucket of time before being compared.
+pub type BucketedEnergy = HashMap<(u64, u32, RaplDomainType), f64>;
+
+/// Comparison of one domain, over all the sockets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainDiff {
+    pub domain: RaplDomainType,
+    /// The number of (bucket, socket) that are in both files.
+    pub pairs: usize,
+    /// The mean of `|a - b|` over the pairs, in Joules.
+    pub mean_abs_diff: f64,
+    /// The mean energy of a bucket in the first file, over the pairs, in Joules.
+    pub mean_a: f64,
+    /// The Pearson correlation between the values of the two files, `None` if it cannot be computed.
+    pub correlation: Option<f64>,
+    /// The number of (bucket, socket) that are only in the first file, or only in the second one.
+    pub unmatched: usize,
+}
+
+/// Reads a CSV file written by `cli_poll_rapl`, see [`parse_csv`], and sums its values per bucket of time.
+/// The bucket of a row is `timestamp_ms / bucket`.
+pub fn read_buckets<R: BufRead>(reader: R, bucket: Duration) -> anyhow::Result<BucketedEnergy> {
+    let bucket_ms = bucket.as_millis() as u64;
+    if bucket_ms == 0 {
+        return Err(anyhow!("the time bucket must be at least 1 ms"));
+    }
+    let mut energy = BucketedEnergy::new();
+    for snapshot in parse_csv(reader) {
+        let (timestamp, measurements) = snapshot?;
+        let timestamp_ms = timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64;
+        for (socket, domains) in measurements.per_socket.iter().enumerate() {
+            for (domain, counter) in domains {
+                if let Some(joules) = counter.joules {
+                    *energy.entry((timestamp_ms / bucket_ms, socket as u32, domain)).or_default() += joules;
+                }
+            }
+        }
+    }
+    Ok(energy)
+}
+
+/// Compares the energy of the domains that are in the two measurements, in the order of [`RaplDomainType::ALL`].
+pub fn compare(a: &BucketedEnergy, b: &BucketedEnergy) -> Vec<DomainDiff> {
+    RaplDomainType::ALL
+        .iter()
+        .filter_map(|&domain| {
+            let mut correlation = Correlation::new();
+            let mut abs_diff_sum = 0.0;
+            let mut unmatched = 0;
+            for (key, &joules_a) in a.iter().filter(|(k, _)| k.2 == domain) {
+                match b.get(key) {
+                    Some(&joules_b) => {
+                        correlation.push(joules_a, joules_b);
+                        abs_diff_sum += (joules_a - joules_b).abs();
+                    }
+                    None => unmatched += 1,
+                }
+            }
+            unmatched += b.keys().filter(|k| k.2 == domain && !a.contains_key(k)).count();
+
+            let pairs = correlation.count();
+            if pairs == 0 && unmatched == 0 {
+                // the domain is in none of the files
+                return None;
+            }
+            Some(DomainDiff {
+                domain,
+                pairs,
+                mean_abs_diff: if pairs > 0 { abs_diff_sum / pairs as f64 } else { 0.0 },
+                mean_a: correlation.mean_x().unwrap_or(0.0),
+                correlation: correlation.coefficient(),
+                unmatched,
+            })
+        })
+        .collect()
+}
+
+/// Reads two CSV files and compares them, see [`read_buckets`] and [`compare`].
+pub fn compare_csv<A: BufRead, B: BufRead>(a: A, b: B, bucket: Duration) -> anyhow::Result<Vec<DomainDiff>> {
+    let a = read_buckets(a, bucket)?;
+    let b = read_buckets(b, bucket)?;
+    Ok(compare(&a, &b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{compare_csv, read_buckets};
+    use crate::RaplDomainType;
+
+    #[test]
+    fn test_read_buckets() -> anyhow::Result<()> {
+        let csv = "timestamp_ms;socket;domain;overflow;joules
+1100;0;Package;false;10
+1100;all;total;false;10
+1600;0;Package;true;5
+2100;0;Package;false;7
+";
+        let buckets = read_buckets(csv.as_bytes(), Duration::from_secs(1))?;
+        assert_eq!(buckets.len(), 2);
+        // the total rows are ignored, the overflow column is parsed but it does not change the value
+        assert_eq!(buckets[&(1, 0, RaplDomainType::Package)], 15.0);
+        assert_eq!(buckets[&(2, 0, RaplDomainType::Package)], 7.0);
+        assert!(read_buckets(csv.as_bytes(), Duration::ZERO).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_csv() -> anyhow::Result<()> {
+        // the same run, measured by two probes that do not poll at the same time
+        let powercap = "timestamp_ms;socket;domain;overflow;joules
+1000;0;Package;false;10
+1000;0;Dram;false;2
+2000;0;Package;false;20
+2000;0;Dram;false;2
+3000;0;Package;false;30
+3000;0;Dram;false;2
+4000;0;Package;false;40
+";
+        let perf_event = "timestamp_ms;socket;domain;overflow;joules;cumulative_joules
+1010;0;Package;false;11;11
+1010;0;Dram;false;2;2
+2010;0;Package;false;21;32
+2010;0;Dram;false;2;4
+3010;0;Package;false;31;63
+3010;0;Dram;false;2;6
+";
+        let diffs = compare_csv(powercap.as_bytes(), perf_event.as_bytes(), Duration::from_secs(1))?;
+        assert_eq!(diffs.len(), 2);
+
+        let package = &diffs[0];
+        assert_eq!(package.domain, RaplDomainType::Package);
+        assert_eq!(package.pairs, 3);
+        assert_eq!(package.mean_abs_diff, 1.0);
+        assert_eq!(package.mean_a, 20.0);
+        assert!((package.correlation.unwrap() - 1.0).abs() < 1e-9);
+        // the last bucket is only in the first file
+        assert_eq!(package.unmatched, 1);
+
+        let dram = &diffs[1];
+        assert_eq!(dram.domain, RaplDomainType::Dram);
+        assert_eq!((dram.pairs, dram.mean_abs_diff, dram.unmatched), (3, 0.0, 0));
+        // constant values: no correlation
+        assert_eq!(dram.correlation, None);
+        Ok(())
+    }
+}
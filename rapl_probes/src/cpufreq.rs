@@ -0,0 +1,116 @@
+//! Sampling of the current frequency of the cpus (cpufreq), to correlate the DVFS state with the energy.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::CpuId;
+
+const CPU_SYSFS_PATH: &str = "/sys/devices/system/cpu";
+
+/// Reads the current frequency of the cpus of each socket.
+pub struct CpuFreqSampler {
+    /// The `scaling_cur_freq` files of the cpus of each socket.
+    /// The cpus without cpufreq are not included.
+    freq_files: Vec<Vec<PathBuf>>,
+}
+
+impl CpuFreqSampler {
+    /// Creates a sampler for the sockets of `socket_cpus` (one cpu per socket, as returned by
+    /// [`crate::cpus_to_monitor`]). Each socket is sampled on all its online cpus.
+    pub fn for_sockets(socket_cpus: &[CpuId]) -> anyhow::Result<CpuFreqSampler> {
+        let root = Path::new(CPU_SYSFS_PATH);
+        let package_of = |cpu: u32| -> anyhow::Result<u32> {
+            let path = root.join(format!("cpu{cpu}/topology/physical_package_id"));
+            let read = fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+            Ok(read.trim_end().parse()?)
+        };
+        let leader_packages = socket_cpus
+            .iter()
+            .map(|c| package_of(c.cpu))
+            .collect::<anyhow::Result<Vec<u32>>>()?;
+
+        let mut socket_members = vec![Vec::new(); socket_cpus.len()];
+        for cpu in crate::online_cpus()? {
+            let package = package_of(cpu)?;
+            if let Some(socket) = leader_packages.iter().position(|p| *p == package) {
+                socket_members[socket].push(cpu);
+            }
+        }
+        Ok(CpuFreqSampler::new(root, &socket_members))
+    }
+
+    /// Creates a sampler for the given cpus of each socket, in the sysfs directory `cpu_root`.
+    fn new(cpu_root: &Path, socket_members: &[Vec<u32>]) -> CpuFreqSampler {
+        let freq_files = socket_members
+            .iter()
+            .map(|cpus| {
+                cpus.iter()
+                    .map(|cpu| cpu_root.join(format!("cpu{cpu}/cpufreq/scaling_cur_freq")))
+                    // some cpus (or virtual machines) have no cpufreq
+                    .filter(|path| path.exists())
+                    .collect()
+            })
+            .collect();
+        CpuFreqSampler { freq_files }
+    }
+
+    /// Returns the average current frequency of each socket, in MHz,
+    /// or `None` for the sockets whose cpus have no cpufreq.
+    pub fn sample(&self) -> Vec<Option<f64>> {
+        self.freq_files
+            .iter()
+            .map(|files| {
+                let frequencies = files
+                    .iter()
+                    .filter_map(|path| fs::read_to_string(path).ok())
+                    .filter_map(|content| parse_frequency_khz(&content));
+                average(frequencies).map(|khz| khz / 1000.0)
+            })
+            .collect()
+    }
+}
+
+/// Parses the content of `scaling_cur_freq`, which is a frequency in kHz.
+fn parse_frequency_khz(content: &str) -> Option<f64> {
+    content.trim().parse::<u64>().ok().map(|khz| khz as f64)
+}
+
+fn average(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{parse_frequency_khz, CpuFreqSampler};
+
+    #[test]
+    fn test_parse_frequency() {
+        assert_eq!(parse_frequency_khz("2400000\n"), Some(2_400_000.0));
+        assert_eq!(parse_frequency_khz("<unknown>\n"), None);
+        assert_eq!(parse_frequency_khz(""), None);
+    }
+
+    #[test]
+    fn test_sample_per_socket() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("rapl_probes-cpufreq-{}", std::process::id()));
+        for (cpu, khz) in [(0, "2000000\n"), (1, "3000000\n"), (2, "1200000\n")] {
+            let dir = root.join(format!("cpu{cpu}/cpufreq"));
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("scaling_cur_freq"), khz)?;
+        }
+        // cpu 3 has no cpufreq, socket 2 contains only cpu 3
+        fs::create_dir_all(root.join("cpu3"))?;
+
+        let sampler = CpuFreqSampler::new(&root, &[vec![0, 1], vec![2, 3], vec![3]]);
+        let frequencies = sampler.sample();
+        fs::remove_dir_all(&root)?;
+
+        assert_eq!(frequencies, vec![Some(2500.0), Some(1200.0), None]);
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
-use anyhow::Context;
-use aya::maps::perf::PerfEventArrayBuffer;
+use anyhow::{anyhow, Context};
+use aya::maps::perf::{AsyncPerfEventArray, AsyncPerfEventArrayBuffer, PerfEventArrayBuffer};
 use aya::maps::{Array, MapData, PerfEventArray};
 use aya::programs::{self, PerfEvent};
 use aya::{include_bytes_aligned, Bpf, BpfError};
@@ -9,14 +9,22 @@ use bytes::BytesMut;
 use log::{debug, warn};
 use std::os::fd::OwnedFd;
 use std::os::fd::FromRawFd;
+use std::time::{Duration, Instant, SystemTime};
 
 use ebpf_common::RaplEnergy;
-use crate::{perf_event, EnergyMeasurements};
+use crate::async_probe::AsyncEnergyProbe;
+use crate::{perf_event, EnergyMeasurements, RaplError};
 use super::perf_event::{pmu_type, PowerEvent};
 use super::{CpuId, EnergyProbe, RaplDomainType};
 
-// See EbpfProbe::new
-const BUF_PAGE_COUNT: usize = 8;
+/// Default number of pages of the ring buffers, see [`EbpfProbe::new`].
+pub const DEFAULT_BUF_PAGE_COUNT: usize = 8;
+
+/// Minimum delay between two warnings about lost events, so that the logs are not flooded.
+const LOST_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default number of output buffers, see [`EbpfProbe::with_out_buffer_count`].
+const DEFAULT_OUT_BUFFER_COUNT: usize = 8;
 
 /// EBPF perf event probe.
 pub struct EbpfProbe {
@@ -27,6 +35,12 @@ pub struct EbpfProbe {
     /// The buffers that receive the values of the energy counters from the EBPF program
     buffers: Vec<EbpfEnergyBuffer>,
 
+    /// The output buffers that receive the events read from `buffers`, reused at each poll
+    out_bufs: Vec<BytesMut>,
+
+    /// Number of events that have been lost since the creation of the probe
+    lost_events: LostEvents,
+
     /// Stores the energy measurements
     measurements: EnergyMeasurements,
 }
@@ -44,28 +58,39 @@ struct EbpfEnergyBuffer {
 }
 
 impl EbpfProbe {
-    pub fn new(cpus: &[CpuId], events: &[&PowerEvent], freq_hz: u64) -> anyhow::Result<EbpfProbe> {
-        crate::check_socket_cpus(cpus)?;
+    /// Creates a probe that reads the `events` on the given `cpus` (one per socket), `freq_hz` times per second.
+    ///
+    /// `buf_page_count` is the number of pages of each ring buffer, which must be a power of two.
+    /// Larger buffers use more memory but lose less events when the userspace polls less often than the
+    /// eBPF program runs. Use [`DEFAULT_BUF_PAGE_COUNT`] if unsure.
+    pub fn new(
+        cpus: &[CpuId],
+        events: &[&PowerEvent],
+        freq_hz: u64,
+        buf_page_count: usize,
+    ) -> Result<EbpfProbe, RaplError> {
+        check_probe_args(cpus, events, buf_page_count, "EbpfProbe")?;
 
         let mut bpf = prepare_ebpf_probe(cpus, events, freq_hz)?;
 
         // Open the event array and store the pointer in the struct,
         // to be able to poll the event buffer and retrieve the values in read_uj
-        let mut events_array = PerfEventArray::try_from(bpf.take_map("EVENTS").expect("map not found: EVENTS"))?;
+        let mut events_array = PerfEventArray::try_from(bpf.take_map("EVENTS").expect("map not found: EVENTS"))
+            .context("failed to open the EVENTS map")?;
 
         // The events are pushed to a ring buffer by the bpf program.
         // The ring buffer is created and accessed through `mmap` (in `PerfEventArray::open`).
         // Here, we allocate more pages in order not to lose events.
         // Aya takes care of adding the mandatory first page, so our `pages` variable is the `n`
         // in `1 + 2^n` of the `perf_event_open` manual (see `man 2 perf_event_open`).
-        let pages = Some(BUF_PAGE_COUNT);
+        let pages = Some(buf_page_count);
 
         // open every event for each cpu
         let mut buffers = Vec::new();
-        for c @ CpuId { cpu, socket: _ } in cpus {
+        for c @ CpuId { cpu, .. } in cpus {
             let index = *cpu;
-            let domains_by_id = events.into_iter().map(|evt| DomainInfo{domain: evt.domain, scale: evt.scale}).collect();
-            
+            let domains_by_id = domain_infos(events);
+
             debug!("Opening EVENTS[{index}] for domains {domains_by_id:?}");
             let buf = events_array.open(index, pages).context("failed to open event array")?;
 
@@ -78,48 +103,294 @@ impl EbpfProbe {
         Ok(EbpfProbe {
             _bpf: bpf,
             buffers,
-            measurements: EnergyMeasurements::new(cpus.len()),
+            out_bufs: new_out_bufs(DEFAULT_OUT_BUFFER_COUNT),
+            lost_events: LostEvents::default(),
+            measurements: EnergyMeasurements::for_cpus(cpus),
+        })
+    }
+
+    /// Sets the number of output buffers, i.e. the maximum number of events that one `read_events` can copy.
+    ///
+    /// This is independent of the size of the ring buffers, `poll()` calls `read_events` until the ring buffers
+    /// are drained. More output buffers mean less calls under bursty load.
+    pub fn with_out_buffer_count(mut self, count: usize) -> EbpfProbe {
+        assert!(count > 0, "at least one output buffer is required");
+        self.out_bufs = new_out_bufs(count);
+        self
+    }
+
+    /// The number of events that have been lost because the ring buffers were full.
+    pub fn lost_events(&self) -> u64 {
+        self.lost_events.total
+    }
+}
+
+/// Dropping the probe detaches the eBPF program, then closes the perf events and the ring buffers.
+impl Drop for EbpfProbe {
+    fn drop(&mut self) {
+        debug!("Detaching the eBPF program and closing {} ring buffers", self.buffers.len());
+    }
+}
+
+/// Counts the lost events and limits the rate of the warnings about them.
+#[derive(Debug, Default)]
+struct LostEvents {
+    total: u64,
+    /// Events lost since the last warning.
+    unreported: u64,
+    last_warning: Option<Instant>,
+}
+
+impl LostEvents {
+    /// Records the events lost on `cpu`, and warns about them if the last warning is old enough.
+    fn report(&mut self, lost: usize, cpu: CpuId) {
+        if lost == 0 {
+            return;
+        }
+        if let Some(n) = self.record(lost as u64, Instant::now()) {
+            warn!(
+                "{n} eBPF events lost (last on cpu {cpu:?}, {} in total), the energy is undercounted: poll more often or use larger buffers",
+                self.total
+            );
+        }
+    }
+
+    /// Records `lost` new lost events. Returns the number of events to report in a warning, if it's time to warn.
+    fn record(&mut self, lost: u64, now: Instant) -> Option<u64> {
+        self.total += lost;
+        self.unreported += lost;
+        let can_warn = match self.last_warning {
+            Some(t) => now.duration_since(t) >= LOST_WARNING_INTERVAL,
+            None => true,
+        };
+        if self.unreported > 0 && can_warn {
+            self.last_warning = Some(now);
+            Some(std::mem::take(&mut self.unreported))
+        } else {
+            None
+        }
+    }
+}
+
+/// Like [`EbpfProbe`], but [`AsyncEnergyProbe::poll`] waits for the events of the eBPF program,
+/// instead of checking whether the ring buffers are readable.
+///
+/// Thus, the probe is polled at the frequency of the eBPF program, and it must be used in a tokio runtime.
+pub struct AsyncEbpfProbe {
+    // keeps the bpf program and its maps alive, see EbpfProbe
+    _bpf: Bpf,
+    buffers: Vec<AsyncEbpfEnergyBuffer>,
+    out_bufs: Vec<BytesMut>,
+    lost_events: LostEvents,
+    measurements: EnergyMeasurements,
+}
+
+struct AsyncEbpfEnergyBuffer {
+    buf: AsyncPerfEventArrayBuffer<MapData>,
+    cpu: CpuId,
+    domains_by_id: Vec<DomainInfo>,
+}
+
+impl AsyncEbpfProbe {
+    /// Creates a probe that reads the `events` on the given `cpus` (one per socket), `freq_hz` times per second.
+    /// See [`EbpfProbe::new`] for `buf_page_count`.
+    pub fn new(
+        cpus: &[CpuId],
+        events: &[&PowerEvent],
+        freq_hz: u64,
+        buf_page_count: usize,
+    ) -> Result<AsyncEbpfProbe, RaplError> {
+        check_probe_args(cpus, events, buf_page_count, "AsyncEbpfProbe")?;
+
+        let mut bpf = prepare_ebpf_probe(cpus, events, freq_hz)?;
+        let mut events_array = AsyncPerfEventArray::try_from(bpf.take_map("EVENTS").expect("map not found: EVENTS"))
+            .context("failed to open the EVENTS map")?;
+
+        let mut buffers = Vec::new();
+        for c in cpus {
+            let domains_by_id = domain_infos(events);
+            debug!("Opening EVENTS[{}] asynchronously for domains {domains_by_id:?}", c.cpu);
+            let buf = events_array
+                .open(c.cpu, Some(buf_page_count))
+                .context("failed to open event array")?;
+            buffers.push(AsyncEbpfEnergyBuffer {
+                buf,
+                cpu: *c,
+                domains_by_id,
+            })
+        }
+        Ok(AsyncEbpfProbe {
+            _bpf: bpf,
+            buffers,
+            out_bufs: new_out_bufs(DEFAULT_OUT_BUFFER_COUNT),
+            lost_events: LostEvents::default(),
+            measurements: EnergyMeasurements::for_cpus(cpus),
+        })
+    }
+
+    /// The number of events that have been lost because the ring buffers were full.
+    pub fn lost_events(&self) -> u64 {
+        self.lost_events.total
+    }
+}
+
+/// See [`EbpfProbe`].
+impl Drop for AsyncEbpfProbe {
+    fn drop(&mut self) {
+        debug!("Detaching the eBPF program and closing {} ring buffers", self.buffers.len());
+    }
+}
+
+impl AsyncEnergyProbe for AsyncEbpfProbe {
+    async fn poll(&mut self) -> Result<(), RaplError> {
+        for (i, energy_buf) in self.buffers.iter_mut().enumerate() {
+            // wait for the next events of this cpu, the eBPF program pushes the values of all the domains at once
+            let events = energy_buf
+                .buf
+                .read_events(&mut self.out_bufs)
+                .await
+                .context("failed to read events")?;
+            if i == 0 {
+                self.measurements.set_timestamp(SystemTime::now());
+            }
+            for data_buf in self.out_bufs.iter().take(events.read) {
+                push_event(&mut self.measurements, energy_buf.cpu.socket, &energy_buf.domains_by_id, data_buf);
+            }
+            self.lost_events.report(events.lost, energy_buf.cpu);
+        }
+        Ok(())
+    }
+
+    fn measurements(&self) -> &EnergyMeasurements {
+        &self.measurements
+    }
+
+    fn reset(&mut self) {
+        self.measurements.clear()
+    }
+}
+
+/// Checks the arguments of the constructors of the eBPF probes.
+fn check_probe_args(cpus: &[CpuId], events: &[&PowerEvent], buf_page_count: usize, probe: &str) -> anyhow::Result<()> {
+    crate::check_not_empty(events, "power event", probe)?;
+    crate::check_socket_cpus(cpus)?;
+    check_event_count(events.len())?;
+    if !buf_page_count.is_power_of_two() {
+        return Err(anyhow!("the page count of the ring buffers must be a power of two, not {buf_page_count}"));
+    }
+    Ok(())
+}
+
+/// The domains of the events, in the order of the `domain_id` sent by the eBPF program.
+fn domain_infos(events: &[&PowerEvent]) -> Vec<DomainInfo> {
+    events
+        .iter()
+        .map(|evt| DomainInfo {
+            domain: evt.domain,
+            scale: evt.scale,
+        })
+        .collect()
+}
+
+/// Parses an event sent by the eBPF program, and pushes its value to the measurements.
+fn push_event(measurements: &mut EnergyMeasurements, socket: u32, domains_by_id: &[DomainInfo], data_buf: &BytesMut) {
+    // parse the energy counter (and more) from the bytes that have been read
+    // See another example at https://github.com/aya-rs/book/blob/4aa9a5b38a0d4b6a05debcb213e5540820eda1fd/examples/cgroup-skb-egress/cgroup-skb-egress/src/main.rs#L68
+    let len = data_buf.len();
+    debug!("polled data from out_bufs = {data_buf:x} (len {len})");
+
+    // the ebpf program pushes RaplEnergy structs, decode them field by field (little-endian)
+    let Some(data) = RaplEnergy::from_le_bytes(data_buf) else {
+        warn!("Ignoring an eBPF event of {len} bytes, expected at least {}", RaplEnergy::ENCODED_LEN);
+        return;
+    };
+    debug!("=> data for cpu {} domain {} = {}", data.cpu_id, data.domain_id, data.energy);
+
+    let rapl_domain_info = &domains_by_id[data.domain_id as usize];
+
+    // the ebpf program reads the same counters as PerfEventProbe, apply the same scale and max value
+    perf_event::push_counter_value(
+        measurements,
+        socket,
+        rapl_domain_info.domain,
+        data.energy,
+        rapl_domain_info.scale,
+    );
+}
+
+fn new_out_bufs(count: usize) -> Vec<BytesMut> {
+    (0..count).map(|_| BytesMut::with_capacity(std::mem::size_of::<RaplEnergy>())).collect()
+}
+
+/// Number of events read and lost by [`drain_events`].
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ReadStats {
+    read: usize,
+    lost: usize,
+}
+
+/// A buffer of events, like the [`PerfEventArrayBuffer`] of aya.
+trait EventSource {
+    fn readable(&self) -> bool;
+
+    /// Copies the pending events into `out_bufs`, at most one event per output buffer.
+    fn read_events(&mut self, out_bufs: &mut [BytesMut]) -> anyhow::Result<ReadStats>;
+}
+
+impl EventSource for PerfEventArrayBuffer<MapData> {
+    fn readable(&self) -> bool {
+        PerfEventArrayBuffer::readable(self)
+    }
+
+    fn read_events(&mut self, out_bufs: &mut [BytesMut]) -> anyhow::Result<ReadStats> {
+        let events = PerfEventArrayBuffer::read_events(self, out_bufs).context("failed to poll events")?;
+        Ok(ReadStats {
+            read: events.read,
+            lost: events.lost,
         })
     }
 }
 
+/// Reads all the pending events of `source`, by calling `read_events` as many times as necessary,
+/// and calls `handle` on each event.
+fn drain_events(
+    source: &mut impl EventSource,
+    out_bufs: &mut [BytesMut],
+    mut handle: impl FnMut(&BytesMut),
+) -> anyhow::Result<ReadStats> {
+    let mut total = ReadStats::default();
+    while source.readable() {
+        // this will clear the buffers and copy the pending events into them
+        let stats = source.read_events(out_bufs)?;
+        for data_buf in out_bufs.iter().take(stats.read) {
+            handle(data_buf);
+        }
+        total.read += stats.read;
+        total.lost += stats.lost;
+        if stats.read == 0 && stats.lost == 0 {
+            break;
+        }
+    }
+    Ok(total)
+}
+
 impl EnergyProbe for EbpfProbe {
-    fn poll(&mut self) -> anyhow::Result<()> {
-        let mut out_bufs: [BytesMut; BUF_PAGE_COUNT] = std::array::from_fn(|_| BytesMut::new());
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.measurements.set_timestamp(SystemTime::now());
+        let measurements = &mut self.measurements;
 
         for energy_buf in &mut self.buffers {
             // read data from the perf event array, if possible
-            let input_buf = &mut energy_buf.buf;
-            if input_buf.readable() {
-                // this will clear the buffers and copy the pending events into them
-                let events_stats = input_buf.read_events(&mut out_bufs).expect("failed to poll events");
-                debug_assert_eq!(events_stats.lost, 0);
-
-                // parse the energy counter (and more) from the bytes that have been read
-                // See another example at https://github.com/aya-rs/book/blob/4aa9a5b38a0d4b6a05debcb213e5540820eda1fd/examples/cgroup-skb-egress/cgroup-skb-egress/src/main.rs#L68
-                for data_buf in out_bufs.iter_mut().take(events_stats.read) {
-                    let len = data_buf.len();
-                    debug!("polled data from out_bufs = {data_buf:x} (len {len})");
-
-                    // the ebpf program pushes pointers to RaplEnergy structs,
-                    // we convert the pointer type and read the struct from it
-                    let ptr = data_buf.as_ptr() as *const RaplEnergy;
-                    let data: RaplEnergy = unsafe { ptr.read_unaligned() };
-                    debug!("=> data for cpu {} domain {} = {}", data.cpu_id, data.domain_id, data.energy);
-
-                    let rapl_domain_info = &energy_buf.domains_by_id[data.domain_id as usize];
-
-                    self.measurements.push(
-                        energy_buf.cpu.socket,
-                        rapl_domain_info.domain,
-                        data.energy,
-                        perf_event::PERF_MAX_ENERGY,
-                        rapl_domain_info.scale as f64,
-                    );
-                }
-            } else {
+            if !energy_buf.buf.readable() {
                 debug!("buffer of cpu {:?} is not readable (if this occurs once at the beginning, this is not a problem)", energy_buf.cpu);
+                continue;
             }
+            let socket = energy_buf.cpu.socket;
+            let domains_by_id = &energy_buf.domains_by_id;
+            let stats = drain_events(&mut energy_buf.buf, &mut self.out_bufs, |data_buf| {
+                push_event(measurements, socket, domains_by_id, data_buf)
+            })?;
+            self.lost_events.report(stats.lost, energy_buf.cpu);
         }
         Ok(())
     }
@@ -131,6 +402,34 @@ impl EnergyProbe for EbpfProbe {
     fn reset(&mut self) {
         self.measurements.clear()
     }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.buffers
+            .iter()
+            .filter(|b| b.cpu.socket == socket)
+            .find_map(|b| b.domains_by_id.iter().find(|d| d.domain == domain))
+            .map(|d| d.scale as f64)
+    }
+}
+
+/// Maximum number of events per socket supported by the eBPF program, see `ebpf/src/main.rs`.
+#[cfg(not(feature = "ebpf_loops"))]
+const MAX_EVENTS: usize = 5;
+
+/// Maximum number of events per socket supported by the eBPF program, see `ebpf/src/main.rs`.
+#[cfg(feature = "ebpf_loops")]
+const MAX_EVENTS: usize = 16;
+
+fn check_event_count(n_events: usize) -> anyhow::Result<()> {
+    if n_events > MAX_EVENTS {
+        let hint = if cfg!(feature = "ebpf_loops") {
+            ""
+        } else {
+            " (build with the ebpf_loops feature to support more events on Linux >= 5.3)"
+        };
+        return Err(anyhow!("too many events for the eBPF probe: {n_events} > {MAX_EVENTS}{hint}"));
+    }
+    Ok(())
 }
 
 /// Loads the BPF bytecode from the compilation result of the "ebpf" module.
@@ -139,12 +438,19 @@ fn load_ebpf_code() -> Result<Bpf, BpfError> {
     // runtime. This approach is recommended for most real-world use cases. If you would
     // like to specify the eBPF program at runtime rather than at compile-time, you can
     // reach for `Bpf::load_file` instead.
-    #[cfg(debug_assertions)]
+    #[cfg(all(debug_assertions, not(feature = "ebpf_loops")))]
     let ebpf_bytecode = include_bytes_aligned!("../../target/bpfel-unknown-none/debug/ebpf");
 
-    #[cfg(not(debug_assertions))]
+    #[cfg(all(not(debug_assertions), not(feature = "ebpf_loops")))]
     let ebpf_bytecode = include_bytes_aligned!("../../target/bpfel-unknown-none/release/ebpf");
 
+    // variant built with `cargo xtask build-ebpf --loops`
+    #[cfg(all(debug_assertions, feature = "ebpf_loops"))]
+    let ebpf_bytecode = include_bytes_aligned!("../../target/ebpf-loops/bpfel-unknown-none/debug/ebpf");
+
+    #[cfg(all(not(debug_assertions), feature = "ebpf_loops"))]
+    let ebpf_bytecode = include_bytes_aligned!("../../target/ebpf-loops/bpfel-unknown-none/release/ebpf");
+
     Bpf::load(ebpf_bytecode)
 }
 
@@ -205,3 +511,83 @@ fn prepare_ebpf_probe(socket_cpus: &[CpuId], events: &[&PowerEvent], freq_hz: u6
 
     Ok(bpf)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::{Duration, Instant};
+
+    use bytes::BytesMut;
+
+    use super::{check_event_count, drain_events, EbpfProbe, EventSource, LostEvents, ReadStats, DEFAULT_BUF_PAGE_COUNT, MAX_EVENTS};
+    use crate::CpuId;
+
+    /// A buffer that contains a list of pending events (one byte each).
+    struct FakeSource {
+        pending: VecDeque<u8>,
+        calls: usize,
+    }
+
+    impl EventSource for FakeSource {
+        fn readable(&self) -> bool {
+            !self.pending.is_empty()
+        }
+
+        fn read_events(&mut self, out_bufs: &mut [BytesMut]) -> anyhow::Result<ReadStats> {
+            self.calls += 1;
+            let mut read = 0;
+            for out in out_bufs.iter_mut() {
+                let Some(event) = self.pending.pop_front() else {
+                    break;
+                };
+                out.clear();
+                out.extend_from_slice(&[event]);
+                read += 1;
+            }
+            Ok(ReadStats { read, lost: 0 })
+        }
+    }
+
+    #[test]
+    fn test_drain_events() -> anyhow::Result<()> {
+        let mut source = FakeSource {
+            pending: (0..10).collect(),
+            calls: 0,
+        };
+        let mut out_bufs = vec![BytesMut::new(); 4];
+        let mut received = Vec::new();
+        let stats = drain_events(&mut source, &mut out_bufs, |buf| received.push(buf[0]))?;
+
+        assert_eq!(received, (0..10).collect::<Vec<u8>>());
+        assert_eq!(stats, ReadStats { read: 10, lost: 0 });
+        assert_eq!(source.calls, 3, "4 + 4 + 2 events");
+        assert!(!source.readable());
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_count() {
+        assert!(check_event_count(1).is_ok());
+        assert!(check_event_count(MAX_EVENTS).is_ok());
+        assert!(check_event_count(MAX_EVENTS + 1).is_err());
+    }
+
+    #[test]
+    fn test_no_event() {
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let err = EbpfProbe::new(&cpus, &[], 1, DEFAULT_BUF_PAGE_COUNT).err().expect("EbpfProbe without events should fail");
+        assert_eq!(err.to_string(), "At least one power event is required for EbpfProbe");
+    }
+
+    #[test]
+    fn test_lost_events_warning_rate() {
+        let t0 = Instant::now();
+        let mut lost = LostEvents::default();
+        assert_eq!(lost.record(3, t0), Some(3));
+        // too early, accumulated
+        assert_eq!(lost.record(2, t0 + Duration::from_secs(1)), None);
+        assert_eq!(lost.record(1, t0 + Duration::from_secs(5)), None);
+        assert_eq!(lost.record(4, t0 + Duration::from_secs(11)), Some(7));
+        assert_eq!(lost.total, 10);
+    }
+}
@@ -0,0 +1,166 @@
+//! Typed errors of the probes, to distinguish the failure modes programmatically
+//! (e.g. "run as root" from "hardware not supported").
+//!
+//! The internals use `anyhow` to add context to the errors. At the boundary (probe constructors and [`crate::EnergyProbe::poll`]),
+//! the `anyhow::Error` is converted to a [`RaplError`] by looking at its causes: an IO error gives its kind
+//! (permission denied, not found), a parsing error gives [`RaplError::ParseError`]. The message keeps the whole chain of causes.
+
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::str::Utf8Error;
+
+use crate::msr::RaplVendor;
+use crate::RaplDomainType;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RaplError {
+    /// The interface exists but cannot be accessed with the current privileges: run as root or set the capabilities.
+    #[error("permission denied (try to run as root): {0}")]
+    PermissionDenied(String),
+    /// A kernel interface (sysfs file, device file) does not exist: the hardware or the kernel module is not available.
+    #[error("not found (the interface may not be supported): {0}")]
+    SysfsNotFound(String),
+    /// The probe does not support this RAPL domain, on the cpus of this vendor if it is known.
+    #[error("The RAPL domain {domain:?} is not supported by this probe{}", on_vendor(.vendor))]
+    UnsupportedDomain {
+        domain: RaplDomainType,
+        vendor: Option<RaplVendor>,
+    },
+    /// The content of a kernel interface cannot be parsed.
+    #[error("parse error: {0}")]
+    ParseError(String),
+    /// The cpu vendor is unknown, or does not support RAPL.
+    #[error("Unsupported CPU vendor {0}")]
+    VendorUnsupported(String),
+    /// Any other error.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for RaplError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<RaplError>() {
+            Ok(rapl_err) => return rapl_err,
+            Err(err) => err,
+        };
+        let io_kind = err.chain().find_map(|cause| cause.downcast_ref::<io::Error>()).map(|e| e.kind());
+        let is_parse_error = err
+            .chain()
+            .any(|cause| cause.is::<ParseIntError>() || cause.is::<ParseFloatError>() || cause.is::<Utf8Error>());
+        match io_kind {
+            Some(io::ErrorKind::PermissionDenied) => RaplError::PermissionDenied(format!("{err:#}")),
+            Some(io::ErrorKind::NotFound) => RaplError::SysfsNotFound(format!("{err:#}")),
+            _ if is_parse_error => RaplError::ParseError(format!("{err:#}")),
+            _ => RaplError::Other(err),
+        }
+    }
+}
+
+/// Formats the vendor of [`RaplError::UnsupportedDomain`].
+fn on_vendor(vendor: &Option<RaplVendor>) -> String {
+    match vendor {
+        Some(vendor) => format!(" on {vendor:?} cpus"),
+        None => String::new(),
+    }
+}
+
+impl From<io::Error> for RaplError {
+    fn from(err: io::Error) -> Self {
+        RaplError::from(anyhow::Error::new(err))
+    }
+}
+
+impl From<Utf8Error> for RaplError {
+    fn from(err: Utf8Error) -> Self {
+        RaplError::ParseError(err.to_string())
+    }
+}
+
+/// Adds the path to an error that occurred while opening a kernel interface.
+/// If the permission has been denied, also adds `permission_hint`, which tells how to get the permission.
+pub(crate) fn open_error(err: io::Error, path: &str, permission_hint: &str) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        anyhow::Error::new(err).context(format!("cannot open {path}. {permission_hint}"))
+    } else {
+        anyhow::Error::new(err).context(format!("failed to open {path}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use anyhow::{anyhow, Context};
+
+    use super::{open_error, RaplError};
+    use crate::msr::RaplVendor;
+    use crate::RaplDomainType;
+
+    #[test]
+    fn test_variants() {
+        let denied = RaplError::PermissionDenied(String::from("open /dev/cpu/0/msr"));
+        assert_eq!(denied.to_string(), "permission denied (try to run as root): open /dev/cpu/0/msr");
+        let not_found = RaplError::SysfsNotFound(String::from("read /sys/devices/power/type"));
+        assert_eq!(
+            not_found.to_string(),
+            "not found (the interface may not be supported): read /sys/devices/power/type"
+        );
+        let domain = RaplError::UnsupportedDomain {
+            domain: RaplDomainType::Dram,
+            vendor: None,
+        };
+        assert_eq!(domain.to_string(), "The RAPL domain Dram is not supported by this probe");
+        let domain = RaplError::UnsupportedDomain {
+            domain: RaplDomainType::Dram,
+            vendor: Some(RaplVendor::Amd),
+        };
+        assert_eq!(domain.to_string(), "The RAPL domain Dram is not supported by this probe on Amd cpus");
+        let parse = RaplError::ParseError(String::from("energy_uj: 'abc'"));
+        assert_eq!(parse.to_string(), "parse error: energy_uj: 'abc'");
+        let vendor = RaplError::VendorUnsupported(String::from("CentaurHauls"));
+        assert_eq!(vendor.to_string(), "Unsupported CPU vendor CentaurHauls");
+        let other = RaplError::Other(anyhow!("something else"));
+        assert_eq!(other.to_string(), "something else");
+    }
+
+    #[test]
+    fn test_from_anyhow() {
+        let io_error = |kind| Err::<(), _>(io::Error::from(kind)).context("open /dev/cpu/0/msr").unwrap_err();
+        assert!(matches!(
+            RaplError::from(io_error(io::ErrorKind::PermissionDenied)),
+            RaplError::PermissionDenied(msg) if msg.starts_with("open /dev/cpu/0/msr: ")
+        ));
+        assert!(matches!(RaplError::from(io_error(io::ErrorKind::NotFound)), RaplError::SysfsNotFound(_)));
+        assert!(matches!(RaplError::from(io_error(io::ErrorKind::Interrupted)), RaplError::Other(_)));
+
+        let parse = "abc".parse::<u64>().context("parse energy_uj").unwrap_err();
+        assert!(matches!(RaplError::from(parse), RaplError::ParseError(msg) if msg.starts_with("parse energy_uj: ")));
+
+        // a RaplError wrapped in anyhow is recovered
+        let wrapped = anyhow::Error::new(RaplError::UnsupportedDomain {
+            domain: RaplDomainType::PP1,
+            vendor: None,
+        });
+        assert!(matches!(
+            RaplError::from(wrapped),
+            RaplError::UnsupportedDomain { domain: RaplDomainType::PP1, vendor: None }
+        ));
+
+        // and it converts back to anyhow for the applications
+        let back: anyhow::Error = RaplError::VendorUnsupported(String::from("x")).into();
+        assert_eq!(back.to_string(), "Unsupported CPU vendor x");
+    }
+
+    #[test]
+    fn test_open_error() {
+        let hint = "Run as root.";
+        let denied = open_error(io::Error::from(io::ErrorKind::PermissionDenied), "/dev/cpu/0/msr", hint);
+        assert_eq!(format!("{denied:#}"), "cannot open /dev/cpu/0/msr. Run as root.: permission denied");
+        assert!(matches!(RaplError::from(denied), RaplError::PermissionDenied(msg) if msg.contains(hint)));
+
+        // the hint is only given when the permission is denied
+        let not_found = open_error(io::Error::from(io::ErrorKind::NotFound), "/dev/cpu/0/msr", hint);
+        assert_eq!(format!("{not_found:#}"), "failed to open /dev/cpu/0/msr: entity not found");
+        assert!(matches!(RaplError::from(not_found), RaplError::SysfsNotFound(_)));
+    }
+}
@@ -0,0 +1,297 @@
+// See https://www.kernel.org/doc/html/latest/hwmon/amd_energy.html
+// for an explanation of the amd_energy driver.
+
+use std::{
+    fs::{self, File},
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{CpuId, EnergyMeasurements, RaplError};
+
+use super::{EnergyProbe, RaplDomainType};
+
+const HWMON_PATH: &str = "/sys/class/hwmon";
+const AMD_ENERGY_DRIVER_NAME: &str = "amd_energy";
+const HWMON_ENERGY_UNIT: f64 = 0.000_001; // 1 microJoules
+
+/// The driver accumulates the 32-bits RAPL counters into 64-bits values,
+/// and we sum the counters of the cores, so the value wraps at u64::MAX.
+const HWMON_MAX_ENERGY: u64 = u64::MAX;
+
+/// An energy sensor of the `amd_energy` hwmon driver.
+#[derive(Debug, Clone)]
+pub struct HwmonSensor {
+    /// The label of the sensor, for instance `Esocket0` or `Ecore012`.
+    pub label: String,
+
+    /// The RAPL domain type: `Package` for the sockets, `PP0` for the cores.
+    pub domain: RaplDomainType,
+
+    /// The id of the socket that contains this sensor.
+    pub socket_id: u32,
+
+    /// The path of the `energyX_input` file, for instance `/sys/class/hwmon/hwmon2/energy1_input`.
+    pub path: PathBuf,
+}
+
+/// The element that an `amd_energy` label refers to.
+#[derive(Debug, PartialEq, Eq)]
+enum SensorTarget {
+    Socket(u32),
+    Core(u32),
+}
+
+/// Parses a label like `Esocket0` or `Ecore012`.
+fn parse_sensor_label(label: &str) -> Option<SensorTarget> {
+    if let Some(id) = label.strip_prefix("Esocket") {
+        id.parse().ok().map(SensorTarget::Socket)
+    } else if let Some(id) = label.strip_prefix("Ecore") {
+        id.parse().ok().map(SensorTarget::Core)
+    } else {
+        None
+    }
+}
+
+/// Returns the socket (physical package) of a cpu.
+fn cpu_socket(cpu: u32) -> anyhow::Result<u32> {
+    let path = format!("/sys/devices/system/cpu/cpu{cpu}/topology/physical_package_id");
+    let read = fs::read_to_string(&path).with_context(|| format!("Failed to read {path}"))?;
+    let id = read.trim_end().parse().with_context(|| format!("Failed to parse {path}: '{read}'"))?;
+    Ok(id)
+}
+
+/// Discovers all the energy sensors of the `amd_energy` hwmon driver.
+///
+/// Returns an empty list if the driver is not loaded.
+pub fn all_hwmon_energy_sensors() -> anyhow::Result<Vec<HwmonSensor>> {
+    discover_sensors(Path::new(HWMON_PATH), &cpu_socket)
+}
+
+fn discover_sensors(
+    hwmon_root: &Path,
+    cpu_socket: &dyn Fn(u32) -> anyhow::Result<u32>,
+) -> anyhow::Result<Vec<HwmonSensor>> {
+    let mut sensors = Vec::new();
+    if !hwmon_root.exists() {
+        return Ok(sensors);
+    }
+    for e in fs::read_dir(hwmon_root)? {
+        let device = e?.path();
+        let name = match fs::read_to_string(device.join("name")) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.trim() != AMD_ENERGY_DRIVER_NAME {
+            continue;
+        }
+        for e in fs::read_dir(&device)? {
+            let label_path = e?.path();
+            let file_name = label_path.file_name().unwrap().to_string_lossy();
+            let Some(prefix) = file_name.strip_suffix("_label").filter(|p| p.starts_with("energy")) else {
+                continue;
+            };
+            let path = device.join(format!("{prefix}_input"));
+            let label = fs::read_to_string(&label_path)?.trim().to_owned();
+            let (domain, socket_id) = match parse_sensor_label(&label) {
+                Some(SensorTarget::Socket(id)) => (RaplDomainType::Package, id),
+                Some(SensorTarget::Core(cpu)) => (RaplDomainType::PP0, cpu_socket(cpu)?),
+                None => return Err(anyhow!("Unknown amd_energy sensor label {label} in {label_path:?}")),
+            };
+            sensors.push(HwmonSensor {
+                label,
+                domain,
+                socket_id,
+                path,
+            });
+        }
+    }
+    sensors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(sensors)
+}
+
+/// Hwmon probe, for the `amd_energy` driver.
+///
+/// The driver has one sensor per core: the energy of the `PP0` domain of a socket
+/// is the sum of the sensors of its cores.
+pub struct HwmonProbe {
+    /// Stores the energy measurements
+    measurements: EnergyMeasurements,
+
+    /// The opened sensors, grouped by (socket, domain)
+    domains: Vec<OpenedDomain>,
+}
+
+struct OpenedDomain {
+    files: Vec<File>,
+    socket: u32,
+    domain: RaplDomainType,
+}
+
+impl HwmonProbe {
+    pub fn new(socket_cpus: &[CpuId], sensors: &[&HwmonSensor]) -> Result<HwmonProbe, RaplError> {
+        crate::check_socket_cpus(socket_cpus)?;
+
+        let mut domains: Vec<OpenedDomain> = Vec::new();
+        // skip the sensors of the sockets that are not monitored
+        let monitored = |s: &&&HwmonSensor| socket_cpus.iter().any(|c| c.socket == s.socket_id);
+        for sensor in sensors.iter().filter(monitored) {
+            let file = File::open(&sensor.path).with_context(|| format!("open {}", sensor.path.to_string_lossy()))?;
+            let existing = domains
+                .iter_mut()
+                .find(|d| d.socket == sensor.socket_id && d.domain == sensor.domain);
+            match existing {
+                Some(d) => d.files.push(file),
+                None => domains.push(OpenedDomain {
+                    files: vec![file],
+                    socket: sensor.socket_id,
+                    domain: sensor.domain,
+                }),
+            }
+        }
+        crate::check_not_empty(&domains, "hwmon sensor", "HwmonProbe")?;
+
+        Ok(HwmonProbe {
+            measurements: EnergyMeasurements::for_cpus(socket_cpus),
+            domains,
+        })
+    }
+}
+
+impl EnergyProbe for HwmonProbe {
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.measurements.set_timestamp(SystemTime::now());
+
+        // reuse the same buffer for all the sensors
+        let mut buf = Vec::with_capacity(24);
+
+        for domain in &mut self.domains {
+            let counter_value = domain.read(&mut buf)?;
+            self.measurements.push(
+                domain.socket,
+                domain.domain,
+                counter_value,
+                HWMON_MAX_ENERGY,
+                HWMON_ENERGY_UNIT,
+            );
+        }
+        Ok(())
+    }
+
+    fn measurements(&self) -> &crate::EnergyMeasurements {
+        &self.measurements
+    }
+
+    fn reset(&mut self) {
+        self.measurements.clear()
+    }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        let measured = self.domains.iter().any(|d| d.socket == socket && d.domain == domain);
+        measured.then_some(HWMON_ENERGY_UNIT)
+    }
+
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        out.clear();
+        let mut buf = Vec::with_capacity(24);
+        for domain in &mut self.domains {
+            out.push((domain.socket, domain.domain, domain.read(&mut buf)?));
+        }
+        Ok(())
+    }
+}
+
+impl OpenedDomain {
+    /// Reads the sensors of the domain and returns the sum of their values, in microJoules.
+    fn read(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<u64> {
+        let mut counter_value: u64 = 0;
+        for file in &mut self.files {
+            // read the file from the beginning
+            file.rewind()?;
+            file.read_to_end(buf)?;
+
+            let content = std::str::from_utf8(buf)?;
+            let value: u64 = content
+                .trim_end()
+                .parse()
+                .with_context(|| format!("failed to parse {file:?}: '{content}'"))?;
+            // the sum wraps like the counters, push() handles the overflow
+            counter_value = counter_value.wrapping_add(value);
+            buf.clear();
+        }
+        Ok(counter_value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{discover_sensors, parse_sensor_label, HwmonProbe, HwmonSensor, SensorTarget};
+    use crate::{CpuId, RaplDomainType};
+
+    #[test]
+    fn test_parse_sensor_label() {
+        assert_eq!(parse_sensor_label("Esocket0"), Some(SensorTarget::Socket(0)));
+        assert_eq!(parse_sensor_label("Esocket1"), Some(SensorTarget::Socket(1)));
+        assert_eq!(parse_sensor_label("Ecore00"), Some(SensorTarget::Core(0)));
+        assert_eq!(parse_sensor_label("Ecore127"), Some(SensorTarget::Core(127)));
+        assert_eq!(parse_sensor_label("Edram"), None);
+    }
+
+    #[test]
+    fn test_discover_sensors() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("rapl_probes-hwmon-{}", std::process::id()));
+        let amd = root.join("hwmon1");
+        let other = root.join("hwmon0");
+        fs::create_dir_all(&amd)?;
+        fs::create_dir_all(&other)?;
+        fs::write(other.join("name"), "k10temp\n")?;
+        fs::write(other.join("temp1_label"), "Tctl\n")?;
+        fs::write(amd.join("name"), "amd_energy\n")?;
+        for (i, label) in ["Ecore000", "Ecore001", "Ecore064", "Esocket0", "Esocket1"].iter().enumerate() {
+            fs::write(amd.join(format!("energy{}_label", i + 1)), format!("{label}\n"))?;
+            fs::write(amd.join(format!("energy{}_input", i + 1)), "1000\n")?;
+        }
+
+        // cores 64+ are on the second socket
+        let sensors = discover_sensors(&root, &|cpu| Ok(cpu / 64))?;
+        fs::remove_dir_all(&root)?;
+
+        let found: Vec<(&str, RaplDomainType, u32)> =
+            sensors.iter().map(|s| (s.label.as_str(), s.domain, s.socket_id)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("Ecore000", RaplDomainType::PP0, 0),
+                ("Ecore001", RaplDomainType::PP0, 0),
+                ("Ecore064", RaplDomainType::PP0, 1),
+                ("Esocket0", RaplDomainType::Package, 0),
+                ("Esocket1", RaplDomainType::Package, 1),
+            ]
+        );
+        assert!(sensors[0].path.ends_with("hwmon1/energy1_input"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_sensor() {
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let err = HwmonProbe::new(&cpus, &[]).err().expect("HwmonProbe without sensors should fail");
+        assert_eq!(err.to_string(), "At least one hwmon sensor is required for HwmonProbe");
+
+        // the only sensor is on a socket that is not monitored
+        let other_socket = HwmonSensor {
+            label: String::from("Esocket1"),
+            domain: RaplDomainType::Package,
+            socket_id: 1,
+            path: PathBuf::from("/nonexistent/hwmon1/energy2_input"),
+        };
+        let err = HwmonProbe::new(&cpus, &[&other_socket]).err().expect("HwmonProbe without sensors should fail");
+        assert_eq!(err.to_string(), "At least one hwmon sensor is required for HwmonProbe");
+    }
+}
@@ -0,0 +1,236 @@
+//! Reading back the measurements written by `cli_poll_rapl`.
+
+use std::io::{BufRead, Lines};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+
+use crate::{EnergyMeasurements, RaplDomainType};
+
+/// Parses the CSV output of `cli_poll_rapl` and groups its rows by timestamp.
+///
+/// Each item is a snapshot: the timestamp of the poll and the measurements of all the (socket, domain) of this poll.
+/// The columns are found by name in the header, so optional columns (label, source, cumulative values, ...) are ignored.
+/// If there is an `elapsed` column, it must contain a duration in seconds.
+/// Empty lines and comment lines, which start with `#`, are skipped, and so are repeated headers
+/// (which occur in appended files).
+/// The synthetic `total` rows are skipped, too.
+/// Without a header, the columns must be `timestamp_ms;socket;domain;overflow;joules`.
+pub fn parse_csv<R: BufRead>(reader: R) -> impl Iterator<Item = anyhow::Result<(SystemTime, EnergyMeasurements)>> {
+    CsvSnapshots {
+        lines: reader.lines(),
+        line_number: 0,
+        columns: Columns::default(),
+        pending: None,
+        done: false,
+    }
+}
+
+/// Indices of the columns that we use.
+struct Columns {
+    timestamp_ms: usize,
+    socket: usize,
+    domain: usize,
+    overflow: usize,
+    joules: usize,
+    elapsed: Option<usize>,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Columns {
+            timestamp_ms: 0,
+            socket: 1,
+            domain: 2,
+            overflow: 3,
+            joules: 4,
+            elapsed: None,
+        }
+    }
+}
+
+impl Columns {
+    fn from_header(header: &str) -> anyhow::Result<Columns> {
+        let names: Vec<&str> = header.split(';').map(str::trim).collect();
+        let find = |name: &str| {
+            names
+                .iter()
+                .position(|n| *n == name)
+                .with_context(|| format!("missing column {name} in header: {header}"))
+        };
+        Ok(Columns {
+            timestamp_ms: find("timestamp_ms")?,
+            socket: find("socket")?,
+            domain: find("domain")?,
+            overflow: find("overflow")?,
+            joules: find("joules")?,
+            elapsed: find("elapsed").ok(),
+        })
+    }
+}
+
+struct Row {
+    timestamp_ms: u64,
+    socket: usize,
+    domain: RaplDomainType,
+    overflow: bool,
+    joules: f64,
+    elapsed: Option<Duration>,
+}
+
+struct CsvSnapshots<R: BufRead> {
+    lines: Lines<R>,
+    line_number: usize,
+    columns: Columns,
+    /// The first row of the next snapshot.
+    pending: Option<Row>,
+    done: bool,
+}
+
+impl<R: BufRead> CsvSnapshots<R> {
+    /// Reads the next data row, skipping the comments and headers.
+    fn next_row(&mut self) -> anyhow::Result<Option<Row>> {
+        for line in self.lines.by_ref() {
+            self.line_number += 1;
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with("timestamp_ms") {
+                self.columns = Columns::from_header(line)?;
+                continue;
+            }
+            if line.split(';').nth(self.columns.domain).map(str::trim) == Some("total") {
+                continue;
+            }
+            let row = parse_row(line, &self.columns).with_context(|| format!("invalid csv line {}", self.line_number))?;
+            return Ok(Some(row));
+        }
+        Ok(None)
+    }
+
+    fn next_snapshot(&mut self) -> anyhow::Result<Option<(SystemTime, EnergyMeasurements)>> {
+        let first = match self.pending.take() {
+            Some(row) => row,
+            None => match self.next_row()? {
+                Some(row) => row,
+                None => return Ok(None),
+            },
+        };
+        let timestamp_ms = first.timestamp_ms;
+        let mut measurements = EnergyMeasurements::new(0);
+        add_row(&mut measurements, first);
+        while let Some(row) = self.next_row()? {
+            if row.timestamp_ms != timestamp_ms {
+                self.pending = Some(row);
+                break;
+            }
+            add_row(&mut measurements, row);
+        }
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(timestamp_ms);
+        Ok(Some((timestamp, measurements)))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvSnapshots<R> {
+    type Item = anyhow::Result<(SystemTime, EnergyMeasurements)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.next_snapshot().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            // stop at the end of the file or at the first error
+            self.done = true;
+        }
+        res
+    }
+}
+
+fn parse_row(line: &str, columns: &Columns) -> anyhow::Result<Row> {
+    let fields: Vec<&str> = line.split(';').map(str::trim).collect();
+    let field = |i: usize| fields.get(i).copied().ok_or_else(|| anyhow!("missing field {i}"));
+
+    let domain_str = field(columns.domain)?;
+    let domain = domain_str
+        .to_lowercase()
+        .parse()
+        .map_err(|_| anyhow!("unknown domain {domain_str}"))?;
+    let elapsed = match columns.elapsed {
+        Some(i) => Some(Duration::try_from_secs_f64(field(i)?.parse()?)?),
+        None => None,
+    };
+    Ok(Row {
+        timestamp_ms: field(columns.timestamp_ms)?.parse()?,
+        socket: field(columns.socket)?.parse()?,
+        domain,
+        overflow: field(columns.overflow)?.parse()?,
+        joules: field(columns.joules)?.parse()?,
+        elapsed,
+    })
+}
+
+fn add_row(measurements: &mut EnergyMeasurements, row: Row) {
+    if measurements.per_socket.len() <= row.socket {
+        measurements.per_socket.resize_with(row.socket + 1, Default::default);
+    }
+    let counter = &mut measurements.per_socket[row.socket][row.domain];
+    counter.joules = Some(row.joules);
+    counter.overflowed = row.overflow;
+    counter.elapsed = row.elapsed;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::parse_csv;
+    use crate::RaplDomainType;
+
+    #[test]
+    fn test_parse_csv() -> anyhow::Result<()> {
+        let csv = "# captured on test-machine
+timestamp_ms;socket;domain;overflow;joules;cumulative_joules
+1000;0;Package;false;10.5;10.5
+1000;0;Dram;false;1.25;1.25
+1000;1;Package;true;9;9
+
+1010;0;Package;false;11;21.5
+1010;all;total;false;11;
+# appended later
+timestamp_ms;socket;domain;label;overflow;joules
+1020;1;PP0;idle;false;2.5
+";
+        let snapshots = parse_csv(csv.as_bytes()).collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(snapshots.len(), 3);
+
+        let (t0, m0) = &snapshots[0];
+        assert_eq!(*t0, SystemTime::UNIX_EPOCH + Duration::from_millis(1000));
+        assert_eq!(m0.per_socket.len(), 2);
+        assert_eq!(m0.per_socket[0][RaplDomainType::Package].joules, Some(10.5));
+        assert_eq!(m0.per_socket[0][RaplDomainType::Dram].joules, Some(1.25));
+        assert_eq!(m0.per_socket[0][RaplDomainType::PP0].joules, None);
+        assert_eq!(m0.per_socket[1][RaplDomainType::Package].joules, Some(9.0));
+        assert!(m0.per_socket[1][RaplDomainType::Package].overflowed);
+
+        let (t1, m1) = &snapshots[1];
+        assert_eq!(*t1, SystemTime::UNIX_EPOCH + Duration::from_millis(1010));
+        assert_eq!(m1.per_socket.len(), 1);
+        assert_eq!(m1.per_socket[0][RaplDomainType::Package].joules, Some(11.0));
+
+        // the second header has a label column
+        let (_, m2) = &snapshots[2];
+        assert_eq!(m2.per_socket[1][RaplDomainType::PP0].joules, Some(2.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_error() {
+        let csv = "1000;0;Package;false;1\n1000;0;Unknown;false;1\n2000;0;Package;false;1\n";
+        let res: Vec<_> = parse_csv(csv.as_bytes()).collect();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Counting of the IO operations, to attribute the energy to the IO activity (Joules per IO operation).
+//!
+//! Two sources are supported:
+//! - a process: the read and write syscalls of the process, from `/proc/<pid>/io` (`syscr + syscw`);
+//! - the whole system: the completed reads and writes of the block devices, from `/proc/diskstats`.
+//!
+//! Note that RAPL measures the energy of the whole socket. With the system-wide counts, the IO operations
+//! are also those of the whole system, but the energy is not only consumed by the IO: dividing it by
+//! the number of operations is only meaningful if the workload is IO-bound and the machine is otherwise idle.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+
+const PROC_PATH: &str = "/proc";
+const SYS_BLOCK_PATH: &str = "/sys/block";
+
+/// Where to count the IO operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoSource {
+    /// The block devices of the whole system.
+    System,
+    /// The syscalls of a process, given by its pid.
+    Process(u32),
+}
+
+impl FromStr for IoSource {
+    type Err = String;
+
+    /// Parses `system`, or the pid of a process.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(IoSource::System),
+            _ => s
+                .parse()
+                .map(IoSource::Process)
+                .map_err(|_| format!("invalid IO source '{s}', expected 'system' or a pid")),
+        }
+    }
+}
+
+/// Counts the IO operations between two samples.
+pub struct IoOpsSampler {
+    source: IoSource,
+    /// The file that contains the counters.
+    path: PathBuf,
+    /// For [`IoSource::System`], the block devices to count (whole disks, not partitions).
+    devices: Vec<String>,
+    /// The total number of operations at the previous sample.
+    previous: u64,
+}
+
+impl IoOpsSampler {
+    pub fn new(source: IoSource) -> anyhow::Result<IoOpsSampler> {
+        IoOpsSampler::with_roots(source, Path::new(PROC_PATH), Path::new(SYS_BLOCK_PATH))
+    }
+
+    fn with_roots(source: IoSource, proc_root: &Path, sys_block: &Path) -> anyhow::Result<IoOpsSampler> {
+        let (path, devices) = match source {
+            IoSource::Process(pid) => (proc_root.join(format!("{pid}/io")), Vec::new()),
+            IoSource::System => {
+                let mut devices = Vec::new();
+                for entry in fs::read_dir(sys_block).with_context(|| format!("Failed to list {sys_block:?}"))? {
+                    let name = entry?.file_name().to_string_lossy().into_owned();
+                    // loop and ram devices do not correspond to a real IO
+                    if !name.starts_with("loop") && !name.starts_with("ram") {
+                        devices.push(name);
+                    }
+                }
+                (proc_root.join("diskstats"), devices)
+            }
+        };
+        let mut sampler = IoOpsSampler {
+            source,
+            path,
+            devices,
+            previous: 0,
+        };
+        sampler.previous = sampler.read_total()?;
+        Ok(sampler)
+    }
+
+    /// Returns the number of IO operations since the previous sample (or since the creation of the sampler).
+    pub fn sample(&mut self) -> anyhow::Result<u64> {
+        let total = self.read_total()?;
+        // the counters of a device are reset when it is removed
+        let ops = total.saturating_sub(self.previous);
+        self.previous = total;
+        Ok(ops)
+    }
+
+    fn read_total(&self) -> anyhow::Result<u64> {
+        let content = fs::read_to_string(&self.path).with_context(|| format!("Failed to read {:?}", self.path))?;
+        match self.source {
+            IoSource::Process(_) => parse_process_io(&content),
+            IoSource::System => parse_diskstats(&content, &self.devices),
+        }
+    }
+}
+
+/// Parses the content of `/proc/<pid>/io` and returns the number of read and write syscalls.
+fn parse_process_io(content: &str) -> anyhow::Result<u64> {
+    let field = |name: &str| -> anyhow::Result<u64> {
+        let line = content
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .ok_or_else(|| anyhow!("{name} not found"))?;
+        Ok(line.trim().parse()?)
+    };
+    Ok(field("syscr")? + field("syscw")?)
+}
+
+/// Parses the content of `/proc/diskstats` and returns the number of completed reads and writes of `devices`.
+fn parse_diskstats(content: &str, devices: &[String]) -> anyhow::Result<u64> {
+    let mut total = 0;
+    for line in content.lines() {
+        // major minor name reads_completed reads_merged sectors_read ms_reading writes_completed ...
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 || !devices.iter().any(|d| d == fields[2]) {
+            continue;
+        }
+        let reads: u64 = fields[3].parse().with_context(|| format!("invalid diskstats line: {line}"))?;
+        let writes: u64 = fields[7].parse().with_context(|| format!("invalid diskstats line: {line}"))?;
+        total += reads + writes;
+    }
+    Ok(total)
+}
+
+/// The energy per IO operation, or `None` if there has been no operation.
+pub fn joules_per_op(joules: f64, ops: u64) -> Option<f64> {
+    (ops > 0).then(|| joules / ops as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{joules_per_op, parse_diskstats, parse_process_io, IoOpsSampler, IoSource};
+
+    #[test]
+    fn test_joules_per_op() {
+        assert_eq!(joules_per_op(10.0, 4), Some(2.5));
+        assert_eq!(joules_per_op(0.0, 100), Some(0.0));
+        assert_eq!(joules_per_op(10.0, 0), None);
+    }
+
+    #[test]
+    fn test_parse_io_source() {
+        assert_eq!("system".parse(), Ok(IoSource::System));
+        assert_eq!("1234".parse(), Ok(IoSource::Process(1234)));
+        assert!("sda".parse::<IoSource>().is_err());
+    }
+
+    #[test]
+    fn test_parse_counters() -> anyhow::Result<()> {
+        let io = "rchar: 4000\nwchar: 1000\nsyscr: 12\nsyscw: 30\nread_bytes: 0\nwrite_bytes: 0\n";
+        assert_eq!(parse_process_io(io)?, 42);
+
+        let diskstats = "   8       0 sda 100 5 800 10 50 2 400 20 0 30 30\n\
+                         \x20  8       1 sda1 90 5 720 9 45 2 360 18 0 27 27\n\
+                         \x20  7       0 loop0 1000 0 2000 5 0 0 0 0 0 5 5\n\
+                         \x20259       0 nvme0n1 7 0 56 1 3 0 24 1 0 2 2\n";
+        let devices = vec![String::from("sda"), String::from("nvme0n1")];
+        assert_eq!(parse_diskstats(diskstats, &devices)?, 100 + 50 + 7 + 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_process() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("rapl_probes-iostats-{}", std::process::id()));
+        let io_file = root.join("proc/42/io");
+        fs::create_dir_all(io_file.parent().unwrap())?;
+        fs::write(&io_file, "syscr: 10\nsyscw: 5\n")?;
+
+        let mut sampler = IoOpsSampler::with_roots(IoSource::Process(42), &root.join("proc"), &root.join("block"))?;
+        fs::write(&io_file, "syscr: 18\nsyscw: 9\n")?;
+        let first = sampler.sample()?;
+        let second = sampler.sample()?;
+        fs::remove_dir_all(&root)?;
+
+        assert_eq!((first, second), (12, 0));
+        Ok(())
+    }
+}
@@ -1,26 +1,76 @@
-use std::{collections::HashSet, fmt, fs, num::ParseIntError, str::FromStr};
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    num::ParseIntError,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
 
 use enum_map::{self, EnumMap};
 
 #[cfg(feature = "enable_ebpf")]
 pub mod ebpf;
+#[cfg(feature = "async")]
+pub mod async_probe;
 
+pub mod capabilities;
+pub mod cgroup;
+pub mod compare;
+pub mod cpufreq;
+pub mod error;
+
+pub mod hwmon;
+pub mod io;
+pub mod iostats;
+pub mod mock;
 pub mod msr;
+pub mod multi;
 pub mod perf_event;
+pub(crate) mod perf_mmap;
 pub mod powercap;
+pub mod powercap_compat;
+pub mod recorder;
+pub mod replay;
+pub mod stats;
+pub mod sysfs;
+pub mod units;
+
+pub use capabilities::{probe_capabilities, ProbeCapabilities, ProbeCapability};
+pub use error::RaplError;
+pub use sysfs::SysfsPaths;
 
 /// A known RAPL domain.
-#[derive(enum_map::Enum, Clone, Copy, Debug, PartialEq, Eq)]
+///
+/// The kernel interfaces don't use the same names for the domains:
+///
+/// | Variant    | perf-event (`/sys/devices/power/events`) | powercap (`/sys/devices/virtual/powercap`) | Accepted by [`RaplDomainType::from_str`] |
+/// |------------|-----------------|---------------|-------------------------------|
+/// | `Package`  | `energy-pkg`    | `package-N`   | `package`, `pkg`              |
+/// | `PP0`      | `energy-cores`  | `core`        | `pp0`, `core`                 |
+/// | `PP1`      | `energy-gpu`    | `uncore`      | `pp1`, `uncore`, `gpu`        |
+/// | `Dram`     | `energy-ram`    | `dram`        | `dram`, `ram`                 |
+/// | `Platform` | `energy-psys`   | `psys`        | `platform`, `psys`            |
+///
+/// With the `serde` feature, domains are serialized with their short names (`pkg`, `core`, `uncore`, `ram`, `psys`),
+/// and all the names accepted by [`RaplDomainType::from_str`] can be deserialized.
+#[derive(enum_map::Enum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RaplDomainType {
     /// entire socket
+    #[cfg_attr(feature = "serde", serde(rename = "pkg", alias = "package"))]
     Package,
     /// power plane 0: core
+    #[cfg_attr(feature = "serde", serde(rename = "core", alias = "pp0"))]
     PP0,
     /// power plane 1: uncore
+    #[cfg_attr(feature = "serde", serde(rename = "uncore", alias = "pp1", alias = "gpu"))]
     PP1,
     ///  DRAM
+    #[cfg_attr(feature = "serde", serde(rename = "ram", alias = "dram"))]
     Dram,
     /// psys (only available on recent client platforms like laptops)
+    #[cfg_attr(feature = "serde", serde(rename = "psys", alias = "platform"))]
     Platform,
 }
 
@@ -37,7 +87,7 @@ impl FromStr for RaplDomainType {
         match s {
             "package" | "pkg" => Ok(RaplDomainType::Package),
             "pp0" | "core" => Ok(RaplDomainType::PP0),
-            "pp1" | "uncore" => Ok(RaplDomainType::PP1),
+            "pp1" | "uncore" | "gpu" => Ok(RaplDomainType::PP1),
             "dram" | "ram" => Ok(RaplDomainType::Dram),
             "platform" | "psys" => Ok(RaplDomainType::Platform),
             _ => Err(s.to_owned()),
@@ -61,35 +111,120 @@ impl RaplDomainType {
         RaplDomainType::PP1,
         RaplDomainType::Platform,
     ];
+
+    /// The short lowercase name of the domain, which is accepted by [`RaplDomainType::from_str`].
+    ///
+    /// This is the name used by serde: `pkg`, `core`, `uncore`, `ram` or `psys`.
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            RaplDomainType::Package => "pkg",
+            RaplDomainType::PP0 => "core",
+            RaplDomainType::PP1 => "uncore",
+            RaplDomainType::Dram => "ram",
+            RaplDomainType::Platform => "psys",
+        }
+    }
+
+    /// The lowercase name of the domain, which is accepted by [`RaplDomainType::from_str`].
+    pub fn canonical_name(&self) -> &'static str {
+        match self {
+            RaplDomainType::Package => "package",
+            RaplDomainType::PP0 => "core",
+            RaplDomainType::PP1 => "uncore",
+            RaplDomainType::Dram => "dram",
+            RaplDomainType::Platform => "platform",
+        }
+    }
 }
 
 pub trait EnergyProbe: Send {
     /// Updates the energy measurements.
-    fn poll(&mut self) -> anyhow::Result<()>;
+    fn poll(&mut self) -> Result<(), RaplError>;
 
     /// Retrieves the latest measurements.
     fn measurements(&self) -> &EnergyMeasurements;
     
     /// Resets the measurements.
     fn reset(&mut self);
+
+    /// The factor that converts the raw counter of `domain` on `socket` to Joules, for diagnostics.
+    /// Returns `None` if the probe does not measure this domain on this socket (or does not know the factor).
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        let _ = (socket, domain);
+        None
+    }
+
+    /// Reads the current raw values of the counters, once, for absolute accounting.
+    /// `out` is cleared, then filled with one `(socket, domain, raw value)` per counter.
+    ///
+    /// Unlike [`EnergyProbe::poll`], the values are not compared to the previous ones, the energy unit is not applied
+    /// (see [`EnergyProbe::energy_unit_for`]) and the measurements are not modified.
+    /// A value counts the energy since the last reset of the counter, which is usually the boot of the machine
+    /// (but the perf events start counting when they are opened). It wraps around at the maximum value of the counter.
+    ///
+    /// Returns an error if the probe cannot read its counters on demand (e.g. the eBPF probe, which receives its values from the kernel).
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        let _ = out;
+        Err(RaplError::Other(anyhow::anyhow!("This probe does not support absolute reads")))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct EnergyMeasurements {
     pub per_socket: Vec<EnumMap<RaplDomainType, EnergyCounter>>,
+
+    /// The polling period, if the number of overflows must be estimated.
+    /// See [EnergyMeasurements::with_overflow_estimation].
+    overflow_estimation_period: Option<Duration>,
+
+    /// When the counters have been read by the last call to [EnergyProbe::poll].
+    last_poll_time: Option<SystemTime>,
+
+    /// The NUMA node of the monitored cpu of each socket, if known.
+    /// See [EnergyMeasurements::for_cpus].
+    numa_nodes: Option<Vec<Option<u32>>>,
 }
 
+/// With the `serde` feature, only the public fields are serialized.
 #[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnergyCounter {
     /// The previous, raw value of the counter (its range depends on the RAPL probe).
     /// The energy unit has not been applied yet.
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) previous_value: Option<u64>,
 
-    /// `true` if an overflow has occured in the last call of `read_consumed_energy`.
+    /// When the previous value of the counter was pushed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) previous_time: Option<Instant>,
+
+    /// The maximum value and the energy unit of the previous value, see [EnergyCounter::raw].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) previous_scale: (u64, f64),
+
+    /// `true` if the counter has wrapped between the two last polls, see also [EnergyCounter::overflow_count].
     pub overflowed: bool,
 
+    /// The number of overflows since the first poll (or since the last reset), including the ones that are estimated
+    /// with [EnergyMeasurements::with_overflow_estimation]. A high count means that the polling frequency was too low
+    /// for the overflow correction to be reliable. The last raw value is given by [EnergyCounter::raw].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub overflow_count: u64,
+
+    /// `true` if the counter could not be read at the last poll, see [EnergyMeasurements::mark_stale].
+    /// `joules` and `elapsed` are then `None`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stale: bool,
+
+    /// The time elapsed between the two last polls, i.e. the interval in which [EnergyCounter::joules] was consumed.
+    pub elapsed: Option<Duration>,
+
     /// The energy consumed since the previous call to [EnergyProbe::poll], in Joules.
     pub joules: Option<f64>,
+
+    /// The total energy consumed since the first poll (or since the last reset), in Joules.
+    /// This is the sum of all the values of `joules`, including the overflow-corrected ones.
+    pub cumulative_joules: f64,
     // NOTE: the energy can be a floating-point number in Joules,
     // without any loss of precision. Why? Because multiplying any number
     // by a float that is a power of two will only change the "exponent" part,
@@ -100,18 +235,152 @@ pub struct EnergyCounter {
     // so we use a f64 here.
 }
 
+/// A raw value of a counter, as given to [EnergyMeasurements::push].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawCounter {
+    pub value: u64,
+    pub max_value: u64,
+    pub energy_unit: f64,
+}
+
+impl EnergyCounter {
+    /// The last raw value of the counter, `None` if no value has been pushed (e.g. before the first poll).
+    pub fn raw(&self) -> Option<RawCounter> {
+        let (max_value, energy_unit) = self.previous_scale;
+        self.previous_value.map(|value| RawCounter {
+            value,
+            max_value,
+            energy_unit,
+        })
+    }
+
+    /// The average power between the two last polls, in Watts.
+    ///
+    /// Like `joules`, returns `None` on the first poll.
+    pub fn watts(&self) -> Option<f64> {
+        let joules = self.joules?;
+        let secs = self.elapsed?.as_secs_f64();
+        if secs > 0.0 {
+            Some(joules / secs)
+        } else {
+            None
+        }
+    }
+}
+
 impl EnergyMeasurements {
     pub fn new(socket_count: usize) -> EnergyMeasurements {
         let v = vec![EnumMap::default(); socket_count];
-        EnergyMeasurements { per_socket: v }
+        EnergyMeasurements {
+            per_socket: v,
+            overflow_estimation_period: None,
+            last_poll_time: None,
+            numa_nodes: None,
+        }
+    }
+
+    /// Like [EnergyMeasurements::new], but estimates the number of overflows when a counter wraps.
+    ///
+    /// By default, only one overflow is corrected, which loses energy when the counter
+    /// wraps several times between two polls (low polling frequency and high power).
+    /// In this mode, the power measured at the previous poll is used to estimate the energy
+    /// consumed during `period`, and the number of overflows is chosen to match this estimation.
+    /// This is only an estimation: a sudden change of power can lead to a wrong number of overflows.
+    pub fn with_overflow_estimation(socket_count: usize, period: Duration) -> EnergyMeasurements {
+        EnergyMeasurements {
+            overflow_estimation_period: Some(period),
+            ..EnergyMeasurements::new(socket_count)
+        }
     }
     
+    /// Like [EnergyMeasurements::new], with one socket per socket id of `socket_cpus`,
+    /// and the NUMA nodes of the cpus as labels (see [EnergyMeasurements::numa_node]).
+    pub fn for_cpus(socket_cpus: &[CpuId]) -> EnergyMeasurements {
+        let mut measurements = EnergyMeasurements::new(socket_count(socket_cpus));
+        if socket_cpus.iter().any(|c| c.numa_node.is_some()) {
+            let mut nodes = vec![None; measurements.per_socket.len()];
+            for c in socket_cpus {
+                nodes[c.socket as usize] = c.numa_node;
+            }
+            measurements.numa_nodes = Some(nodes);
+        }
+        measurements
+    }
+
+    /// The NUMA node of the cpu that measures `socket`, `None` if it is unknown.
+    ///
+    /// This is only a label: the measurements are still per socket, even if a socket contains several NUMA nodes.
+    pub fn numa_node(&self, socket: u32) -> Option<u32> {
+        self.numa_nodes.as_ref()?.get(socket as usize).copied().flatten()
+    }
+
     pub fn clear(&mut self) {
         for m in &mut self.per_socket {
             m.clear();
         }
+        self.last_poll_time = None;
+    }
+
+    /// When the counters have been read by the last call to [EnergyProbe::poll],
+    /// or `None` if the probe has not been polled yet.
+    ///
+    /// Use this instead of sampling the clock after `poll()`, to avoid adding the duration of the poll to the timestamp.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.last_poll_time
+    }
+
+    /// Records the time at which the counters are read.
+    /// Implementations of [EnergyProbe] should call it at the beginning of `poll()`.
+    pub fn set_timestamp(&mut self, time: SystemTime) {
+        self.last_poll_time = Some(time);
+    }
+
+    /// The last raw value of `domain` on `socket`, before the energy unit is applied, with its maximum value and unit.
+    /// Returns `None` if no value has been pushed for this counter, or if the socket does not exist.
+    pub fn raw(&self, socket: u32, domain: RaplDomainType) -> Option<RawCounter> {
+        self.per_socket.get(socket as usize)?[domain].raw()
+    }
+
+    /// The energy consumed by `domain` since the previous poll, summed across all the sockets.
+    ///
+    /// The sockets that have no value yet for this domain are ignored.
+    /// Returns `None` if no socket has a value.
+    pub fn total_for_domain(&self, domain: RaplDomainType) -> Option<f64> {
+        self.per_socket
+            .iter()
+            .filter_map(|s| s[domain].joules)
+            .reduce(|a, b| a + b)
     }
 
+    /// The counters that have a value, with their socket and domain,
+    /// in the order of the sockets and then of the domains (as declared in [`RaplDomainType`]).
+    ///
+    /// The domains that are not measured, and the counters that have only one value so far, are skipped.
+    pub fn iter_measured(&self) -> impl Iterator<Item = (u32, RaplDomainType, &EnergyCounter)> {
+        self.per_socket.iter().enumerate().flat_map(|(socket, domains)| {
+            domains
+                .iter()
+                .filter(|(_, counter)| counter.joules.is_some())
+                .map(move |(domain, counter)| (socket as u32, domain, counter))
+        })
+    }
+
+    /// The energy consumed by all the domains of all the sockets since the previous poll.
+    ///
+    /// Note that the domains overlap (`PP0` and `PP1` are part of `Package`, which can be part of `Platform`),
+    /// thus this is not the energy consumed by the machine. Use [`EnergyMeasurements::total_for_domain`]
+    /// to choose the domains to add.
+    pub fn total_all_domains(&self) -> f64 {
+        RaplDomainType::ALL
+            .into_iter()
+            .filter_map(|d| self.total_for_domain(d))
+            .sum()
+    }
+
+    /// Pushes a new raw value of a counter, and computes the energy consumed since the previous value.
+    ///
+    /// `max_value` is the maximum value that the counter can take before wrapping to zero,
+    /// `energy_unit` is the value of one unit of the counter, in Joules.
     pub fn push(
         &mut self,
         socket_id: u32,
@@ -119,53 +388,242 @@ impl EnergyMeasurements {
         counter_value: u64,
         max_value: u64,
         energy_unit: f64,
+    ) {
+        self.push_at(socket_id, domain, counter_value, max_value, energy_unit, Instant::now())
+    }
+
+    /// Records that `domain` on `socket` could not be read at this poll: the counter becomes [stale](EnergyCounter::stale).
+    ///
+    /// The previous raw value is kept, thus the next pushed value gives the energy consumed since the last successful
+    /// read: no energy is lost, unless the counter has wrapped several times in the meantime.
+    pub fn mark_stale(&mut self, socket_id: u32, domain: RaplDomainType) {
+        let counter = &mut self.per_socket[socket_id as usize][domain];
+        counter.stale = true;
+        counter.joules = None;
+        counter.elapsed = None;
+        counter.overflowed = false;
+    }
+
+    /// Like [EnergyMeasurements::push], but with the time at which the counter has been read.
+    pub fn push_at(
+        &mut self,
+        socket_id: u32,
+        domain: RaplDomainType,
+        counter_value: u64,
+        max_value: u64,
+        energy_unit: f64,
+        time: Instant,
     ) {
         let current = counter_value;
         let counter = &mut self.per_socket[socket_id as usize][domain];
         if let Some(prev) = counter.previous_value {
             if current < prev {
-                // one or more overflow have occured, we cannot know how many, so we correct only one.
-                let corrected = max_value - prev + current;
+                // one or more overflow have occured, we cannot know how many, so we correct only one,
+                // unless the overflow estimation is enabled.
+                // The counter goes from prev to max_value, wraps to 0 (+1), then goes to current.
+                // With max_value = u64::MAX, the wrapping operations give the right result (current - prev mod 2^64).
+                let corrected = (max_value - prev).wrapping_add(current).wrapping_add(1);
+                // additional overflows, estimated from the previous power
+                let (additional, range) = match (self.overflow_estimation_period, counter.watts(), max_value.checked_add(1)) {
+                    (Some(period), Some(watts), Some(range)) => {
+                        // choose the number of overflows that gives the energy closest to the estimation
+                        let expected = watts * period.as_secs_f64() / energy_unit;
+                        (((expected - corrected as f64) / range as f64).round().max(0.0), range as f64)
+                    }
+                    _ => (0.0, 0.0),
+                };
                 counter.overflowed = true;
-                counter.joules = Some(corrected as f64 * energy_unit)
+                counter.overflow_count += 1 + additional as u64;
+                counter.joules = Some((corrected as f64 + additional * range) * energy_unit)
             } else {
                 let diff = current - prev;
                 counter.overflowed = false;
                 counter.joules = Some(diff as f64 * energy_unit)
             }
+            counter.cumulative_joules += counter.joules.unwrap_or(0.0);
         }
+        counter.elapsed = counter.previous_time.map(|t| time.saturating_duration_since(t));
+        counter.stale = false;
         counter.previous_value = Some(current);
+        counter.previous_time = Some(time);
+        counter.previous_scale = (max_value, energy_unit);
+    }
+}
+
+/// The RAPL domains offered by each interface (perf-event and powercap).
+///
+/// Both interfaces should offer the same domains, but this is not always the case,
+/// because of bugs in the Linux kernel (especially on AMD cpus).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainAvailability {
+    /// Domains available via perf-event, sorted and without duplicates.
+    pub perf_event: Vec<RaplDomainType>,
+    /// Domains available via powercap, sorted and without duplicates.
+    pub powercap: Vec<RaplDomainType>,
+}
+
+impl DomainAvailability {
+    pub fn new(
+        perf_event: impl IntoIterator<Item = RaplDomainType>,
+        powercap: impl IntoIterator<Item = RaplDomainType>,
+    ) -> DomainAvailability {
+        fn sorted_unique(domains: impl IntoIterator<Item = RaplDomainType>) -> Vec<RaplDomainType> {
+            let mut v: Vec<RaplDomainType> = domains.into_iter().collect();
+            v.sort_by_key(|k| k.to_string());
+            v.dedup();
+            v
+        }
+        DomainAvailability {
+            perf_event: sorted_unique(perf_event),
+            powercap: sorted_unique(powercap),
+        }
+    }
+
+    /// Builds the report from the discovered perf events and power zones.
+    pub fn from_discovery(
+        perf_events: &[perf_event::PowerEvent],
+        power_zones: &powercap::PowerZoneHierarchy,
+    ) -> DomainAvailability {
+        DomainAvailability::new(
+            perf_events.iter().map(|e| e.domain),
+            power_zones.flat.iter().map(|z| z.domain),
+        )
+    }
+
+    /// Returns `true` if both interfaces offer the same domains.
+    pub fn is_consistent(&self) -> bool {
+        self.perf_event == self.powercap
+    }
+
+    /// The domains that are available via both interfaces.
+    pub fn intersection(&self) -> Vec<RaplDomainType> {
+        self.perf_event
+            .iter()
+            .filter(|d| self.powercap.contains(d))
+            .copied()
+            .collect()
+    }
+
+    /// The domains that are available via at least one interface.
+    pub fn union(&self) -> Vec<RaplDomainType> {
+        let both = self.perf_event.iter().chain(self.powercap.iter()).copied();
+        DomainAvailability::new(both, []).perf_event
+    }
+
+    /// The domains of the interface that offers the most domains (perf-event if they are equal).
+    pub fn most_complete(&self) -> &[RaplDomainType] {
+        if self.perf_event.len() >= self.powercap.len() {
+            &self.perf_event
+        } else {
+            &self.powercap
+        }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CpuId {
     pub cpu: u32,
     pub socket: u32,
+    /// The NUMA node of the cpu, `None` if it is unknown (e.g. kernel without NUMA support).
+    /// A socket can contain several NUMA nodes (e.g. with sub-NUMA clustering): the measurements are still per socket.
+    pub numa_node: Option<u32>,
 }
 
 /// Retrieves the CPUs to monitor (one per socket) in order
 /// to get RAPL perf counters.
+///
+/// The socket of each cpu is read from its topology in sysfs. If the topology is not available,
+/// the cpus are assumed to be given in the order of the sockets.
 pub fn cpus_to_monitor() -> anyhow::Result<Vec<CpuId>> {
-    let mask = fs::read_to_string("/sys/devices/power/cpumask")?;
-    let cpus_and_sockets = parse_cpu_and_socket_list(&mask)?;
-    Ok(cpus_and_sockets)
+    cpus_to_monitor_in(&SysfsPaths::from_env())
 }
 
-fn parse_cpu_and_socket_list(cpulist: &str) -> anyhow::Result<Vec<CpuId>> {
-    let cpus = parse_cpu_list(cpulist);
+/// Like [`cpus_to_monitor`], with the sysfs of `paths`.
+pub fn cpus_to_monitor_in(paths: &SysfsPaths) -> anyhow::Result<Vec<CpuId>> {
+    let mask = fs::read_to_string(paths.path("/sys/devices/power/cpumask"))?;
+    let mut cpus = parse_cpu_and_socket_list(&mask, |cpu| package_of_in(paths, cpu))?;
+    for c in &mut cpus {
+        c.numa_node = numa_node_of(&paths.path(CPU_SYSFS_PATH), c.cpu);
+    }
+    Ok(cpus)
+}
+
+const CPU_SYSFS_PATH: &str = "/sys/devices/system/cpu";
+
+/// Returns the socket of `cpu`, given by its topology in sysfs, or `None` if the topology is not available.
+pub fn package_of(cpu: u32) -> Option<u32> {
+    package_of_in(&SysfsPaths::from_env(), cpu)
+}
+
+fn package_of_in(paths: &SysfsPaths, cpu: u32) -> Option<u32> {
+    let path = paths.path(format!("{CPU_SYSFS_PATH}/cpu{cpu}/topology/physical_package_id"));
+    fs::read_to_string(path).ok()?.trim_end().parse().ok()
+}
+
+/// Returns the NUMA node of `cpu`, given by the `node<N>` link of its directory in `cpu_root`,
+/// or `None` if there is no such link (e.g. kernel without NUMA support).
+fn numa_node_of(cpu_root: &Path, cpu: u32) -> Option<u32> {
+    let entries = fs::read_dir(cpu_root.join(format!("cpu{cpu}"))).ok()?;
+    entries
+        .filter_map(|entry| entry.ok())
+        .find_map(|entry| entry.file_name().to_str()?.strip_prefix("node")?.parse().ok())
+}
+
+/// Keeps the CPUs of the given `sockets`, to monitor only a subset of the sockets.
+///
+/// The socket ids are not changed: the probes created with the selected CPUs index their measurements
+/// with the original ids, and the sockets that are not selected have no measurement.
+/// Returns an error if a socket is not in `socket_cpus`.
+pub fn select_sockets(socket_cpus: &[CpuId], sockets: &[u32]) -> anyhow::Result<Vec<CpuId>> {
+    if let Some(unknown) = sockets.iter().find(|s| !socket_cpus.iter().any(|c| c.socket == **s)) {
+        let known: Vec<String> = socket_cpus.iter().map(|c| c.socket.to_string()).collect();
+        return Err(anyhow::anyhow!(
+            "Socket {unknown} does not exist, the available sockets are: {}",
+            known.join(",")
+        ));
+    }
+    Ok(socket_cpus.iter().filter(|c| sockets.contains(&c.socket)).copied().collect())
+}
 
-    // here we assume that /sys/devices/power/cpumask returns one cpu per socket
-    let cpus_and_sockets = cpus?
-        .into_iter()
-        .enumerate()
-        .map(|(i, cpu)| CpuId { cpu, socket: i as u32 })
+/// Associates each cpu to its socket, given by `package_of`.
+///
+/// The socket ids are used as indices in [`EnergyMeasurements::per_socket`], hence they must be `0..cpus.len()`
+/// (a subset of the sockets can be selected afterwards, see [`select_sockets`]).
+/// If it is not the case, or if the socket of a cpu is unknown, falls back to the order of the cpus.
+fn assign_sockets(cpus: &[u32], package_of: impl Fn(u32) -> Option<u32>) -> Vec<CpuId> {
+    let by_order = || {
+        cpus.iter()
+            .enumerate()
+            .map(|(i, &cpu)| CpuId { cpu, socket: i as u32, numa_node: None })
+            .collect()
+    };
+    let Some(packages) = cpus.iter().map(|&cpu| package_of(cpu)).collect::<Option<Vec<u32>>>() else {
+        log::debug!("cpu topology not available, assuming that the cpumask is in the order of the sockets");
+        return by_order();
+    };
+    let mut sorted = packages.clone();
+    sorted.sort_unstable();
+    if sorted.into_iter().ne(0..cpus.len() as u32) {
+        log::warn!("unexpected package ids {packages:?} for the cpus {cpus:?}, assuming that the cpumask is in the order of the sockets");
+        return by_order();
+    }
+    let mut cpus_and_sockets: Vec<CpuId> = cpus
+        .iter()
+        .zip(packages)
+        .map(|(&cpu, socket)| CpuId { cpu, socket, numa_node: None })
         .collect();
+    cpus_and_sockets.sort_by_key(|c| c.socket);
+    cpus_and_sockets
+}
 
-    Ok(cpus_and_sockets)
+fn parse_cpu_and_socket_list(cpulist: &str, package_of: impl Fn(u32) -> Option<u32>) -> anyhow::Result<Vec<CpuId>> {
+    let cpus = parse_cpu_list(cpulist)?;
+    Ok(assign_sockets(&cpus, package_of))
 }
 
-fn parse_cpu_list(cpulist: &str) -> anyhow::Result<Vec<u32>> {
+/// Parses a list of cpus in the format of the kernel, e.g. `0-3,8,10-11`.
+pub fn parse_cpu_list(cpulist: &str) -> anyhow::Result<Vec<u32>> {
     // handles "n" or "start-end"
     fn parse_cpulist_item(item: &str) -> anyhow::Result<Vec<u32>> {
         let bounds: Vec<u32> = item
@@ -173,9 +631,9 @@ fn parse_cpu_list(cpulist: &str) -> anyhow::Result<Vec<u32>> {
             .map(str::parse)
             .collect::<Result<Vec<u32>, ParseIntError>>()?;
 
-        match bounds.as_slice() {
-            &[start, end] => Ok((start..=end).collect()),
-            &[n] => Ok(vec![n]),
+        match *bounds.as_slice() {
+            [start, end] => Ok((start..=end).collect()),
+            [n] => Ok(vec![n]),
             _ => Err(anyhow::anyhow!("invalid cpulist: {}", item)),
         }
     }
@@ -194,10 +652,29 @@ fn parse_cpu_list(cpulist: &str) -> anyhow::Result<Vec<u32>> {
 }
 
 pub fn online_cpus() -> anyhow::Result<Vec<u32>> {
-    let list = fs::read_to_string("/sys/devices/system/cpu/online")?;
+    online_cpus_in(&SysfsPaths::from_env())
+}
+
+/// Like [`online_cpus`], with the sysfs of `paths`.
+pub fn online_cpus_in(paths: &SysfsPaths) -> anyhow::Result<Vec<u32>> {
+    let list = fs::read_to_string(paths.path("/sys/devices/system/cpu/online"))?;
     parse_cpu_list(&list)
 }
 
+/// Checks that a probe is given at least one thing to measure (`what` is e.g. "RAPL domain").
+pub(crate) fn check_not_empty<T>(items: &[T], what: &str, probe: &str) -> anyhow::Result<()> {
+    if items.is_empty() {
+        return Err(anyhow::anyhow!("At least one {what} is required for {probe}"));
+    }
+    Ok(())
+}
+
+/// The number of sockets to allocate in [`EnergyMeasurements`], which is indexed by socket id.
+/// It is larger than `cpus.len()` if some sockets are not monitored (see [`select_sockets`]).
+pub(crate) fn socket_count(cpus: &[CpuId]) -> usize {
+    cpus.iter().map(|c| c.socket as usize + 1).max().unwrap_or(0)
+}
+
 /// Checks that the given slice contains only one CPU per socket.
 pub(crate) fn check_socket_cpus(cpus: &[CpuId]) -> anyhow::Result<()> {
     let mut seen_sockets: HashSet<u32> = HashSet::new();
@@ -215,38 +692,439 @@ pub(crate) fn check_socket_cpus(cpus: &[CpuId]) -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use crate::parse_cpu_and_socket_list;
-    use crate::CpuId;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use crate::{assign_sockets, numa_node_of, parse_cpu_and_socket_list, select_sockets, socket_count};
+    use crate::{CpuId, DomainAvailability, EnergyMeasurements, RaplDomainType, RawCounter};
+
+    #[test]
+    fn test_canonical_name() {
+        for domain in RaplDomainType::ALL {
+            assert_eq!(domain.canonical_name().parse::<RaplDomainType>(), Ok(domain));
+            assert_eq!(domain.short_name().parse::<RaplDomainType>(), Ok(domain));
+        }
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let aliases = [
+            (RaplDomainType::Package, &["package", "pkg"][..]),
+            (RaplDomainType::PP0, &["pp0", "core"]),
+            (RaplDomainType::PP1, &["pp1", "uncore", "gpu"]),
+            (RaplDomainType::Dram, &["dram", "ram"]),
+            (RaplDomainType::Platform, &["platform", "psys"]),
+        ];
+        for (domain, names) in aliases {
+            for name in names {
+                let parsed: RaplDomainType = name.parse().unwrap();
+                assert_eq!(parsed, domain, "wrong domain for {name}");
+                // round-trip through the canonical and short names
+                assert_eq!(parsed.canonical_name().parse::<RaplDomainType>(), Ok(domain));
+                assert_eq!(parsed.short_name().parse::<RaplDomainType>(), Ok(domain));
+            }
+        }
+        assert_eq!("GPU".parse::<RaplDomainType>(), Err(String::from("GPU")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() -> anyhow::Result<()> {
+        let json = serde_json::to_string(&RaplDomainType::ALL)?;
+        assert_eq!(json, r#"["pkg","core","uncore","ram","psys"]"#);
+        let parsed: Vec<RaplDomainType> = serde_json::from_str(&json)?;
+        assert_eq!(parsed, RaplDomainType::ALL);
+        for domain in RaplDomainType::ALL {
+            let name: String = serde_json::from_str(&serde_json::to_string(&domain)?)?;
+            assert_eq!(name, domain.short_name());
+            assert_eq!(name.parse::<RaplDomainType>(), Ok(domain));
+        }
+        let long: RaplDomainType = serde_json::from_str(r#""package""#)?;
+        assert_eq!(long, RaplDomainType::Package);
+        let gpu: RaplDomainType = serde_json::from_str(r#""gpu""#)?;
+        assert_eq!(gpu, RaplDomainType::PP1);
+
+        let cpu = CpuId { cpu: 64, socket: 1, numa_node: None };
+        assert_eq!(serde_json::from_str::<CpuId>(&serde_json::to_string(&cpu)?)?, cpu);
+        Ok(())
+    }
+
+    #[test]
+    fn test_assign_sockets() {
+        // cpu 0 is on the second socket
+        let topology = |cpu| match cpu {
+            0 => Some(1),
+            32 => Some(0),
+            _ => None,
+        };
+        assert_eq!(
+            assign_sockets(&[0, 32], topology),
+            vec![CpuId { cpu: 32, socket: 0, numa_node: None }, CpuId { cpu: 0, socket: 1, numa_node: None }]
+        );
+        // unknown topology: order of the cpumask
+        assert_eq!(
+            assign_sockets(&[0, 64], topology),
+            vec![CpuId { cpu: 0, socket: 0, numa_node: None }, CpuId { cpu: 64, socket: 1, numa_node: None }]
+        );
+        // package ids that cannot be used as indices
+        assert_eq!(
+            assign_sockets(&[0, 32], |cpu| Some(cpu * 2)),
+            vec![CpuId { cpu: 0, socket: 0, numa_node: None }, CpuId { cpu: 32, socket: 1, numa_node: None }]
+        );
+    }
 
     #[test]
     fn test_parse_cpumask() -> anyhow::Result<()> {
         let single = "0";
-        assert_eq!(parse_cpu_and_socket_list(single)?, vec![CpuId { cpu: 0, socket: 0 }]);
+        assert_eq!(parse_cpu_and_socket_list(single, |_| None)?, vec![CpuId { cpu: 0, socket: 0, numa_node: None }]);
 
         let comma = "0,64";
         assert_eq!(
-            parse_cpu_and_socket_list(comma)?,
-            vec![CpuId { cpu: 0, socket: 0 }, CpuId { cpu: 64, socket: 1 }]
+            parse_cpu_and_socket_list(comma, |_| None)?,
+            vec![CpuId { cpu: 0, socket: 0, numa_node: None }, CpuId { cpu: 64, socket: 1, numa_node: None }]
         );
 
         let caret = "0-1";
         assert_eq!(
-            parse_cpu_and_socket_list(caret)?,
-            vec![CpuId { cpu: 0, socket: 0 }, CpuId { cpu: 1, socket: 1 }]
+            parse_cpu_and_socket_list(caret, |_| None)?,
+            vec![CpuId { cpu: 0, socket: 0, numa_node: None }, CpuId { cpu: 1, socket: 1, numa_node: None }]
         );
 
         let combined = "1-3,5-6";
         assert_eq!(
-            parse_cpu_and_socket_list(combined)?,
+            parse_cpu_and_socket_list(combined, |_| None)?,
+            vec![
+                CpuId { cpu: 1, socket: 0, numa_node: None },
+                CpuId { cpu: 2, socket: 1, numa_node: None },
+                CpuId { cpu: 3, socket: 2, numa_node: None },
+                CpuId { cpu: 5, socket: 3, numa_node: None },
+                CpuId { cpu: 6, socket: 4, numa_node: None },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_sockets() -> anyhow::Result<()> {
+        let cpus: Vec<CpuId> = (0..4).map(|s| CpuId { cpu: 16 * s, socket: s, numa_node: None }).collect();
+        let selected = select_sockets(&cpus, &[2, 0])?;
+        assert_eq!(selected, vec![CpuId { cpu: 0, socket: 0, numa_node: None }, CpuId { cpu: 32, socket: 2, numa_node: None }]);
+        // the measurements are still indexed by the original socket ids
+        assert_eq!(socket_count(&selected), 3);
+        assert_eq!(socket_count(&cpus[3..]), 4);
+
+        let err = select_sockets(&cpus, &[1, 4]).unwrap_err();
+        assert_eq!(err.to_string(), "Socket 4 does not exist, the available sockets are: 0,1,2,3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_domain_availability() {
+        use RaplDomainType::*;
+
+        let perf_only = DomainAvailability::new([Package, Dram, Package], []);
+        assert_eq!(perf_only.perf_event, vec![Dram, Package]);
+        assert!(perf_only.powercap.is_empty());
+        assert!(!perf_only.is_consistent());
+        assert!(perf_only.intersection().is_empty());
+        assert_eq!(perf_only.union(), vec![Dram, Package]);
+        assert_eq!(perf_only.most_complete(), &[Dram, Package]);
+
+        let powercap_only = DomainAvailability::new([], [PP0, Package]);
+        assert!(powercap_only.perf_event.is_empty());
+        assert!(!powercap_only.is_consistent());
+        assert!(powercap_only.intersection().is_empty());
+        assert_eq!(powercap_only.union(), vec![PP0, Package]);
+        assert_eq!(powercap_only.most_complete(), &[PP0, Package]);
+
+        let overlapping = DomainAvailability::new([Package, PP0, Dram], [Package, PP0, Platform, PP1]);
+        assert!(!overlapping.is_consistent());
+        assert_eq!(overlapping.intersection(), vec![PP0, Package]);
+        assert_eq!(overlapping.union(), vec![Dram, PP0, PP1, Package, Platform]);
+        assert_eq!(overlapping.most_complete(), &[PP0, PP1, Package, Platform]);
+
+        let same = DomainAvailability::new([Package, Dram], [Dram, Package]);
+        assert!(same.is_consistent());
+        assert_eq!(same.intersection(), same.union());
+    }
+
+    #[test]
+    fn test_watts() {
+        let mut m = EnergyMeasurements::new(1);
+        let t0 = Instant::now();
+        m.push_at(0, RaplDomainType::Package, 1000, u32::MAX as u64, 0.001, t0);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, None);
+        assert_eq!(counter.watts(), None);
+
+        // 2 J in 500 ms
+        m.push_at(0, RaplDomainType::Package, 3000, u32::MAX as u64, 0.001, t0 + Duration::from_millis(500));
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, Some(2.0));
+        assert_eq!(counter.elapsed, Some(Duration::from_millis(500)));
+        assert_eq!(counter.watts(), Some(4.0));
+
+        // the interval is not assumed to be fixed: 1 J in 2 s
+        m.push_at(0, RaplDomainType::Package, 4000, u32::MAX as u64, 0.001, t0 + Duration::from_millis(2500));
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.watts(), Some(0.5));
+    }
+
+    #[test]
+    fn test_push_first_and_normal() {
+        let mut m = EnergyMeasurements::new(1);
+        m.push(0, RaplDomainType::Package, 1000, u32::MAX as u64, 0.5);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, None);
+        assert!(!counter.overflowed);
+        assert_eq!(counter.previous_value, Some(1000));
+
+        m.push(0, RaplDomainType::Package, 1600, u32::MAX as u64, 0.5);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, Some(300.0));
+        assert!(!counter.overflowed);
+
+        // other domains are untouched
+        assert_eq!(m.per_socket[0][RaplDomainType::Dram].joules, None);
+    }
+
+    #[test]
+    fn test_push_wrap_from_high_baseline() {
+        let max = u32::MAX as u64;
+        let mut m = EnergyMeasurements::new(1);
+        // the counter is already close to its maximum on the first poll
+        m.push(0, RaplDomainType::Package, max - 9, max, 1.0);
+        assert_eq!(m.per_socket[0][RaplDomainType::Package].joules, None);
+
+        // it wraps before the second poll: 9 units to reach max, 1 to wrap to 0, then 5 units
+        m.push(0, RaplDomainType::Package, 5, max, 1.0);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, Some(15.0));
+        assert!(counter.overflowed);
+
+        // the next poll is normal again
+        m.push(0, RaplDomainType::Package, 7, max, 1.0);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, Some(2.0));
+        assert!(!counter.overflowed);
+    }
+
+    #[test]
+    fn test_raw() {
+        let max = 999;
+        let mut m = EnergyMeasurements::new(2);
+        assert_eq!(m.raw(0, RaplDomainType::Package), None);
+        let raw = |value| Some(RawCounter { value, max_value: max, energy_unit: 0.5 });
+        // the raw value is available from the first push, before any energy
+        for value in [990, 5, 20] {
+            m.push(0, RaplDomainType::Package, value, max, 0.5);
+            assert_eq!(m.raw(0, RaplDomainType::Package), raw(value));
+        }
+        // the wrap is corrected in the energy only
+        assert_eq!(m.per_socket[0][RaplDomainType::Package].joules, Some(7.5));
+        assert_eq!(m.raw(0, RaplDomainType::Dram), None);
+        assert_eq!(m.raw(1, RaplDomainType::Package), None);
+        assert_eq!(m.raw(2, RaplDomainType::Package), None);
+
+        m.clear();
+        assert_eq!(m.raw(0, RaplDomainType::Package), None);
+    }
+
+    #[test]
+    fn test_mark_stale() {
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+        let mut m = EnergyMeasurements::new(1);
+        m.push_at(0, RaplDomainType::Package, 100, u32::MAX as u64, 1.0, at(0));
+        m.push_at(0, RaplDomainType::Package, 150, u32::MAX as u64, 1.0, at(1));
+
+        // the counter cannot be read for two polls
+        m.mark_stale(0, RaplDomainType::Package);
+        m.mark_stale(0, RaplDomainType::Package);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert!(counter.stale);
+        assert_eq!((counter.joules, counter.elapsed), (None, None));
+        assert_eq!(counter.cumulative_joules, 50.0);
+        assert_eq!(m.iter_measured().count(), 0);
+
+        // the next value covers the whole gap
+        m.push_at(0, RaplDomainType::Package, 450, u32::MAX as u64, 1.0, at(4));
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert!(!counter.stale);
+        assert_eq!(counter.joules, Some(300.0));
+        assert_eq!(counter.elapsed, Some(Duration::from_secs(3)));
+        assert_eq!(counter.cumulative_joules, 350.0);
+    }
+
+    #[test]
+    fn test_push_overflow_estimation() {
+        // the counter wraps at 1000, and consumes 1900 units per second
+        let max = 999;
+        let t0 = Instant::now();
+        let period = Duration::from_secs(1);
+        let mut estimated = EnergyMeasurements::with_overflow_estimation(1, period);
+        let mut default = EnergyMeasurements::new(1);
+        for m in [&mut estimated, &mut default] {
+            m.push_at(0, RaplDomainType::Package, 0, max, 1.0, t0);
+            m.push_at(0, RaplDomainType::Package, 950, max, 1.0, t0 + Duration::from_millis(500));
+            // 950 + 1900 = 2850: the counter has wrapped twice
+            m.push_at(0, RaplDomainType::Package, 850, max, 1.0, t0 + Duration::from_millis(1500));
+        }
+        let estimated = &estimated.per_socket[0][RaplDomainType::Package];
+        assert!(estimated.overflowed);
+        assert_eq!(estimated.joules, Some(1900.0));
+        assert_eq!(estimated.overflow_count, 2);
+
+        // by default, only one overflow is corrected
+        let default = &default.per_socket[0][RaplDomainType::Package];
+        assert!(default.overflowed);
+        assert_eq!(default.joules, Some(900.0));
+        assert_eq!(default.overflow_count, 1);
+    }
+
+    #[test]
+    fn test_overflow_count() {
+        let max = 999;
+        let mut m = EnergyMeasurements::new(1);
+        let mut counts = Vec::new();
+        let mut flags = Vec::new();
+        // wraps between 900 and 100, between 800 and 50, and between 990 and 10
+        for value in [500, 900, 100, 800, 50, 60, 990, 10, 20] {
+            m.push(0, RaplDomainType::Package, value, max, 1.0);
+            let counter = &m.per_socket[0][RaplDomainType::Package];
+            counts.push(counter.overflow_count);
+            flags.push(counter.overflowed);
+        }
+        assert_eq!(counts, [0, 0, 1, 1, 2, 2, 2, 3, 3]);
+        // `overflowed` only tells if the last poll has wrapped
+        assert_eq!(flags, [false, false, true, false, true, false, false, true, false]);
+        assert_eq!(m.raw(0, RaplDomainType::Package).map(|r| r.value), Some(20));
+
+        // a stale counter keeps its count
+        m.mark_stale(0, RaplDomainType::Package);
+        assert_eq!(m.per_socket[0][RaplDomainType::Package].overflow_count, 3);
+
+        m.clear();
+        m.push(0, RaplDomainType::Package, 900, max, 1.0);
+        m.push(0, RaplDomainType::Package, 5, max, 1.0);
+        assert_eq!(m.per_socket[0][RaplDomainType::Package].overflow_count, 1);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let mut m = EnergyMeasurements::new(1);
+        assert_eq!(m.timestamp(), None);
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1234);
+        m.set_timestamp(t);
+        assert_eq!(m.clone().timestamp(), Some(t));
+        m.clear();
+        assert_eq!(m.timestamp(), None);
+    }
+
+    #[test]
+    fn test_cumulative_joules() {
+        let mut m = EnergyMeasurements::new(1);
+        let mut totals = Vec::new();
+        // the last push wraps around the 32-bits counter
+        for value in [100, 150, 400, 1000, u32::MAX as u64 - 9, 10] {
+            m.push(0, RaplDomainType::Package, value, u32::MAX as u64, 0.5);
+            totals.push(m.per_socket[0][RaplDomainType::Package].cumulative_joules);
+        }
+        let steps = [0.0, 25.0, 125.0, 300.0, (u32::MAX as f64 - 1009.0) * 0.5, 10.0];
+        let mut total = 0.0;
+        for (i, step) in steps.into_iter().enumerate() {
+            total += step;
+            assert_eq!(totals[i], total, "wrong total after push {i}");
+        }
+        assert!(m.per_socket[0][RaplDomainType::Package].overflowed);
+
+        m.clear();
+        assert_eq!(m.per_socket[0][RaplDomainType::Package].cumulative_joules, 0.0);
+    }
+
+    #[test]
+    fn test_push_wrap_u64() {
+        let mut m = EnergyMeasurements::new(1);
+        m.push(0, RaplDomainType::Package, u64::MAX - 1, u64::MAX, 1.0);
+        m.push(0, RaplDomainType::Package, 3, u64::MAX, 1.0);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert_eq!(counter.joules, Some(5.0));
+        assert!(counter.overflowed);
+    }
+
+    #[test]
+    fn test_totals() {
+        let mut m = EnergyMeasurements::new(2);
+        assert_eq!(m.total_for_domain(RaplDomainType::Package), None);
+        assert_eq!(m.total_all_domains(), 0.0);
+
+        for (socket, pkg) in [(0, 100), (1, 300)] {
+            m.push(socket, RaplDomainType::Package, 0, u32::MAX as u64, 1.0);
+            m.push(socket, RaplDomainType::Package, pkg, u32::MAX as u64, 1.0);
+        }
+        // the dram counter of socket 1 has only one value: it must be ignored, not counted as zero
+        m.push(0, RaplDomainType::Dram, 0, u32::MAX as u64, 1.0);
+        m.push(0, RaplDomainType::Dram, 10, u32::MAX as u64, 1.0);
+        m.push(1, RaplDomainType::Dram, 0, u32::MAX as u64, 1.0);
+
+        assert_eq!(m.total_for_domain(RaplDomainType::Package), Some(400.0));
+        assert_eq!(m.total_for_domain(RaplDomainType::Dram), Some(10.0));
+        assert_eq!(m.total_for_domain(RaplDomainType::PP0), None);
+        assert_eq!(m.total_all_domains(), 410.0);
+    }
+
+    #[test]
+    fn test_iter_measured() {
+        let mut m = EnergyMeasurements::new(3);
+        assert_eq!(m.iter_measured().count(), 0);
+
+        for socket in [0, 2] {
+            for domain in [RaplDomainType::Dram, RaplDomainType::Package] {
+                m.push(socket, domain, 0, u32::MAX as u64, 1.0);
+                m.push(socket, domain, 10 + socket as u64, u32::MAX as u64, 1.0);
+            }
+        }
+        // only one value: not measured yet
+        m.push(0, RaplDomainType::PP0, 0, u32::MAX as u64, 1.0);
+
+        let measured: Vec<_> = m.iter_measured().map(|(socket, domain, c)| (socket, domain, c.joules.unwrap())).collect();
+        assert_eq!(
+            measured,
             vec![
-                CpuId { cpu: 1, socket: 0 },
-                CpuId { cpu: 2, socket: 1 },
-                CpuId { cpu: 3, socket: 2 },
-                CpuId { cpu: 5, socket: 3 },
-                CpuId { cpu: 6, socket: 4 },
+                (0, RaplDomainType::Package, 10.0),
+                (0, RaplDomainType::Dram, 10.0),
+                (2, RaplDomainType::Package, 12.0),
+                (2, RaplDomainType::Dram, 12.0),
             ]
         );
+    }
+
+    #[test]
+    fn test_numa_node() -> anyhow::Result<()> {
+        // in sysfs, cpu<N>/node<M> is a link to the node directory
+        let root = std::env::temp_dir().join(format!("rapl_probes-numa-{}", std::process::id()));
+        for dir in ["cpu0/node0", "cpu0/topology", "cpu0/cpufreq", "cpu8/topology", "cpu8/node1", "cpu16/topology", "cpu24/nodes"] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+        assert_eq!(numa_node_of(&root, 0), Some(0));
+        assert_eq!(numa_node_of(&root, 8), Some(1));
+        // no NUMA support, or an entry that is not a node
+        assert_eq!(numa_node_of(&root, 16), None);
+        assert_eq!(numa_node_of(&root, 24), None);
+        // no such cpu
+        assert_eq!(numa_node_of(&root, 32), None);
+        std::fs::remove_dir_all(&root)?;
 
+        let cpus = [
+            CpuId { cpu: 0, socket: 0, numa_node: Some(0) },
+            CpuId { cpu: 16, socket: 2, numa_node: Some(3) },
+        ];
+        let m = EnergyMeasurements::for_cpus(&cpus);
+        assert_eq!(m.per_socket.len(), 3);
+        assert_eq!((m.numa_node(0), m.numa_node(1), m.numa_node(2), m.numa_node(3)), (Some(0), None, Some(3), None));
+        // without NUMA information, the measurements are only per socket
+        let m = EnergyMeasurements::for_cpus(&[CpuId { cpu: 0, socket: 0, numa_node: None }]);
+        assert_eq!(m.numa_node(0), None);
         Ok(())
     }
 }
@@ -0,0 +1,119 @@
+//! In-memory probe, to test the code that consumes an [`EnergyProbe`] without RAPL hardware.
+
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError};
+
+/// A probe that returns a scripted sequence of raw counter values.
+///
+/// Each call to [`EnergyProbe::poll`] advances to the next value of every counter, and pushes it
+/// through [`EnergyMeasurements::push`], like a real probe. Thus, the overflow correction is applied.
+///
+/// ```
+/// use rapl_probes::{mock::MockProbe, EnergyProbe, RaplDomainType};
+///
+/// let mut probe = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![0, 10, 25], u32::MAX as u64, 1.0);
+/// probe.poll().unwrap();
+/// probe.poll().unwrap();
+/// assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, Some(10.0));
+/// ```
+pub struct MockProbe {
+    measurements: EnergyMeasurements,
+    counters: Vec<ScriptedCounter>,
+    /// Index of the next value to push.
+    next: usize,
+}
+
+struct ScriptedCounter {
+    socket: u32,
+    domain: RaplDomainType,
+    values: Vec<u64>,
+    max_value: u64,
+    energy_unit: f64,
+}
+
+impl MockProbe {
+    pub fn new(socket_count: usize) -> MockProbe {
+        MockProbe {
+            measurements: EnergyMeasurements::new(socket_count),
+            counters: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Adds a counter, which will take the raw `values` in order.
+    ///
+    /// `max_value` and `energy_unit` are passed to [`EnergyMeasurements::push`].
+    pub fn with_counter(
+        mut self,
+        socket: u32,
+        domain: RaplDomainType,
+        values: Vec<u64>,
+        max_value: u64,
+        energy_unit: f64,
+    ) -> MockProbe {
+        assert!(
+            (socket as usize) < self.measurements.per_socket.len(),
+            "invalid socket {socket} for MockProbe"
+        );
+        self.counters.push(ScriptedCounter {
+            socket,
+            domain,
+            values,
+            max_value,
+            energy_unit,
+        });
+        self
+    }
+
+    /// The number of polls that have been done since the creation of the probe.
+    pub fn poll_count(&self) -> usize {
+        self.next
+    }
+}
+
+impl EnergyProbe for MockProbe {
+    /// Pushes the next value of each counter.
+    /// Returns an error if the script of a counter is exhausted.
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.measurements.set_timestamp(SystemTime::now());
+        for c in &self.counters {
+            let value = *c.values.get(self.next).ok_or_else(|| {
+                anyhow!("no more values for the counter {}/{:?} of MockProbe", c.socket, c.domain)
+            })?;
+            self.measurements.push(c.socket, c.domain, value, c.max_value, c.energy_unit);
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn measurements(&self) -> &EnergyMeasurements {
+        &self.measurements
+    }
+
+    fn reset(&mut self) {
+        self.measurements.clear()
+    }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.counters
+            .iter()
+            .find(|c| c.socket == socket && c.domain == domain)
+            .map(|c| c.energy_unit)
+    }
+
+    /// Returns the current value of each counter, i.e. the last polled one (or the first one before the first poll).
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        out.clear();
+        let current = self.next.saturating_sub(1);
+        for c in &self.counters {
+            let value = *c.values.get(current).ok_or_else(|| {
+                anyhow!("no value for the counter {}/{:?} of MockProbe", c.socket, c.domain)
+            })?;
+            out.push((c.socket, c.domain, value));
+        }
+        Ok(())
+    }
+}
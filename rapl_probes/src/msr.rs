@@ -2,16 +2,22 @@
 // MSR_PKG_ENERGY_STATUS reports the measured energy usage of the package.
 
 use std::{
-    fs::File,
+    collections::HashMap,
+    fs::{self, File},
     io,
     os::unix::prelude::FileExt,
     process::{Command, Stdio},
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
 };
 
 use anyhow::{anyhow, Context};
+use enum_map::EnumMap;
+use log::warn;
 use regex::Regex;
 
-use crate::EnergyMeasurements;
+use crate::error::open_error;
+use crate::{EnergyMeasurements, RaplError};
 
 use super::{CpuId, EnergyProbe, RaplDomainType};
 
@@ -38,20 +44,32 @@ mod amd {
     pub const MSR_PKG_ENERGY_STATUS: Addr = 0xc001029b;
 }
 
-/// Mask to apply when reading the energy values
-const MSR_ENERGY_MASK: Addr = 0xffffffff;
+/// Mask to apply when reading the energy values.
+///
+/// In all the `MSR_*_ENERGY_STATUS` registers (Intel and AMD), the energy counter is in bits 31:0,
+/// and bits 63:32 are reserved: they must be ignored, whatever their value.
+const MSR_ENERGY_MASK: u64 = 0xffffffff;
+
+/// Energy unit of the DRAM domain on some Intel server platforms, which do not use the ESU of
+/// MSR_RAPL_POWER_UNIT for DRAM but a fixed unit of 2^-16 Joules (15.3 microJoules).
+const INTEL_SERVER_DRAM_ENERGY_UNIT: f64 = 1.0 / 65536.0;
 
-/// Maximum value of the MSR counter.
-/// Note that this technically depends on the exact hardware, but for our purposes it's good enough.
+/// Default maximum value of the MSR counters (32 bits), see [`domain_max_energy`].
 const MSR_MAX_ENERGY: u64 = u32::MAX as u64;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RaplVendor {
     Intel,
     Amd,
 }
 
+/// Values of MSR_RAPL_POWER_UNIT that have already been read, shared by all the probes of the process.
+static POWER_UNIT_CACHE: LazyLock<PowerUnitCache> = LazyLock::new(PowerUnitCache::default);
+
 /// Reads the RAPL MSR values (via /dev/cpu/<cpu_id>/msr for one CPU per socket).
+///
+/// On AMD cpus, the register of the core domain (PP0) is per core, not per socket: by default, the PP0 value is
+/// the energy of the single core that is read on each socket. See [`MsrProbe::with_all_cores`] to measure all the cores.
 pub struct MsrProbe {
     /// Stores the energy measurements
     measurements: EnergyMeasurements,
@@ -61,33 +79,109 @@ pub struct MsrProbe {
 
     /// The MSR RAPL registers to read for each descriptor
     domains: Vec<RaplMsrDomain>,
+
+    /// The per-core registers that are read on all the cores of each socket, instead of one cpu per socket.
+    all_cores: Vec<SocketCores>,
 }
 
 struct RaplMsrDomain {
     domain: RaplDomainType,
     addr: Addr,
+    /// The maximum value of the counter, after which it wraps to zero.
+    max_energy: u64,
 }
 
 struct RaplMsrAccess {
     /// File descriptor to the MSR sysfs for one cpu
     fd: File,
-    /// RAPL energy unit (a f32 would be enough but we only do f64-math with it)
-    energy_unit: f64,
+    /// The cpu of the descriptor
+    cpu: u32,
+    /// RAPL energy unit of each domain (a f32 would be enough but we only do f64-math with it)
+    energy_units: EnumMap<RaplDomainType, f64>,
     /// Socket id
     socket_id: u32,
 }
 
-impl EnergyProbe for MsrProbe {
-    fn poll(&mut self) -> anyhow::Result<()> {
-        for msr in &mut self.msr_per_cpu {
-            for RaplMsrDomain { domain, addr } in &self.domains {
-                let msr_value = read_msr(&msr.fd, *addr)
-                    .with_context(|| format!("failed to read MSR {addr} for domain {domain:?}"))?;
+/// A per-core register, read on one cpu of each physical core of a socket (see [`MsrProbe::with_all_cores`]).
+struct SocketCores {
+    socket_id: u32,
+    domain: RaplDomainType,
+    addr: Addr,
+    max_energy: u64,
+    /// MSR file descriptors, one per physical core
+    fds: Vec<File>,
+    sum: CoreEnergySum,
+}
+
+impl SocketCores {
+    /// Reads the register of all the cores, and returns the total energy of the socket, see [`CoreEnergySum`].
+    fn read_total(&mut self) -> anyhow::Result<u64> {
+        let values = self
+            .fds
+            .iter()
+            .map(|fd| read_msr(fd, self.addr).map(energy_counter_value))
+            .collect::<io::Result<Vec<u64>>>()
+            .with_context(|| {
+                format!("failed to read MSR {} for domain {:?} on the cores of socket {}", self.addr, self.domain, self.socket_id)
+            })?;
+        Ok(self.sum.update(&values, self.max_energy))
+    }
+}
+
+/// Running sum of the energy of several counters that wrap independently, e.g. the core energy of the cores of a socket.
+///
+/// The raw values of the counters cannot be added: each one wraps at its own time. Instead, the energy consumed
+/// since the previous read is computed for each counter (with the correction of the overflow), and accumulated.
+#[derive(Debug, Default)]
+struct CoreEnergySum {
+    previous: Vec<u64>,
+    /// In the unit of the counters, starts at zero.
+    total: u64,
+}
+
+impl CoreEnergySum {
+    /// Adds the energy consumed by each counter since the previous call, and returns the total.
+    /// The first call only records the values.
+    fn update(&mut self, values: &[u64], max_energy: u64) -> u64 {
+        if self.previous.len() == values.len() {
+            for (&previous, &current) in self.previous.iter().zip(values) {
+                let consumed = if current >= previous {
+                    current - previous
+                } else {
+                    // one overflow, like EnergyMeasurements::push
+                    (max_energy - previous) + current + 1
+                };
+                self.total = self.total.wrapping_add(consumed);
+            }
+        }
+        self.previous.clear();
+        self.previous.extend_from_slice(values);
+        self.total
+    }
+}
+
+/// Reads the counter of `domain` for the cpu of `msr`, or the sum of its cores if [`MsrProbe::with_all_cores`] applies.
+/// Also returns the maximum value of the counter.
+fn read_counter(msr: &RaplMsrAccess, domain: &RaplMsrDomain, all_cores: &mut [SocketCores]) -> anyhow::Result<(u64, u64)> {
+    if let Some(cores) = all_cores.iter_mut().find(|c| c.socket_id == msr.socket_id && c.domain == domain.domain) {
+        // the sum does not wrap like a 32-bit register
+        return Ok((cores.read_total()?, u64::MAX));
+    }
+    let addr = domain.addr;
+    let msr_value =
+        read_msr(&msr.fd, addr).with_context(|| format!("failed to read MSR {addr} for domain {:?}", domain.domain))?;
+    Ok((energy_counter_value(msr_value), domain.max_energy))
+}
 
-                let counter_value = msr_value & MSR_ENERGY_MASK;
+impl EnergyProbe for MsrProbe {
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.measurements.set_timestamp(SystemTime::now());
+        for msr in &self.msr_per_cpu {
+            for d in &self.domains {
+                let (counter_value, max_energy) = read_counter(msr, d, &mut self.all_cores)?;
 
                 self.measurements
-                    .push(msr.socket_id, *domain, counter_value, MSR_MAX_ENERGY, msr.energy_unit);
+                    .push(msr.socket_id, d.domain, counter_value, max_energy, msr.energy_units[d.domain]);
             }
         }
         Ok(())
@@ -100,42 +194,257 @@ impl EnergyProbe for MsrProbe {
     fn reset(&mut self) {
         self.measurements.clear()
     }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        if !self.domains.iter().any(|d| d.domain == domain) {
+            return None;
+        }
+        let msr = self.msr_per_cpu.iter().find(|m| m.socket_id == socket)?;
+        Some(msr.energy_units[domain])
+    }
+
+    /// With [`MsrProbe::with_all_cores`], the value of the summed domain is the energy of the cores since their first read.
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        out.clear();
+        for msr in &self.msr_per_cpu {
+            for d in &self.domains {
+                let (counter_value, _) = read_counter(msr, d, &mut self.all_cores)?;
+                out.push((msr.socket_id, d.domain, counter_value));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl MsrProbe {
-    pub fn new(cpus: &[CpuId], domains: &[RaplDomainType]) -> anyhow::Result<MsrProbe> {
+    pub fn new(cpus: &[CpuId], domains: &[RaplDomainType]) -> Result<MsrProbe, RaplError> {
+        crate::check_not_empty(domains, "RAPL domain", "MsrProbe")?;
         crate::check_socket_cpus(cpus)?;
         let vendor = cpu_vendor()?;
+        // check the domains before opening the MSR devices
+        let domains = msr_domains(domains, vendor)?;
+        let family_model = match cpu_family_model() {
+            Ok(fm) => Some(fm),
+            Err(e) => {
+                warn!("Failed to detect the cpu model, the DRAM energy unit may be wrong on some platforms. {e}");
+                None
+            }
+        };
         let msr_per_cpu = cpus
             .iter()
-            .map(|CpuId { socket, cpu }| {
-                let path = format!("/dev/cpu/{cpu}/msr");
-                let fd = File::open(path)?;
-                let energy_unit = read_energy_unit(&fd, vendor)? as f64;
+            .map(|CpuId { socket, cpu, .. }| {
+                let fd = open_msr(*cpu)?;
+                // the units never change: read them only once per cpu, even if the probe is created multiple times
+                let power_unit = POWER_UNIT_CACHE.get_or_read(*cpu, vendor, || read_power_unit(&fd, vendor))?;
+                let energy_units = EnumMap::from_fn(|d| domain_energy_unit(power_unit, d, vendor, family_model));
                 Ok(RaplMsrAccess {
                     fd,
-                    energy_unit,
+                    cpu: *cpu,
+                    energy_units,
                     socket_id: *socket,
                 })
             })
-            .collect::<io::Result<Vec<RaplMsrAccess>>>()?;
+            .collect::<anyhow::Result<Vec<RaplMsrAccess>>>()?;
 
-        let domains = domains
-            .iter()
-            .map(|d| {
-                Ok(RaplMsrDomain {
-                    domain: *d,
-                    addr: domain_msr_address(*d, vendor).context("RAPL domain should exist in MSR")?,
-                })
-            })
-            .collect::<anyhow::Result<Vec<RaplMsrDomain>>>()?;
+        let requested: Vec<RaplDomainType> = domains.iter().map(|d| d.domain).collect();
+        let domains = usable_domains(&msr_per_cpu, domains);
+        if domains.is_empty() {
+            return Err(RaplError::UnsupportedDomain {
+                domain: requested[0],
+                vendor: Some(vendor),
+            });
+        }
+
+        if domains.iter().any(|d| d.addr == amd::MSR_CORE_ENERGY_STATUS) {
+            log::info!("On AMD cpus, the core domain is measured on a single core per socket, unless all the cores are read");
+        }
 
         Ok(MsrProbe {
-            measurements: EnergyMeasurements::new(cpus.len()),
+            measurements: EnergyMeasurements::for_cpus(cpus),
             msr_per_cpu,
             domains,
+            all_cores: Vec::new(),
         })
     }
+
+    /// Measures the core domain (PP0) of AMD cpus on all the cores of each socket.
+    ///
+    /// The register of this domain is per core on AMD: it is read on one cpu of each physical core
+    /// (the SMT siblings share it), and the energy of the cores is summed per socket.
+    /// Does nothing if the core domain is not measured, or on Intel, whose PP0 register covers all the cores.
+    pub fn with_all_cores(mut self) -> Result<MsrProbe, RaplError> {
+        let Some(core_domain) = self.domains.iter().find(|d| d.addr == amd::MSR_CORE_ENERGY_STATUS) else {
+            return Ok(self);
+        };
+        let topology = crate::online_cpus()?
+            .into_iter()
+            .map(|cpu| {
+                let package = crate::package_of(cpu);
+                let core = core_of(cpu);
+                match (package, core) {
+                    (Some(package), Some(core)) => Ok((cpu, package, core)),
+                    _ => Err(anyhow!("the topology of cpu {cpu} is not available, cannot find the cores")),
+                }
+            })
+            .collect::<anyhow::Result<Vec<(u32, u32, u32)>>>()?;
+        let mut all_cores = Vec::with_capacity(self.msr_per_cpu.len());
+        for msr in &self.msr_per_cpu {
+            let package = crate::package_of(msr.cpu)
+                .ok_or_else(|| anyhow!("the package of cpu {} is not available, cannot find its cores", msr.cpu))?;
+            let fds = one_cpu_per_core(&topology, package)
+                .into_iter()
+                .map(open_msr)
+                .collect::<anyhow::Result<Vec<File>>>()?;
+            log::debug!("Reading the core energy of {} cores on socket {}", fds.len(), msr.socket_id);
+            all_cores.push(SocketCores {
+                socket_id: msr.socket_id,
+                domain: core_domain.domain,
+                addr: core_domain.addr,
+                max_energy: core_domain.max_energy,
+                fds,
+                sum: CoreEnergySum::default(),
+            });
+        }
+        self.all_cores = all_cores;
+        Ok(self)
+    }
+}
+
+/// Opens the MSR device of a cpu.
+fn open_msr(cpu: u32) -> anyhow::Result<File> {
+    let path = format!("/dev/cpu/{cpu}/msr");
+    File::open(&path).map_err(|e| {
+        let cpu_online = crate::online_cpus().map(|online| online.contains(&cpu)).unwrap_or(false);
+        if is_msr_module_missing(&e, cpu_online) {
+            anyhow::Error::new(e).context(format!(
+                "{path} does not exist, the msr kernel module is probably not loaded. Load it with `sudo modprobe msr`."
+            ))
+        } else {
+            open_error(e, &path, MSR_PERMISSION_HINT)
+        }
+    })
+}
+
+/// Returns the physical core of `cpu` (its `core_id` in sysfs), or `None` if the topology is not available.
+fn core_of(cpu: u32) -> Option<u32> {
+    let path = crate::sysfs::SysfsPaths::from_env().path(format!("/sys/devices/system/cpu/cpu{cpu}/topology/core_id"));
+    fs::read_to_string(path).ok()?.trim_end().parse().ok()
+}
+
+/// Selects the first cpu of each physical core of `package`, given the `(cpu, package, core_id)` of the online cpus.
+/// The core ids are only unique within a package.
+fn one_cpu_per_core(topology: &[(u32, u32, u32)], package: u32) -> Vec<u32> {
+    let mut seen_cores = std::collections::HashSet::new();
+    topology
+        .iter()
+        .filter(|(_, p, core)| *p == package && seen_cores.insert(*core))
+        .map(|(cpu, _, _)| *cpu)
+        .collect()
+}
+
+impl MsrProbe {
+    /// The domains that are measured: the requested domains whose MSR could be read, see [`MsrProbe::new`].
+    pub fn domains(&self) -> Vec<RaplDomainType> {
+        self.domains.iter().map(|d| d.domain).collect()
+    }
+}
+
+/// Keeps the domains whose MSR can be read on every cpu, and contains a plausible value.
+///
+/// Some cpus do not implement the MSR of a domain that exists on other cpus of the same vendor (e.g. the platform domain):
+/// the read fails, or returns zero (like the kernel, we consider that such a domain is not supported),
+/// or a value whose reserved bits are set. These domains are dropped with a warning.
+fn usable_domains(msr_per_cpu: &[RaplMsrAccess], domains: Vec<RaplMsrDomain>) -> Vec<RaplMsrDomain> {
+    let check = |d: &RaplMsrDomain| -> Result<(), String> {
+        for msr in msr_per_cpu {
+            let value = read_msr(&msr.fd, d.addr).map_err(|e| format!("cannot read it on socket {}: {e}", msr.socket_id))?;
+            if value == 0 {
+                return Err(format!("its value is zero on socket {}", msr.socket_id));
+            }
+            if value & !MSR_ENERGY_MASK != 0 {
+                return Err(format!("its reserved bits are set on socket {} ({value:#x})", msr.socket_id));
+            }
+        }
+        Ok(())
+    };
+    domains
+        .into_iter()
+        .filter(|d| match check(d) {
+            Ok(()) => true,
+            Err(problem) => {
+                warn!("The domain {:?} is not supported by this cpu, ignoring it: MSR {:#x} {problem}", d.domain, d.addr);
+                false
+            }
+        })
+        .collect()
+}
+
+/// A thread-safe cache of the values of MSR_RAPL_POWER_UNIT, by cpu and vendor.
+///
+/// The units are fixed by the hardware, hence the entries are never invalidated.
+#[derive(Default)]
+struct PowerUnitCache {
+    values: Mutex<HashMap<(u32, RaplVendor), u64>>,
+}
+
+impl PowerUnitCache {
+    /// Returns the cached value for this cpu, or calls `read` and caches its result if it succeeds.
+    fn get_or_read(&self, cpu: u32, vendor: RaplVendor, read: impl FnOnce() -> io::Result<u64>) -> io::Result<u64> {
+        // don't hold the lock during the read: two threads may read the same MSR, which is harmless
+        if let Some(value) = self.get(cpu, vendor) {
+            return Ok(value);
+        }
+        let value = read()?;
+        self.values.lock().unwrap().insert((cpu, vendor), value);
+        Ok(value)
+    }
+
+    fn get(&self, cpu: u32, vendor: RaplVendor) -> Option<u64> {
+        self.values.lock().unwrap().get(&(cpu, vendor)).copied()
+    }
+}
+
+/// Finds the MSR address of each domain, or fails if a domain is not available on this vendor's cpus.
+fn msr_domains(domains: &[RaplDomainType], vendor: RaplVendor) -> Result<Vec<RaplMsrDomain>, RaplError> {
+    domains
+        .iter()
+        .map(|&domain| match domain_msr_address(domain, vendor) {
+            Some(addr) => Ok(RaplMsrDomain {
+                domain,
+                addr,
+                max_energy: domain_max_energy(domain, vendor),
+            }),
+            None => Err(RaplError::UnsupportedDomain {
+                domain,
+                vendor: Some(vendor),
+            }),
+        })
+        .collect()
+}
+
+/// Returns the maximum value of the energy counter of a domain.
+///
+/// The counters of all the Intel and AMD cpus that we know of have 32 bits (the upper bits of the registers are reserved),
+/// and there is no register that reports their width. If a platform has narrower counters, it should be handled here,
+/// otherwise the overflows would be corrected with a wrong range.
+fn domain_max_energy(_domain: RaplDomainType, _vendor: RaplVendor) -> u64 {
+    MSR_MAX_ENERGY
+}
+
+/// How to get the permission to open the MSR devices.
+const MSR_PERMISSION_HINT: &str = "Reading the MSRs requires to run as root, or the CAP_SYS_RAWIO capability \
+(`sudo setcap cap_sys_rawio=ep <executable>`), and the msr kernel module (`sudo modprobe msr`).";
+
+/// Returns `true` if the MSR device of a cpu could not be opened because the `msr` kernel module is not loaded:
+/// the device file is missing although the cpu is online.
+fn is_msr_module_missing(open_error: &io::Error, cpu_online: bool) -> bool {
+    open_error.kind() == io::ErrorKind::NotFound && cpu_online
+}
+
+/// Extracts the energy counter from the raw value of a `MSR_*_ENERGY_STATUS` register,
+/// by discarding the reserved bits. The result is always less or equal to [`MSR_MAX_ENERGY`].
+fn energy_counter_value(msr_value: u64) -> u64 {
+    msr_value & MSR_ENERGY_MASK
 }
 
 fn read_msr(msr: &File, at: Addr) -> io::Result<u64> {
@@ -144,23 +453,26 @@ fn read_msr(msr: &File, at: Addr) -> io::Result<u64> {
     Ok(u64::from_ne_bytes(buf))
 }
 
-/// Extract the energy unit from the Model Specific Register `msr`.
+/// Reads the content of MSR_RAPL_POWER_UNIT, which contains the units of the RAPL counters.
+fn read_power_unit(msr: &File, vendor: RaplVendor) -> io::Result<u64> {
+    let offset = match vendor {
+        RaplVendor::Intel => intel::MSR_RAPL_POWER_UNIT,
+        RaplVendor::Amd => amd::MSR_RAPL_POWER_UNIT,
+    };
+    read_msr(msr, offset)
+}
+
+/// Extract the energy unit from the value of the Model Specific Register MSR_RAPL_POWER_UNIT.
 ///
 /// # Wrong values
 ///
 /// Note that the returned energy unit may not apply for all measurements,
 /// because some architectures use a different unit for some domains (e.g. DRAM).
-/// This is platform-dependent, and I do not wish to implement all of them here.
+/// This is platform-dependent, see [`domain_energy_unit`] for the cases that we handle.
 ///
 /// See [Linux source code - rapl.c](https://github.com/torvalds/linux/blob/0036fb00a756a2f6e360d44e2e3d2200a8afbc9b/arch/x86/events/rapl.c#L612)
 ///
-fn read_energy_unit(msr: &File, vendor: RaplVendor) -> io::Result<f32> {
-    let offset = match vendor {
-        RaplVendor::Intel => intel::MSR_RAPL_POWER_UNIT,
-        RaplVendor::Amd => amd::MSR_RAPL_POWER_UNIT,
-    };
-    let msr_value = read_msr(msr, offset)?;
-
+fn energy_unit_from_power_unit(msr_value: u64) -> f32 {
     // According to the Intel Software Developer manual, the value we're interested in is
     // "energy status unit" at bits 12:8 (mask 0x1F00)
     let esu = (msr_value & 0x1F00) >> 8;
@@ -168,8 +480,158 @@ fn read_energy_unit(msr: &File, vendor: RaplVendor) -> io::Result<f32> {
     // The energy unit, aka "multiplier", is 1/(2^esu) = (1/2)^esu
     // This means that when we read an energy value from MSR, the actual value is
     // `msr_value * multiplier` Joules.
-    let multiplier = 0.5_f32.powi(esu as i32);
-    Ok(multiplier)
+    0.5_f32.powi(esu as i32)
+}
+
+/// Returns the energy unit of a RAPL domain, given the value of MSR_RAPL_POWER_UNIT.
+///
+/// On some Intel server platforms, the DRAM domain uses a fixed unit instead of the ESU.
+/// The list of models comes from the Linux kernel (`rapl_defaults_hsw_server` and `rapl_defaults_spr_server`
+/// in `drivers/powercap/intel_rapl_common.c`).
+fn domain_energy_unit(
+    power_unit: u64,
+    domain: RaplDomainType,
+    vendor: RaplVendor,
+    family_model: Option<(u32, u32)>,
+) -> f64 {
+    let has_fixed_dram_unit = vendor == RaplVendor::Intel
+        && matches!(
+            family_model,
+            // Haswell-X, Broadwell-X, Skylake-X, Icelake-X, Icelake-D, Sapphire Rapids, Emerald Rapids, Xeon Phi KNL and KNM
+            Some((6, 0x3F | 0x4F | 0x55 | 0x6A | 0x6C | 0x8F | 0xCF | 0x57 | 0x85))
+        );
+    if domain == RaplDomainType::Dram && has_fixed_dram_unit {
+        INTEL_SERVER_DRAM_ENERGY_UNIT
+    } else {
+        energy_unit_from_power_unit(power_unit) as f64
+    }
+}
+
+/// Returns the family and model of the cpu, from `/proc/cpuinfo`.
+pub(crate) fn cpu_family_model() -> anyhow::Result<(u32, u32)> {
+    cpu_model_info().map(|info| (info.family, info.model))
+}
+
+/// The identification of the cpu model, which is needed to interpret some measurements (e.g. the unit of the DRAM energy
+/// of the Intel servers).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuModelInfo {
+    /// The vendor id, for instance `GenuineIntel` or `AuthenticAMD`.
+    pub vendor_id: String,
+    pub family: u32,
+    pub model: u32,
+    /// Unknown on some virtual machines.
+    pub stepping: Option<u32>,
+    /// The marketing name, for instance `Intel(R) Xeon(R) CPU E5-2630 v3 @ 2.40GHz`.
+    pub model_name: Option<String>,
+}
+
+impl CpuModelInfo {
+    /// The vendor, if it supports RAPL.
+    pub fn vendor(&self) -> Option<RaplVendor> {
+        match self.vendor_id.as_str() {
+            "AuthenticAMD" | "HygonGenuine" => Some(RaplVendor::Amd),
+            "GenuineIntel" => Some(RaplVendor::Intel),
+            _ => None,
+        }
+    }
+
+    /// The microarchitecture of the cpu, if it is known.
+    /// Only the usual models of the cpus that support RAPL are known.
+    pub fn microarchitecture(&self) -> Option<&'static str> {
+        let uarch = match (self.vendor()?, self.family, self.model) {
+            (RaplVendor::Intel, 6, model) => match model {
+                0x2A | 0x2D => "Sandy Bridge",
+                0x3A | 0x3E => "Ivy Bridge",
+                0x3C | 0x3F | 0x45 | 0x46 => "Haswell",
+                0x3D | 0x47 | 0x4F | 0x56 => "Broadwell",
+                // the server models share the model number, and differ by stepping
+                0x55 => match self.stepping {
+                    Some(5..=7) => "Cascade Lake",
+                    Some(10 | 11) => "Cooper Lake",
+                    _ => "Skylake",
+                },
+                0x4E | 0x5E => "Skylake",
+                0x8E | 0x9E => "Kaby Lake/Coffee Lake",
+                0xA5 | 0xA6 => "Comet Lake",
+                0x6A | 0x6C | 0x7D | 0x7E => "Ice Lake",
+                0x8C | 0x8D => "Tiger Lake",
+                0xA7 => "Rocket Lake",
+                0x97 | 0x9A => "Alder Lake",
+                0xB7 | 0xBA | 0xBF => "Raptor Lake",
+                0xAA | 0xAC => "Meteor Lake",
+                0x8F => "Sapphire Rapids",
+                0xCF => "Emerald Rapids",
+                0xAD | 0xAE => "Granite Rapids",
+                0x57 | 0x85 => "Xeon Phi",
+                0x5C | 0x5F | 0x7A => "Goldmont",
+                _ => return None,
+            },
+            (RaplVendor::Amd, 0x17, model) if model < 0x30 => "Zen/Zen+",
+            (RaplVendor::Amd, 0x17, _) => "Zen 2",
+            (RaplVendor::Amd, 0x19, 0x10..=0x1F | 0x60..=0x7F | 0xA0..=0xAF) => "Zen 4",
+            (RaplVendor::Amd, 0x19, _) => "Zen 3",
+            (RaplVendor::Amd, 0x1A, _) => "Zen 5",
+            _ => return None,
+        };
+        Some(uarch)
+    }
+}
+
+impl std::fmt::Display for CpuModelInfo {
+    /// Formats the model as `GenuineIntel family 6 model 0x3f stepping 2 (Haswell): <model name>`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} family {} model {:#x}", self.vendor_id, self.family, self.model)?;
+        if let Some(stepping) = self.stepping {
+            write!(f, " stepping {stepping}")?;
+        }
+        if let Some(uarch) = self.microarchitecture() {
+            write!(f, " ({uarch})")?;
+        }
+        if let Some(name) = &self.model_name {
+            write!(f, ": {name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the vendor, family, model and stepping of the cpu, from `/proc/cpuinfo`.
+pub fn cpu_model_info() -> anyhow::Result<CpuModelInfo> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").context("failed to read /proc/cpuinfo")?;
+    parse_cpu_model_info(&cpuinfo)
+}
+
+fn parse_cpu_model_info(cpuinfo: &str) -> anyhow::Result<CpuModelInfo> {
+    // only look at the first processor, all the cpus of a machine have the same model
+    let mut vendor_id = None;
+    let mut family = None;
+    let mut model = None;
+    let mut stepping = None;
+    let mut model_name = None;
+    for line in cpuinfo.lines().take_while(|l| !l.trim().is_empty()) {
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            match key.trim() {
+                "vendor_id" => vendor_id = Some(value.to_owned()),
+                "cpu family" => family = Some(value.parse()?),
+                "model" => model = Some(value.parse()?),
+                // "unknown" on some virtual machines
+                "stepping" => stepping = value.parse().ok(),
+                "model name" => model_name = Some(value.to_owned()),
+                _ => (),
+            }
+        }
+    }
+    match (vendor_id, family, model) {
+        (Some(vendor_id), Some(family), Some(model)) => Ok(CpuModelInfo {
+            vendor_id,
+            family,
+            model,
+            stepping,
+            model_name,
+        }),
+        _ => Err(anyhow!("cpu vendor, family or model not found in cpuinfo")),
+    }
 }
 
 pub fn cpu_vendor() -> anyhow::Result<RaplVendor> {
@@ -193,9 +655,10 @@ pub fn cpu_vendor() -> anyhow::Result<RaplVendor> {
 
     // turn it into the right enum variant
     match vendor {
-        "AuthenticAMD" => Ok(RaplVendor::Amd),
+        // the Hygon cpus are based on AMD Zen, with the same RAPL MSRs
+        "AuthenticAMD" | "HygonGenuine" => Ok(RaplVendor::Amd),
         "GenuineIntel" => Ok(RaplVendor::Intel),
-        _ => Err(anyhow!("Unsupported CPU vendor {vendor}")),
+        _ => Err(RaplError::VendorUnsupported(vendor.to_owned()).into()),
     }
 }
 
@@ -230,3 +693,350 @@ pub fn all_domains(vendor: RaplVendor) -> Vec<RaplDomainType> {
         RaplVendor::Amd => vec![RaplDomainType::Package, RaplDomainType::PP0],
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use enum_map::EnumMap;
+
+    use super::{domain_energy_unit, energy_counter_value, is_msr_module_missing, msr_domains, one_cpu_per_core, parse_cpu_model_info, usable_domains, CoreEnergySum, MsrProbe, PowerUnitCache, RaplMsrAccess, RaplMsrDomain, RaplVendor, SocketCores, MSR_MAX_ENERGY, MSR_PERMISSION_HINT};
+    use crate::error::open_error;
+    use crate::{CpuId, EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError};
+
+    /// Value of MSR_RAPL_POWER_UNIT on a Haswell-X: ESU = 14, i.e. 61 microJoules.
+    const HSX_POWER_UNIT: u64 = 0x000A0E03;
+
+    #[test]
+    fn test_msr_module_missing() {
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(is_msr_module_missing(&not_found, true));
+        // an offline cpu has no msr device, even if the module is loaded
+        assert!(!is_msr_module_missing(&not_found, false));
+        assert!(!is_msr_module_missing(&denied, true));
+    }
+
+    #[test]
+    fn test_permission_denied() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let err = RaplError::from(open_error(denied, "/dev/cpu/0/msr", MSR_PERMISSION_HINT));
+        let RaplError::PermissionDenied(msg) = err else {
+            panic!("unexpected error {err:?}");
+        };
+        assert!(msg.starts_with("cannot open /dev/cpu/0/msr. "));
+        assert!(msg.contains("CAP_SYS_RAWIO"));
+        assert!(msg.contains("sudo modprobe msr"));
+    }
+
+    #[test]
+    fn test_energy_counter_mask() {
+        // the reserved high bits are ignored
+        assert_eq!(energy_counter_value(0xdead_beef_0012_3456), 0x0012_3456);
+        assert_eq!(energy_counter_value(0xffff_ffff_0000_0000), 0);
+        assert_eq!(energy_counter_value(u64::MAX), MSR_MAX_ENERGY);
+        assert_eq!(energy_counter_value(0x8000_0000), 0x8000_0000);
+        // the counter is not sign-extended
+        assert_eq!(energy_counter_value(0xffff_ffff_ffff_fffe), 0xffff_fffe);
+    }
+
+    #[test]
+    fn test_dram_energy_unit_override() {
+        let esu_unit = 0.5_f64.powi(14);
+        let haswell_x = Some((6, 0x3F));
+        assert_eq!(
+            domain_energy_unit(HSX_POWER_UNIT, RaplDomainType::Package, RaplVendor::Intel, haswell_x),
+            esu_unit
+        );
+        assert_eq!(
+            domain_energy_unit(HSX_POWER_UNIT, RaplDomainType::Dram, RaplVendor::Intel, haswell_x),
+            0.5_f64.powi(16)
+        );
+
+        // a client platform uses the ESU for every domain
+        let skylake = Some((6, 0x5E));
+        assert_eq!(
+            domain_energy_unit(HSX_POWER_UNIT, RaplDomainType::Dram, RaplVendor::Intel, skylake),
+            esu_unit
+        );
+        // unknown model: no override
+        assert_eq!(
+            domain_energy_unit(HSX_POWER_UNIT, RaplDomainType::Dram, RaplVendor::Intel, None),
+            esu_unit
+        );
+    }
+
+    #[test]
+    fn test_parse_cpu_model_info() -> anyhow::Result<()> {
+        let cpuinfo = "processor\t: 0
+vendor_id\t: GenuineIntel
+cpu family\t: 6
+model\t\t: 63
+model name\t: Intel(R) Xeon(R) CPU E5-2630 v3 @ 2.40GHz
+stepping\t: 2
+
+processor\t: 1
+vendor_id\t: GenuineIntel
+cpu family\t: 6
+model\t\t: 63
+";
+        let info = parse_cpu_model_info(cpuinfo)?;
+        assert_eq!((info.family, info.model), (6, 0x3F));
+        assert_eq!(info.vendor_id, "GenuineIntel");
+        assert_eq!(info.vendor(), Some(RaplVendor::Intel));
+        assert_eq!(info.stepping, Some(2));
+        assert_eq!(info.model_name.as_deref(), Some("Intel(R) Xeon(R) CPU E5-2630 v3 @ 2.40GHz"));
+        assert_eq!(info.microarchitecture(), Some("Haswell"));
+        assert_eq!(
+            info.to_string(),
+            "GenuineIntel family 6 model 0x3f stepping 2 (Haswell): Intel(R) Xeon(R) CPU E5-2630 v3 @ 2.40GHz"
+        );
+        assert!(parse_cpu_model_info("processor\t: 0\n").is_err());
+
+        // AMD EPYC 7702, without stepping
+        let cpuinfo = "processor\t: 0
+vendor_id\t: AuthenticAMD
+cpu family\t: 23
+model\t\t: 49
+stepping\t: unknown
+";
+        let info = parse_cpu_model_info(cpuinfo)?;
+        assert_eq!(info.vendor(), Some(RaplVendor::Amd));
+        assert_eq!(info.stepping, None);
+        assert_eq!(info.to_string(), "AuthenticAMD family 23 model 0x31 (Zen 2)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_domain() {
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let err = MsrProbe::new(&cpus, &[]).err().expect("MsrProbe without domains should fail");
+        assert_eq!(err.to_string(), "At least one RAPL domain is required for MsrProbe");
+    }
+
+    #[test]
+    fn test_unsupported_domain() {
+        let err = msr_domains(&[RaplDomainType::Package, RaplDomainType::Dram], RaplVendor::Amd)
+            .err()
+            .expect("DRAM is not available on AMD");
+        assert_eq!(err.to_string(), "The RAPL domain Dram is not supported by this probe on Amd cpus");
+        let domains = msr_domains(&[RaplDomainType::Package, RaplDomainType::Dram], RaplVendor::Intel).unwrap();
+        assert_eq!(domains.len(), 2);
+    }
+
+    #[test]
+    fn test_power_unit_cache() -> io::Result<()> {
+        let cache = PowerUnitCache::default();
+        let mut reads = 0;
+        let mut reader = |value: u64| {
+            reads += 1;
+            move || Ok(value)
+        };
+        assert_eq!(cache.get(0, RaplVendor::Intel), None);
+        assert_eq!(cache.get_or_read(0, RaplVendor::Intel, reader(HSX_POWER_UNIT))?, HSX_POWER_UNIT);
+        assert_eq!(cache.get(0, RaplVendor::Intel), Some(HSX_POWER_UNIT));
+        // the cached value is returned, the reader is not called
+        assert_eq!(cache.get_or_read(0, RaplVendor::Intel, || panic!("cached value not used"))?, HSX_POWER_UNIT);
+        // other keys are read
+        assert_eq!(cache.get_or_read(1, RaplVendor::Intel, reader(1))?, 1);
+        assert_eq!(cache.get_or_read(0, RaplVendor::Amd, reader(2))?, 2);
+        assert_eq!(reads, 3);
+
+        // errors are not cached
+        let failed = cache.get_or_read(2, RaplVendor::Intel, || Err(io::Error::from(io::ErrorKind::PermissionDenied)));
+        assert!(failed.is_err());
+        assert_eq!(cache.get(2, RaplVendor::Intel), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_energy_unit_for() -> anyhow::Result<()> {
+        let vendor = RaplVendor::Intel;
+        let msr_per_cpu = (0..2)
+            .map(|socket_id| {
+                Ok(RaplMsrAccess {
+                    // not read by this test
+                    fd: std::fs::File::open("/dev/null")?,
+                    cpu: socket_id,
+                    energy_units: EnumMap::from_fn(|d| domain_energy_unit(HSX_POWER_UNIT, d, vendor, Some((6, 0x3F)))),
+                    socket_id,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let probe = MsrProbe {
+            measurements: EnergyMeasurements::new(2),
+            msr_per_cpu,
+            domains: msr_domains(&[RaplDomainType::Package, RaplDomainType::Dram], vendor)?,
+            all_cores: Vec::new(),
+        };
+
+        for socket in 0..2 {
+            for domain in [RaplDomainType::Package, RaplDomainType::Dram] {
+                let unit = probe.energy_unit_for(socket, domain).unwrap();
+                // RAPL units are powers of two: 2^-14 for the package, 2^-16 for the DRAM of Haswell-X
+                assert_eq!(unit.log2().fract(), 0.0, "{domain:?} unit {unit} is not a power of two");
+            }
+        }
+        assert_eq!(probe.energy_unit_for(0, RaplDomainType::Package), Some(1.0 / 16384.0));
+        assert_eq!(probe.energy_unit_for(0, RaplDomainType::PP0), None);
+        assert_eq!(probe.energy_unit_for(2, RaplDomainType::Package), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usable_domains() -> anyhow::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        // a regular file that stands for /dev/cpu/0/msr, with the registers at their offsets
+        let path = std::env::temp_dir().join(format!("rapl_probes-msr-usable-{}", std::process::id()));
+        let fd = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        fd.write_all_at(&0x1234u64.to_ne_bytes(), super::intel::MSR_PKG_ENERGY_STATUS)?;
+        fd.write_all_at(&0u64.to_ne_bytes(), super::intel::MSR_DRAM_ENERGY_STATUS)?;
+        fd.write_all_at(&0xdead_beef_0000_1234u64.to_ne_bytes(), super::intel::MSR_PP0_ENERGY_STATUS)?;
+        // MSR_PLATFORM_ENERGY_STATUS is after the end of the file: its read fails
+        let msr_per_cpu = vec![RaplMsrAccess {
+            fd: fd.try_clone()?,
+            cpu: 0,
+            energy_units: EnumMap::from_fn(|_| 1.0),
+            socket_id: 0,
+        }];
+        std::fs::remove_file(&path)?;
+
+        let requested = [RaplDomainType::Package, RaplDomainType::Dram, RaplDomainType::PP0, RaplDomainType::Platform];
+        let domains = usable_domains(&msr_per_cpu, msr_domains(&requested, RaplVendor::Intel)?);
+        let mut probe = MsrProbe {
+            measurements: EnergyMeasurements::new(1),
+            msr_per_cpu,
+            domains,
+            all_cores: Vec::new(),
+        };
+        assert_eq!(probe.domains(), vec![RaplDomainType::Package]);
+
+        // the dropped domains are not polled
+        probe.poll()?;
+        fd.write_all_at(&0x1240u64.to_ne_bytes(), super::intel::MSR_PKG_ENERGY_STATUS)?;
+        probe.poll()?;
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, Some(12.0));
+        assert!(probe.measurements().per_socket[0][RaplDomainType::Platform].joules.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_domain_max_energy() -> anyhow::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        // a regular file that stands for /dev/cpu/0/msr, with the registers at their offsets
+        let path = std::env::temp_dir().join(format!("rapl_probes-msr-{}", std::process::id()));
+        let fd = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        let write_register = |addr: u64, value: u64| fd.write_all_at(&value.to_ne_bytes(), addr);
+
+        let mut probe = MsrProbe {
+            measurements: EnergyMeasurements::new(1),
+            msr_per_cpu: vec![RaplMsrAccess {
+                fd: fd.try_clone()?,
+                cpu: 0,
+                energy_units: EnumMap::from_fn(|_| 1.0),
+                socket_id: 0,
+            }],
+            domains: vec![
+                RaplMsrDomain {
+                    domain: RaplDomainType::Package,
+                    addr: super::intel::MSR_PKG_ENERGY_STATUS,
+                    max_energy: 999,
+                },
+                RaplMsrDomain {
+                    domain: RaplDomainType::Dram,
+                    addr: super::intel::MSR_DRAM_ENERGY_STATUS,
+                    max_energy: MSR_MAX_ENERGY,
+                },
+            ],
+            all_cores: Vec::new(),
+        };
+        write_register(super::intel::MSR_PKG_ENERGY_STATUS, 900)?;
+        write_register(super::intel::MSR_DRAM_ENERGY_STATUS, 900)?;
+        probe.poll()?;
+        // both counters wrap
+        write_register(super::intel::MSR_PKG_ENERGY_STATUS, 100)?;
+        write_register(super::intel::MSR_DRAM_ENERGY_STATUS, 100)?;
+        probe.poll()?;
+        std::fs::remove_file(&path)?;
+
+        // each counter wraps at its own max value
+        let m = &probe.measurements().per_socket[0];
+        assert_eq!(m[RaplDomainType::Package].joules, Some((999 - 900 + 100 + 1) as f64));
+        assert_eq!(m[RaplDomainType::Dram].joules, Some((MSR_MAX_ENERGY - 900 + 100 + 1) as f64));
+        Ok(())
+    }
+
+    #[test]
+    fn test_core_energy_sum() {
+        let mut sum = CoreEnergySum::default();
+        // the first values are the reference
+        assert_eq!(sum.update(&[100, 5000, 900], 999), 0);
+        assert_eq!(sum.update(&[110, 5020, 950], 999), 10 + 20 + 50);
+        // the third core wraps: 999 - 950 + 30 + 1 = 80
+        assert_eq!(sum.update(&[120, 5040, 30], 999), 80 + 10 + 20 + 80);
+        // unchanged values: no energy, although the sum of the raw values has decreased since the first call
+        assert_eq!(sum.update(&[120, 5040, 30], 999), 190);
+    }
+
+    #[test]
+    fn test_one_cpu_per_core() {
+        // (cpu, package, core_id): 2 packages of 2 cores with SMT, the siblings are numbered after the first threads
+        let topology = [(0, 0, 0), (1, 0, 1), (2, 1, 0), (3, 1, 1), (4, 0, 0), (5, 0, 1), (6, 1, 0), (7, 1, 1)];
+        assert_eq!(one_cpu_per_core(&topology, 0), vec![0, 1]);
+        assert_eq!(one_cpu_per_core(&topology, 1), vec![2, 3]);
+        assert!(one_cpu_per_core(&topology, 2).is_empty());
+    }
+
+    #[test]
+    fn test_all_cores() -> anyhow::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        // regular files that stand for the /dev/cpu/N/msr of two cores of the same socket
+        let paths: Vec<_> = (0..2)
+            .map(|core| std::env::temp_dir().join(format!("rapl_probes-msr-core{core}-{}", std::process::id())))
+            .collect();
+        let fds = paths
+            .iter()
+            .map(|path| std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path))
+            .collect::<io::Result<Vec<_>>>()?;
+        let write_core = |core: usize, value: u64| fds[core].write_all_at(&value.to_ne_bytes(), super::amd::MSR_CORE_ENERGY_STATUS);
+        let domains = msr_domains(&[RaplDomainType::PP0], RaplVendor::Amd)?;
+        let core_domain = &domains[0];
+        let mut probe = MsrProbe {
+            measurements: EnergyMeasurements::new(1),
+            msr_per_cpu: vec![RaplMsrAccess {
+                fd: fds[0].try_clone()?,
+                cpu: 0,
+                energy_units: EnumMap::from_fn(|_| 1.0),
+                socket_id: 0,
+            }],
+            all_cores: vec![SocketCores {
+                socket_id: 0,
+                domain: core_domain.domain,
+                addr: core_domain.addr,
+                max_energy: core_domain.max_energy,
+                fds: fds.iter().map(|fd| fd.try_clone()).collect::<io::Result<Vec<_>>>()?,
+                sum: CoreEnergySum::default(),
+            }],
+            domains,
+        };
+        for path in &paths {
+            std::fs::remove_file(path)?;
+        }
+
+        write_core(0, 1000)?;
+        write_core(1, MSR_MAX_ENERGY - 5)?;
+        probe.poll()?;
+        // the second core wraps
+        write_core(0, 1010)?;
+        write_core(1, 4)?;
+        probe.poll()?;
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::PP0].joules, Some(20.0));
+        write_core(0, 1020)?;
+        probe.poll()?;
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::PP0].joules, Some(10.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! Several probes polled together, to compare the RAPL interfaces on the same run (e.g. powercap and perf-event).
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError};
+
+/// Polls several probes at the same cadence, and keeps the measurements of each one, tagged by a label.
+///
+/// Each probe compares its counters with its own previous values: they must all be polled each time,
+/// otherwise their measurements would not cover the same intervals. Thus, the probes are only accessible together.
+///
+/// As an [`EnergyProbe`], this is the first probe (the reference): [`EnergyProbe::measurements`] returns its measurements.
+/// The measurements of all the probes are given by [`MultiProbe::sources`].
+#[derive(Default)]
+pub struct MultiProbe {
+    probes: Vec<(String, Box<dyn EnergyProbe>)>,
+}
+
+impl MultiProbe {
+    pub fn new() -> MultiProbe {
+        MultiProbe::default()
+    }
+
+    /// Adds a probe, whose measurements are tagged with `label`. The labels should be unique.
+    pub fn with_probe(mut self, label: impl Into<String>, probe: Box<dyn EnergyProbe>) -> MultiProbe {
+        self.probes.push((label.into(), probe));
+        self
+    }
+
+    /// The label and the latest measurements of each probe, in the order in which the probes have been added.
+    pub fn sources(&self) -> impl Iterator<Item = (&str, &EnergyMeasurements)> {
+        self.probes.iter().map(|(label, probe)| (label.as_str(), probe.measurements()))
+    }
+
+    /// The number of probes.
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    fn reference(&self) -> &dyn EnergyProbe {
+        self.probes.first().expect("MultiProbe without probe").1.as_ref()
+    }
+}
+
+impl EnergyProbe for MultiProbe {
+    /// Polls all the probes, one after the other.
+    fn poll(&mut self) -> Result<(), RaplError> {
+        for (label, probe) in &mut self.probes {
+            // the typed errors (e.g. permission denied) are kept, the label is only added to the others
+            probe.poll().map_err(|e| match e {
+                RaplError::Other(err) => RaplError::Other(err.context(format!("failed to poll {label}"))),
+                e => e,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The measurements of the first probe.
+    ///
+    /// # Panics
+    /// If there is no probe.
+    fn measurements(&self) -> &EnergyMeasurements {
+        self.reference().measurements()
+    }
+
+    fn reset(&mut self) {
+        for (_, probe) in &mut self.probes {
+            probe.reset();
+        }
+    }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.reference().energy_unit_for(socket, domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiProbe;
+    use crate::mock::MockProbe;
+    use crate::{EnergyProbe, RaplDomainType};
+
+    #[test]
+    fn test_multi_probe() -> anyhow::Result<()> {
+        // the second probe measures 10% more energy
+        let a = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![0, 100, 300], u32::MAX as u64, 1.0);
+        let b = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![50, 160, 380], u32::MAX as u64, 1.0);
+        let mut probe = MultiProbe::new().with_probe("a", Box::new(a)).with_probe("b", Box::new(b));
+        assert_eq!(probe.len(), 2);
+
+        let package = |probe: &MultiProbe| -> Vec<(String, Option<f64>)> {
+            probe
+                .sources()
+                .map(|(label, m)| (label.to_owned(), m.per_socket[0][RaplDomainType::Package].joules))
+                .collect()
+        };
+        probe.poll()?;
+        assert_eq!(package(&probe), [(String::from("a"), None), (String::from("b"), None)]);
+        probe.poll()?;
+        assert_eq!(package(&probe), [(String::from("a"), Some(100.0)), (String::from("b"), Some(110.0))]);
+        probe.poll()?;
+        assert_eq!(package(&probe), [(String::from("a"), Some(200.0)), (String::from("b"), Some(220.0))]);
+        // the reference is the first probe
+        assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, Some(200.0));
+
+        // the scripts are exhausted: the error tells which probe failed
+        let err = probe.poll().unwrap_err();
+        assert!(err.to_string().starts_with("failed to poll a"), "{err}");
+
+        probe.reset();
+        assert_eq!(package(&probe), [(String::from("a"), None), (String::from("b"), None)]);
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
-use anyhow::{Context, Result};
-use log::debug;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, warn};
 use perf_event_open_sys as sys;
 use std::{
     fs::{self, File},
     io::{self, Read},
     os::fd::FromRawFd,
     path::Path,
+    time::SystemTime,
 };
 
-use crate::EnergyMeasurements;
+use crate::msr::{self, RaplVendor};
+use crate::perf_mmap::MmapRing;
+use crate::{EnergyMeasurements, RaplError, SysfsPaths};
 
 use super::{CpuId, EnergyProbe, RaplDomainType};
 
@@ -24,7 +27,7 @@ pub struct PowerEvent {
     /// The RAPL domain type, as an enum.
     pub domain: RaplDomainType,
     /// The event code to use as a "config" field for perf_event_open
-    pub code: u8,
+    pub code: u64,
     /// should be "Joules"
     pub unit: String,
     /// The scale to apply in order to get joules (`energy_j = count * scale`).
@@ -41,29 +44,88 @@ impl PowerEvent {
     /// * `cpu_id` - Defines which CPU (core) to monitor, given by [`super::cpus_to_monitor()`]
     ///
     pub fn perf_event_open(&self, pmu_type: u32, cpu_id: u32) -> std::io::Result<i32> {
-        // Only some combination of (pid, cpu) are valid.
-        // For RAPL PMU events, we use (-1, cpu) which means "all processes, one cpu".
-        let pid = -1; // all processes
-        let cpu = cpu_id as i32;
+        self.perf_event_open_with_options(pmu_type, cpu_id, &PerfAttrOptions::default())
+    }
+
+    /// Like [`PowerEvent::perf_event_open`], but with some flags of `perf_event_attr`.
+    ///
+    /// The RAPL events are not attached to a task, thus they ignore these flags: this is for the other PMUs
+    /// (e.g. the software clock events).
+    pub fn perf_event_open_with_options(&self, pmu_type: u32, cpu_id: u32, options: &PerfAttrOptions) -> std::io::Result<i32> {
+        let mut attr = self.perf_event_attr(pmu_type, options);
+        open_with_attr(&mut attr, cpu_id, -1)
+    }
+
+    /// Like [`PowerEvent::perf_event_open`], but with a group leader and a read format.
+    ///
+    /// Pass `group_fd = -1` to open a group leader (or an independent event),
+    /// and the fd of the leader to add the event to its group.
+    fn perf_event_open_in_group(&self, pmu_type: u32, cpu_id: u32, group_fd: i32, read_format: u64) -> std::io::Result<i32> {
+        let mut attr = self.perf_event_attr(pmu_type, &PerfAttrOptions::default());
+        attr.read_format = read_format;
+        open_with_attr(&mut attr, cpu_id, group_fd)
+    }
+
+    /// Like [`PowerEvent::perf_event_open`], but in sampling mode: the kernel writes the value of the counter
+    /// in the ring buffer of the event every `sample_period` increments (see [`crate::perf_mmap`]).
+    fn perf_event_open_sampling(&self, pmu_type: u32, cpu_id: u32, sample_period: u64) -> std::io::Result<i32> {
+        let mut attr = self.perf_event_attr(pmu_type, &PerfAttrOptions::default());
+        attr.__bindgen_anon_1.sample_period = sample_period;
+        attr.sample_type = sys::bindings::PERF_SAMPLE_READ;
+        open_with_attr(&mut attr, cpu_id, -1)
+    }
 
+    fn perf_event_attr(&self, pmu_type: u32, options: &PerfAttrOptions) -> sys::bindings::perf_event_attr {
         let mut attr = sys::bindings::perf_event_attr::default();
-        attr.config = self.code.into();
+        attr.config = self.code;
         attr.type_ = pmu_type;
         attr.size = core::mem::size_of_val(&attr) as u32;
-        debug!("{attr:?}");
+        attr.set_exclude_kernel(options.exclude_kernel.into());
+        attr.set_exclude_hv(options.exclude_hv.into());
+        attr.set_inherit(options.inherit.into());
+        attr.set_pinned(options.pinned.into());
+        attr
+    }
+}
 
-        let result = unsafe { sys::perf_event_open(&mut attr, pid, cpu, -1, 0) };
-        if result == -1 {
-            Err(std::io::Error::last_os_error())
-        } else {
-            Ok(result)
-        }
+/// Flags of `perf_event_attr`, see [`PowerEvent::perf_event_open_with_options`].
+///
+/// The default value sets none of them, like [`PowerEvent::perf_event_open`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PerfAttrOptions {
+    /// Does not count the events that occur in kernel space.
+    pub exclude_kernel: bool,
+    /// Does not count the events that occur in the hypervisor.
+    pub exclude_hv: bool,
+    /// Also counts the events of the child tasks, created after the event is opened.
+    pub inherit: bool,
+    /// Always keeps the event on the PMU, instead of multiplexing it with the other events.
+    pub pinned: bool,
+}
+
+fn open_with_attr(attr: &mut sys::bindings::perf_event_attr, cpu_id: u32, group_fd: i32) -> std::io::Result<i32> {
+    // Only some combination of (pid, cpu) are valid.
+    // For RAPL PMU events, we use (-1, cpu) which means "all processes, one cpu".
+    let pid = -1; // all processes
+    let cpu = cpu_id as i32;
+    debug!("{attr:?}");
+
+    let result = unsafe { sys::perf_event_open(attr, pid, cpu, group_fd, 0) };
+    if result == -1 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(result)
     }
 }
 
 /// Retrieves the type of the RAPL PMU (Power Monitoring Unit) in the Linux kernel.
 pub fn pmu_type() -> Result<u32> {
-    let path = Path::new("/sys/devices/power/type");
+    pmu_type_in(&SysfsPaths::from_env())
+}
+
+/// Like [`pmu_type`], with the sysfs of `paths`.
+pub fn pmu_type_in(paths: &SysfsPaths) -> Result<u32> {
+    let path = &paths.path("/sys/devices/power/type");
     let read = fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
     let typ = read
         .trim_end()
@@ -76,17 +138,43 @@ pub fn pmu_type() -> Result<u32> {
 /// There can be more than just `cores`, `pkg` and `dram`.
 /// For instance, there can be `gpu` and
 /// [`psys`](https://patchwork.kernel.org/project/linux-pm/patch/1458253409-13318-1-git-send-email-srinivas.pandruvada@linux.intel.com/).
+///
+/// On AMD cpus, the scale of the `cores` event is replaced by the one of `pkg` if they differ.
 pub fn all_power_events() -> Result<Vec<PowerEvent>> {
+    let vendor = match msr::cpu_vendor() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("Could not determine the cpu vendor, the scales of the RAPL perf events will not be checked: {e:?}");
+            None
+        }
+    };
+    all_power_events_in(&SysfsPaths::from_env(), vendor)
+}
+
+/// Like [`all_power_events`], with the sysfs of `paths` and the given cpu `vendor` (`None` if it is unknown).
+pub fn all_power_events_in(paths: &SysfsPaths, vendor: Option<RaplVendor>) -> Result<Vec<PowerEvent>> {
+    read_power_events(&paths.path("/sys/devices/power/events"), vendor)
+}
+
+/// Parses the content of an event file, `event=0x<code>`, where the code is in hexadecimal.
+///
+/// The code is the `config` field of `perf_event_attr`, which has 64 bits: it is not limited to one byte,
+/// although all the current RAPL events have a code between 0x01 and 0x05.
+fn parse_event_code(content: &str) -> Result<u64> {
+    let code_str = content
+        .trim_end()
+        .strip_prefix("event=0x")
+        .with_context(|| format!("missing prefix event=0x in '{content}'"))?;
+    Ok(u64::from_str_radix(code_str, 16)?)
+}
+
+/// Reads the RAPL power events in `events_dir`, usually `/sys/devices/power/events`.
+fn read_power_events(events_dir: &Path, vendor: Option<RaplVendor>) -> Result<Vec<PowerEvent>> {
     let mut events: Vec<PowerEvent> = Vec::new();
 
-    fn read_event_code(path: &Path) -> Result<u8> {
+    fn read_event_code(path: &Path) -> Result<u64> {
         let read = fs::read_to_string(path)?;
-        let code_str = read
-            .trim_end()
-            .strip_prefix("event=0x")
-            .with_context(|| format!("Failed to strip {path:?}: '{read}'"))?;
-        let code = u8::from_str_radix(code_str, 16).with_context(|| format!("Failed to parse {path:?}: '{read}'"))?; // hexadecimal
-        Ok(code)
+        parse_event_code(&read).with_context(|| format!("Failed to parse {path:?}: '{read}'"))
     }
 
     fn read_event_unit(main: &Path) -> Result<String> {
@@ -119,7 +207,7 @@ pub fn all_power_events() -> Result<Vec<PowerEvent>> {
     }
 
     // Find all the events
-    for e in fs::read_dir("/sys/devices/power/events")? {
+    for e in fs::read_dir(events_dir)? {
         let entry = e?;
         let path = entry.path();
         let file_name = path.file_name().unwrap().to_string_lossy();
@@ -143,61 +231,263 @@ pub fn all_power_events() -> Result<Vec<PowerEvent>> {
             }
         }
     }
+    if vendor == Some(RaplVendor::Amd) {
+        check_amd_scales(&mut events);
+    }
     Ok(events)
 }
 
+/// On AMD cpus, all the RAPL domains share the energy unit of `MSR_AMD_RAPL_POWER_UNIT`,
+/// but some kernels report a wrong scale for the `cores` event.
+/// If the scale of `cores` differs from the one of `pkg`, warns and uses the scale of `pkg` instead.
+fn check_amd_scales(events: &mut [PowerEvent]) {
+    let Some(pkg_scale) = events.iter().find(|e| e.domain == RaplDomainType::Package).map(|e| e.scale) else {
+        return;
+    };
+    for evt in events.iter_mut().filter(|e| e.domain == RaplDomainType::PP0) {
+        if evt.scale != pkg_scale {
+            warn!(
+                "The scale of the AMD RAPL perf event '{}' ({:e}) differs from the scale of 'pkg' ({:e}), this is a known kernel bug. Using the scale of 'pkg'.",
+                evt.name, evt.scale, pkg_scale
+            );
+            evt.scale = pkg_scale;
+        }
+    }
+}
+
 /// Energy probe based on perf_event for intel RAPL.
 pub struct PerfEventProbe {
     /// Stores the energy measurements
     measurements: EnergyMeasurements,
 
     /// Ready-to-use power events with additional metadata
-    events: Vec<OpenedPowerEvent>,
+    events: OpenedEvents,
+}
+
+enum OpenedEvents {
+    /// One group of events per socket, read with a single syscall.
+    Grouped(Vec<PowerEventGroup>),
+    /// Independent events, one syscall per event (fallback if the events cannot be grouped).
+    Independent(Vec<OpenedPowerEvent>),
+    /// Events in sampling mode, read from their mmap ring buffer (see [`PerfEventProbe::with_sampling`]).
+    Sampled(Vec<SampledPowerEvent>),
 }
 
 struct OpenedPowerEvent {
     fd: File,
-    scale: f64,
+    scale: f32,
     socket: u32,
     domain: RaplDomainType,
 }
 
+/// A power event in sampling mode, with its ring buffer.
+struct SampledPowerEvent {
+    event: OpenedPowerEvent,
+    ring: MmapRing,
+}
+
+/// The events of one socket, in a perf event group.
+struct PowerEventGroup {
+    /// The group leader, which is read with `PERF_FORMAT_GROUP` to get the values of all the events.
+    leader: File,
+    /// The other events of the group, kept open.
+    members: Vec<File>,
+    socket: u32,
+    /// The domains and scales of the events, in the order of the group (leader first).
+    events: Vec<(RaplDomainType, f32)>,
+    /// Buffer for the group read: the number of events, then one value per event.
+    buf: Vec<u8>,
+}
+
 impl PerfEventProbe {
-    pub fn new(socket_cpus: &[CpuId], events: &[&PowerEvent]) -> anyhow::Result<PerfEventProbe> {
+    pub fn new(socket_cpus: &[CpuId], events: &[&PowerEvent]) -> Result<PerfEventProbe, RaplError> {
+        crate::check_not_empty(events, "power event", "PerfEventProbe")?;
         crate::check_socket_cpus(socket_cpus)?;
         let pmu_type = pmu_type()?;
-        let mut opened = Vec::with_capacity(socket_cpus.len() * events.len());
-        for CpuId { cpu, socket } in socket_cpus {
-            for event in events {
-                let raw_fd = event.perf_event_open(pmu_type, *cpu)?;
-                let fd = unsafe { File::from_raw_fd(raw_fd) };
-                let scale = event.scale as f64;
-                opened.push(OpenedPowerEvent {
-                    fd,
-                    scale,
-                    socket: *socket,
-                    domain: event.domain,
-                })
+        let opened = match open_grouped(pmu_type, socket_cpus, events) {
+            Ok(groups) => OpenedEvents::Grouped(groups),
+            Err(e) => {
+                warn!("Failed to open the RAPL perf events as groups, falling back to independent reads: {e}");
+                OpenedEvents::Independent(open_independent(pmu_type, socket_cpus, events)?)
             }
-        }
+        };
         Ok(PerfEventProbe {
-            measurements: EnergyMeasurements::new(socket_cpus.len()),
+            measurements: EnergyMeasurements::for_cpus(socket_cpus),
             events: opened,
         })
     }
+
+    /// Creates a probe that opens the events in sampling mode, and reads their values from the mmap ring buffer
+    /// instead of making a `read()` syscall per event, to reduce the overhead at high frequency.
+    ///
+    /// The kernel writes a sample every `sample_period` increments of a counter (in units of the event's scale,
+    /// i.e. `sample_period * scale` joules). When no sample has been written since the last poll, the event is read
+    /// with `read()`, hence the period should be small enough to produce at least one sample per poll.
+    /// See [`crate::perf_mmap`] for the details and the added complexity.
+    ///
+    /// If the events cannot be opened in sampling mode (the RAPL PMU of most kernels rejects it),
+    /// falls back to [`PerfEventProbe::new`].
+    pub fn with_sampling(socket_cpus: &[CpuId], events: &[&PowerEvent], sample_period: u64) -> Result<PerfEventProbe, RaplError> {
+        crate::check_not_empty(events, "power event", "PerfEventProbe")?;
+        crate::check_socket_cpus(socket_cpus)?;
+        if sample_period == 0 {
+            return Err(RaplError::Other(anyhow!("The sampling period of the perf events must be positive")));
+        }
+        let pmu_type = pmu_type()?;
+        match open_sampled(pmu_type, socket_cpus, events, sample_period) {
+            Ok(sampled) => Ok(PerfEventProbe {
+                measurements: EnergyMeasurements::for_cpus(socket_cpus),
+                events: OpenedEvents::Sampled(sampled),
+            }),
+            Err(e) => {
+                warn!("Failed to open the RAPL perf events in sampling mode, falling back to read(): {e}");
+                PerfEventProbe::new(socket_cpus, events)
+            }
+        }
+    }
 }
 
-impl EnergyProbe for PerfEventProbe {
-    fn poll(&mut self) -> anyhow::Result<()> {
-        for evt in &mut self.events {
-            let counter_value = read_perf_event(&mut evt.fd)
-                .with_context(|| format!("failed to read perf_event {:?} for domain {:?}", evt.fd, evt.domain))?;
+fn open_independent(pmu_type: u32, socket_cpus: &[CpuId], events: &[&PowerEvent]) -> io::Result<Vec<OpenedPowerEvent>> {
+    let mut opened = Vec::with_capacity(socket_cpus.len() * events.len());
+    for CpuId { cpu, socket, .. } in socket_cpus {
+        for event in events {
+            let raw_fd = event.perf_event_open(pmu_type, *cpu)?;
+            let fd = unsafe { File::from_raw_fd(raw_fd) };
+            opened.push(OpenedPowerEvent {
+                fd,
+                scale: event.scale,
+                socket: *socket,
+                domain: event.domain,
+            })
+        }
+    }
+    Ok(opened)
+}
 
-            self.measurements
-                .push(evt.socket, evt.domain, counter_value, PERF_MAX_ENERGY, evt.scale);
+fn open_sampled(
+    pmu_type: u32,
+    socket_cpus: &[CpuId],
+    events: &[&PowerEvent],
+    sample_period: u64,
+) -> io::Result<Vec<SampledPowerEvent>> {
+    let mut opened = Vec::with_capacity(socket_cpus.len() * events.len());
+    for CpuId { cpu, socket, .. } in socket_cpus {
+        for event in events {
+            let raw_fd = event.perf_event_open_sampling(pmu_type, *cpu, sample_period)?;
+            let fd = unsafe { File::from_raw_fd(raw_fd) };
+            let ring = MmapRing::new(&fd)?;
+            opened.push(SampledPowerEvent {
+                event: OpenedPowerEvent {
+                    fd,
+                    scale: event.scale,
+                    socket: *socket,
+                    domain: event.domain,
+                },
+                ring,
+            })
+        }
+    }
+    Ok(opened)
+}
+
+fn open_grouped(pmu_type: u32, socket_cpus: &[CpuId], events: &[&PowerEvent]) -> io::Result<Vec<PowerEventGroup>> {
+    let read_format = sys::bindings::PERF_FORMAT_GROUP as u64;
+    let mut groups = Vec::with_capacity(socket_cpus.len());
+    for CpuId { cpu, socket, .. } in socket_cpus {
+        let Some((first, others)) = events.split_first() else {
+            continue;
+        };
+        let raw_leader = first.perf_event_open_in_group(pmu_type, *cpu, -1, read_format)?;
+        let leader = unsafe { File::from_raw_fd(raw_leader) };
+        let mut members = Vec::with_capacity(others.len());
+        for event in others {
+            let raw_fd = event.perf_event_open_in_group(pmu_type, *cpu, raw_leader, 0)?;
+            members.push(unsafe { File::from_raw_fd(raw_fd) });
+        }
+        groups.push(PowerEventGroup {
+            leader,
+            members,
+            socket: *socket,
+            events: events.iter().map(|e| (e.domain, e.scale)).collect(),
+            buf: vec![0u8; 8 * (1 + events.len())],
+        });
+    }
+    Ok(groups)
+}
+
+impl OpenedEvents {
+    /// Reads the raw values of the events, and gives them to `f` with their socket, domain and scale.
+    fn read(&mut self, mut f: impl FnMut(u32, RaplDomainType, u64, f32)) -> anyhow::Result<()> {
+        match self {
+            OpenedEvents::Grouped(groups) => {
+                for group in groups {
+                    // one read for all the events of the socket
+                    group
+                        .leader
+                        .read_exact(&mut group.buf)
+                        .with_context(|| format!("failed to read perf_event group {:?}", group.leader))?;
+                    let values = parse_group_values(&group.buf, group.events.len())?;
+                    for ((domain, scale), counter_value) in group.events.iter().zip(values) {
+                        f(group.socket, *domain, counter_value, *scale);
+                    }
+                }
+            }
+            OpenedEvents::Independent(events) => {
+                for evt in events {
+                    let counter_value = read_perf_event(&mut evt.fd)
+                        .with_context(|| format!("failed to read perf_event {:?} for domain {:?}", evt.fd, evt.domain))?;
+
+                    f(evt.socket, evt.domain, counter_value, evt.scale);
+                }
+            }
+            OpenedEvents::Sampled(sampled) => {
+                for SampledPowerEvent { event: evt, ring } in sampled {
+                    let sample = ring
+                        .read_last_sample()
+                        .with_context(|| format!("failed to read the ring buffer of perf_event {:?}", evt.fd))?;
+                    let counter_value = match sample {
+                        Some(value) => value,
+                        // no new sample since the last poll, the value in the ring is outdated
+                        None => read_perf_event(&mut evt.fd).with_context(|| {
+                            format!("failed to read perf_event {:?} for domain {:?}", evt.fd, evt.domain)
+                        })?,
+                    };
+                    f(evt.socket, evt.domain, counter_value, evt.scale);
+                }
+            }
         }
         Ok(())
     }
+}
+
+impl OpenedEvents {
+    /// The number of file descriptors of the events.
+    fn fd_count(&self) -> usize {
+        match self {
+            OpenedEvents::Grouped(groups) => groups.iter().map(|g| 1 + g.members.len()).sum(),
+            OpenedEvents::Independent(events) => events.len(),
+            OpenedEvents::Sampled(sampled) => sampled.len(),
+        }
+    }
+}
+
+/// The files of the events (and the ring buffers in sampling mode) are closed when the probe is dropped,
+/// which disables the counters in the kernel.
+impl Drop for PerfEventProbe {
+    fn drop(&mut self) {
+        debug!("Closing {} perf_event file descriptors", self.events.fd_count());
+    }
+}
+
+impl EnergyProbe for PerfEventProbe {
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.measurements.set_timestamp(SystemTime::now());
+        let measurements = &mut self.measurements;
+        self.events.read(|socket, domain, counter_value, scale| {
+            push_counter_value(measurements, socket, domain, counter_value, scale);
+        })?;
+        Ok(())
+    }
 
     fn measurements(&self) -> &crate::EnergyMeasurements {
         &self.measurements
@@ -206,11 +496,236 @@ impl EnergyProbe for PerfEventProbe {
     fn reset(&mut self) {
         self.measurements.clear()
     }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        let scale = match &self.events {
+            OpenedEvents::Grouped(groups) => groups
+                .iter()
+                .filter(|g| g.socket == socket)
+                .find_map(|g| g.events.iter().find(|(d, _)| *d == domain).map(|(_, scale)| *scale)),
+            OpenedEvents::Independent(events) => events
+                .iter()
+                .find(|e| e.socket == socket && e.domain == domain)
+                .map(|e| e.scale),
+            OpenedEvents::Sampled(sampled) => sampled
+                .iter()
+                .map(|s| &s.event)
+                .find(|e| e.socket == socket && e.domain == domain)
+                .map(|e| e.scale),
+        };
+        scale.map(f64::from)
+    }
+
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        out.clear();
+        self.events.read(|socket, domain, counter_value, _| out.push((socket, domain, counter_value)))?;
+        Ok(())
+    }
+}
+
+/// Parses the result of a read on a group leader opened with `PERF_FORMAT_GROUP` (and no other flag),
+/// which is `{ u64 nr; u64 values[nr]; }`.
+fn parse_group_values(buf: &[u8], n_events: usize) -> anyhow::Result<impl Iterator<Item = u64> + '_> {
+    let mut words = buf.chunks_exact(8).map(|b| u64::from_ne_bytes(b.try_into().unwrap()));
+    let nr = words.next().context("empty perf_event group read")?;
+    if nr != n_events as u64 || buf.len() < 8 * (1 + n_events) {
+        return Err(anyhow!("unexpected perf_event group read: {nr} values, expected {n_events}"));
+    }
+    Ok(words.take(n_events))
+}
+
+/// Pushes a raw value of a RAPL perf counter, with the `scale` of its [`PowerEvent`].
+///
+/// The eBPF probe reads the same perf counters, so it uses this function too, to apply the same scale and maximum value.
+pub(crate) fn push_counter_value(
+    measurements: &mut EnergyMeasurements,
+    socket: u32,
+    domain: RaplDomainType,
+    counter_value: u64,
+    scale: f32,
+) {
+    measurements.push(socket, domain, counter_value, PERF_MAX_ENERGY, scale as f64);
 }
 
 fn read_perf_event(fd: &mut File) -> io::Result<u64> {
     let mut buf = [0u8; 8];
     // rewind() is INVALID for perf events, we must read "at the cursor" every time
-    fd.read(&mut buf)?;
+    fd.read_exact(&mut buf)?;
     Ok(u64::from_ne_bytes(buf))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::{
+        parse_event_code, parse_group_values, push_counter_value, read_power_events, OpenedEvents, OpenedPowerEvent,
+        PerfAttrOptions, PerfEventProbe, PowerEvent, PowerEventGroup,
+    };
+    use crate::msr::RaplVendor;
+    use crate::{CpuId, EnergyMeasurements, RaplDomainType};
+
+    #[test]
+    fn test_push_counter_value() {
+        // the usual scale of the RAPL perf events, "0x1.0p-32"
+        let scale = 0.5_f32.powi(32);
+        let mut m = EnergyMeasurements::new(1);
+        push_counter_value(&mut m, 0, RaplDomainType::Package, u64::MAX - (1 << 32) + 1, scale);
+        // the perf counter is 64 bits wide, it wraps at u64::MAX
+        push_counter_value(&mut m, 0, RaplDomainType::Package, 1 << 33, scale);
+        let counter = &m.per_socket[0][RaplDomainType::Package];
+        assert!(counter.overflowed);
+        assert_eq!(counter.joules, Some(3.0));
+    }
+
+    #[test]
+    fn test_parse_group_values() -> anyhow::Result<()> {
+        let buf: Vec<u8> = [3u64, 10, 20, u64::MAX].iter().flat_map(|v| v.to_ne_bytes()).collect();
+        assert_eq!(parse_group_values(&buf, 3)?.collect::<Vec<_>>(), vec![10, 20, u64::MAX]);
+        assert!(parse_group_values(&buf, 2).is_err());
+        assert!(parse_group_values(&buf[..16], 3).is_err());
+        assert!(parse_group_values(&[], 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_amd_core_scale() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("rapl_probes-perf-events-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        for (name, code, scale) in [("pkg", "0x02", "2.3283064365386962890625e-10"), ("cores", "0x01", "1.52587890625e-05")] {
+            fs::write(dir.join(format!("energy-{name}")), format!("event={code}\n"))?;
+            fs::write(dir.join(format!("energy-{name}.unit")), "Joules\n")?;
+            fs::write(dir.join(format!("energy-{name}.scale")), format!("{scale}\n"))?;
+        }
+
+        let scale_of = |events: &[super::PowerEvent], domain| events.iter().find(|e| e.domain == domain).unwrap().scale;
+        let intel = read_power_events(&dir, Some(RaplVendor::Intel))?;
+        let amd = read_power_events(&dir, Some(RaplVendor::Amd));
+        fs::remove_dir_all(&dir)?;
+        let amd = amd?;
+
+        let pkg_scale = 0.5_f32.powi(32);
+        assert_eq!(intel.len(), 2);
+        assert_eq!(scale_of(&intel, RaplDomainType::PP0), 0.5_f32.powi(16), "intel scales must not be changed");
+        assert_eq!(scale_of(&amd, RaplDomainType::Package), pkg_scale);
+        assert_eq!(scale_of(&amd, RaplDomainType::PP0), pkg_scale);
+        let cores = amd.iter().find(|e| e.name == "cores").unwrap();
+        assert_eq!(cores.code, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_perf_event_attr() {
+        let event = PowerEvent {
+            name: String::from("pkg"),
+            domain: RaplDomainType::Package,
+            code: 2,
+            unit: String::from("Joules"),
+            scale: 0.5_f32.powi(32),
+        };
+        let attr = event.perf_event_attr(23, &PerfAttrOptions::default());
+        assert_eq!((attr.type_, attr.config), (23, 2));
+        assert_eq!(attr.size as usize, std::mem::size_of_val(&attr));
+        // the default options set no flag, like before they existed
+        assert_eq!(
+            format!("{attr:?}"),
+            format!("{:?}", perf_event_open_sys::bindings::perf_event_attr { type_: 23, config: 2, size: attr.size, ..Default::default() })
+        );
+
+        let options = PerfAttrOptions {
+            exclude_kernel: true,
+            pinned: true,
+            ..Default::default()
+        };
+        let attr = event.perf_event_attr(23, &options);
+        assert_eq!((attr.exclude_kernel(), attr.exclude_hv(), attr.inherit(), attr.pinned()), (1, 0, 0, 1));
+        assert_eq!((attr.exclude_user(), attr.disabled()), (0, 0));
+        let options = PerfAttrOptions {
+            exclude_hv: true,
+            inherit: true,
+            ..Default::default()
+        };
+        let attr = event.perf_event_attr(23, &options);
+        assert_eq!((attr.exclude_kernel(), attr.exclude_hv(), attr.inherit(), attr.pinned()), (0, 1, 1, 0));
+    }
+
+    #[test]
+    fn test_parse_event_code() -> anyhow::Result<()> {
+        assert_eq!(parse_event_code("event=0x02\n")?, 0x02);
+        assert_eq!(parse_event_code("event=0x05")?, 0x05);
+        // a hypothetical future event with a code larger than one byte
+        assert_eq!(parse_event_code("event=0x0123\n")?, 0x0123);
+        assert!(parse_event_code("0x02\n").is_err());
+        assert!(parse_event_code("event=0xzz\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_event() {
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let err = PerfEventProbe::new(&cpus, &[]).err().expect("PerfEventProbe without events should fail");
+        assert_eq!(err.to_string(), "At least one power event is required for PerfEventProbe");
+    }
+
+    fn open_fd_count() -> usize {
+        fs::read_dir("/proc/self/fd").unwrap().count()
+    }
+
+    /// A probe whose events are `/dev/null`, to check the lifetime of the file descriptors without the RAPL PMU.
+    fn fake_probe(grouped: bool) -> PerfEventProbe {
+        let dev_null = || File::open("/dev/null").unwrap();
+        let events = if grouped {
+            OpenedEvents::Grouped(vec![PowerEventGroup {
+                leader: dev_null(),
+                members: vec![dev_null(), dev_null()],
+                socket: 0,
+                events: vec![(RaplDomainType::Package, 1.0), (RaplDomainType::Dram, 1.0), (RaplDomainType::PP0, 1.0)],
+                buf: vec![0u8; 8 * 4],
+            }])
+        } else {
+            OpenedEvents::Independent(
+                [RaplDomainType::Package, RaplDomainType::Dram]
+                    .into_iter()
+                    .map(|domain| OpenedPowerEvent {
+                        fd: dev_null(),
+                        scale: 1.0,
+                        socket: 0,
+                        domain,
+                    })
+                    .collect(),
+            )
+        };
+        PerfEventProbe {
+            measurements: EnergyMeasurements::new(1),
+            events,
+        }
+    }
+
+    #[test]
+    fn test_no_fd_leak() {
+        assert_eq!(fake_probe(true).events.fd_count(), 3);
+        assert_eq!(fake_probe(false).events.fd_count(), 2);
+
+        // the other tests may open files in parallel, hence the margin
+        let before = open_fd_count();
+        for i in 0..1000 {
+            let probe = fake_probe(i % 2 == 0);
+            drop(probe);
+        }
+        let after = open_fd_count();
+        assert!(after < before + 50, "{} file descriptors leaked", after - before);
+
+        // the real probe closes the events that it has opened, even when its creation fails
+        let Ok(events) = super::all_power_events() else {
+            return; // no RAPL PMU
+        };
+        let events: Vec<_> = events.iter().collect();
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let before = open_fd_count();
+        for _ in 0..200 {
+            let _ = PerfEventProbe::new(&cpus, &events);
+        }
+        let after = open_fd_count();
+        assert!(after < before + 50, "{} file descriptors leaked", after - before);
+    }
+}
@@ -0,0 +1,255 @@
+//! Reading of the perf events from their mmap ring buffer, in sampling mode.
+//!
+//! In sampling mode, the kernel writes a `PERF_RECORD_SAMPLE` record in a ring buffer shared with userspace
+//! every `sample_period` increments of the counter. With `PERF_SAMPLE_READ`, the record contains the value
+//! of the counter, which is then read from memory, without any syscall.
+//!
+//! This is more complex than a `read()` on the file descriptor:
+//! - the ring buffer (one metadata page followed by 2^n data pages) must be mapped, and unmapped on drop;
+//! - the kernel writes `data_head` concurrently: it must be read before the records, with a memory barrier,
+//!   and `data_tail` must be written back to give the space of the records back to the kernel;
+//! - a record can wrap around the end of the buffer;
+//! - a value is only available when the counter has crossed a sampling period, not on every poll.
+//!
+//! Moreover, the RAPL PMU of the current kernels does not support sampling (`perf_event_open` fails with `EINVAL`).
+//! That is why [`PerfEventProbe::with_sampling`](crate::perf_event::PerfEventProbe::with_sampling) falls back to
+//! the usual reads when the events cannot be opened in sampling mode, and when there is no new sample in the ring.
+
+use std::fs::File;
+use std::io;
+use std::mem::offset_of;
+use std::os::fd::AsRawFd;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{fence, Ordering};
+
+use anyhow::anyhow;
+use perf_event_open_sys::bindings::{perf_event_mmap_page, PERF_RECORD_SAMPLE};
+
+/// Number of data pages of the ring buffer, must be a power of two.
+const DATA_PAGES: usize = 8;
+
+const DATA_HEAD_OFFSET: usize = offset_of!(perf_event_mmap_page, data_head);
+const DATA_TAIL_OFFSET: usize = offset_of!(perf_event_mmap_page, data_tail);
+const DATA_OFFSET_OFFSET: usize = offset_of!(perf_event_mmap_page, data_offset);
+const DATA_SIZE_OFFSET: usize = offset_of!(perf_event_mmap_page, data_size);
+
+/// Size of `struct perf_event_header`: `u32 type; u16 misc; u16 size;`.
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// The position of the data in the ring buffer, from its metadata page (`struct perf_event_mmap_page`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RingHeader {
+    /// Where the kernel will write the next record. It never wraps: take it modulo `data_size`.
+    pub data_head: u64,
+    /// Where userspace will read the next record.
+    pub data_tail: u64,
+    /// Offset of the data in the mapping.
+    pub data_offset: u64,
+    /// Size of the data.
+    pub data_size: u64,
+}
+
+/// Parses the metadata page of the ring buffer.
+///
+/// The fields are read with volatile reads, because the kernel modifies `data_head` concurrently.
+pub(crate) fn parse_ring_header(page: &[u8]) -> anyhow::Result<RingHeader> {
+    if page.len() < DATA_SIZE_OFFSET + 8 {
+        return Err(anyhow!("perf mmap page too small: {} bytes", page.len()));
+    }
+    let header = RingHeader {
+        data_head: read_u64_volatile(page, DATA_HEAD_OFFSET),
+        data_tail: read_u64_volatile(page, DATA_TAIL_OFFSET),
+        data_offset: read_u64_volatile(page, DATA_OFFSET_OFFSET),
+        data_size: read_u64_volatile(page, DATA_SIZE_OFFSET),
+    };
+    // data_offset and data_size have been added in Linux 4.1
+    if header.data_size == 0 {
+        return Err(anyhow!("perf mmap page without data_size, the kernel is too old"));
+    }
+    if header.data_tail > header.data_head {
+        return Err(anyhow!("invalid perf ring buffer: tail {} > head {}", header.data_tail, header.data_head));
+    }
+    Ok(header)
+}
+
+fn read_u64_volatile(page: &[u8], offset: usize) -> u64 {
+    let bytes = &page[offset..offset + 8];
+    // [u8; 8] has an alignment of 1, any offset is fine
+    u64::from_ne_bytes(unsafe { ptr::read_volatile(bytes.as_ptr().cast::<[u8; 8]>()) })
+}
+
+/// Returns the value of the last `PERF_RECORD_SAMPLE` record between `tail` and `head`, or `None` if there is none.
+///
+/// The events must be opened with `sample_type = PERF_SAMPLE_READ` and `read_format = 0`,
+/// so that the body of a sample is just the `u64` value of the counter.
+/// The other records (e.g. `PERF_RECORD_LOST`) are skipped.
+pub(crate) fn last_sample_value(data: &[u8], tail: u64, head: u64) -> anyhow::Result<Option<u64>> {
+    let mut value = None;
+    let mut pos = tail;
+    while pos < head {
+        let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+        copy_from_ring(data, pos, &mut header);
+        let record_type = u32::from_ne_bytes(header[0..4].try_into().unwrap());
+        let size = u16::from_ne_bytes(header[6..8].try_into().unwrap()) as u64;
+        if size < RECORD_HEADER_SIZE || pos + size > head {
+            return Err(anyhow!("invalid perf record at {pos}: size {size}, head {head}"));
+        }
+        if record_type == PERF_RECORD_SAMPLE {
+            if size < RECORD_HEADER_SIZE + 8 {
+                return Err(anyhow!("perf sample at {pos} too small to contain a value: size {size}"));
+            }
+            let mut v = [0u8; 8];
+            copy_from_ring(data, pos + RECORD_HEADER_SIZE, &mut v);
+            value = Some(u64::from_ne_bytes(v));
+        }
+        pos += size;
+    }
+    Ok(value)
+}
+
+/// Copies `out.len()` bytes from the ring buffer `data`, starting at `pos` (modulo its size), and wrapping around its end.
+fn copy_from_ring(data: &[u8], pos: u64, out: &mut [u8]) {
+    let start = (pos % data.len() as u64) as usize;
+    let first = out.len().min(data.len() - start);
+    let (before_end, after_wrap) = out.split_at_mut(first);
+    before_end.copy_from_slice(&data[start..start + first]);
+    after_wrap.copy_from_slice(&data[..after_wrap.len()]);
+}
+
+/// The ring buffer of a perf event opened in sampling mode, mapped in memory.
+pub(crate) struct MmapRing {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// The mapping is owned by the ring, and only modified through `&mut self`.
+unsafe impl Send for MmapRing {}
+
+impl MmapRing {
+    /// Maps the ring buffer of the perf event `fd`.
+    pub(crate) fn new(fd: &File) -> io::Result<MmapRing> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = page_size * (1 + DATA_PAGES);
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let ptr = NonNull::new(ptr.cast()).expect("mmap returned a null pointer");
+        Ok(MmapRing { ptr, len })
+    }
+
+    /// Returns the value of the last sample written since the previous call, or `None` if there is none,
+    /// and gives the space of the records back to the kernel.
+    pub(crate) fn read_last_sample(&mut self) -> anyhow::Result<Option<u64>> {
+        let mapping = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) };
+        let header = parse_ring_header(mapping)?;
+        // the records written before data_head are visible after this barrier (paired with the kernel's barrier)
+        fence(Ordering::Acquire);
+
+        let start = header.data_offset as usize;
+        let end = start + header.data_size as usize;
+        if end > self.len {
+            return Err(anyhow!("perf ring buffer data ({start}..{end}) outside of the mapping ({} bytes)", self.len));
+        }
+        let value = last_sample_value(&mapping[start..end], header.data_tail, header.data_head)?;
+
+        // the records must be read before the kernel can overwrite them
+        fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(self.ptr.as_ptr().add(DATA_TAIL_OFFSET).cast::<u64>(), header.data_head) };
+        Ok(value)
+    }
+}
+
+impl Drop for MmapRing {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        last_sample_value, parse_ring_header, RingHeader, DATA_HEAD_OFFSET, DATA_OFFSET_OFFSET, DATA_SIZE_OFFSET,
+        DATA_TAIL_OFFSET,
+    };
+    use perf_event_open_sys::bindings::{PERF_RECORD_LOST, PERF_RECORD_SAMPLE};
+
+    fn record(record_type: u32, body: &[u64]) -> Vec<u8> {
+        let size = 8 + 8 * body.len() as u16;
+        let mut bytes = Vec::new();
+        bytes.extend(record_type.to_ne_bytes());
+        bytes.extend(0u16.to_ne_bytes());
+        bytes.extend(size.to_ne_bytes());
+        bytes.extend(body.iter().flat_map(|v| v.to_ne_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn test_parse_ring_header() -> anyhow::Result<()> {
+        let mut page = vec![0u8; 4096];
+        for (offset, value) in [
+            (DATA_HEAD_OFFSET, 4200u64),
+            (DATA_TAIL_OFFSET, 4096),
+            (DATA_OFFSET_OFFSET, 4096),
+            (DATA_SIZE_OFFSET, 8 * 4096),
+        ] {
+            page[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+        }
+        let header = parse_ring_header(&page)?;
+        assert_eq!(
+            header,
+            RingHeader {
+                data_head: 4200,
+                data_tail: 4096,
+                data_offset: 4096,
+                data_size: 8 * 4096
+            }
+        );
+
+        assert!(parse_ring_header(&page[..DATA_SIZE_OFFSET]).is_err());
+        // tail after head
+        page[DATA_TAIL_OFFSET..DATA_TAIL_OFFSET + 8].copy_from_slice(&5000u64.to_ne_bytes());
+        assert!(parse_ring_header(&page).is_err());
+        // no data_size (old kernel)
+        page[DATA_SIZE_OFFSET..DATA_SIZE_OFFSET + 8].copy_from_slice(&0u64.to_ne_bytes());
+        assert!(parse_ring_header(&page).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_sample_value() -> anyhow::Result<()> {
+        let records: Vec<u8> = [
+            record(PERF_RECORD_SAMPLE, &[100]),
+            record(PERF_RECORD_LOST, &[1, 3]),
+            record(PERF_RECORD_SAMPLE, &[250]),
+        ]
+        .concat();
+        assert_eq!(records.len(), 56);
+
+        // write the records at the end of a 64-bytes ring, so that they wrap around
+        let mut data = vec![0u8; 64];
+        let tail = 64 * 10 + 40;
+        for (i, b) in records.iter().enumerate() {
+            data[(tail + i) % 64] = *b;
+        }
+        let head = (tail + records.len()) as u64;
+        let tail = tail as u64;
+        assert_eq!(last_sample_value(&data, tail, head)?, Some(250));
+        // only the first sample
+        assert_eq!(last_sample_value(&data, tail, tail + 16)?, Some(100));
+        // nothing new
+        assert_eq!(last_sample_value(&data, head, head)?, None);
+        // the head is in the middle of a record
+        assert!(last_sample_value(&data, tail, tail + 12).is_err());
+        Ok(())
+    }
+}
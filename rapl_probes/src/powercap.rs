@@ -4,21 +4,44 @@
 use std::{
     fmt::Display,
     fs::{self, File},
-    io::{Read, Seek},
+    os::unix::fs::FileExt,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 use anyhow::{anyhow, Context};
 
-use crate::{EnergyMeasurements, CpuId};
+use crate::error::open_error;
+use crate::{EnergyMeasurements, CpuId, RaplError, SysfsPaths};
 
 use super::{EnergyProbe, RaplDomainType};
 
-const POWERCAP_RAPL_PATH: &str = "/sys/devices/virtual/powercap/intel-rapl";
+pub(crate) const POWERCAP_RAPL_PATH: &str = "/sys/devices/virtual/powercap/intel-rapl";
 const POWER_ZONE_PREFIX: &str = "intel-rapl";
 const POWERCAP_ENERGY_UNIT: f64 = 0.000_001; // 1 microJoules
 
+/// Size of the buffer that receives the content of `energy_uj`: a u64 has at most 20 digits, plus the newline.
+const ENERGY_UJ_BUF_SIZE: usize = 24;
+
+/// Default number of consecutive polls in which a zone can fail to be read before [`PowercapProbe::poll`] fails,
+/// see [`PowercapProbe::with_max_read_failures`].
+const MAX_CONSECUTIVE_READ_FAILURES: u32 = 10;
+
+/// A high power for a RAPL domain, used to check the plausibility of `max_energy_range_uj`.
+const HIGH_POWER_WATTS: u64 = 500;
+/// At [`HIGH_POWER_WATTS`], a counter should not overflow in less than this number of seconds.
+const MIN_OVERFLOW_SECONDS: u64 = 10;
+/// The RAPL counters have 32 bits, and the largest energy unit is about 1 mJ (2^-10 J):
+/// a larger `max_energy_range_uj` cannot be right.
+const MAX_PLAUSIBLE_RANGE_UJ: u64 = (1 << 32) * 1_000_000 / 1024;
+
+/// How to get the permission to read `energy_uj`.
+const ENERGY_PERMISSION_HINT: &str = "Since the fix of CVE-2020-8694 (Platypus attack) in Linux 5.10 and the stable kernels, \
+energy_uj is only readable by root. Run as root, or give the read permission to the users \
+(`sudo chmod o+r /sys/class/powercap/intel-rapl*/energy_uj`, to do again after each boot).";
+
 /// Hierarchy of power zones
+#[derive(Debug, Clone)]
 pub struct PowerZoneHierarchy {
     /// All the zones in the same Vec.
     pub flat: Vec<PowerZone>,
@@ -50,7 +73,43 @@ pub struct PowerZone {
     pub socket_id: Option<u32>,
 }
 
+impl PowerZoneHierarchy {
+    /// Removes the zones that match one of the `excluded` names or paths (see [`PowerZone::matches`]),
+    /// with their sub-zones. Fails if a name or path matches no zone, which is probably a typo.
+    pub fn exclude(&mut self, excluded: &[String]) -> anyhow::Result<()> {
+        if let Some(unknown) = excluded.iter().find(|e| !self.flat.iter().any(|z| z.matches(e))) {
+            return Err(anyhow!("Cannot exclude the power zone '{unknown}': there is no such zone"));
+        }
+        // the sub-zones are in the directory of their parent
+        let excluded_paths: Vec<PathBuf> = self
+            .flat
+            .iter()
+            .filter(|z| excluded.iter().any(|e| z.matches(e)))
+            .map(|z| z.path.clone())
+            .collect();
+        let is_kept = |z: &PowerZone| !excluded_paths.iter().any(|p| z.path.starts_with(p));
+
+        fn retain_rec(zones: &mut Vec<PowerZone>, is_kept: &impl Fn(&PowerZone) -> bool) {
+            zones.retain(is_kept);
+            for z in zones {
+                retain_rec(&mut z.children, is_kept);
+            }
+        }
+        retain_rec(&mut self.top, &is_kept);
+        self.flat.retain(is_kept);
+        Ok(())
+    }
+}
+
 impl PowerZone {
+    /// Returns `true` if `name_or_path` is the name of this zone (e.g. `dram`), the name of its directory
+    /// (e.g. `intel-rapl:0:0`) or its full path in sysfs.
+    pub fn matches(&self, name_or_path: &str) -> bool {
+        self.name == name_or_path
+            || self.path.file_name().is_some_and(|f| f == name_or_path)
+            || self.path == Path::new(name_or_path)
+    }
+
     pub fn energy_path(&self) -> PathBuf {
         self.path.join("energy_uj")
     }
@@ -83,19 +142,48 @@ impl Display for PowerZone {
     }
 }
 
-/// Discovers all the RAPL power zones in the powercap sysfs.
-pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
-    fn parse_zone_name(name: &str) -> Option<RaplDomainType> {
-        match name {
-            "psys" => Some(RaplDomainType::Platform),
-            "core" => Some(RaplDomainType::PP0),
-            "uncore" => Some(RaplDomainType::PP1),
-            "dram" => Some(RaplDomainType::Dram),
-            _ if name.starts_with("package-") => Some(RaplDomainType::Package),
-            _ => None,
+/// Returns the domain of a power zone, given by its name.
+///
+/// Besides the names of the RAPL domains in the kernel (`package-<id>`, `core`, `uncore`, `dram` and `psys`),
+/// accepts `gpu` for the uncore, which is the graphics on client cpus, and the packages of the multi-die cpus
+/// (`package-<id>-die-<die>`).
+fn parse_zone_name(name: &str) -> Option<RaplDomainType> {
+    match name {
+        "psys" => Some(RaplDomainType::Platform),
+        "core" => Some(RaplDomainType::PP0),
+        "uncore" | "gpu" => Some(RaplDomainType::PP1),
+        "dram" => Some(RaplDomainType::Dram),
+        _ if parse_package_id(name).is_some() => Some(RaplDomainType::Package),
+        _ => None,
+    }
+}
+
+/// Returns the socket id of a package zone, named `package-<id>` or `package-<id>-die-<die>`.
+fn parse_package_id(name: &str) -> Option<u32> {
+    let id = name.strip_prefix("package-")?;
+    match id.split_once("-die-") {
+        Some((id, die)) => {
+            die.parse::<u32>().ok()?;
+            id.parse().ok()
         }
+        None => id.parse().ok(),
     }
+}
+
+/// Discovers all the RAPL power zones in the powercap sysfs.
+pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
+    all_power_zones_in(&SysfsPaths::from_env())
+}
+
+/// Like [`all_power_zones`], with the sysfs of `paths`.
+pub fn all_power_zones_in(paths: &SysfsPaths) -> anyhow::Result<PowerZoneHierarchy> {
+    power_zones_in(&paths.path(POWERCAP_RAPL_PATH))
+}
 
+/// Discovers the RAPL power zones in `root`, which has the same structure as the `intel-rapl` directory of sysfs.
+///
+/// The zones whose name is unknown are skipped with a warning, but their sub-zones are kept (in place of the unknown zone).
+pub(crate) fn power_zones_in(root: &Path) -> anyhow::Result<PowerZoneHierarchy> {
     /// Recursively explore a power zone
     fn explore_rec(dir: &Path, parent_socket: Option<u32>, flat: &mut Vec<PowerZone>) -> anyhow::Result<Vec<PowerZone>> {
         let mut zones = Vec::new();
@@ -107,19 +195,12 @@ pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
             if path.is_dir() && file_name.starts_with(POWER_ZONE_PREFIX) {
                 let name_path = path.join("name");
                 let name = fs::read_to_string(&name_path)?.trim().to_owned();
-                let socket_id = {
-                    if let Some(parent_id) = parent_socket {
-                        Some(parent_id)
-                    } else if let Some(id_str) = name.strip_prefix("package-") {
-                        let id: u32 = id_str
-                            .parse()
-                            .with_context(|| format!("Failed to extract package id from '{name}'"))?;
-                        Some(id)
-                    } else {
-                        None
-                    }
+                let socket_id = parent_socket.or_else(|| parse_package_id(&name));
+                let Some(domain) = parse_zone_name(&name) else {
+                    log::warn!("Unknown RAPL powercap zone '{name}' in {path:?}, skipping it");
+                    zones.extend(explore_rec(&path, socket_id, flat)?);
+                    continue;
                 };
-                let domain = parse_zone_name(&name).with_context(|| format!("Unknown RAPL powercap zone {name}"))?;
                 let children = explore_rec(&path, socket_id, flat)?; // recursively explore
                 let zone = PowerZone {
                     name,
@@ -136,7 +217,7 @@ pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
         Ok(zones)
     }
     let mut flat = Vec::new();
-    let top = explore_rec(Path::new(POWERCAP_RAPL_PATH), None, &mut flat)?;
+    let top = explore_rec(root, None, &mut flat)?;
     Ok(PowerZoneHierarchy { flat, top })
 }
 
@@ -147,28 +228,34 @@ pub struct PowercapProbe<const CHECK_UTF8: bool> {
 
     /// Ready-to-use powercap zones with additional metadata
     zones: Vec<OpenedZone>,
+
+    /// How many consecutive read failures of a zone are tolerated.
+    max_read_failures: u32,
 }
 
 struct OpenedZone {
     file: File,
+    /// The path of `energy_uj`, for the error messages.
+    path: PathBuf,
     socket: u32,
     domain: RaplDomainType,
     /// The maximum energy value for this zone, as reported by `max_energy_uj`
     max_energy_uj: u64,
+    /// The number of consecutive polls in which `energy_uj` could not be read.
+    failures: u32,
 }
 
 impl<const CHECK_UTF: bool> PowercapProbe<CHECK_UTF> {
-    pub fn new(socket_cpus: &[CpuId], zones: &[&PowerZone]) -> anyhow::Result<PowercapProbe<CHECK_UTF>> {
-        if zones.is_empty() {
-            return Err(anyhow!("At least one power zone is required for PowercapProbe"))?;
-        }
+    pub fn new(socket_cpus: &[CpuId], zones: &[&PowerZone]) -> Result<PowercapProbe<CHECK_UTF>, RaplError> {
         crate::check_socket_cpus(socket_cpus)?;
 
         let mut opened = Vec::new();
 
-        for zone in zones {
+        // skip the zones of the sockets that are not monitored, but keep psys
+        let monitored = |z: &&&PowerZone| z.socket_id.is_none_or(|s| socket_cpus.iter().any(|c| c.socket == s));
+        for zone in zones.iter().filter(monitored) {
             let file = File::open(zone.energy_path())
-                .with_context(|| format!("open {}", zone.energy_path().to_string_lossy()))?;
+                .map_err(|e| open_error(e, &zone.energy_path().to_string_lossy(), ENERGY_PERMISSION_HINT))?;
 
             let str_max_energy_uj = fs::read_to_string(zone.max_energy_path())
                 .with_context(|| format!("read {}", zone.max_energy_path().to_string_lossy()))?;
@@ -178,40 +265,81 @@ impl<const CHECK_UTF: bool> PowercapProbe<CHECK_UTF> {
                 .parse()
                 .with_context(|| format!("parse max_energy_uj: '{str_max_energy_uj}'"))?;
 
+            // a wrong maximum breaks the overflow correction, warn now instead of producing bad data silently
+            if let Err(problem) = check_max_energy_range(max_energy_uj) {
+                log::warn!(
+                    "Suspicious {}: {problem}. The overflows of this zone will not be corrected properly (this is a known bug of powercap on some AMD cpus).",
+                    zone.max_energy_path().to_string_lossy()
+                );
+            }
+
             opened.push(OpenedZone {
                 file,
+                path: zone.energy_path(),
                 max_energy_uj,
+                failures: 0,
                 socket: zone.socket_id.unwrap_or(0), // put psys in socket 0
                 domain: zone.domain,
             })
         }
+        crate::check_not_empty(&opened, "power zone", "PowercapProbe")?;
 
         Ok(PowercapProbe {
-            measurements: EnergyMeasurements::new(socket_cpus.len()),
+            measurements: EnergyMeasurements::for_cpus(socket_cpus),
             zones: opened,
+            max_read_failures: MAX_CONSECUTIVE_READ_FAILURES,
         })
     }
+
+    /// Sets the number of consecutive polls in which a zone can fail to be read (its default is 10).
+    ///
+    /// The file `energy_uj` can become unreadable during a measurement, for instance when its permissions are changed
+    /// or when its zone disappears (hotplug). Such a failure makes the counter of the zone stale
+    /// (see [`EnergyMeasurements::mark_stale`]) and the other zones are still measured.
+    /// [`EnergyProbe::poll`] fails when the zone cannot be read in more than `max` consecutive polls.
+    /// With `max = 0`, the first failure is an error.
+    pub fn with_max_read_failures(mut self, max: u32) -> PowercapProbe<CHECK_UTF> {
+        self.max_read_failures = max;
+        self
+    }
 }
 
 impl<const CHECK_UTF: bool> EnergyProbe for PowercapProbe<CHECK_UTF> {
-    fn poll(&mut self) -> anyhow::Result<()> {
+    fn poll(&mut self) -> Result<(), RaplError> {
+        self.measurements.set_timestamp(SystemTime::now());
+
         // reuse the same buffer for all the zones
         // the size of the content of the file `energy_uj` should never exceed those of `max_energy_uj`,
         // which is 16 bytes on all our test machines
-        let mut buf = Vec::with_capacity(16);
+        let mut buf = [0u8; ENERGY_UJ_BUF_SIZE];
 
+        // NOTE: there is one syscall per zone, and it cannot be batched with readv/preadv:
+        // vectored reads scatter the content of ONE file descriptor into multiple buffers,
+        // they cannot gather the content of multiple files. Only io_uring could submit the reads
+        // of all the zones at once.
         for zone in &mut self.zones {
-            // read the file from the beginning
-            zone.file.rewind()?;
-            zone.file.read_to_end(&mut buf)?;
-
-            // parse the content of the file
-            let content = if CHECK_UTF {
-                std::str::from_utf8(&buf)?
-            } else {
-                unsafe { std::str::from_utf8_unchecked(&buf) }
+            let counter_value = match read_energy_uj::<CHECK_UTF>(zone, &mut buf) {
+                Ok(value) => value,
+                Err(e) => {
+                    zone.failures += 1;
+                    let path = zone.path.to_string_lossy();
+                    if zone.failures > self.max_read_failures {
+                        let msg = format!("failed to read {path} in {} consecutive polls", zone.failures);
+                        return Err(e.context(msg).into());
+                    }
+                    if zone.failures == 1 {
+                        log::warn!("Failed to read {path}, its energy is unknown until it can be read again: {e:#}");
+                    } else {
+                        log::debug!("Failed to read {path} ({} consecutive failures): {e:#}", zone.failures);
+                    }
+                    self.measurements.mark_stale(zone.socket, zone.domain);
+                    continue;
+                }
             };
-            let counter_value: u64 = content.trim_end().parse().with_context(|| format!("failed to parse {:?}: '{content}'", zone.file))?;
+            if zone.failures > 0 {
+                log::info!("{} can be read again, after {} failures", zone.path.to_string_lossy(), zone.failures);
+                zone.failures = 0;
+            }
 
             // store the value, handle the overflow if there is one
             log::debug!("pushing {}/{} value {counter_value}", zone.socket, zone.domain);
@@ -223,9 +351,6 @@ impl<const CHECK_UTF: bool> EnergyProbe for PowercapProbe<CHECK_UTF> {
                 zone.max_energy_uj, // the maximum energy depends on the zone
                 POWERCAP_ENERGY_UNIT,
             );
-
-            // clear the buffer, so that we can fill it again
-            buf.clear();
         }
         Ok(())
     }
@@ -237,11 +362,74 @@ impl<const CHECK_UTF: bool> EnergyProbe for PowercapProbe<CHECK_UTF> {
     fn reset(&mut self) {
         self.measurements.clear()
     }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        let measured = self.zones.iter().any(|z| z.socket == socket && z.domain == domain);
+        measured.then_some(POWERCAP_ENERGY_UNIT)
+    }
+
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        out.clear();
+        let mut buf = [0u8; ENERGY_UJ_BUF_SIZE];
+        for zone in &self.zones {
+            out.push((zone.socket, zone.domain, read_energy_uj::<CHECK_UTF>(zone, &mut buf)?));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the maximum value of an energy counter is plausible, and describes the problem if it is not.
+///
+/// The counter must not overflow in a few seconds at [`HIGH_POWER_WATTS`], and must not exceed the range of a 32-bits
+/// RAPL counter with the largest energy unit.
+fn check_max_energy_range(max_energy_uj: u64) -> Result<(), String> {
+    let min_range_uj = HIGH_POWER_WATTS * MIN_OVERFLOW_SECONDS * 1_000_000;
+    if max_energy_uj < min_range_uj {
+        Err(format!(
+            "the maximum energy, {max_energy_uj} uJ, is implausibly small (less than {MIN_OVERFLOW_SECONDS} seconds at {HIGH_POWER_WATTS} W)"
+        ))
+    } else if max_energy_uj > MAX_PLAUSIBLE_RANGE_UJ {
+        Err(format!(
+            "the maximum energy, {max_energy_uj} uJ, is implausibly large (more than {MAX_PLAUSIBLE_RANGE_UJ} uJ)"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads the value of `energy_uj`, in microJoules.
+fn read_energy_uj<const CHECK_UTF: bool>(zone: &OpenedZone, buf: &mut [u8; ENERGY_UJ_BUF_SIZE]) -> anyhow::Result<u64> {
+    // read the file from the beginning, without seeking (pread)
+    let n = zone.file.read_at(buf, 0)?;
+    if n == buf.len() {
+        return Err(anyhow!("the content of {:?} is too long, it may have been truncated", zone.file));
+    }
+    let bytes = &buf[..n];
+
+    // parse the content of the file
+    let content = if CHECK_UTF {
+        std::str::from_utf8(bytes)?
+    } else {
+        unsafe { std::str::from_utf8_unchecked(bytes) }
+    };
+    content
+        .trim_end()
+        .parse()
+        .with_context(|| format!("failed to parse {:?}: '{content}'", zone.file))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::all_power_zones;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        all_power_zones, check_max_energy_range, parse_package_id, parse_zone_name, power_zones_in, PowerZone,
+        PowerZoneHierarchy, PowercapProbe, ENERGY_PERMISSION_HINT,
+    };
+    use crate::error::open_error;
+    use crate::{CpuId, EnergyProbe, RaplDomainType, RaplError};
 
     #[test]
     fn test_powercap() {
@@ -255,4 +443,222 @@ mod tests {
             println!("{z}")
         }
     }
+
+    #[test]
+    fn test_no_zone() {
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let err = PowercapProbe::<true>::new(&cpus, &[]).err().expect("PowercapProbe without zones should fail");
+        assert_eq!(err.to_string(), "At least one power zone is required for PowercapProbe");
+
+        // the only zone is on a socket that is not monitored
+        let mut other_socket = zone("package-1", RaplDomainType::Package, Path::new("/nonexistent/intel-rapl:1"), vec![]);
+        other_socket.socket_id = Some(1);
+        let err = PowercapProbe::<true>::new(&cpus, &[&other_socket]).err().expect("PowercapProbe without zones should fail");
+        assert_eq!(err.to_string(), "At least one power zone is required for PowercapProbe");
+    }
+
+    fn zone(name: &str, domain: RaplDomainType, path: &Path, children: Vec<PowerZone>) -> PowerZone {
+        PowerZone {
+            name: name.to_owned(),
+            domain,
+            path: path.to_path_buf(),
+            children,
+            socket_id: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_exclude_zones() -> anyhow::Result<()> {
+        let root = PathBuf::from("/sys/devices/virtual/powercap/intel-rapl");
+        let pkg0 = root.join("intel-rapl:0");
+        let core = zone("core", RaplDomainType::PP0, &pkg0.join("intel-rapl:0:0"), vec![]);
+        let dram = zone("dram", RaplDomainType::Dram, &pkg0.join("intel-rapl:0:1"), vec![]);
+        let package = zone("package-0", RaplDomainType::Package, &pkg0, vec![core.clone(), dram.clone()]);
+        let hierarchy = PowerZoneHierarchy {
+            flat: vec![core.clone(), dram.clone(), package.clone()],
+            top: vec![package],
+        };
+        let names = |h: &PowerZoneHierarchy| h.flat.iter().map(|z| z.name.clone()).collect::<Vec<_>>();
+
+        // by name
+        assert!(dram.matches("dram"));
+        let mut h = hierarchy.clone();
+        h.exclude(&[String::from("dram")])?;
+        assert_eq!(names(&h), vec!["core", "package-0"]);
+        assert_eq!(h.top[0].children.len(), 1);
+
+        // by path, with or without the directory, and with the sub-zones
+        assert!(core.matches("/sys/devices/virtual/powercap/intel-rapl/intel-rapl:0/intel-rapl:0:0/"));
+        assert!(core.matches("intel-rapl:0:0"));
+        assert!(!core.matches("intel-rapl:0"));
+        let mut h = hierarchy.clone();
+        h.exclude(&[String::from("/sys/devices/virtual/powercap/intel-rapl/intel-rapl:0")])?;
+        assert!(h.flat.is_empty() && h.top.is_empty());
+
+        // unknown zone
+        let mut h = hierarchy;
+        let err = h.exclude(&[String::from("psys")]).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot exclude the power zone 'psys': there is no such zone");
+        Ok(())
+    }
+
+    #[test]
+    fn test_zone_names() -> anyhow::Result<()> {
+        assert_eq!(parse_zone_name("gpu"), Some(RaplDomainType::PP1));
+        assert_eq!(parse_zone_name("package-1-die-0"), Some(RaplDomainType::Package));
+        assert_eq!(parse_package_id("package-1-die-0"), Some(1));
+        assert_eq!(parse_package_id("package-12"), Some(12));
+        assert_eq!(parse_package_id("package-x"), None);
+        assert_eq!(parse_zone_name("mmio"), None);
+
+        let root = std::env::temp_dir().join(format!("rapl_probes-powercap-names-{}", std::process::id()));
+        let zones = [
+            ("intel-rapl:0", "package-0"),
+            ("intel-rapl:0/intel-rapl:0:0", "core"),
+            ("intel-rapl:0/intel-rapl:0:1", "mmio"),
+            ("intel-rapl:0/intel-rapl:0:1/intel-rapl:0:1:0", "dram"),
+            ("intel-rapl:0/intel-rapl:0:2", "gpu"),
+            ("intel-rapl:1", "package-1-die-0"),
+            ("intel-rapl:2", "something-new"),
+            ("intel-rapl:3", "psys"),
+        ];
+        for (dir, name) in zones {
+            fs::create_dir_all(root.join(dir))?;
+            fs::write(root.join(dir).join("name"), format!("{name}\n"))?;
+        }
+        let hierarchy = power_zones_in(&root);
+        fs::remove_dir_all(&root)?;
+        let hierarchy = hierarchy?;
+
+        // the unknown zones are skipped, their sub-zones are kept with the socket of their parent
+        let mut flat: Vec<_> = hierarchy.flat.iter().map(|z| (z.name.as_str(), z.domain, z.socket_id)).collect();
+        flat.sort_by_key(|(name, _, _)| *name);
+        assert_eq!(
+            flat,
+            vec![
+                ("core", RaplDomainType::PP0, Some(0)),
+                ("dram", RaplDomainType::Dram, Some(0)),
+                ("gpu", RaplDomainType::PP1, Some(0)),
+                ("package-0", RaplDomainType::Package, Some(0)),
+                ("package-1-die-0", RaplDomainType::Package, Some(1)),
+                ("psys", RaplDomainType::Platform, None),
+            ]
+        );
+        let top: Vec<_> = hierarchy.top.iter().map(|z| z.name.as_str()).collect();
+        assert_eq!(top, vec!["package-0", "package-1-die-0", "psys"]);
+        let children: Vec<_> = hierarchy.top[0].children.iter().map(|z| z.name.as_str()).collect();
+        assert_eq!(children, vec!["core", "dram", "gpu"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_absolute() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("rapl_probes-powercap-absolute-{}", std::process::id()));
+        let pkg = root.join("intel-rapl:0");
+        fs::create_dir_all(&pkg)?;
+        fs::write(pkg.join("name"), "package-0\n")?;
+        fs::write(pkg.join("max_energy_range_uj"), "1000\n")?;
+        let zones = power_zones_in(&root)?;
+        let zones: Vec<&PowerZone> = zones.flat.iter().collect();
+
+        fs::write(pkg.join("energy_uj"), "100\n")?;
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+        let mut probe = PowercapProbe::<true>::new(&cpus, &zones)?;
+        let mut out = Vec::new();
+        let mut values = Vec::new();
+        for energy_uj in [100, 100, 750, 20] {
+            fs::write(pkg.join("energy_uj"), format!("{energy_uj}\n"))?;
+            probe.read_absolute(&mut out)?;
+            assert_eq!(out.len(), 1);
+            let (socket, domain, value) = out[0];
+            assert_eq!((socket, domain), (0, RaplDomainType::Package));
+            values.push(value);
+        }
+        // the measurements are not modified
+        assert!(probe.measurements().per_socket[0][RaplDomainType::Package].joules.is_none());
+        fs::remove_dir_all(&root)?;
+
+        assert_eq!(values, vec![100, 100, 750, 20]);
+        // successive reads are non-decreasing, modulo the wrap around max_energy_range_uj
+        let increments: Vec<u64> = values.windows(2).map(|w| (w[1] + 1001 - w[0]) % 1001).collect();
+        assert_eq!(increments, vec![0, 650, 271]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_intermittent_read_failure() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("rapl_probes-powercap-failure-{}", std::process::id()));
+        let pkg = root.join("intel-rapl:0");
+        let dram = pkg.join("intel-rapl:0:0");
+        fs::create_dir_all(&dram)?;
+        fs::write(pkg.join("name"), "package-0\n")?;
+        fs::write(dram.join("name"), "dram\n")?;
+        for dir in [&pkg, &dram] {
+            fs::write(dir.join("max_energy_range_uj"), "262143328850\n")?;
+            fs::write(dir.join("energy_uj"), "1000\n")?;
+        }
+        let result = (|| {
+            let zones = power_zones_in(&root)?;
+            let zones: Vec<&PowerZone> = zones.flat.iter().collect();
+            let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }];
+            let mut probe = PowercapProbe::<true>::new(&cpus, &zones)?.with_max_read_failures(2);
+            probe.poll()?;
+
+            // the dram cannot be read for two polls, the package is still measured
+            fs::write(dram.join("energy_uj"), "")?;
+            for energy_uj in [2000, 3000] {
+                fs::write(pkg.join("energy_uj"), format!("{energy_uj}\n"))?;
+                probe.poll()?;
+                let m = &probe.measurements().per_socket[0];
+                assert_eq!(m[RaplDomainType::Package].joules, Some(0.001));
+                assert!(!m[RaplDomainType::Package].stale);
+                assert!(m[RaplDomainType::Dram].stale);
+                assert_eq!(m[RaplDomainType::Dram].joules, None);
+            }
+
+            // the dram is back, with the energy consumed since the last read
+            fs::write(dram.join("energy_uj"), "4000\n")?;
+            probe.poll()?;
+            let dram_counter = &probe.measurements().per_socket[0][RaplDomainType::Dram];
+            assert!(!dram_counter.stale);
+            assert_eq!(dram_counter.joules, Some(0.003));
+
+            // more than 2 consecutive failures is an error
+            fs::write(dram.join("energy_uj"), "garbage\n")?;
+            probe.poll()?;
+            probe.poll()?;
+            let err = probe.poll().unwrap_err();
+            assert!(err.to_string().contains("in 3 consecutive polls"), "{err}");
+            anyhow::Ok(())
+        })();
+        fs::remove_dir_all(&root)?;
+        result
+    }
+
+    #[test]
+    fn test_max_energy_range_plausibility() {
+        // usual values: 2^32 counts of 61 uJ (package) and of 15.3 uJ (dram)
+        assert_eq!(check_max_energy_range(262143328850), Ok(()));
+        assert_eq!(check_max_energy_range(65712999613), Ok(()));
+        // less than 10 seconds at 500 W
+        assert!(check_max_energy_range(0).is_err());
+        assert!(check_max_energy_range(4_294_967_295).is_err());
+        assert_eq!(check_max_energy_range(5_000_000_000), Ok(()));
+        // more than 2^32 counts of 1 mJ
+        assert!(check_max_energy_range(u64::MAX).is_err());
+        assert!(check_max_energy_range(18_446_744_073_709).is_err());
+    }
+
+    #[test]
+    fn test_permission_denied() {
+        // root can read the file even without the permission: simulate the error of a normal user
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let err = RaplError::from(open_error(denied, "/sys/class/powercap/intel-rapl:0/energy_uj", ENERGY_PERMISSION_HINT));
+        let RaplError::PermissionDenied(msg) = err else {
+            panic!("unexpected error {err:?}");
+        };
+        assert!(msg.starts_with("cannot open /sys/class/powercap/intel-rapl:0/energy_uj. "));
+        assert!(msg.contains("CVE-2020-8694"));
+        assert!(msg.contains("sudo chmod o+r"));
+    }
 }
@@ -0,0 +1,196 @@
+//! An API similar to the one of the [`powercap`](https://crates.io/crates/powercap) crate,
+//! backed by the discovery of [`crate::powercap`].
+//!
+//! It helps to migrate code written for that crate: the zones are read in the same way (energy in microJoules),
+//! and [`IntelRapl::probe`] creates a [`PowercapProbe`] for the same zones, which handles the overflows.
+//!
+//! ```no_run
+//! use rapl_probes::powercap_compat::IntelRapl;
+//!
+//! let rapl = IntelRapl::try_default()?;
+//! for zone in &rapl.zones {
+//!     println!("{} = {} uJ", zone.name, zone.energy()?);
+//! }
+//! println!("total: {} uJ", rapl.total_energy()?);
+//! # anyhow::Ok(())
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::powercap::{power_zones_in, PowerZone, PowercapProbe, POWERCAP_RAPL_PATH};
+use crate::{CpuId, EnergyMeasurements, RaplDomainType, RaplError, SysfsPaths};
+
+/// The `intel-rapl` control type of powercap, and its top-level zones.
+#[derive(Debug, Clone)]
+pub struct IntelRapl {
+    /// The top-level zones (packages and psys). The sub-zones are in [`Zone::children`].
+    pub zones: Vec<Zone>,
+}
+
+/// A power zone, for instance `package-0` or `dram`.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    /// The name of the zone, as returned by powercap.
+    pub name: String,
+    /// The RAPL domain type of the zone.
+    pub domain: RaplDomainType,
+    /// The socket that contains this zone, `None` for psys.
+    pub socket_id: Option<u32>,
+    /// The path of the zone in sysfs.
+    pub path: PathBuf,
+    /// The sub-zones (can be empty).
+    pub children: Vec<Zone>,
+    /// The zone, as discovered by [`crate::powercap`].
+    power_zone: PowerZone,
+}
+
+impl IntelRapl {
+    /// Discovers the zones of the system.
+    pub fn try_default() -> anyhow::Result<IntelRapl> {
+        IntelRapl::try_from_path(&SysfsPaths::from_env().path(POWERCAP_RAPL_PATH))
+    }
+
+    /// Discovers the zones in `root`, which has the same structure as `/sys/devices/virtual/powercap/intel-rapl`.
+    pub fn try_from_path(root: &Path) -> anyhow::Result<IntelRapl> {
+        let hierarchy = power_zones_in(root)?;
+        let zones = hierarchy.top.iter().map(Zone::from).collect();
+        Ok(IntelRapl { zones })
+    }
+
+    /// The sum of the energy counters of the packages, in microJoules.
+    ///
+    /// Like the counters themselves, the result wraps around, and psys is not included.
+    pub fn total_energy(&self) -> anyhow::Result<u64> {
+        self.zones
+            .iter()
+            .filter(|z| z.domain == RaplDomainType::Package)
+            .map(|z| z.energy())
+            .sum()
+    }
+
+    /// Iterates on all the zones, including the sub-zones.
+    pub fn all_zones(&self) -> impl Iterator<Item = &Zone> {
+        fn rec<'a>(zone: &'a Zone, out: &mut Vec<&'a Zone>) {
+            out.push(zone);
+            for child in &zone.children {
+                rec(child, out);
+            }
+        }
+        let mut all = Vec::new();
+        for z in &self.zones {
+            rec(z, &mut all);
+        }
+        all.into_iter()
+    }
+
+    /// Creates a probe that measures all the zones, on the given `socket_cpus` (one cpu per socket).
+    pub fn probe(&self, socket_cpus: &[CpuId]) -> Result<PowercapProbe<true>, RaplError> {
+        let zones: Vec<&PowerZone> = self.all_zones().map(|z| &z.power_zone).collect();
+        PowercapProbe::new(socket_cpus, &zones)
+    }
+}
+
+impl Zone {
+    /// The current value of the energy counter, in microJoules.
+    pub fn energy(&self) -> anyhow::Result<u64> {
+        read_u64(&self.power_zone.energy_path())
+    }
+
+    /// The maximum value of the energy counter, in microJoules, after which it wraps around.
+    pub fn max_energy_range(&self) -> anyhow::Result<u64> {
+        read_u64(&self.power_zone.max_energy_path())
+    }
+
+    /// The energy measured for this zone by a probe since its first poll, in microJoules,
+    /// or `None` if the probe does not measure this zone.
+    pub fn measured_energy(&self, measurements: &EnergyMeasurements) -> Option<u64> {
+        // psys is in socket 0, like in PowercapProbe
+        let socket = self.socket_id.unwrap_or(0) as usize;
+        let counter = &measurements.per_socket.get(socket)?[self.domain];
+        // no value before the second poll
+        counter.joules.map(|_| (counter.cumulative_joules * 1e6).round() as u64)
+    }
+
+    /// The zone, as discovered by [`crate::powercap`].
+    pub fn power_zone(&self) -> &PowerZone {
+        &self.power_zone
+    }
+}
+
+impl From<&PowerZone> for Zone {
+    fn from(z: &PowerZone) -> Self {
+        Zone {
+            name: z.name.clone(),
+            domain: z.domain,
+            socket_id: z.socket_id,
+            path: z.path.clone(),
+            children: z.children.iter().map(Zone::from).collect(),
+            power_zone: z.clone(),
+        }
+    }
+}
+
+fn read_u64(path: &Path) -> anyhow::Result<u64> {
+    let content = fs::read_to_string(path).with_context(|| format!("read {}", path.to_string_lossy()))?;
+    content
+        .trim_end()
+        .parse()
+        .with_context(|| format!("parse {}: '{content}'", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::IntelRapl;
+    use crate::{CpuId, EnergyProbe, RaplDomainType};
+
+    fn write_zone(dir: &Path, name: &str, energy_uj: u64) -> anyhow::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("name"), format!("{name}\n"))?;
+        fs::write(dir.join("energy_uj"), format!("{energy_uj}\n"))?;
+        fs::write(dir.join("max_energy_range_uj"), "262143328850\n")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_fixture() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("rapl_probes-powercap-compat-{}", std::process::id()));
+        let pkg0 = root.join("intel-rapl:0");
+        let pkg1 = root.join("intel-rapl:1");
+        write_zone(&pkg0, "package-0", 1_000)?;
+        write_zone(&pkg0.join("intel-rapl:0:0"), "dram", 400)?;
+        write_zone(&pkg1, "package-1", 2_000)?;
+        write_zone(&root.join("intel-rapl:2"), "psys", 5_000)?;
+
+        let rapl = IntelRapl::try_from_path(&root)?;
+        let names: Vec<&str> = rapl.zones.iter().map(|z| z.name.as_str()).collect();
+        assert_eq!(names, vec!["package-0", "package-1", "psys"]);
+        assert_eq!(rapl.zones[0].children.len(), 1);
+        let dram = &rapl.zones[0].children[0];
+        assert_eq!((dram.domain, dram.socket_id), (RaplDomainType::Dram, Some(0)));
+        assert_eq!(dram.energy()?, 400);
+        assert_eq!(dram.max_energy_range()?, 262143328850);
+        assert_eq!(rapl.total_energy()?, 3_000);
+        assert_eq!(rapl.all_zones().count(), 4);
+
+        // the probe reads the same zones
+        let cpus = [CpuId { cpu: 0, socket: 0, numa_node: None }, CpuId { cpu: 1, socket: 1, numa_node: None }];
+        let mut probe = rapl.probe(&cpus)?;
+        probe.poll()?;
+        fs::write(pkg0.join("energy_uj"), "1500\n")?;
+        fs::write(pkg0.join("intel-rapl:0:0/energy_uj"), "450\n")?;
+        probe.poll()?;
+        let m = probe.measurements();
+        fs::remove_dir_all(&root)?;
+
+        assert_eq!(rapl.zones[0].measured_energy(m), Some(500));
+        assert_eq!(dram.measured_energy(m), Some(50));
+        assert_eq!(rapl.zones[1].measured_energy(m), Some(0));
+        Ok(())
+    }
+}
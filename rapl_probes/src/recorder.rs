@@ -0,0 +1,328 @@
+//! Records the measurements of a probe in a background thread,
+//! for programs that embed the probes instead of using the CLI.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context};
+use enum_map::EnumMap;
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType};
+
+/// Polls an [`EnergyProbe`] at a fixed period, between [`Recorder::start`] and [`Recorder::stop`].
+pub struct Recorder {
+    /// The probe, when it is not used by the recording thread.
+    probe: Option<Box<dyn EnergyProbe>>,
+    period: Duration,
+    running: Option<RunningRecord>,
+    /// The cumulative energy at the last poll, updated by the recording thread.
+    latest: Arc<Mutex<Checkpoint>>,
+}
+
+/// The recording thread gives the probe back when it stops.
+type RecordingResult = (Box<dyn EnergyProbe>, anyhow::Result<Vec<Snapshot>>);
+
+struct RunningRecord {
+    stop: Arc<AtomicBool>,
+    start: SystemTime,
+    handle: JoinHandle<RecordingResult>,
+}
+
+/// The cumulative energy of every (socket, domain) at some point of a recording.
+/// See [`Recorder::checkpoint`].
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// The time of the poll that gave the cumulative energy.
+    pub timestamp: SystemTime,
+    cumulative_joules: Vec<EnumMap<RaplDomainType, f64>>,
+}
+
+/// The energy consumed between two checkpoints.
+#[derive(Debug, Clone)]
+pub struct EnergyDelta {
+    /// The time elapsed between the two checkpoints.
+    pub duration: Duration,
+    per_socket: Vec<EnumMap<RaplDomainType, f64>>,
+}
+
+/// The measurements of one poll.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub timestamp: SystemTime,
+    pub measurements: EnergyMeasurements,
+}
+
+/// The result of a recording.
+#[derive(Debug, Clone)]
+pub struct RecordedSession {
+    /// When the recording started.
+    pub start: SystemTime,
+    /// When the recording stopped.
+    pub end: SystemTime,
+    /// The measurements of every poll, in order.
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl Recorder {
+    pub fn new(probe: Box<dyn EnergyProbe>, period: Duration) -> Recorder {
+        Recorder {
+            probe: Some(probe),
+            period,
+            running: None,
+            latest: Arc::new(Mutex::new(Checkpoint::from_measurements(&EnergyMeasurements::new(0)))),
+        }
+    }
+
+    /// Captures the cumulative energy of the last poll.
+    ///
+    /// Use [`Recorder::energy_since`] later to get the energy consumed since this checkpoint,
+    /// independently of the polling period. The precision is limited by the polling period,
+    /// because the checkpoint contains the energy measured by the last poll, not the current energy.
+    /// Checkpoints are only valid for the current recording: [`Recorder::start`] resets the energy.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// The energy consumed since `checkpoint`.
+    pub fn energy_since(&self, checkpoint: &Checkpoint) -> EnergyDelta {
+        self.checkpoint().energy_since(checkpoint)
+    }
+
+    /// Starts polling the probe in a background thread.
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        let mut probe = self.probe.take().context("the recorder is already started")?;
+        probe.reset();
+
+        let period = self.period;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let latest = self.latest.clone();
+        *latest.lock().unwrap() = Checkpoint::from_measurements(probe.measurements());
+        let handle = thread::Builder::new()
+            .name(String::from("rapl-recorder"))
+            .spawn(move || {
+                let res = record(probe.as_mut(), period, &stop_flag, &latest);
+                (probe, res)
+            })?;
+
+        self.running = Some(RunningRecord {
+            stop,
+            start: SystemTime::now(),
+            handle,
+        });
+        Ok(())
+    }
+
+    /// Stops the recording and returns the recorded measurements.
+    ///
+    /// The probe is polled one last time, so that the energy consumed until now is included.
+    pub fn stop(&mut self) -> anyhow::Result<RecordedSession> {
+        let running = self.running.take().context("the recorder is not started")?;
+        running.stop.store(true, Ordering::Relaxed);
+        running.handle.thread().unpark();
+        let (probe, res) = running
+            .handle
+            .join()
+            .map_err(|_| anyhow!("the recording thread panicked"))?;
+        self.probe = Some(probe);
+        Ok(RecordedSession {
+            start: running.start,
+            end: SystemTime::now(),
+            snapshots: res?,
+        })
+    }
+}
+
+fn record(
+    probe: &mut dyn EnergyProbe,
+    period: Duration,
+    stop: &AtomicBool,
+    latest: &Mutex<Checkpoint>,
+) -> anyhow::Result<Vec<Snapshot>> {
+    let mut snapshots = Vec::new();
+    let mut next_deadline = Instant::now();
+    loop {
+        probe.poll().context("refreshing measurements")?;
+        let measurements = probe.measurements().clone();
+        *latest.lock().unwrap() = Checkpoint::from_measurements(&measurements);
+        snapshots.push(Snapshot {
+            timestamp: measurements.timestamp().unwrap_or_else(SystemTime::now),
+            measurements,
+        });
+        // at least two polls are required to measure something
+        if stop.load(Ordering::Relaxed) && snapshots.len() >= 2 {
+            break;
+        }
+
+        // use absolute deadlines to avoid drifting, and wake up early if stop() is called
+        next_deadline += period;
+        while !stop.load(Ordering::Relaxed) {
+            match next_deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => thread::park_timeout(remaining),
+                None => break,
+            }
+        }
+    }
+    Ok(snapshots)
+}
+
+impl Checkpoint {
+    /// Captures the cumulative energy of some measurements.
+    pub fn from_measurements(measurements: &EnergyMeasurements) -> Checkpoint {
+        Checkpoint {
+            timestamp: measurements.timestamp().unwrap_or_else(SystemTime::now),
+            cumulative_joules: measurements
+                .per_socket
+                .iter()
+                .map(|domains| EnumMap::from_fn(|d| domains[d].cumulative_joules))
+                .collect(),
+        }
+    }
+
+    /// The energy consumed between `earlier` and this checkpoint.
+    ///
+    /// The cumulative energy already includes the overflow corrections,
+    /// so the result is correct even if a counter has wrapped between the checkpoints.
+    pub fn energy_since(&self, earlier: &Checkpoint) -> EnergyDelta {
+        let per_socket = self
+            .cumulative_joules
+            .iter()
+            .enumerate()
+            .map(|(socket, domains)| {
+                let before = earlier.cumulative_joules.get(socket);
+                EnumMap::from_fn(|d| domains[d] - before.map_or(0.0, |b| b[d]))
+            })
+            .collect();
+        EnergyDelta {
+            duration: self.timestamp.duration_since(earlier.timestamp).unwrap_or(Duration::ZERO),
+            per_socket,
+        }
+    }
+}
+
+impl EnergyDelta {
+    /// The energy consumed by a domain, in Joules (zero if the domain is not measured).
+    pub fn joules(&self, socket: u32, domain: RaplDomainType) -> f64 {
+        self.per_socket.get(socket as usize).map_or(0.0, |d| d[domain])
+    }
+}
+
+impl RecordedSession {
+    /// The duration of the recording.
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or(Duration::ZERO)
+    }
+
+    /// The total energy consumed by a domain during the recording,
+    /// or `None` if no measurement is available.
+    pub fn total_joules(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.snapshots
+            .iter()
+            .filter_map(|s| s.measurements.per_socket.get(socket as usize)?[domain].joules)
+            .reduce(|a, b| a + b)
+    }
+
+    /// The mean power of a domain during the recording, in Watts.
+    pub fn mean_watts(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        let secs = self.duration().as_secs_f64();
+        if secs == 0.0 {
+            return None;
+        }
+        Some(self.total_joules(socket, domain)? / secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Checkpoint, Recorder};
+    use crate::mock::MockProbe;
+    use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError};
+
+    #[test]
+    fn test_checkpoint_with_wrap() -> anyhow::Result<()> {
+        let max = u32::MAX as u64;
+        let mut probe = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![0, 100, max - 50, 49, 300], max, 1.0);
+        probe.poll()?;
+        probe.poll()?;
+        let cp1 = Checkpoint::from_measurements(probe.measurements());
+        // the counter wraps between the checkpoints
+        probe.poll()?;
+        probe.poll()?;
+        let cp2 = Checkpoint::from_measurements(probe.measurements());
+        probe.poll()?;
+        let cp3 = Checkpoint::from_measurements(probe.measurements());
+
+        let delta = cp2.energy_since(&cp1);
+        assert_eq!(delta.joules(0, RaplDomainType::Package), (max - 150) as f64 + 100.0);
+        assert_eq!(cp3.energy_since(&cp2).joules(0, RaplDomainType::Package), 251.0);
+        assert_eq!(delta.joules(0, RaplDomainType::Dram), 0.0);
+        assert_eq!(delta.joules(1, RaplDomainType::Package), 0.0);
+        Ok(())
+    }
+
+    /// A probe whose package counter increases by 1 unit at each poll.
+    struct FakeProbe {
+        counter: u64,
+        measurements: EnergyMeasurements,
+    }
+
+    impl EnergyProbe for FakeProbe {
+        fn poll(&mut self) -> Result<(), RaplError> {
+            self.counter += 1;
+            self.measurements
+                .push(0, RaplDomainType::Package, self.counter, u32::MAX as u64, 0.5);
+            Ok(())
+        }
+
+        fn measurements(&self) -> &EnergyMeasurements {
+            &self.measurements
+        }
+
+        fn reset(&mut self) {
+            self.measurements.clear()
+        }
+    }
+
+    #[test]
+    fn test_recorder() -> anyhow::Result<()> {
+        let probe = FakeProbe {
+            counter: 0,
+            measurements: EnergyMeasurements::new(1),
+        };
+        let mut recorder = Recorder::new(Box::new(probe), Duration::from_millis(1));
+        assert!(recorder.stop().is_err(), "stop() before start() should fail");
+
+        recorder.start()?;
+        assert!(recorder.start().is_err(), "the recorder cannot be started twice");
+        let checkpoint = recorder.checkpoint();
+        std::thread::sleep(Duration::from_millis(50));
+        let since_checkpoint = recorder.energy_since(&checkpoint).joules(0, RaplDomainType::Package);
+        let session = recorder.stop()?;
+        assert!(since_checkpoint > 0.0);
+
+        let n = session.snapshots.len();
+        assert!(n >= 2, "not enough snapshots: {n}");
+        // the first poll has no previous value, each following one consumes 0.5 J
+        assert!(session.snapshots[0].measurements.per_socket[0][RaplDomainType::Package]
+            .joules
+            .is_none());
+        assert_eq!(
+            session.total_joules(0, RaplDomainType::Package),
+            Some((n - 1) as f64 * 0.5)
+        );
+        assert_eq!(session.total_joules(0, RaplDomainType::Dram), None);
+        assert!(session.mean_watts(0, RaplDomainType::Package).unwrap() > 0.0);
+
+        // the recorder can be restarted, with fresh measurements,
+        // and stopping it immediately still polls the probe at the beginning and at the end
+        recorder.start()?;
+        let session = recorder.stop()?;
+        assert!(session.snapshots.len() >= 2);
+        assert!(session.total_joules(0, RaplDomainType::Package).is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! Offline replay of raw counter values, to compute the energy again with other parameters
+//! (e.g. another overflow correction) without the RAPL hardware.
+//!
+//! The values are recorded by [`write_raw`], which is used by the `raw` output of `cli_poll_rapl`,
+//! and replayed by [`ReplayProbe`].
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context};
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError, RawCounter};
+
+/// The header of the raw format. The rows of a poll are consecutive and have the same timestamp.
+/// The domains are written with their [`RaplDomainType::canonical_name`].
+pub const RAW_HEADER: &str = "timestamp_us;socket;domain;raw_counter;max_value;energy_unit";
+
+/// Writes the raw value of each counter of `measurements`, polled at `timestamp`, in the format of [`RAW_HEADER`].
+///
+/// The counters that have no value yet are skipped. Unlike the CSV output, the first poll has rows:
+/// it is the reference of the second one.
+pub fn write_raw<W: Write + ?Sized>(
+    writer: &mut W,
+    timestamp: SystemTime,
+    measurements: &EnergyMeasurements,
+) -> anyhow::Result<()> {
+    let timestamp_us = timestamp.duration_since(SystemTime::UNIX_EPOCH)?.as_micros();
+    for (socket, domains) in measurements.per_socket.iter().enumerate() {
+        for (domain, counter) in domains {
+            if let Some(RawCounter {
+                value,
+                max_value,
+                energy_unit,
+            }) = counter.raw()
+            {
+                let domain = domain.canonical_name();
+                // the Display of f64 is exact: the energy unit is read back without loss
+                writeln!(writer, "{timestamp_us};{socket};{domain};{value};{max_value};{energy_unit}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A probe that replays the raw values written by [`write_raw`].
+///
+/// Each call to [`EnergyProbe::poll`] pushes the values of the next recorded poll through [`EnergyMeasurements::push`],
+/// like a real probe: the energy, the overflows and the elapsed time (from the recorded timestamps) are computed again.
+///
+/// ```
+/// use rapl_probes::{replay::ReplayProbe, EnergyProbe, RaplDomainType};
+///
+/// let raw = "timestamp_us;socket;domain;raw_counter;max_value;energy_unit
+/// 1000000;0;package;4294967290;4294967295;1
+/// 2000000;0;package;10;4294967295;1
+/// ";
+/// let mut probe = ReplayProbe::from_reader(raw.as_bytes()).unwrap();
+/// probe.poll().unwrap();
+/// probe.poll().unwrap();
+/// let package = &probe.measurements().per_socket[0][RaplDomainType::Package];
+/// assert_eq!(package.joules, Some(16.0));
+/// assert!(package.overflowed);
+/// ```
+pub struct ReplayProbe {
+    measurements: EnergyMeasurements,
+    polls: Vec<RecordedPoll>,
+    /// Index of the next poll to replay.
+    next: usize,
+    /// The monotonic time that corresponds to the first recorded timestamp.
+    origin: Instant,
+}
+
+struct RecordedPoll {
+    timestamp: SystemTime,
+    counters: Vec<(u32, RaplDomainType, RawCounter)>,
+}
+
+impl RecordedPoll {
+    fn contains(&self, socket: u32, domain: RaplDomainType) -> bool {
+        self.counters.iter().any(|(s, d, _)| *s == socket && *d == domain)
+    }
+}
+
+impl ReplayProbe {
+    /// Reads all the polls of a file in the raw format.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<ReplayProbe> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+        ReplayProbe::from_reader(BufReader::new(file)).with_context(|| format!("failed to read {path:?}"))
+    }
+
+    /// Reads all the polls in the raw format.
+    /// Empty lines, comment lines (which start with `#`) and headers are skipped.
+    pub fn from_reader<R: BufRead>(reader: R) -> anyhow::Result<ReplayProbe> {
+        let mut polls: Vec<RecordedPoll> = Vec::new();
+        let mut socket_count = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line == RAW_HEADER {
+                continue;
+            }
+            let (timestamp, socket, domain, raw) = parse_raw_row(line).with_context(|| format!("invalid line {}", i + 1))?;
+            socket_count = socket_count.max(socket as usize + 1);
+            // a counter that is already in the poll starts a new one, even if the clock has not changed
+            match polls.last_mut() {
+                Some(poll) if poll.timestamp == timestamp && !poll.contains(socket, domain) => {
+                    poll.counters.push((socket, domain, raw))
+                }
+                _ => polls.push(RecordedPoll {
+                    timestamp,
+                    counters: vec![(socket, domain, raw)],
+                }),
+            }
+        }
+        Ok(ReplayProbe {
+            measurements: EnergyMeasurements::new(socket_count),
+            polls,
+            next: 0,
+            origin: Instant::now(),
+        })
+    }
+
+    /// Replays the values with [`EnergyMeasurements::with_overflow_estimation`].
+    pub fn with_overflow_estimation(mut self, period: Duration) -> ReplayProbe {
+        self.measurements = EnergyMeasurements::with_overflow_estimation(self.measurements.per_socket.len(), period);
+        self
+    }
+
+    /// The number of polls that have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.polls.len() - self.next
+    }
+}
+
+fn parse_raw_row(line: &str) -> anyhow::Result<(SystemTime, u32, RaplDomainType, RawCounter)> {
+    let fields: Vec<&str> = line.split(';').map(str::trim).collect();
+    let [timestamp_us, socket, domain, value, max_value, energy_unit] = fields[..] else {
+        return Err(anyhow!("expected 6 fields ({RAW_HEADER}), got {}", fields.len()));
+    };
+    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_micros(timestamp_us.parse()?);
+    let domain = domain.parse().map_err(|_| anyhow!("unknown domain {domain}"))?;
+    let raw = RawCounter {
+        value: value.parse()?,
+        max_value: max_value.parse()?,
+        energy_unit: energy_unit.parse()?,
+    };
+    Ok((timestamp, socket.parse()?, domain, raw))
+}
+
+impl EnergyProbe for ReplayProbe {
+    /// Pushes the values of the next recorded poll.
+    /// Returns an error if all the polls have been replayed.
+    fn poll(&mut self) -> Result<(), RaplError> {
+        let first = self.polls.first().map(|p| p.timestamp);
+        let poll = self
+            .polls
+            .get(self.next)
+            .ok_or_else(|| anyhow!("all the {} recorded polls have been replayed", self.polls.len()))?;
+        self.measurements.set_timestamp(poll.timestamp);
+        let since_first = first.and_then(|t| poll.timestamp.duration_since(t).ok()).unwrap_or_default();
+        let time = self.origin + since_first;
+        for &(socket, domain, raw) in &poll.counters {
+            self.measurements.push_at(socket, domain, raw.value, raw.max_value, raw.energy_unit, time);
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn measurements(&self) -> &EnergyMeasurements {
+        &self.measurements
+    }
+
+    /// Clears the measurements, but does not rewind the recording.
+    fn reset(&mut self) {
+        self.measurements.clear()
+    }
+
+    fn energy_unit_for(&self, socket: u32, domain: RaplDomainType) -> Option<f64> {
+        self.polls
+            .iter()
+            .flat_map(|p| &p.counters)
+            .find(|(s, d, _)| *s == socket && *d == domain)
+            .map(|(_, _, raw)| raw.energy_unit)
+    }
+
+    /// Returns the values of the last replayed poll.
+    fn read_absolute(&mut self, out: &mut Vec<(u32, RaplDomainType, u64)>) -> Result<(), RaplError> {
+        out.clear();
+        let current = self
+            .next
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("no poll has been replayed yet"))?;
+        out.extend(self.polls[current].counters.iter().map(|&(s, d, raw)| (s, d, raw.value)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::SystemTime;
+
+    use super::{write_raw, ReplayProbe, RAW_HEADER};
+    use crate::mock::MockProbe;
+    use crate::{EnergyProbe, RaplDomainType};
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        // the package of socket 1 wraps, and the DRAM has another unit
+        let mut probe = MockProbe::new(2)
+            .with_counter(0, RaplDomainType::Package, vec![100, 300, 700, 1500], u32::MAX as u64, 0.5_f64.powi(14))
+            .with_counter(0, RaplDomainType::Dram, vec![0, 10, 20, 30], u32::MAX as u64, 0.5_f64.powi(16))
+            .with_counter(1, RaplDomainType::Package, vec![900, 990, 50, 150], 999, 1.0);
+
+        let mut raw: Vec<u8> = Vec::new();
+        writeln!(raw, "{RAW_HEADER}")?;
+        let mut measured = Vec::new();
+        for _ in 0..4 {
+            probe.poll()?;
+            let m = probe.measurements();
+            write_raw(&mut raw, m.timestamp().unwrap(), m)?;
+            measured.push(m.clone());
+        }
+
+        let mut replay = ReplayProbe::from_reader(raw.as_slice())?;
+        assert_eq!(replay.remaining(), 4);
+        assert_eq!(replay.energy_unit_for(0, RaplDomainType::Dram), Some(0.5_f64.powi(16)));
+        for expected in &measured {
+            replay.poll()?;
+            let replayed = replay.measurements();
+            // the timestamps are written in microseconds
+            let micros = |t: SystemTime| t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_micros();
+            assert_eq!(micros(replayed.timestamp().unwrap()), micros(expected.timestamp().unwrap()));
+            for (socket, domains) in expected.per_socket.iter().enumerate() {
+                for (domain, counter) in domains {
+                    let replayed = &replayed.per_socket[socket][domain];
+                    assert_eq!(replayed.joules, counter.joules, "{socket}/{domain:?}");
+                    assert_eq!(replayed.overflowed, counter.overflowed, "{socket}/{domain:?}");
+                    assert_eq!(replayed.raw(), counter.raw(), "{socket}/{domain:?}");
+                }
+            }
+        }
+        assert_eq!(replay.measurements().per_socket[1][RaplDomainType::Package].joules, Some(100.0));
+
+        let mut absolute = Vec::new();
+        replay.read_absolute(&mut absolute)?;
+        assert!(absolute.contains(&(1, RaplDomainType::Package, 150)));
+        assert!(replay.poll().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_raw() {
+        let raw = format!("{RAW_HEADER}\n1000;0;package;10;4294967295\n");
+        let err = ReplayProbe::from_reader(raw.as_bytes()).err().unwrap();
+        assert_eq!(err.to_string(), "invalid line 2");
+        assert!(ReplayProbe::from_reader("1000;0;gpu2;10;4294967295;1\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,406 @@
+//! Statistics of the power over a whole measurement, for a summary at the end of a benchmark.
+
+use std::fmt::Write as _;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::anyhow;
+use enum_map::EnumMap;
+
+use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType, RaplError};
+
+/// Statistics of one domain of one socket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainStats {
+    /// The total energy consumed, in Joules.
+    pub joules: f64,
+    /// The total time during which `joules` has been consumed.
+    pub elapsed: Duration,
+    /// The number of recorded values.
+    pub samples: u64,
+    /// The lowest power between two polls, in Watts.
+    pub min_watts: f64,
+    /// The highest power between two polls, in Watts.
+    pub max_watts: f64,
+}
+
+impl DomainStats {
+    /// The mean power, in Watts.
+    ///
+    /// This is weighted by time, i.e. this is the total energy divided by the total duration,
+    /// and not the mean of the samples (which would be biased if the period is irregular).
+    pub fn mean_watts(&self) -> Option<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        (secs > 0.0).then(|| self.joules / secs)
+    }
+}
+
+impl Default for DomainStats {
+    fn default() -> Self {
+        DomainStats {
+            joules: 0.0,
+            elapsed: Duration::ZERO,
+            samples: 0,
+            min_watts: f64::INFINITY,
+            max_watts: f64::NEG_INFINITY,
+        }
+    }
+}
+
+/// Accumulates the measurements of each socket and domain, to compute the min/max/mean power and the total energy.
+#[derive(Debug, Default, Clone)]
+pub struct EnergyStats {
+    per_socket: Vec<EnumMap<RaplDomainType, Option<DomainStats>>>,
+    first_timestamp: Option<SystemTime>,
+    last_timestamp: Option<SystemTime>,
+}
+
+impl EnergyStats {
+    pub fn new() -> EnergyStats {
+        EnergyStats::default()
+    }
+
+    /// Records the values of the last poll. The counters that have no value yet (first poll) are ignored.
+    pub fn record(&mut self, measurements: &EnergyMeasurements) {
+        if let Some(t) = measurements.timestamp() {
+            self.first_timestamp.get_or_insert(t);
+            self.last_timestamp = Some(t);
+        }
+        if self.per_socket.len() < measurements.per_socket.len() {
+            self.per_socket.resize(measurements.per_socket.len(), EnumMap::default());
+        }
+        for (stats, domains_of_socket) in self.per_socket.iter_mut().zip(&measurements.per_socket) {
+            for (domain, counter) in domains_of_socket {
+                let (Some(joules), Some(elapsed)) = (counter.joules, counter.elapsed) else {
+                    continue;
+                };
+                let s = stats[domain].get_or_insert_with(DomainStats::default);
+                s.joules += joules;
+                s.elapsed += elapsed;
+                s.samples += 1;
+                if let Some(watts) = counter.watts() {
+                    s.min_watts = s.min_watts.min(watts);
+                    s.max_watts = s.max_watts.max(watts);
+                }
+            }
+        }
+    }
+
+    /// The statistics of a domain of a socket, or `None` if it has not been measured.
+    pub fn get(&self, socket: u32, domain: RaplDomainType) -> Option<&DomainStats> {
+        self.per_socket.get(socket as usize)?[domain].as_ref()
+    }
+
+    /// The time between the first and the last recorded polls.
+    pub fn duration(&self) -> Option<Duration> {
+        self.last_timestamp?.duration_since(self.first_timestamp?).ok()
+    }
+
+    /// Returns a printable report, with one line per socket and domain.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        if let Some(d) = self.duration() {
+            writeln!(out, "Measured during {:.3}s", d.as_secs_f64()).unwrap();
+        }
+        for (socket, domains_of_socket) in self.per_socket.iter().enumerate() {
+            for (domain, stats) in domains_of_socket {
+                let Some(s) = stats else {
+                    continue;
+                };
+                let domain = domain.canonical_name();
+                write!(out, "socket {socket} {domain:<8} {:>12.3} J", s.joules).unwrap();
+                match s.mean_watts() {
+                    Some(mean) if s.min_watts <= s.max_watts => writeln!(
+                        out,
+                        "  mean {mean:.3} W  min {:.3} W  max {:.3} W  ({} samples)",
+                        s.min_watts, s.max_watts, s.samples
+                    )
+                    .unwrap(),
+                    _ => writeln!(out, "  ({} samples)", s.samples).unwrap(),
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Measures the energy consumed during `duration`, by polling `probe` every `poll_period`, and returns the totals.
+///
+/// The probe is reset, then polled once to initialize the counters: this first poll gives no energy.
+/// The last poll happens at the end of `duration`, even if it is not a multiple of `poll_period`.
+/// This blocks the current thread until the end of the measurement.
+pub fn measure_for(probe: &mut dyn EnergyProbe, duration: Duration, poll_period: Duration) -> Result<EnergyStats, RaplError> {
+    if poll_period.is_zero() {
+        return Err(RaplError::Other(anyhow!("The polling period must be positive")));
+    }
+    let mut stats = EnergyStats::new();
+    probe.reset();
+    let start = Instant::now();
+    probe.poll()?;
+    stats.record(probe.measurements());
+
+    let end = start + duration;
+    let mut next_poll = start;
+    while next_poll < end {
+        next_poll = (next_poll + poll_period).min(end);
+        if let Some(wait) = next_poll.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+        probe.poll()?;
+        stats.record(probe.measurements());
+    }
+    Ok(stats)
+}
+
+/// Mean and 95% confidence interval of a value measured over several repetitions (e.g. energy or time of a benchmark).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepetitionStats {
+    /// The number of repetitions.
+    pub n: usize,
+    pub mean: f64,
+    /// The sample standard deviation (with Bessel's correction), `None` if there is only one value.
+    pub stddev: Option<f64>,
+    /// The half-width of the 95% confidence interval of the mean, based on the Student's t-distribution,
+    /// `None` if there is only one value.
+    pub ci95_half_width: Option<f64>,
+}
+
+/// Two-sided 95% critical values of the Student's t-distribution, for 1 to 30 degrees of freedom.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160, 2.145, 2.131, 2.120,
+    2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Two-sided 95% critical value of the normal distribution, used above 30 degrees of freedom.
+const Z_95: f64 = 1.960;
+
+impl RepetitionStats {
+    /// Computes the statistics of `values`, or returns `None` if there is no value.
+    pub fn from_values(values: &[f64]) -> Option<RepetitionStats> {
+        let n = values.len();
+        if n == 0 {
+            return None;
+        }
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let (stddev, ci95_half_width) = if n > 1 {
+            let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            let stddev = variance.sqrt();
+            let t = T_95.get(n - 2).copied().unwrap_or(Z_95);
+            (Some(stddev), Some(t * stddev / (n as f64).sqrt()))
+        } else {
+            (None, None)
+        };
+        Some(RepetitionStats {
+            n,
+            mean,
+            stddev,
+            ci95_half_width,
+        })
+    }
+}
+
+impl std::fmt::Display for RepetitionStats {
+    /// Formats the statistics as `mean ± half-width (n = ...)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ci95_half_width {
+            Some(hw) => write!(f, "{:.3} ± {:.3} (95% CI, n = {})", self.mean, hw, self.n),
+            None => write!(f, "{:.3} (n = {})", self.mean, self.n),
+        }
+    }
+}
+
+/// Pearson correlation between two series of values, e.g. the frequency and the power of a socket.
+///
+/// The values are not stored: the moments are updated at each new pair, with Welford's algorithm,
+/// which is numerically stable even for long measurements.
+#[derive(Debug, Clone, Default)]
+pub struct Correlation {
+    n: usize,
+    mean_x: f64,
+    mean_y: f64,
+    /// Sum of the squared deviations of x from its mean.
+    m2_x: f64,
+    /// Sum of the squared deviations of y from its mean.
+    m2_y: f64,
+    /// Sum of the products of the deviations of x and y.
+    co_moment: f64,
+}
+
+impl Correlation {
+    pub fn new() -> Correlation {
+        Correlation::default()
+    }
+
+    /// Adds a pair of values.
+    pub fn push(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        let n = self.n as f64;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += dx / n;
+        self.mean_y += dy / n;
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.co_moment += dx * (y - self.mean_y);
+    }
+
+    /// The number of pairs.
+    pub fn count(&self) -> usize {
+        self.n
+    }
+
+    /// The mean of x, `None` if there is no value.
+    pub fn mean_x(&self) -> Option<f64> {
+        (self.n > 0).then_some(self.mean_x)
+    }
+
+    /// The coefficient of variation of x (sample standard deviation divided by the mean),
+    /// `None` if there are less than two values or if the mean is zero.
+    pub fn variation_x(&self) -> Option<f64> {
+        if self.n < 2 || self.mean_x == 0.0 {
+            return None;
+        }
+        let stddev = (self.m2_x / (self.n - 1) as f64).sqrt();
+        Some(stddev / self.mean_x.abs())
+    }
+
+    /// The Pearson correlation coefficient, between -1 and 1,
+    /// `None` if there are less than two pairs or if one of the series is constant.
+    pub fn coefficient(&self) -> Option<f64> {
+        if self.n < 2 || self.m2_x <= 0.0 || self.m2_y <= 0.0 {
+            return None;
+        }
+        let r = self.co_moment / (self.m2_x * self.m2_y).sqrt();
+        // the rounding errors can go slightly beyond the bounds
+        Some(r.clamp(-1.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use super::{measure_for, Correlation, EnergyStats, RepetitionStats};
+    use crate::mock::MockProbe;
+    use crate::{EnergyMeasurements, EnergyProbe, RaplDomainType};
+
+    #[test]
+    fn test_stats() {
+        let mut m = EnergyMeasurements::new(2);
+        let mut stats = EnergyStats::new();
+        let t0 = Instant::now();
+        let wall0 = SystemTime::now();
+        // package of socket 0: 10 W during 1s, 30 W during 1s, 20 W during 2s
+        // dram of socket 1: constant 5 W
+        for (secs, pkg, dram) in [(0, 0, 0), (1, 10, 5), (2, 40, 10), (4, 80, 20)] {
+            let t = t0 + Duration::from_secs(secs);
+            m.set_timestamp(wall0 + Duration::from_secs(secs));
+            m.push_at(0, RaplDomainType::Package, pkg, u32::MAX as u64, 1.0, t);
+            m.push_at(1, RaplDomainType::Dram, dram, u32::MAX as u64, 1.0, t);
+            stats.record(&m);
+        }
+
+        let pkg = stats.get(0, RaplDomainType::Package).unwrap();
+        assert_eq!(pkg.joules, 80.0);
+        assert_eq!(pkg.samples, 3);
+        assert_eq!(pkg.min_watts, 10.0);
+        assert_eq!(pkg.max_watts, 30.0);
+        assert_eq!(pkg.mean_watts(), Some(20.0));
+
+        let dram = stats.get(1, RaplDomainType::Dram).unwrap();
+        assert_eq!(dram.joules, 20.0);
+        assert_eq!((dram.min_watts, dram.max_watts, dram.mean_watts()), (5.0, 5.0, Some(5.0)));
+
+        assert!(stats.get(0, RaplDomainType::Dram).is_none());
+        assert!(stats.get(2, RaplDomainType::Package).is_none());
+        assert_eq!(stats.duration(), Some(Duration::from_secs(4)));
+
+        let summary = stats.summary();
+        assert_eq!(summary.lines().count(), 3, "{summary}");
+        assert!(summary.contains("socket 0 package        80.000 J  mean 20.000 W  min 10.000 W  max 30.000 W  (3 samples)"), "{summary}");
+    }
+
+    #[test]
+    fn test_measure_for() -> anyhow::Result<()> {
+        // the counter does not start at zero: the first poll must not count as energy
+        let values = vec![1000, 1010, 1025, 1045, 1050];
+        let mut probe = MockProbe::new(1).with_counter(0, RaplDomainType::Package, values, u32::MAX as u64, 1.0);
+
+        // polls at 0, 20, 40 and 50 ms
+        let stats = measure_for(&mut probe, Duration::from_millis(50), Duration::from_millis(20))?;
+        assert_eq!(probe.poll_count(), 4);
+        let pkg = stats.get(0, RaplDomainType::Package).unwrap();
+        assert_eq!(pkg.joules, 45.0);
+        assert_eq!(pkg.samples, 3);
+        assert!(stats.duration().unwrap() >= Duration::from_millis(50));
+        assert!(probe.measurements().per_socket[0][RaplDomainType::Package].joules.is_some());
+
+        assert!(measure_for(&mut probe, Duration::from_millis(50), Duration::ZERO).is_err());
+        Ok(())
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_repetition_stats() {
+        assert_eq!(RepetitionStats::from_values(&[]), None);
+
+        let single = RepetitionStats::from_values(&[4.2]).unwrap();
+        assert_eq!((single.mean, single.stddev, single.ci95_half_width), (4.2, None, None));
+        assert_eq!(single.to_string(), "4.200 (n = 1)");
+
+        // n = 2: t = 12.706, s = sqrt(2), half-width = 12.706 * sqrt(2) / sqrt(2)
+        let two = RepetitionStats::from_values(&[1.0, 3.0]).unwrap();
+        assert_eq!(two.mean, 2.0);
+        assert_close(two.stddev.unwrap(), 2f64.sqrt());
+        assert_close(two.ci95_half_width.unwrap(), 12.706);
+
+        // n = 5: mean 10, s = sqrt(2.5) = 1.5811, t = 2.776, half-width = 2.776 * 1.5811 / sqrt(5) = 1.9630
+        let five = RepetitionStats::from_values(&[8.0, 9.0, 10.0, 11.0, 12.0]).unwrap();
+        assert_eq!(five.mean, 10.0);
+        assert_close(five.stddev.unwrap(), 1.5811);
+        assert_close(five.ci95_half_width.unwrap(), 1.9630);
+        assert_eq!(five.to_string(), "10.000 ± 1.963 (95% CI, n = 5)");
+
+        // large n: normal approximation
+        let values: Vec<f64> = (0..100).map(|i| (i % 2) as f64).collect();
+        let large = RepetitionStats::from_values(&values).unwrap();
+        assert_close(large.ci95_half_width.unwrap(), 1.960 * large.stddev.unwrap() / 10.0);
+    }
+
+    fn correlation_of(pairs: impl IntoIterator<Item = (f64, f64)>) -> Correlation {
+        let mut c = Correlation::new();
+        for (x, y) in pairs {
+            c.push(x, y);
+        }
+        c
+    }
+
+    #[test]
+    fn test_correlation() {
+        assert_eq!(Correlation::new().coefficient(), None);
+        assert_eq!(Correlation::new().mean_x(), None);
+        assert_eq!(correlation_of([(1.0, 2.0)]).coefficient(), None);
+
+        // linear relations
+        let increasing = correlation_of((0..50).map(|i| (2000.0 + 10.0 * i as f64, 30.0 + 0.5 * i as f64)));
+        assert_close(increasing.coefficient().unwrap(), 1.0);
+        let decreasing = correlation_of((0..50).map(|i| (i as f64, 100.0 - 3.0 * i as f64)));
+        assert_close(decreasing.coefficient().unwrap(), -1.0);
+        // constant series
+        assert_eq!(correlation_of((0..10).map(|i| (3000.0, i as f64))).coefficient(), None);
+
+        // x = 1..5, y = 2, 4, 5, 4, 5: r = 6 / sqrt(10 * 6)
+        let c = correlation_of([(1.0, 2.0), (2.0, 4.0), (3.0, 5.0), (4.0, 4.0), (5.0, 5.0)]);
+        assert_eq!(c.count(), 5);
+        assert_close(c.coefficient().unwrap(), 0.7746);
+        assert_eq!(c.mean_x(), Some(3.0));
+        // stddev of x = sqrt(2.5)
+        assert_close(c.variation_x().unwrap(), 2.5f64.sqrt() / 3.0);
+
+        // uncorrelated: a frequency that alternates, and a power that does not follow it
+        let uncorrelated = correlation_of((0..100).map(|i| ((2000 + 1000 * (i % 2)) as f64, (40 + 10 * ((i / 2) % 2)) as f64)));
+        assert_close(uncorrelated.coefficient().unwrap(), 0.0);
+    }
+}
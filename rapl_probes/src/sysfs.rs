@@ -0,0 +1,100 @@
+//! Location of the sysfs interfaces used by the discovery, which can be moved for the containers and the tests.
+//!
+//! By default, the interfaces are read at their usual paths. If the environment variable `RAPL_SYSFS_ROOT` is set,
+//! they are read under this directory instead: `/sys/devices/power/type` becomes `$RAPL_SYSFS_ROOT/sys/devices/power/type`.
+
+use std::path::{Path, PathBuf};
+
+/// The environment variable that sets the root of [`SysfsPaths::from_env`].
+pub const SYSFS_ROOT_ENV: &str = "RAPL_SYSFS_ROOT";
+
+/// The directory that contains `sys`, usually `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysfsPaths {
+    root: PathBuf,
+}
+
+impl SysfsPaths {
+    /// The real paths of the host.
+    pub fn host() -> SysfsPaths {
+        SysfsPaths::with_root("/")
+    }
+
+    /// Reads the interfaces under `root`, which has the same structure as `/` (e.g. a bind mount, or a test fixture).
+    pub fn with_root(root: impl Into<PathBuf>) -> SysfsPaths {
+        SysfsPaths { root: root.into() }
+    }
+
+    /// The root given by `RAPL_SYSFS_ROOT`, or the real paths if the variable is not set (or empty).
+    pub fn from_env() -> SysfsPaths {
+        match std::env::var_os(SYSFS_ROOT_ENV) {
+            Some(root) if !root.is_empty() => SysfsPaths::with_root(root),
+            _ => SysfsPaths::host(),
+        }
+    }
+
+    /// Returns the location of `path`, which is the absolute path of an interface on the host (e.g. `/sys/devices/power/type`).
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::SysfsPaths;
+    use crate::msr::RaplVendor;
+    use crate::powercap::{all_power_zones_in, PowercapProbe};
+    use crate::{cpus_to_monitor_in, online_cpus_in, perf_event, CpuId, EnergyProbe, RaplDomainType};
+
+    /// A machine with two sockets, in `tests/fixtures`.
+    fn fixture() -> SysfsPaths {
+        SysfsPaths::with_root(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/two-sockets"))
+    }
+
+    #[test]
+    fn test_path() {
+        let host = SysfsPaths::host();
+        assert_eq!(host.path("/sys/devices/power/type"), PathBuf::from("/sys/devices/power/type"));
+        let container = SysfsPaths::with_root("/host");
+        assert_eq!(container.path("/sys/devices/power/type"), PathBuf::from("/host/sys/devices/power/type"));
+        assert_eq!(container.path("sys/devices/power/type"), PathBuf::from("/host/sys/devices/power/type"));
+    }
+
+    #[test]
+    fn test_fixture_discovery() -> anyhow::Result<()> {
+        let paths = fixture();
+        assert_eq!(online_cpus_in(&paths)?, (0..16).collect::<Vec<u32>>());
+        assert_eq!(
+            cpus_to_monitor_in(&paths)?,
+            vec![
+                CpuId { cpu: 0, socket: 0, numa_node: Some(0) },
+                CpuId { cpu: 8, socket: 1, numa_node: Some(1) },
+            ]
+        );
+
+        assert_eq!(perf_event::pmu_type_in(&paths)?, 23);
+        let mut events = perf_event::all_power_events_in(&paths, Some(RaplVendor::Intel))?;
+        events.sort_by_key(|e| e.code);
+        let events: Vec<_> = events.iter().map(|e| (e.name.as_str(), e.domain, e.code)).collect();
+        assert_eq!(
+            events,
+            vec![("cores", RaplDomainType::PP0, 1), ("pkg", RaplDomainType::Package, 2), ("ram", RaplDomainType::Dram, 3)]
+        );
+
+        let zones = all_power_zones_in(&paths)?;
+        let top: Vec<_> = zones.top.iter().map(|z| (z.name.as_str(), z.socket_id)).collect();
+        assert_eq!(top, vec![("package-0", Some(0)), ("package-1", Some(1)), ("psys", None)]);
+        assert_eq!(zones.flat.len(), 5);
+
+        // the zones of the fixture can be read like the real ones
+        let packages: Vec<_> = zones.top.iter().filter(|z| z.domain == RaplDomainType::Package).collect();
+        let mut probe = PowercapProbe::<true>::new(&cpus_to_monitor_in(&paths)?, &packages)?;
+        let mut values = Vec::new();
+        probe.read_absolute(&mut values)?;
+        assert_eq!(values, vec![(0, RaplDomainType::Package, 1000), (1, RaplDomainType::Package, 2000)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! Human-readable formatting of energy and power values.
+//!
+//! These functions are meant for outputs that are read by humans.
+//! Machine-readable outputs (CSV, etc.) should keep the raw values in Joules and Watts.
+
+/// Formats an energy with the most appropriate unit among µJ, mJ, J and kJ.
+pub fn format_energy(joules: f64) -> String {
+    format_with_prefix(joules, "J", &[(1e3, "k"), (1.0, ""), (1e-3, "m"), (1e-6, "µ")])
+}
+
+/// Formats a power with the most appropriate unit among mW, W and kW.
+pub fn format_power(watts: f64) -> String {
+    format_with_prefix(watts, "W", &[(1e3, "k"), (1.0, ""), (1e-3, "m")])
+}
+
+/// Formats `value` with the largest prefix whose factor is less or equal to `|value|`,
+/// or with the smallest one if there is none. `prefixes` must be sorted by decreasing factor.
+fn format_with_prefix(value: f64, unit: &str, prefixes: &[(f64, &str)]) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return format!("{value} {unit}");
+    }
+    let abs = value.abs();
+    let mut i = prefixes.iter().position(|(factor, _)| abs >= *factor).unwrap_or(prefixes.len() - 1);
+    // rounding can reach the next prefix: 999.9999 J is 1 kJ, not 1000 J
+    if i > 0 && (abs / prefixes[i].0 * 1e3).round() >= 1e6 {
+        i -= 1;
+    }
+    let (factor, prefix) = prefixes[i];
+
+    // at most 3 decimals, without the trailing zeros
+    let scaled = format!("{:.3}", value / factor);
+    let scaled = scaled.trim_end_matches('0').trim_end_matches('.');
+    format!("{scaled} {prefix}{unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_energy, format_power};
+
+    #[test]
+    fn test_format_energy() {
+        assert_eq!(format_energy(0.0), "0 J");
+        assert_eq!(format_energy(0.0005), "500 µJ");
+        assert_eq!(format_energy(0.000_000_5), "0.5 µJ");
+        assert_eq!(format_energy(0.001), "1 mJ");
+        assert_eq!(format_energy(0.999), "999 mJ");
+        assert_eq!(format_energy(0.9999999), "1 J");
+        assert_eq!(format_energy(1.0), "1 J");
+        assert_eq!(format_energy(12.3456), "12.346 J");
+        assert_eq!(format_energy(999.9999), "1 kJ");
+        assert_eq!(format_energy(1000.0), "1 kJ");
+        assert_eq!(format_energy(2_500_000.0), "2500 kJ");
+        assert_eq!(format_energy(-0.25), "-250 mJ");
+    }
+
+    #[test]
+    fn test_format_power() {
+        assert_eq!(format_power(0.0), "0 W");
+        assert_eq!(format_power(0.0001), "0.1 mW");
+        assert_eq!(format_power(0.5), "500 mW");
+        assert_eq!(format_power(45.2), "45.2 W");
+        assert_eq!(format_power(999.9), "999.9 W");
+        assert_eq!(format_power(999.9996), "1 kW");
+        assert_eq!(format_power(1500.0), "1.5 kW");
+    }
+}
@@ -40,3 +40,45 @@
 //         }
 //     }
 // }
+
+use rapl_probes::{mock::MockProbe, EnergyProbe, RaplDomainType};
+
+#[test]
+fn mock_probe_sequence() -> anyhow::Result<()> {
+    let mut probe = MockProbe::new(2)
+        .with_counter(0, RaplDomainType::Package, vec![100, 150, 400], u32::MAX as u64, 0.5)
+        .with_counter(1, RaplDomainType::Dram, vec![7, 8, 10], u32::MAX as u64, 1.0);
+
+    // the first poll only initializes the counters
+    probe.poll()?;
+    assert_eq!(probe.measurements().per_socket[0][RaplDomainType::Package].joules, None);
+
+    probe.poll()?;
+    let m = probe.measurements();
+    assert_eq!(m.per_socket[0][RaplDomainType::Package].joules, Some(25.0));
+    assert_eq!(m.per_socket[1][RaplDomainType::Dram].joules, Some(1.0));
+    assert!(m.timestamp().is_some());
+
+    probe.poll()?;
+    let m = probe.measurements();
+    assert_eq!(m.per_socket[0][RaplDomainType::Package].joules, Some(125.0));
+    assert_eq!(m.per_socket[0][RaplDomainType::Package].cumulative_joules, 150.0);
+    assert_eq!(m.per_socket[1][RaplDomainType::Dram].joules, Some(2.0));
+    assert_eq!(probe.poll_count(), 3);
+
+    // the script is exhausted
+    assert!(probe.poll().is_err());
+    Ok(())
+}
+
+#[test]
+fn mock_probe_overflow() -> anyhow::Result<()> {
+    let max = u32::MAX as u64;
+    let mut probe = MockProbe::new(1).with_counter(0, RaplDomainType::Package, vec![max - 4, 5], max, 1.0);
+    probe.poll()?;
+    probe.poll()?;
+    let counter = &probe.measurements().per_socket[0][RaplDomainType::Package];
+    assert!(counter.overflowed);
+    assert_eq!(counter.joules, Some(10.0));
+    Ok(())
+}
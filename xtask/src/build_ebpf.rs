@@ -37,6 +37,10 @@ pub struct Options {
     /// Build the release target
     #[clap(long)]
     pub release: bool,
+    /// Build the variant that reads the events in a loop (Linux >= 5.3), which supports more than 5 events.
+    /// It is placed in `target/ebpf-loops`, use it with the `ebpf_loops` feature of the userspace application.
+    #[clap(long)]
+    pub loops: bool,
 }
 
 pub fn build_ebpf(opts: Options) -> Result<(), anyhow::Error> {
@@ -52,6 +56,9 @@ pub fn build_ebpf(opts: Options) -> Result<(), anyhow::Error> {
     if opts.release {
         args.push("--release")
     }
+    if opts.loops {
+        args.extend(["--features", "ebpf_loops", "--target-dir", "../target/ebpf-loops"]);
+    }
 
     // Command::new creates a child process which inherits all env variables. This means env
     // vars set by the cargo xtask command are also inherited. RUSTUP_TOOLCHAIN is removed
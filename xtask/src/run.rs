@@ -13,6 +13,9 @@ pub struct Options {
     /// Build and run the release target
     #[clap(long)]
     pub release: bool,
+    /// Use the eBPF program that reads the events in a loop (Linux >= 5.3)
+    #[clap(long)]
+    pub loops: bool,
     /// The command used to wrap your application
     #[clap(short, long, default_value = "sudo -E")]
     pub runner: String,
@@ -29,6 +32,9 @@ fn build(opts: &Options) -> Result<(), anyhow::Error> {
     if opts.release {
         args.push("--release")
     }
+    if opts.loops {
+        args.extend(["--features", "ebpf_loops"]);
+    }
     let status = Command::new("cargo")
         .args(&args)
         .status()
@@ -43,6 +49,7 @@ pub fn run(opts: Options) -> Result<(), anyhow::Error> {
     let build_opts = BuildOptions {
         target: opts.bpf_target,
         release: opts.release,
+        loops: opts.loops,
     };
     build_ebpf(build_opts).context("Error while building eBPF program")?;
 